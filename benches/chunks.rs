@@ -0,0 +1,87 @@
+//! Allocations and time to parse and edit a typical small file, whose
+//! chunk list fits the inline storage, and to parse it into a reused
+//! arena and inspect it. Run with `cargo bench --bench chunks`
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
+
+use ping::arena::Arena;
+use ping::chunk::Chunk;
+use ping::png::Png;
+
+struct Counting;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: Counting = Counting;
+
+const ROUNDS: usize = 100_000;
+
+fn typical() -> Vec<u8> {
+    let ihdr = [0, 0, 0, 16, 0, 0, 0, 16, 8, 2, 0, 0, 0];
+    let chunks = [
+        ("IHDR", &ihdr[..]),
+        ("sRGB", &[0]),
+        ("gAMA", &[0, 0, 177, 143]),
+        ("pHYs", &[0, 0, 11, 19, 0, 0, 11, 19, 1]),
+        ("tEXt", b"Software\0ping"),
+        ("tEXt", b"Author\0Ferris"),
+        ("IDAT", &[0; 64]),
+        ("IEND", &[]),
+    ];
+    let chunks = chunks
+        .iter()
+        .map(|(chunk_type, data)| Chunk::new(chunk_type.parse().unwrap(), data.to_vec()));
+    Png::from_chunks(chunks.collect()).as_bytes()
+}
+
+fn main() {
+    let bytes = typical();
+    let before = ALLOCATIONS.load(Ordering::Relaxed);
+    let start = Instant::now();
+    for _ in 0..ROUNDS {
+        let mut png = Png::try_from(bytes.as_slice()).unwrap();
+        png.retain_chunks(|c| c.chunk_type().as_str() != "tEXt");
+        std::hint::black_box(&png);
+    }
+    let elapsed = start.elapsed();
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed) - before;
+    println!(
+        "parse and edit: {:.2?} and {:.1} allocations per file",
+        elapsed / ROUNDS as u32,
+        allocations as f64 / ROUNDS as f64
+    );
+
+    let mut arena = Arena::new();
+    let before = ALLOCATIONS.load(Ordering::Relaxed);
+    let start = Instant::now();
+    for _ in 0..ROUNDS {
+        let png = arena.parse(&bytes).unwrap();
+        std::hint::black_box(png.chunk_by_type("tEXt"));
+    }
+    let elapsed = start.elapsed();
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed) - before;
+    println!(
+        "parse into an arena: {:.2?} and {:.1} allocations per file",
+        elapsed / ROUNDS as u32,
+        allocations as f64 / ROUNDS as f64
+    );
+}
@@ -0,0 +1,56 @@
+//! Sequential against parallel CRC checks on a file of many IDAT chunks.
+//! Run with `cargo bench --bench validate`; the gain needs several cores
+
+use std::time::{Duration, Instant};
+
+use ping::chunk::Chunk;
+use ping::png::Png;
+use ping::validate::{validate, ValidateOptions};
+
+const CHUNKS: usize = 256;
+const CHUNK_SIZE: usize = 1 << 20;
+const ROUNDS: u32 = 10;
+
+fn time(bytes: &[u8], options: &ValidateOptions) -> Duration {
+    validate(bytes, options).unwrap();
+    let start = Instant::now();
+    for _ in 0..ROUNDS {
+        validate(bytes, options).unwrap();
+    }
+    start.elapsed() / ROUNDS
+}
+
+fn main() {
+    let ihdr = [0, 0, 0, 1, 0, 0, 0, 1, 8, 0, 0, 0, 0];
+    let mut chunks = vec![Chunk::new("IHDR".parse().unwrap(), ihdr.to_vec())];
+    chunks.extend(
+        (0..CHUNKS).map(|i| Chunk::new("IDAT".parse().unwrap(), vec![i as u8; CHUNK_SIZE])),
+    );
+    chunks.push(Chunk::new("IEND".parse().unwrap(), Vec::new()));
+    let bytes = Png::from_chunks(chunks).as_bytes();
+
+    let megabytes = bytes.len() as f64 / (1 << 20) as f64;
+    let sequential = time(
+        &bytes,
+        &ValidateOptions {
+            parallel: false,
+            ..ValidateOptions::default()
+        },
+    );
+    let parallel = time(
+        &bytes,
+        &ValidateOptions {
+            parallel: true,
+            ..ValidateOptions::default()
+        },
+    );
+    for (name, elapsed) in [("sequential", sequential), ("parallel", parallel)] {
+        let rate = megabytes / elapsed.as_secs_f64();
+        println!("{:<10} {:>10.2?} {:>8.0} MiB/s", name, elapsed, rate);
+    }
+    println!(
+        "{:.2}x on {} threads",
+        sequential.as_secs_f64() / parallel.as_secs_f64(),
+        rayon::current_num_threads()
+    );
+}
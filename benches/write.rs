@@ -0,0 +1,66 @@
+//! Writing a file of many small text chunks, each chunk or the whole file
+//! serialized with `as_bytes` and written against `write_to`. Run with
+//! `cargo bench --bench write`
+
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use ping::chunk::Chunk;
+use ping::png::Png;
+
+const CHUNKS: usize = 20_000;
+const ROUNDS: u32 = 20;
+
+fn time(path: &Path, write: impl Fn(&mut BufWriter<File>)) -> Duration {
+    let round = || {
+        let mut out = BufWriter::new(File::create(path).unwrap());
+        let start = Instant::now();
+        write(&mut out);
+        out.flush().unwrap();
+        start.elapsed()
+    };
+    round();
+    (0..ROUNDS).map(|_| round()).sum::<Duration>() / ROUNDS
+}
+
+fn main() {
+    let ihdr = [0, 0, 0, 1, 0, 0, 0, 1, 8, 0, 0, 0, 0];
+    let mut chunks = vec![Chunk::new("IHDR".parse().unwrap(), ihdr.to_vec())];
+    chunks.extend((0..CHUNKS).map(|i| {
+        let text = format!("Comment\0note {} of a text-heavy file", i);
+        Chunk::new("tEXt".parse().unwrap(), text.into_bytes())
+    }));
+    chunks.push(Chunk::new("IEND".parse().unwrap(), Vec::new()));
+    let png = Png::from_chunks(chunks);
+    let path = std::env::temp_dir().join("ping-bench-write.png");
+
+    // Chunk by chunk through a buffer, as the streaming writers go
+    let copied = time(&path, |out| {
+        out.write_all(png.header()).unwrap();
+        for chunk in png.chunks() {
+            out.write_all(&chunk.as_bytes()).unwrap();
+        }
+    });
+    let chunk_vectored = time(&path, |out| {
+        out.write_all(png.header()).unwrap();
+        for chunk in png.chunks() {
+            chunk.write_to(&mut *out).unwrap();
+        }
+    });
+    // The whole file straight to the file
+    let whole = time(&path, |out| {
+        out.get_mut().write_all(&png.as_bytes()).unwrap()
+    });
+    let png_vectored = time(&path, |out| png.write_to(out.get_mut()).unwrap());
+    std::fs::remove_file(&path).unwrap();
+    for (name, elapsed) in [
+        ("Chunk::as_bytes", copied),
+        ("Chunk::write_to", chunk_vectored),
+        ("Png::as_bytes", whole),
+        ("Png::write_to", png_vectored),
+    ] {
+        println!("{:<16} {:>10.2?}", name, elapsed);
+    }
+}
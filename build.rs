@@ -0,0 +1,19 @@
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+    // The header goes to OUT_DIR, since a build mustn't write to the source
+    // tree; copy it over include/ping.h after changing the C API:
+    //   cbindgen --config cbindgen.toml --output include/ping.h
+    // ffi's tests fail while the copy is out of date
+    #[cfg(feature = "ffi")]
+    {
+        println!("cargo:rerun-if-changed=src/ffi.rs");
+        println!("cargo:rerun-if-changed=cbindgen.toml");
+        let dir = std::env::var("CARGO_MANIFEST_DIR").unwrap();
+        let out = std::env::var("OUT_DIR").unwrap();
+        cbindgen::generate(&dir)
+            .expect("unable to generate the C header")
+            .write_to_file(format!("{}/ping.h", out));
+    }
+    #[cfg(feature = "node")]
+    napi_build::setup();
+}
@@ -0,0 +1,11 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use ping::chunk::Chunk;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(chunk) = Chunk::try_from(data) {
+        assert_eq!(chunk.as_bytes(), data);
+        let _ = chunk.data_as_string();
+    }
+});
@@ -0,0 +1,29 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use ping::chunk_type::ChunkType;
+use ping::message;
+use ping::payload::{self, Keys};
+use ping::png::Png;
+
+fuzz_target!(|data: &[u8]| {
+    let rust: ChunkType = "ruSt".parse().unwrap();
+    // The input as one chunk's payload
+    if let Ok((inner, _)) = payload::correct(data) {
+        let _ = payload::decode(&inner, &rust, &Keys::default());
+    }
+    // The input as a file, decoding every ancillary chunk type in it
+    let Ok(png) = Png::try_from(data) else {
+        return;
+    };
+    let mut types: Vec<ChunkType> = png
+        .chunks()
+        .iter()
+        .map(|c| *c.chunk_type())
+        .filter(|t| !t.is_critical())
+        .collect();
+    types.dedup();
+    for chunk_type in types {
+        let _ = message::extract(&png, &chunk_type, None);
+    }
+});
@@ -0,0 +1,34 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use ping::arena::Arena;
+use ping::pixels::{self, Pixels};
+use ping::png::Png;
+use ping::streaming;
+use ping::text::TextChunk;
+use ping::validate::{self, ValidateOptions};
+
+fuzz_target!(|data: &[u8]| {
+    // Every check on the input as a file, CRCs and all
+    let options = ValidateOptions {
+        parallel: false,
+        keywords: true,
+        unique: true,
+        placement: true,
+        header: true,
+        indices: true,
+    };
+    let _ = validate::validate(data, &options);
+    let _ = validate::report(data, &options);
+    let _ = streaming::validate(data, &streaming::Options::default());
+    let _ = Arena::new().parse(data).map(|png| png.to_png());
+    // Decoding its chunks, wherever their CRCs came from
+    let Ok((png, _)) = Png::parse_ignoring_crc(data) else {
+        return;
+    };
+    for chunk in png.chunks() {
+        let _ = TextChunk::parse_checked(chunk);
+    }
+    let _ = pixels::check_indices(&png);
+    let _ = Pixels::read(&png);
+});
@@ -0,0 +1,16 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use ping::png::Png;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(png) = Png::try_from(data) {
+        // Whatever parses must write back out as a file that parses the same
+        let bytes = png.as_bytes();
+        let again = Png::try_from(bytes.as_slice()).expect("re-parse");
+        assert_eq!(again.as_bytes(), bytes);
+    }
+    if let Ok((png, _)) = Png::parse_ignoring_crc(data) {
+        let _ = png.as_bytes();
+    }
+});
@@ -0,0 +1,109 @@
+//! Writes a starting corpus for each fuzz target under `corpus/`: small
+//! valid files and payloads, and the near misses the parser has to reject
+
+use std::fs;
+use std::path::Path;
+
+use ping::chunk::Chunk;
+use ping::chunk_type::ChunkType;
+use ping::compress::Codec;
+use ping::message;
+use ping::payload;
+use ping::png::Png;
+
+fn chunk(chunk_type: &str, data: &[u8]) -> Chunk {
+    Chunk::new(chunk_type.parse::<ChunkType>().unwrap(), data.to_vec())
+}
+
+/// A 1x1 RGB image with `extra` before IEND
+fn minimal(extra: impl IntoIterator<Item = Chunk>) -> Png {
+    let mut ihdr = 1u32.to_be_bytes().to_vec();
+    ihdr.extend_from_slice(&1u32.to_be_bytes());
+    ihdr.extend_from_slice(&[8, 2, 0, 0, 0]);
+    let mut chunks = vec![
+        chunk("IHDR", &ihdr),
+        chunk("IDAT", &Codec::Zlib.compress(&[0, 255, 0, 0]).unwrap()),
+    ];
+    chunks.extend(extra);
+    chunks.push(chunk("IEND", b""));
+    Png::from_chunks(chunks)
+}
+
+fn with_message(message: &[u8], password: Option<&str>) -> Vec<u8> {
+    let mut png = minimal([]);
+    message::embed(&mut png, "ruSt".parse().unwrap(), message, password).unwrap();
+    png.as_bytes()
+}
+
+fn files() -> Vec<(&'static str, Vec<u8>)> {
+    let valid = minimal([]).as_bytes();
+    let mut bad_crc = valid.clone();
+    let last = bad_crc.len() - 1;
+    bad_crc[last] ^= 1;
+    let mut huge_length = valid.clone();
+    huge_length[Png::STANDARD_HEADER.len()..][..4].copy_from_slice(&u32::MAX.to_be_bytes());
+    let mut trailing = minimal([]);
+    trailing.set_trailing(b"after the end".to_vec());
+    let ecc = payload::protect(b"hello", 8).unwrap();
+    let split = payload::split((0..=255).cycle().take(2048).collect(), 512);
+
+    vec![
+        ("valid", valid.clone()),
+        ("empty", Vec::new()),
+        ("signature_only", Png::STANDARD_HEADER.to_vec()),
+        (
+            "bad_signature",
+            [b"\x89PNG\r\n\x1a\x0b".as_slice(), &valid[8..]].concat(),
+        ),
+        ("truncated", valid[..valid.len() - 3].to_vec()),
+        ("bad_crc", bad_crc),
+        ("huge_length", huge_length),
+        ("no_iend", valid[..valid.len() - Chunk::OVERHEAD].to_vec()),
+        ("trailing", trailing.as_bytes()),
+        ("message", with_message(b"hello", None)),
+        ("encrypted", with_message(b"hello", Some("hunter2"))),
+        ("ecc", minimal([chunk("ruSt", &ecc)]).as_bytes()),
+        (
+            "split",
+            minimal(split.iter().map(|part| chunk("ruSt", part))).as_bytes(),
+        ),
+    ]
+}
+
+fn write(target: &str, seeds: impl IntoIterator<Item = (String, Vec<u8>)>) {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("corpus")
+        .join(target);
+    fs::create_dir_all(&dir).unwrap();
+    for (name, bytes) in seeds {
+        fs::write(dir.join(name), bytes).unwrap();
+    }
+}
+
+fn main() {
+    let files = files();
+    let named = || {
+        files
+            .iter()
+            .map(|(name, bytes)| (name.to_string(), bytes.clone()))
+    };
+    write("png_parse", named());
+    write("decode", named());
+
+    // Every chunk of every file that parses, valid or not
+    let mut chunks = Vec::new();
+    for (name, bytes) in &files {
+        let Ok((png, _)) = Png::parse_ignoring_crc(bytes) else {
+            continue;
+        };
+        for (index, chunk) in png.chunks().iter().enumerate() {
+            chunks.push((format!("{}_{}", name, index), chunk.as_bytes()));
+        }
+    }
+    chunks.push(("short".to_string(), vec![0; Chunk::OVERHEAD - 1]));
+    chunks.push((
+        "bad_type".to_string(),
+        [&[0; 4][..], b"ru5t", &[0; 4]].concat(),
+    ));
+    write("chunk_parse", chunks);
+}
@@ -0,0 +1,108 @@
+/* Generated by cbindgen from src/ffi.rs; do not edit */
+
+#ifndef PING_H
+#define PING_H
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+typedef enum PingStatus {
+  PING_OK = 0,
+  /**
+   * A null pointer, invalid UTF-8 or an invalid chunk type
+   */
+  PING_INVALID_ARGUMENT = 1,
+  /**
+   * The input isn't a PNG file
+   */
+  PING_INVALID_PNG = 2,
+  PING_CHUNK_NOT_FOUND = 3,
+  /**
+   * Anything else, such as a wrong password
+   */
+  PING_FAILED = 4,
+  /**
+   * A bug; the library caught a panic rather than unwind into C
+   */
+  PING_PANICKED = 5,
+} PingStatus;
+
+/**
+ * Bytes allocated by the library, to be released with [`ping_free`]
+ */
+typedef struct PingBuffer {
+  uint8_t *data;
+  size_t len;
+} PingBuffer;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/**
+ * Parse the `len` bytes at `data` as a PNG file and write its chunk
+ * listing, one line per chunk and not NUL-terminated, to `out`
+ *
+ * # Safety
+ *
+ * `data` must point to `len` readable bytes and `out` to a writable
+ * [`PingBuffer`]
+ */
+enum PingStatus ping_parse(const uint8_t *data, size_t len, struct PingBuffer *out);
+
+/**
+ * Add `message_len` bytes at `message` in a new chunk of `chunk_type`,
+ * encrypted under `password` unless it's null, and write the new file to
+ * `out`
+ *
+ * # Safety
+ *
+ * `data` and `message` must point to that many readable bytes,
+ * `chunk_type` and `password` must be NUL-terminated strings, `password`
+ * may be null, and `out` must point to a writable [`PingBuffer`]
+ */
+enum PingStatus ping_encode(const uint8_t *data,
+                            size_t len,
+                            const char *chunk_type,
+                            const uint8_t *message,
+                            size_t message_len,
+                            const char *password,
+                            struct PingBuffer *out);
+
+/**
+ * Write the message in the chunks of `chunk_type` to `out`, decrypting it
+ * with `password` if it's encrypted
+ *
+ * # Safety
+ *
+ * As for [`ping_encode`]
+ */
+enum PingStatus ping_decode(const uint8_t *data,
+                            size_t len,
+                            const char *chunk_type,
+                            const char *password,
+                            struct PingBuffer *out);
+
+/**
+ * Release a buffer filled in by the library; an empty buffer is ignored
+ *
+ * # Safety
+ *
+ * `buffer` must have come from this library and not been freed before
+ */
+void ping_free(struct PingBuffer buffer);
+
+/**
+ * Description of the last failure on this thread, empty after a success.
+ * Valid until the next call on the same thread
+ */
+const char *ping_last_error(void);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* PING_H */
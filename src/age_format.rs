@@ -0,0 +1,95 @@
+use std::fmt::Display;
+use std::io::{Read, Write};
+use std::str::FromStr;
+
+use age::{x25519, Decryptor, Encryptor, Identity, IdentityFile, Recipient};
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum AgeError {
+    InvalidRecipient(String),
+    InvalidIdentity,
+    /// None of the identities can decrypt the payload
+    NoMatchingKey,
+    Encrypt,
+    Decrypt(String),
+}
+
+impl Display for AgeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AgeError::InvalidRecipient(recipient) => {
+                write!(f, "invalid age recipient {}", recipient)
+            }
+            AgeError::InvalidIdentity => write!(f, "invalid age identity file"),
+            AgeError::NoMatchingKey => write!(f, "payload is not encrypted to this age identity"),
+            AgeError::Encrypt => write!(f, "age encryption failed"),
+            AgeError::Decrypt(e) => write!(f, "age decryption failed: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for AgeError {}
+
+/// Encrypt to `age1...` X25519 recipients in age's binary format
+pub fn encrypt(message: &[u8], recipients: &[String]) -> Result<Vec<u8>, AgeError> {
+    let recipients = recipients
+        .iter()
+        .map(|r| x25519::Recipient::from_str(r).map_err(|_| AgeError::InvalidRecipient(r.clone())))
+        .collect::<Result<Vec<_>, _>>()?;
+    let encryptor = Encryptor::with_recipients(recipients.iter().map(|r| r as &dyn Recipient))
+        .map_err(|_| AgeError::Encrypt)?;
+    let mut ciphertext = Vec::new();
+    let mut writer = encryptor
+        .wrap_output(&mut ciphertext)
+        .map_err(|_| AgeError::Encrypt)?;
+    writer.write_all(message).map_err(|_| AgeError::Encrypt)?;
+    writer.finish().map_err(|_| AgeError::Encrypt)?;
+    Ok(ciphertext)
+}
+
+/// Decrypt with the keys in an age identity file, as written by age-keygen
+pub fn decrypt(ciphertext: &[u8], identities: &str) -> Result<Vec<u8>, AgeError> {
+    let identities = IdentityFile::from_buffer(identities.as_bytes())
+        .and_then(|file| file.into_identities().map_err(std::io::Error::other))
+        .map_err(|_| AgeError::InvalidIdentity)?;
+    let decryptor = Decryptor::new(ciphertext).map_err(|e| AgeError::Decrypt(e.to_string()))?;
+    let mut reader = decryptor
+        .decrypt(identities.iter().map(|i| i.as_ref() as &dyn Identity))
+        .map_err(|e| match e {
+            age::DecryptError::NoMatchingKeys => AgeError::NoMatchingKey,
+            e => AgeError::Decrypt(e.to_string()),
+        })?;
+    let mut message = Vec::new();
+    reader
+        .read_to_end(&mut message)
+        .map_err(|e| AgeError::Decrypt(e.to_string()))?;
+    Ok(message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use age::secrecy::ExposeSecret;
+
+    #[test]
+    fn test_round_trip() {
+        let identity = x25519::Identity::generate();
+        let other = x25519::Identity::generate();
+        let recipients = [identity.to_public().to_string()];
+
+        let ciphertext = encrypt(b"attack at dawn", &recipients).unwrap();
+        let file = identity.to_string();
+        assert_eq!(
+            decrypt(&ciphertext, file.expose_secret()).unwrap(),
+            b"attack at dawn"
+        );
+        assert_eq!(
+            decrypt(&ciphertext, other.to_string().expose_secret()),
+            Err(AgeError::NoMatchingKey)
+        );
+        assert_eq!(
+            encrypt(b"", &["age1nope".to_string()]),
+            Err(AgeError::InvalidRecipient("age1nope".to_string()))
+        );
+    }
+}
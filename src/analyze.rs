@@ -0,0 +1,460 @@
+use std::fmt::Display;
+
+use crate::order;
+use crate::pixels::Pixels;
+use crate::png::Png;
+use crate::stego::{self, StegoError, Trailer};
+
+/// More text chunks than this is unusual outside of heavily tagged files
+const TEXT_CHUNK_LIMIT: usize = 8;
+
+/// Chunks shorter than this say too little about their entropy
+const MIN_ENTROPY_LEN: usize = 64;
+
+/// Fraction of the highest entropy a chunk of its length can have above
+/// which its data looks encrypted or compressed
+const ENTROPY_THRESHOLD: f64 = 0.85;
+
+/// Chunk types whose data is compressed, and so high-entropy anyway
+const COMPRESSED_TYPES: [&str; 3] = ["zTXt", "iTXt", "iCCP"];
+
+/// Chi-square p-value above which value pairs look equalized by embedding.
+/// Noisy but smooth images have fairly even pairs too, so only a p-value
+/// this close to 1 counts
+const CHI_SQUARE_THRESHOLD: f64 = 0.95;
+
+/// Number of growing prefixes of the samples the chi-square test runs on
+const CHI_SQUARE_STEPS: usize = 32;
+
+/// Pairs of values expected fewer times than this are left out of the
+/// chi-square statistic, which is unreliable for them
+const MIN_EXPECTED: f64 = 5.0;
+
+/// RS estimate of the embedded fraction above which the low bits look
+/// tampered with; clean photographs usually estimate a few percent
+const RS_THRESHOLD: f64 = 0.1;
+
+/// A sign that a file carries hidden data
+#[derive(Debug, Clone, PartialEq)]
+pub enum Finding {
+    /// A chunk of a type the PNG specification doesn't define
+    UnknownChunk {
+        chunk_type: String,
+        offset: usize,
+        length: usize,
+    },
+    TextChunks(usize),
+    /// An ancillary chunk whose data looks random
+    HighEntropy {
+        chunk_type: String,
+        offset: usize,
+        entropy: f64,
+    },
+    Trailer(Trailer),
+    /// Pairs of sample values differing in the low bit are equally common
+    /// over this fraction of the samples, counted from the start
+    ChiSquare(f64),
+    /// RS analysis estimates this fraction of the samples' low bits carry
+    /// a message
+    Rs(f64),
+}
+
+impl Display for Finding {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Finding::UnknownChunk {
+                chunk_type,
+                offset,
+                length,
+            } => write!(
+                f,
+                "non-standard chunk {} ({} bytes @{})",
+                chunk_type, length, offset
+            ),
+            Finding::TextChunks(count) => write!(f, "{} text chunks", count),
+            Finding::HighEntropy {
+                chunk_type,
+                offset,
+                entropy,
+            } => write!(
+                f,
+                "{} chunk @{} looks random ({:.2} bits per byte)",
+                chunk_type, offset, entropy
+            ),
+            Finding::Trailer(trailer) => write!(
+                f,
+                "{} bytes after IEND @{}: {}",
+                trailer.length, trailer.offset, trailer.preview
+            ),
+            Finding::ChiSquare(prefix) => write!(
+                f,
+                "low bits of the first {:.0}% of samples look embedded (chi-square)",
+                prefix * 100.0
+            ),
+            Finding::Rs(rate) => write!(
+                f,
+                "low bits of about {:.0}% of samples look embedded (RS analysis)",
+                rate * 100.0
+            ),
+        }
+    }
+}
+
+/// Statistics of the pixel samples' low bits
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LsbStats {
+    /// Chi-square p-value over all samples, near 1 when embedding has
+    /// equalized pairs of values
+    pub chi_square: f64,
+    /// Longest prefix of the samples, as a fraction, over which the p-value
+    /// stays above [`CHI_SQUARE_THRESHOLD`]; embedding from the start keeps
+    /// it there until the message ends
+    pub embedded_prefix: f64,
+    /// RS analysis estimate of the embedded fraction
+    pub rs_rate: f64,
+}
+
+/// What [`analyze`] found
+#[derive(Debug)]
+pub struct Analysis {
+    pub findings: Vec<Finding>,
+    /// Why the pixels couldn't be analyzed, if they couldn't
+    pub lsb: Result<LsbStats, StegoError>,
+}
+
+/// Look for signs of hidden data in the chunk layout, chunk contents, data
+/// after IEND and pixel low bits
+pub fn analyze(png: &Png) -> Analysis {
+    let mut findings = Vec::new();
+    let mut text_chunks = 0;
+    for (chunk, offset) in png.chunks().iter().zip(png.chunk_offsets()) {
+        let chunk_type = chunk.chunk_type().to_string();
+        if !order::is_standard(&chunk_type) {
+            findings.push(Finding::UnknownChunk {
+                chunk_type: chunk_type.clone(),
+                offset,
+                length: chunk.data().len(),
+            });
+        }
+        if order::rank(&chunk_type) == order::rank("tEXt") {
+            text_chunks += 1;
+        }
+        let data = chunk.data();
+        if chunk.chunk_type().is_critical()
+            || COMPRESSED_TYPES.contains(&chunk_type.as_str())
+            || data.len() < MIN_ENTROPY_LEN
+        {
+            continue;
+        }
+        let entropy = stego::entropy(data);
+        let highest = (data.len() as f64).log2().min(8.0);
+        if entropy > ENTROPY_THRESHOLD * highest {
+            findings.push(Finding::HighEntropy {
+                chunk_type,
+                offset,
+                entropy,
+            });
+        }
+    }
+    if text_chunks > TEXT_CHUNK_LIMIT {
+        findings.push(Finding::TextChunks(text_chunks));
+    }
+    if let Some(trailer) = stego::scan(png) {
+        findings.push(Finding::Trailer(trailer));
+    }
+
+    let lsb = lsb_stats(png);
+    if let Ok(stats) = &lsb {
+        if stats.embedded_prefix > 0.0 {
+            findings.push(Finding::ChiSquare(stats.embedded_prefix));
+        }
+        if stats.rs_rate > RS_THRESHOLD {
+            findings.push(Finding::Rs(stats.rs_rate));
+        }
+    }
+    Analysis { findings, lsb }
+}
+
+fn lsb_stats(png: &Png) -> Result<LsbStats, StegoError> {
+    let pixels = Pixels::read(png)?;
+    let samples: Vec<u8> = stego::carriers(&pixels)?
+        .map(|index| pixels.data[index])
+        .collect();
+
+    let mut histogram = [0usize; 256];
+    let mut embedded_steps = 0;
+    let mut counted = 0;
+    for step in 1..=CHI_SQUARE_STEPS {
+        let end = samples.len() * step / CHI_SQUARE_STEPS;
+        for sample in &samples[counted..end] {
+            histogram[*sample as usize] += 1;
+        }
+        counted = end;
+        if embedded_steps == step - 1
+            && chi_square(&histogram).is_some_and(|p| p > CHI_SQUARE_THRESHOLD)
+        {
+            embedded_steps = step;
+        }
+    }
+
+    let channels = pixels.ihdr.channels();
+    let by_channel: Vec<Vec<i16>> = (0..channels)
+        .map(|channel| {
+            samples
+                .iter()
+                .skip(channel)
+                .step_by(channels)
+                .map(|s| *s as i16)
+                .collect()
+        })
+        .collect();
+    Ok(LsbStats {
+        chi_square: chi_square(&histogram).unwrap_or(0.0),
+        embedded_prefix: embedded_steps as f64 / CHI_SQUARE_STEPS as f64,
+        rs_rate: rs_rate(&by_channel),
+    })
+}
+
+/// Westfeld and Pfitzmann's test: the probability that each pair of values
+/// differing only in the low bit is as evenly split as a random low bit
+/// would make it, or `None` with too few samples to tell
+fn chi_square(histogram: &[usize; 256]) -> Option<f64> {
+    let mut statistic = 0.0;
+    let mut pairs = 0;
+    for pair in histogram.chunks_exact(2) {
+        let expected = (pair[0] + pair[1]) as f64 / 2.0;
+        if expected < MIN_EXPECTED {
+            continue;
+        }
+        statistic += (pair[0] as f64 - expected).powi(2) / expected;
+        pairs += 1;
+    }
+    if pairs < 2 {
+        return None;
+    }
+    Some(gamma_q((pairs - 1) as f64 / 2.0, statistic / 2.0))
+}
+
+/// Fridrich, Goljan and Du's RS analysis over groups of four neighbouring
+/// samples of one channel, estimating the fraction of samples embedded
+/// from how flipping low bits changes the groups' smoothness
+fn rs_rate(channels: &[Vec<i16>]) -> f64 {
+    let flipped: Vec<Vec<i16>> = channels
+        .iter()
+        .map(|samples| samples.iter().map(|s| s ^ 1).collect())
+        .collect();
+    let (d0, dn0) = regular_minus_singular(channels);
+    let (d1, dn1) = regular_minus_singular(&flipped);
+
+    let a = 2.0 * (d1 + d0);
+    let b = dn0 - dn1 - d1 - 3.0 * d0;
+    let c = d0 - dn0;
+    let x = if a.abs() < f64::EPSILON {
+        if b.abs() < f64::EPSILON {
+            return 0.0;
+        }
+        -c / b
+    } else {
+        let discriminant = b * b - 4.0 * a * c;
+        if discriminant < 0.0 {
+            // the curves have met: regular and singular groups are as
+            // common as when every low bit is replaced
+            return 1.0;
+        }
+        let roots = [
+            (-b + discriminant.sqrt()) / (2.0 * a),
+            (-b - discriminant.sqrt()) / (2.0 * a),
+        ];
+        if roots[0].abs() < roots[1].abs() {
+            roots[0]
+        } else {
+            roots[1]
+        }
+    };
+    let rate = x / (x - 0.5);
+    if rate > 0.0 {
+        rate.min(1.0)
+    } else {
+        0.0
+    }
+}
+
+/// Fraction of regular minus fraction of singular groups, under the mask
+/// flipping the middle two samples' low bits and under its negative
+fn regular_minus_singular(channels: &[Vec<i16>]) -> (f64, f64) {
+    let smoothness = |g: &[i16]| -> i16 { g.windows(2).map(|w| (w[1] - w[0]).abs()).sum() };
+    let flip = |s: i16| s ^ 1;
+    let flip_negative = |s: i16| ((s + 1) ^ 1) - 1;
+
+    let (mut groups, mut positive, mut negative) = (0, 0i64, 0i64);
+    for group in channels.iter().flat_map(|samples| samples.chunks_exact(4)) {
+        let before = smoothness(group);
+        let masked = [group[0], flip(group[1]), flip(group[2]), group[3]];
+        positive += (smoothness(&masked) - before).signum() as i64;
+        let masked = [
+            group[0],
+            flip_negative(group[1]),
+            flip_negative(group[2]),
+            group[3],
+        ];
+        negative += (smoothness(&masked) - before).signum() as i64;
+        groups += 1;
+    }
+    if groups == 0 {
+        return (0.0, 0.0);
+    }
+    (
+        positive as f64 / groups as f64,
+        negative as f64 / groups as f64,
+    )
+}
+
+/// Natural log of the gamma function, by Lanczos' approximation
+fn ln_gamma(x: f64) -> f64 {
+    const COEFFICIENTS: [f64; 9] = [
+        0.999_999_999_999_809_9,
+        676.520_368_121_885_1,
+        -1_259.139_216_722_402_8,
+        771.323_428_777_653_1,
+        -176.615_029_162_140_6,
+        12.507_343_278_686_905,
+        -0.138_571_095_265_720_12,
+        9.984_369_578_019_572e-6,
+        1.505_632_735_149_311_6e-7,
+    ];
+    let x = x - 1.0;
+    let sum = COEFFICIENTS[1..]
+        .iter()
+        .enumerate()
+        .fold(COEFFICIENTS[0], |sum, (i, c)| {
+            sum + c / (x + i as f64 + 1.0)
+        });
+    let t = x + 7.5;
+    0.5 * (2.0 * std::f64::consts::PI).ln() + (x + 0.5) * t.ln() - t + sum.ln()
+}
+
+/// Regularized upper incomplete gamma function Q(a, x), which for `a` half
+/// the degrees of freedom and `x` half the statistic is the chi-square
+/// test's p-value
+fn gamma_q(a: f64, x: f64) -> f64 {
+    const ITERATIONS: usize = 500;
+    const TINY: f64 = 1e-300;
+    if x <= 0.0 {
+        return 1.0;
+    }
+    let scale = (-x + a * x.ln() - ln_gamma(a)).exp();
+    if x < a + 1.0 {
+        // series for the lower function P(a, x)
+        let (mut term, mut sum) = (1.0 / a, 1.0 / a);
+        for n in 1..ITERATIONS {
+            term *= x / (a + n as f64);
+            sum += term;
+            if term.abs() < sum.abs() * f64::EPSILON {
+                break;
+            }
+        }
+        return (1.0 - sum * scale).clamp(0.0, 1.0);
+    }
+    // Lentz's method for the continued fraction
+    let mut b = x + 1.0 - a;
+    let mut c = 1.0 / TINY;
+    let mut d = 1.0 / b;
+    let mut h = d;
+    for n in 1..ITERATIONS {
+        let an = -(n as f64) * (n as f64 - a);
+        b += 2.0;
+        d = an * d + b;
+        if d.abs() < TINY {
+            d = TINY;
+        }
+        c = b + an / c;
+        if c.abs() < TINY {
+            c = TINY;
+        }
+        d = 1.0 / d;
+        let delta = d * c;
+        h *= delta;
+        if (delta - 1.0).abs() < f64::EPSILON {
+            break;
+        }
+    }
+    (scale * h).clamp(0.0, 1.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::Chunk;
+    use crate::chunk_type::ChunkType;
+    use crate::pixels::tests::testing_png;
+    use std::str::FromStr;
+
+    /// A smooth gradient with some noise and every low bit cleared
+    fn even_png() -> Png {
+        let mut png = testing_png(128, 128);
+        let mut pixels = Pixels::read(&png).unwrap();
+        let stride = pixels.ihdr.stride();
+        for (i, sample) in pixels.data.iter_mut().enumerate() {
+            let (x, y) = (i % stride / 3, i / stride);
+            *sample = (x + y + i * 7 % 5).min(255) as u8 & !1;
+        }
+        pixels.write(&mut png);
+        png
+    }
+
+    #[test]
+    fn test_gamma_q() {
+        // chi-square with 2 degrees of freedom has p-value e^(-x/2)
+        assert!((gamma_q(1.0, 1.5) - (-1.5f64).exp()).abs() < 1e-12);
+        assert!((gamma_q(1.0, 0.25) - (-0.25f64).exp()).abs() < 1e-12);
+        assert!((ln_gamma(5.0) - 24f64.ln()).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_clean_image() {
+        let analysis = analyze(&even_png());
+        assert_eq!(analysis.findings, []);
+        let stats = analysis.lsb.unwrap();
+        assert!(stats.chi_square < 0.01);
+        assert_eq!(stats.embedded_prefix, 0.0);
+    }
+
+    #[test]
+    fn test_lsb_embedding_detected() {
+        let mut png = even_png();
+        let mut payload = vec![0; stego::capacity(&png).unwrap()];
+        blake3::Hasher::new().finalize_xof().fill(&mut payload);
+        stego::embed(&mut png, &payload).unwrap();
+
+        let stats = analyze(&png).lsb.unwrap();
+        assert!(stats.chi_square > CHI_SQUARE_THRESHOLD);
+        assert!(stats.embedded_prefix > 0.9);
+        assert!(stats.rs_rate > 0.5);
+    }
+
+    #[test]
+    fn test_chunk_findings() {
+        let mut png = even_png();
+        let mut data = vec![0; 200];
+        blake3::Hasher::new().finalize_xof().fill(&mut data);
+        png.append_chunk(Chunk::new(ChunkType::from_str("ruSt").unwrap(), data));
+        for _ in 0..=TEXT_CHUNK_LIMIT {
+            png.append_chunk(Chunk::new(
+                ChunkType::from_str("tEXt").unwrap(),
+                b"Comment\0hi".to_vec(),
+            ));
+        }
+        stego::embed_trailer(&mut png, b"after the end").unwrap();
+
+        let findings = analyze(&png).findings;
+        assert!(
+            matches!(&findings[0], Finding::UnknownChunk { chunk_type, length: 200, .. } if chunk_type == "ruSt")
+        );
+        assert!(
+            matches!(&findings[1], Finding::HighEntropy { chunk_type, .. } if chunk_type == "ruSt")
+        );
+        assert_eq!(findings[2], Finding::TextChunks(TEXT_CHUNK_LIMIT + 1));
+        assert!(matches!(&findings[3], Finding::Trailer(t) if t.length == 13));
+        assert_eq!(findings.len(), 4);
+    }
+}
@@ -0,0 +1,192 @@
+//! Parsing for short-lived inspection: every chunk's data is copied into
+//! one buffer the chunks point into, so a parse costs a couple of
+//! allocations rather than one per chunk, and none at all once an
+//! [`Arena`] reused across files has grown to fit them. Everything is
+//! freed together when the arena is dropped or parses the next file
+
+#![deny(clippy::unwrap_used, clippy::expect_used)]
+
+use alloc::vec::Vec;
+use core::ops::Range;
+
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::png::{Frames, Png, PngError};
+
+#[derive(Debug, Clone)]
+struct Slot {
+    chunk_type: ChunkType,
+    data: Range<usize>,
+    crc: u32,
+}
+
+/// Memory one parsed file at a time lives in
+#[derive(Debug, Default)]
+pub struct Arena {
+    data: Vec<u8>,
+    slots: Vec<Slot>,
+}
+
+impl Arena {
+    pub fn new() -> Arena {
+        Arena::default()
+    }
+
+    /// Parse `bytes` as [`Png::try_from`] does, checking every CRC, into
+    /// the arena in place of the file it held before
+    pub fn parse(&mut self, bytes: &[u8]) -> Result<ArenaPng<'_>, PngError> {
+        self.data.clear();
+        self.slots.clear();
+        self.data.reserve(bytes.len());
+        let mut frames = Frames::new(bytes)?;
+        for frame in &mut frames {
+            let frame = frame?;
+            let start = self.data.len();
+            self.data.extend_from_slice(frame.data);
+            self.slots.push(Slot {
+                chunk_type: frame.chunk_type,
+                data: start..self.data.len(),
+                crc: frame.crc,
+            });
+        }
+        let start = self.data.len();
+        self.data.extend_from_slice(&bytes[frames.end()..]);
+        Ok(ArenaPng {
+            arena: self,
+            trailing: start..self.data.len(),
+        })
+    }
+
+    /// Bytes the arena can hold without growing
+    pub fn capacity(&self) -> usize {
+        self.data.capacity()
+    }
+}
+
+/// A chunk whose data is in an [`Arena`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ArenaChunk<'a> {
+    chunk_type: ChunkType,
+    data: &'a [u8],
+    crc: u32,
+}
+
+impl<'a> ArenaChunk<'a> {
+    pub fn chunk_type(&self) -> &ChunkType {
+        &self.chunk_type
+    }
+    pub fn data(&self) -> &'a [u8] {
+        self.data
+    }
+    pub fn length(&self) -> u32 {
+        self.data.len() as u32
+    }
+    pub fn crc(&self) -> u32 {
+        self.crc
+    }
+    /// A [`Chunk`] with its own copy of the data
+    pub fn to_chunk(&self) -> Chunk {
+        Chunk::new(self.chunk_type, self.data.to_vec())
+    }
+}
+
+/// A file parsed into an [`Arena`], which it borrows
+#[derive(Debug, Clone)]
+pub struct ArenaPng<'a> {
+    arena: &'a Arena,
+    trailing: Range<usize>,
+}
+
+impl<'a> ArenaPng<'a> {
+    pub fn chunks(&self) -> impl ExactSizeIterator<Item = ArenaChunk<'a>> + 'a {
+        let arena = self.arena;
+        arena.slots.iter().map(move |slot| ArenaChunk {
+            chunk_type: slot.chunk_type,
+            data: &arena.data[slot.data.clone()],
+            crc: slot.crc,
+        })
+    }
+    pub fn chunk_by_type(&self, chunk_type: &str) -> Option<ArenaChunk<'a>> {
+        self.chunks()
+            .find(|c| c.chunk_type.bytes()[..] == *chunk_type.as_bytes())
+    }
+    /// Data after IEND, which decoders ignore
+    pub fn trailing(&self) -> &'a [u8] {
+        &self.arena.data[self.trailing.clone()]
+    }
+    /// A [`Png`] owning a copy of every chunk, to keep or edit
+    pub fn to_png(&self) -> Png {
+        let mut png = Png::from_chunks(self.chunks().map(|c| c.to_chunk()).collect());
+        png.set_trailing(self.trailing().to_vec());
+        png
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::png::CrcMismatch;
+    use core::str::FromStr;
+
+    fn testing_bytes() -> Vec<u8> {
+        let mut png = Png::from_chunks(
+            [
+                ("IHDR", &[0; 13][..]),
+                ("tEXt", b"Author\0me"),
+                ("IDAT", &[9; 4096]),
+                ("IEND", b""),
+            ]
+            .into_iter()
+            .map(|(t, d)| Chunk::new(ChunkType::from_str(t).unwrap(), d.to_vec()))
+            .collect(),
+        );
+        png.set_trailing(b"after".to_vec());
+        png.as_bytes()
+    }
+
+    #[test]
+    fn test_parse() {
+        let bytes = testing_bytes();
+        let mut arena = Arena::new();
+        let png = arena.parse(&bytes).unwrap();
+        assert_eq!(png.chunks().len(), 4);
+        assert_eq!(png.chunk_by_type("tEXt").unwrap().data(), b"Author\0me");
+        assert_eq!(png.trailing(), b"after");
+        assert_eq!(png.to_png().as_bytes(), bytes);
+        let chunks: Vec<ArenaChunk> = png.chunks().collect();
+        let idat = chunks[2].data().as_ptr_range();
+        assert_eq!(chunks[1].data().as_ptr_range().end, idat.start);
+
+        // A second file reuses the memory the first grew
+        let capacity = arena.capacity();
+        let png = arena.parse(&bytes).unwrap();
+        assert_eq!(png.chunks().len(), 4);
+        assert_eq!(arena.capacity(), capacity);
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let mut bytes = testing_bytes();
+        let mut arena = Arena::new();
+        assert!(matches!(
+            arena.parse(&bytes[..40]),
+            Err(PngError::Truncated(_))
+        ));
+        bytes[40] ^= 1;
+        assert!(matches!(
+            arena.parse(&bytes),
+            Err(PngError::Crc(CrcMismatch {
+                index: 1,
+                offset: 33,
+                ..
+            }))
+        ));
+        assert_eq!(
+            arena.parse(b"not a png").unwrap_err(),
+            PngError::Signature {
+                offset: 0,
+                found: b'n'
+            }
+        );
+    }
+}
@@ -0,0 +1,512 @@
+use std::path::PathBuf;
+
+use clap::{Args, Parser, Subcommand, ValueEnum};
+
+use ping::cache::Identity;
+use ping::checksum::Algorithm;
+use ping::compress::Codec;
+use ping::crypto::{Cipher, Kdf, KdfParams};
+use ping::payload;
+use ping::shred::Fill;
+use ping::stego::Method;
+use ping::template::Template;
+
+#[derive(Debug, Parser)]
+#[command(name = "ping", version, about = "Hide messages in PNG chunks")]
+#[cfg_attr(
+    feature = "net",
+    command(after_help = "Input files may be http:// or https:// URLs.")
+)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Command,
+    /// Print bytes parsed, chunks seen and where the time went to stderr
+    #[arg(long, global = true)]
+    pub timings: bool,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Embed a message in a new chunk. Builds with the jpeg, gif or webp
+    /// features also embed in those files, where the chunk type only
+    /// names what a signature covers
+    Encode(EncodeArgs),
+    /// Print the message stored in a chunk
+    Decode(DecodeArgs),
+    /// Remove the first chunk of a type
+    Remove(RemoveArgs),
+    /// List every chunk in a file
+    Print(PrintArgs),
+    /// Search text chunks (and optionally raw payloads) across files
+    Grep(GrepArgs),
+    /// Sign the critical chunks with an Ed25519 key. Ancillary chunks
+    /// aren't signed, including tRNS, gAMA, cHRM, sRGB and iCCP, which
+    /// change how the image renders
+    Sign(SignArgs),
+    /// Check a signature added by `sign`
+    Verify(VerifyArgs),
+    /// Print digests of the file, its image data and each chunk
+    Checksum(ChecksumArgs),
+    /// Report everything wrong with a file, reading past damage to find
+    /// all of it, and what's in it besides
+    Check(CheckArgs),
+    /// Change the type code of a chunk
+    RenameChunk(RenameChunkArgs),
+    /// Rewrite the file with chunks in canonical order
+    Reorder(ReorderArgs),
+    /// Write every chunk to its own file plus a manifest
+    Split(SplitArgs),
+    /// Rebuild a file from a directory written by `split`
+    Join(JoinArgs),
+    /// Print how many bytes the lsb method can hide in a file, or with
+    /// --size compare every method for a payload of that size
+    Capacity(CapacityArgs),
+    /// Generate an X25519 key pair for --recipient and --identity
+    Keygen(KeygenArgs),
+    /// Report files with data hidden after IEND
+    Scan(ScanArgs),
+    /// Look for signs of hidden data: unusual chunks, random-looking data,
+    /// data after IEND and tampered pixel low bits
+    Analyze(AnalyzeArgs),
+    /// Print each chunk's entropy and compressibility, and with several
+    /// files how common each chunk type is across them
+    Stats(StatsArgs),
+    /// Validate, strip or encode many files at once in parallel
+    Batch(BatchArgs),
+    /// Export text metadata as exiftool does, or import exiftool's JSON
+    #[cfg(feature = "exiftool")]
+    Metadata(MetadataArgs),
+    /// Apply the edits in a JSON script to each file, in one pass per file
+    #[cfg(feature = "script")]
+    Script(ScriptArgs),
+    /// Answer HTTP requests to list, scrub or decode uploaded files
+    #[cfg(feature = "serve")]
+    Serve(ServeArgs),
+}
+
+#[derive(Debug, Args)]
+pub struct EncodeArgs {
+    pub file: PathBuf,
+    pub chunk_type: String,
+    pub message: String,
+    /// Write to this file instead of overwriting the input
+    pub output: Option<PathBuf>,
+    /// Treat MESSAGE as the path of a file to embed, along with its name,
+    /// MIME type and modification time
+    #[arg(long)]
+    pub from_file: bool,
+    /// Stream the file's contents into the image rather than reading it
+    /// whole, for files too big for memory. Only the contents are kept, not
+    /// the name or times; compression and password or keyfile encryption
+    /// still apply
+    #[arg(long, requires = "from_file",
+          conflicts_with_all = ["deniable", "recipient", "age_recipient", "sign", "integrity", "ecc"])]
+    pub stream: bool,
+    /// Compress the message before embedding it; decode detects the codec
+    #[arg(long, value_enum, default_value_t = Codec::None)]
+    pub compress: Codec,
+    /// Encrypt the message under a password, read from PING_PASSWORD or
+    /// prompted for
+    #[arg(long)]
+    pub encrypt: bool,
+    /// Accept a typed-in password that is easy to guess
+    #[arg(long)]
+    pub allow_weak_password: bool,
+    /// Derive the encryption key from this file, alone or together with
+    /// --encrypt's password for two-factor protection
+    #[arg(long, conflicts_with = "recipient")]
+    pub keyfile: Option<PathBuf>,
+    /// Encrypt to this X25519 public key instead of a password; repeat for
+    /// several recipients, any of whom can decode
+    #[arg(short, long, conflicts_with = "encrypt")]
+    pub recipient: Vec<PathBuf>,
+    /// Encrypt with age to this age1... public key, so existing age keys
+    /// can decode; repeatable. Needs a build with the age feature
+    #[arg(long, conflicts_with_all = ["encrypt", "keyfile", "recipient"])]
+    pub age_recipient: Vec<String>,
+    /// Use two equal-sized encrypted slots, so a second message may or may
+    /// not be present; the unused slot is random filler
+    #[arg(long, requires = "encrypt")]
+    pub deniable: bool,
+    /// Put this decoy message in the second slot, under a different password
+    /// read from PING_DECOY_PASSWORD or prompted for
+    #[arg(long, requires = "deniable")]
+    pub decoy: Option<String>,
+    /// Append a keyed BLAKE3 tag so decode can detect tampering and truncation
+    #[arg(long)]
+    pub integrity: bool,
+    /// File with the integrity tag key; without one the tag only detects corruption
+    #[arg(long, requires = "integrity")]
+    pub integrity_key: Option<PathBuf>,
+    /// Record the payload's key and nonce here, refusing any already used
+    /// for a different payload
+    #[arg(long)]
+    pub nonce_log: Option<PathBuf>,
+    /// Payload format version to write; 1 is readable by older releases
+    #[arg(long, default_value_t = payload::VERSION,
+          value_parser = clap::value_parser!(u8).range(1..=payload::VERSION as i64))]
+    pub payload_version: u8,
+    /// Where to store the payload: its own chunk, the pixel low bits, or a
+    /// chunk disguised as ordinary metadata
+    #[arg(long, value_enum, default_value_t = Method::Chunk)]
+    pub method: Method,
+    /// Split payloads larger than this many bytes across several chunks
+    #[arg(long, default_value_t = 1 << 20)]
+    pub max_chunk_size: usize,
+    /// Add this many Reed-Solomon parity bytes per 255-byte block, so the
+    /// payload survives damage to up to half as many bytes of each
+    #[arg(long, value_parser = clap::value_parser!(u8).range(2..=ping::ecc::MAX_PARITY as i64))]
+    pub ecc: Option<u8>,
+    /// Read the written file back and decode the payload before reporting
+    /// success
+    #[arg(long, conflicts_with = "stream")]
+    pub verify: bool,
+    /// Threads to compress the payload, and any image data rewritten, on;
+    /// defaults to one per core
+    #[arg(long)]
+    pub threads: Option<usize>,
+    /// Write the same bytes whenever the inputs are the same: chunks in
+    /// canonical order, tIME and an embedded file's time set from
+    /// SOURCE_DATE_EPOCH or else dropped, and compression on one thread.
+    /// Encryption needs fresh randomness, so it's refused
+    #[arg(long, conflicts_with_all = ["encrypt", "keyfile", "recipient", "age_recipient", "stream", "threads"])]
+    pub deterministic: bool,
+    /// Sign the payload and chunk type with this Ed25519 private key
+    #[arg(long)]
+    pub sign: Option<PathBuf>,
+    /// Key derivation function for --encrypt and --keyfile; decode reads it
+    /// from the payload
+    #[arg(long, value_enum, default_value_t = Kdf::Argon2id, conflicts_with_all = ["deniable", "stream"])]
+    pub kdf: Kdf,
+    /// Cipher for --encrypt and --keyfile; decode reads it from the payload
+    #[arg(long, value_enum, default_value_t = Cipher::Aes256Gcm, conflicts_with_all = ["deniable", "stream"])]
+    pub cipher: Cipher,
+    /// Memory cost in KiB; scrypt rounds it down to a power of two
+    #[arg(long, default_value_t = KdfParams::default().memory_kib, requires = "encrypt")]
+    pub kdf_memory: u32,
+    /// Iteration count, used by scrypt as its parallelization parameter p
+    #[arg(long, default_value_t = KdfParams::default().time_cost, requires = "encrypt")]
+    pub kdf_time: u32,
+}
+
+#[derive(Debug, Args)]
+pub struct DecodeArgs {
+    pub file: PathBuf,
+    pub chunk_type: String,
+    /// Where encode stored the payload
+    #[arg(long, value_enum, default_value_t = Method::Chunk)]
+    pub method: Method,
+    /// Write the message to this file; `auto` restores an embedded file
+    /// under its recorded name
+    #[arg(short, long)]
+    pub output: Option<PathBuf>,
+    /// File with the integrity tag key used at encode time
+    #[arg(long)]
+    pub integrity_key: Option<PathBuf>,
+    /// Require a payload signature from this Ed25519 public key
+    #[arg(long)]
+    pub verify: Option<PathBuf>,
+    /// Record decoded payloads here, failing on one decoded before
+    #[arg(long)]
+    pub nonce_log: Option<PathBuf>,
+    /// Keyfile used at encode time
+    #[arg(long)]
+    pub keyfile: Option<PathBuf>,
+    /// X25519 secret key for payloads encrypted to recipients
+    #[arg(short, long)]
+    pub identity: Option<PathBuf>,
+    /// age identity file, as written by age-keygen
+    #[arg(long)]
+    pub age_identity: Option<PathBuf>,
+    /// Derive the key however costly the payload asks, short of the hard
+    /// caps, rather than refuse more than 1 GiB, 16 passes or 16 lanes
+    #[arg(long)]
+    pub no_kdf_limit: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct RemoveArgs {
+    pub file: PathBuf,
+    pub chunk_type: String,
+    /// Overwrite the removed chunks on disk before rewriting the file
+    #[arg(long, value_enum, num_args = 0..=1, default_missing_value = "random")]
+    pub shred: Option<Fill>,
+}
+
+#[derive(Debug, Args)]
+pub struct PrintArgs {
+    pub file: PathBuf,
+    /// Per-chunk template, e.g. "{type}\t{length}\t{crc:x}"; fields are
+    /// index, type, length, crc, offset, critical and keyword
+    #[arg(long)]
+    pub format: Option<Template>,
+    /// Read only the chunk headers, seeking past the data, so huge files
+    /// list quickly; CRCs are shown as stored, unchecked
+    #[arg(long)]
+    pub lazy: bool,
+    /// List what can be read of a damaged file, warning of each way it
+    /// breaks the spec instead of failing
+    #[arg(long, conflicts_with = "lazy")]
+    pub lenient: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct GrepArgs {
+    pub pattern: String,
+    #[arg(required = true)]
+    pub files: Vec<PathBuf>,
+    /// Only search chunks of this type
+    #[arg(long)]
+    pub chunk_type: Option<String>,
+    /// Treat the pattern as a regular expression
+    #[arg(long)]
+    pub regex: bool,
+    /// Also search the raw payload bytes of non-text chunks
+    #[arg(long)]
+    pub raw: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct SignArgs {
+    pub file: PathBuf,
+    /// Ed25519 private key (PKCS#8 PEM, raw or hex)
+    #[arg(long)]
+    pub key: PathBuf,
+    /// Sign a digest of the image under this hash instead of the image
+    /// itself, quicker for large files; verify reads which from the file
+    #[arg(long, value_enum)]
+    pub digest: Option<Algorithm>,
+    /// Write to this file instead of overwriting the input
+    #[arg(short, long)]
+    pub output: Option<PathBuf>,
+}
+
+#[derive(Debug, Args)]
+pub struct VerifyArgs {
+    pub file: PathBuf,
+    /// Ed25519 public key (PEM, raw or hex)
+    #[arg(long)]
+    pub pubkey: PathBuf,
+}
+
+#[derive(Debug, Args)]
+pub struct CheckArgs {
+    pub file: PathBuf,
+    /// Print the report as JSON: arrays of errors, warnings and info, each
+    /// finding with a code, severity, message, chunk index and offset
+    #[arg(long)]
+    pub json: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct ChecksumArgs {
+    pub file: PathBuf,
+    #[arg(long, value_enum, default_value = "sha256")]
+    pub algo: Algorithm,
+}
+
+#[derive(Debug, Args)]
+pub struct RenameChunkArgs {
+    pub file: PathBuf,
+    pub old_type: String,
+    pub new_type: String,
+    /// Allow renaming to or from critical and public chunk types
+    #[arg(long)]
+    pub force: bool,
+    /// Write to this file instead of overwriting the input
+    #[arg(short, long)]
+    pub output: Option<PathBuf>,
+}
+
+#[derive(Debug, Args)]
+pub struct ReorderArgs {
+    pub file: PathBuf,
+    /// Write to this file instead of overwriting the input
+    #[arg(short, long)]
+    pub output: Option<PathBuf>,
+}
+
+#[derive(Debug, Args)]
+pub struct SplitArgs {
+    pub file: PathBuf,
+    #[arg(short, long)]
+    pub output: PathBuf,
+}
+
+#[derive(Debug, Args)]
+pub struct JoinArgs {
+    pub dir: PathBuf,
+    #[arg(short, long)]
+    pub output: PathBuf,
+}
+
+#[derive(Debug, Args)]
+pub struct CapacityArgs {
+    pub file: PathBuf,
+    /// Estimate growth and detectability of a payload this many bytes long
+    #[arg(long)]
+    pub size: Option<usize>,
+    #[arg(long, default_value = "ruSt")]
+    pub chunk_type: String,
+    #[arg(long, default_value_t = 1 << 20)]
+    pub max_chunk_size: usize,
+}
+
+#[derive(Debug, Args)]
+pub struct KeygenArgs {
+    /// Secret key file; the public key goes next to it with a .pub suffix
+    #[arg(short, long)]
+    pub output: PathBuf,
+}
+
+#[derive(Debug, Args)]
+pub struct ScanArgs {
+    #[arg(required = true)]
+    pub files: Vec<PathBuf>,
+    /// Fail if any file has data after IEND
+    #[arg(long)]
+    pub strict: bool,
+    /// Rewrite files with data after IEND to end at IEND
+    #[arg(long)]
+    pub strip: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct AnalyzeArgs {
+    pub file: PathBuf,
+}
+
+#[derive(Debug, Args)]
+pub struct StatsArgs {
+    #[arg(required = true)]
+    pub files: Vec<PathBuf>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum BatchOperation {
+    /// Check each file parses and follows the spec's chunk rules: a valid
+    /// IHDR first, IEND last, everything between where it belongs, one of
+    /// each once-only type, PLTE and tRNS as the color type needs, palette
+    /// indices within PLTE and valid text keywords
+    Validate,
+    /// Drop ancillary chunks that don't affect rendering, and trailing data
+    Strip,
+    /// Embed --message in a new --chunk-type chunk
+    Encode,
+}
+
+#[derive(Debug, Args)]
+pub struct BatchArgs {
+    #[arg(value_enum)]
+    pub operation: BatchOperation,
+    #[arg(required = true)]
+    pub files: Vec<PathBuf>,
+    /// Files to work on at once; defaults to one per core
+    #[arg(short, long)]
+    pub jobs: Option<usize>,
+    /// Files read ahead, and files waiting to be written, at most;
+    /// defaults to twice the jobs
+    #[arg(long)]
+    pub queue_depth: Option<usize>,
+    /// Threads reading files, and as many writing them; defaults to two
+    #[arg(long)]
+    pub io_threads: Option<usize>,
+    /// Write changed files into this directory instead of over the inputs
+    #[arg(short, long)]
+    pub output_dir: Option<PathBuf>,
+    #[arg(long, required_if_eq("operation", "encode"))]
+    pub chunk_type: Option<String>,
+    #[arg(long, required_if_eq("operation", "encode"))]
+    pub message: Option<String>,
+    /// Encrypt the message under a password, read from PING_PASSWORD or
+    /// prompted for once for the whole batch
+    #[arg(long)]
+    pub encrypt: bool,
+    /// Accept a typed-in password that is easy to guess
+    #[arg(long)]
+    pub allow_weak_password: bool,
+    /// Stream each file through in at most this many MiB instead of
+    /// reading it whole, for files too big for memory. Encoding then
+    /// stores the message as a streamed payload
+    #[arg(long)]
+    pub max_memory: Option<usize>,
+    /// With validate, keep results in this file, by default in
+    /// ping/validate under the user cache directory, and validate only the
+    /// files changed since
+    #[arg(long, num_args = 0..=1, value_name = "FILE")]
+    pub cache: Option<Option<PathBuf>>,
+    /// What tells that a file changed since it was cached
+    #[arg(long, value_enum, default_value_t = Identity::Metadata)]
+    pub cache_identity: Identity,
+}
+
+#[cfg(feature = "exiftool")]
+#[derive(Debug, Args)]
+pub struct MetadataArgs {
+    #[command(subcommand)]
+    pub command: MetadataCommand,
+}
+
+#[cfg(feature = "exiftool")]
+#[derive(Debug, Subcommand)]
+pub enum MetadataCommand {
+    /// Print each file's tags
+    Export {
+        #[arg(required = true)]
+        files: Vec<PathBuf>,
+        /// Print JSON as `exiftool -j` does, which `import` and
+        /// `exiftool -json=` read
+        #[arg(long)]
+        exiftool_json: bool,
+    },
+    /// Write the tags in exiftool JSON into each file as text chunks
+    Import {
+        /// JSON from `export --exiftool-json` or `exiftool -j`, or - to
+        /// read it from stdin
+        json: PathBuf,
+        #[arg(required = true)]
+        files: Vec<PathBuf>,
+        /// Write changed files into this directory instead of over the
+        /// inputs
+        #[arg(short, long)]
+        output_dir: Option<PathBuf>,
+    },
+}
+
+#[cfg(feature = "script")]
+#[derive(Debug, Args)]
+pub struct ScriptArgs {
+    /// JSON array of operations, or - to read it from stdin
+    pub script: PathBuf,
+    #[arg(required = true)]
+    pub files: Vec<PathBuf>,
+    /// Files to work on at once; defaults to one per core
+    #[arg(short, long)]
+    pub jobs: Option<usize>,
+    /// Write changed files into this directory instead of over the inputs
+    #[arg(short, long)]
+    pub output_dir: Option<PathBuf>,
+    /// Accept a typed-in password that is easy to guess, for operations
+    /// that encrypt
+    #[arg(long)]
+    pub allow_weak_password: bool,
+}
+
+#[cfg(feature = "serve")]
+#[derive(Debug, Args)]
+pub struct ServeArgs {
+    #[arg(short, long, default_value_t = 8080)]
+    pub port: u16,
+    /// Address to listen on; 0.0.0.0 to accept connections from elsewhere
+    #[arg(long, default_value = "127.0.0.1")]
+    pub bind: String,
+    /// Largest upload accepted, in bytes
+    #[arg(long, default_value_t = 64 << 20)]
+    pub max_size: usize,
+    /// Requests handled at once; defaults to one per core
+    #[arg(short, long)]
+    pub jobs: Option<usize>,
+    /// Seconds a connection may send nothing before it's dropped
+    #[arg(long, default_value_t = 30, value_parser = clap::value_parser!(u64).range(1..))]
+    pub timeout: u64,
+}
@@ -0,0 +1,252 @@
+//! Reading and writing PNGs on tokio streams, for services that take
+//! uploads without blocking their runtime threads
+
+#![deny(clippy::unwrap_used, clippy::expect_used)]
+
+use std::fmt::Display;
+use std::io;
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::crc::CrcHasher;
+use crate::png::{CrcMismatch, Png, PngError};
+
+#[derive(Debug)]
+pub enum AsyncError {
+    Io(io::Error),
+    Png(PngError),
+}
+
+impl Display for AsyncError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AsyncError::Io(e) => write!(f, "{}", e),
+            AsyncError::Png(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for AsyncError {}
+
+impl From<io::Error> for AsyncError {
+    fn from(e: io::Error) -> Self {
+        AsyncError::Io(e)
+    }
+}
+
+impl From<PngError> for AsyncError {
+    fn from(e: PngError) -> Self {
+        AsyncError::Png(e)
+    }
+}
+
+/// Reads a file one chunk at a time, stopping after IEND
+pub struct ChunkReader<R> {
+    reader: R,
+    /// Offset in the file of the next chunk
+    offset: usize,
+    /// Position of the next chunk in the file's chunk list
+    index: usize,
+    done: bool,
+}
+
+impl<R: AsyncRead + Unpin> ChunkReader<R> {
+    /// Read and check the signature
+    pub async fn new(mut reader: R) -> Result<ChunkReader<R>, AsyncError> {
+        let mut header = Vec::with_capacity(8);
+        (&mut reader).take(8).read_to_end(&mut header).await?;
+        Png::check_signature(&header)?;
+        Ok(ChunkReader {
+            reader,
+            offset: header.len(),
+            index: 0,
+            done: false,
+        })
+    }
+
+    /// The next chunk, or `None` after IEND or at the end of a file that
+    /// lacks one
+    pub async fn next_chunk(&mut self) -> Result<Option<Chunk>, AsyncError> {
+        let mut data = Vec::new();
+        Ok(self
+            .read_chunk(Some(&mut data))
+            .await?
+            .map(|(chunk_type, crc)| Chunk::from_checked(chunk_type, data, crc)))
+    }
+
+    /// Check the next chunk's CRC without keeping its data, returning its
+    /// type and length, or `None` where [`next_chunk`](Self::next_chunk)
+    /// would
+    pub async fn skip_chunk(&mut self) -> Result<Option<(ChunkType, u32)>, AsyncError> {
+        let start = self.offset;
+        Ok(self.read_chunk(None).await?.map(|(chunk_type, _)| {
+            let length = self.offset - start - Chunk::OVERHEAD;
+            (chunk_type, length as u32)
+        }))
+    }
+
+    /// Read one chunk, hashing its data as it arrives and appending it to
+    /// `keep` if given, and return its type and checked CRC
+    async fn read_chunk(
+        &mut self,
+        mut keep: Option<&mut Vec<u8>>,
+    ) -> Result<Option<(ChunkType, u32)>, AsyncError> {
+        if self.done {
+            return Ok(None);
+        }
+        let mut head = Vec::with_capacity(8);
+        (&mut self.reader).take(8).read_to_end(&mut head).await?;
+        if head.is_empty() {
+            self.done = true;
+            return Ok(None);
+        }
+        let Some((length, chunk_type)) = Chunk::read_head(&head) else {
+            return Err(PngError::Truncated(self.offset).into());
+        };
+        let length = length as usize;
+        let chunk_type =
+            ChunkType::try_from(chunk_type).map_err(|e| PngError::Chunk(self.offset, e))?;
+        let mut hasher = CrcHasher::new();
+        hasher.update(&head[4..]);
+        // Read what's there a block at a time rather than allocate the
+        // claimed length up front
+        let mut block = Vec::new();
+        let mut left = length;
+        while left > 0 {
+            let n = left.min(Chunk::READ_BLOCK);
+            let buffer = match keep.as_deref_mut() {
+                Some(data) => data,
+                None => {
+                    block.clear();
+                    &mut block
+                }
+            };
+            let start = buffer.len();
+            buffer.resize(start + n, 0);
+            self.fill(&mut buffer[start..]).await?;
+            hasher.update(&buffer[start..]);
+            left -= n;
+        }
+        let mut crc = [0; 4];
+        self.fill(&mut crc).await?;
+        let (expected, actual) = (u32::from_be_bytes(crc), hasher.finalize());
+        if expected != actual {
+            return Err(PngError::Crc(CrcMismatch {
+                index: self.index,
+                offset: self.offset,
+                chunk_type,
+                length: length as u32,
+                stored: expected,
+                computed: actual,
+            })
+            .into());
+        }
+        self.offset += length + Chunk::OVERHEAD;
+        self.index += 1;
+        self.done = &chunk_type.bytes() == b"IEND";
+        Ok(Some((chunk_type, expected)))
+    }
+
+    /// Fill `buffer` from the reader, a short read being a truncated chunk
+    async fn fill(&mut self, buffer: &mut [u8]) -> Result<(), AsyncError> {
+        match self.reader.read_exact(buffer).await {
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                Err(PngError::Truncated(self.offset).into())
+            }
+            result => Ok(result.map(|_| ())?),
+        }
+    }
+
+    /// Offset in the file of the next chunk
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    /// The underlying reader, positioned after the last chunk read
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+}
+
+impl Png {
+    /// Parse a file from `reader`, keeping whatever follows IEND as
+    /// trailing data
+    pub async fn from_async_reader(reader: impl AsyncRead + Unpin) -> Result<Png, AsyncError> {
+        let mut chunks = ChunkReader::new(reader).await?;
+        let mut png = Png::from_chunks(Vec::new());
+        while let Some(chunk) = chunks.next_chunk().await? {
+            png.append_chunk(chunk);
+        }
+        let mut trailing = Vec::new();
+        chunks.into_inner().read_to_end(&mut trailing).await?;
+        png.set_trailing(trailing);
+        Ok(png)
+    }
+
+    /// Write the file to `writer` and flush it
+    pub async fn write_async(&self, mut writer: impl AsyncWrite + Unpin) -> io::Result<()> {
+        writer.write_all(&self.as_bytes()).await?;
+        writer.flush().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pixels::tests::testing_png;
+
+    #[tokio::test]
+    async fn test_round_trip() {
+        let mut png = testing_png(4, 4);
+        png.set_trailing(b"after the end".to_vec());
+        let mut bytes = Vec::new();
+        png.write_async(&mut bytes).await.unwrap();
+        assert_eq!(bytes, png.as_bytes());
+
+        let read = Png::from_async_reader(bytes.as_slice()).await.unwrap();
+        assert_eq!(read.as_bytes(), bytes);
+        assert_eq!(read.trailing(), b"after the end");
+    }
+
+    #[tokio::test]
+    async fn test_streamed_crc_checks() {
+        let mut png = testing_png(4, 4);
+        let big: Vec<u8> = (0..2 * Chunk::READ_BLOCK + 1).map(|i| i as u8).collect();
+        png.append_chunk(Chunk::new("raIn".parse().unwrap(), big.clone()));
+        let mut bytes = png.as_bytes();
+        let read = Png::from_async_reader(bytes.as_slice()).await.unwrap();
+        assert_eq!(read.chunk_by_type("raIn").unwrap().data(), big);
+
+        let at = png.chunk_offsets()[2];
+        bytes[at + 8 + Chunk::READ_BLOCK] ^= 1;
+        let mut chunks = ChunkReader::new(bytes.as_slice()).await.unwrap();
+        assert_eq!(chunks.skip_chunk().await.unwrap().unwrap().1, 13);
+        chunks.skip_chunk().await.unwrap();
+        let error = chunks.skip_chunk().await.unwrap_err();
+        assert!(matches!(
+            error,
+            AsyncError::Png(PngError::Crc(CrcMismatch { offset, .. })) if offset == at
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_errors() {
+        let error = Png::from_async_reader(&b"nope"[..]).await.unwrap_err();
+        assert!(matches!(
+            error,
+            AsyncError::Png(PngError::Signature {
+                offset: 0,
+                found: b'n'
+            })
+        ));
+
+        let bytes = testing_png(4, 4).as_bytes();
+        let error = Png::from_async_reader(&bytes[..bytes.len() - 3])
+            .await
+            .unwrap_err();
+        let last = testing_png(4, 4).chunk_offsets().pop().unwrap();
+        assert!(matches!(error, AsyncError::Png(PngError::Truncated(at)) if at == last));
+    }
+}
@@ -0,0 +1,194 @@
+use std::fmt::Display;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// MIME types by file extension, for the formats people most often embed
+const MIME_TYPES: &[(&str, &str)] = &[
+    ("txt", "text/plain"),
+    ("md", "text/markdown"),
+    ("html", "text/html"),
+    ("csv", "text/csv"),
+    ("json", "application/json"),
+    ("xml", "application/xml"),
+    ("pdf", "application/pdf"),
+    ("zip", "application/zip"),
+    ("gz", "application/gzip"),
+    ("tar", "application/x-tar"),
+    ("png", "image/png"),
+    ("jpg", "image/jpeg"),
+    ("gif", "image/gif"),
+    ("webp", "image/webp"),
+    ("svg", "image/svg+xml"),
+    ("mp3", "audio/mpeg"),
+    ("wav", "audio/wav"),
+    ("mp4", "video/mp4"),
+];
+
+const DEFAULT_MIME: &str = "application/octet-stream";
+
+/// A file embedded with its name, MIME type and modification time
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Attachment {
+    pub name: String,
+    pub mime: String,
+    /// Seconds since the Unix epoch
+    pub modified: u64,
+    pub content: Vec<u8>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum AttachmentError {
+    Truncated,
+    InvalidName,
+}
+
+impl Display for AttachmentError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AttachmentError::Truncated => write!(f, "embedded file record is truncated"),
+            AttachmentError::InvalidName => write!(f, "embedded file has an invalid name"),
+        }
+    }
+}
+
+impl std::error::Error for AttachmentError {}
+
+/// MIME type for a file name's extension
+pub fn mime_for(name: &str) -> &'static str {
+    let extension = Path::new(name)
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_ascii_lowercase());
+    MIME_TYPES
+        .iter()
+        .find(|(ext, _)| Some(*ext) == extension.as_deref())
+        .map_or(DEFAULT_MIME, |(_, mime)| mime)
+}
+
+impl Attachment {
+    pub fn read(path: &Path) -> crate::Result<Attachment> {
+        let name = path
+            .file_name()
+            .and_then(|n| n.to_str())
+            .ok_or(AttachmentError::InvalidName)?
+            .to_string();
+        let modified = std::fs::metadata(path)?
+            .modified()?
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
+        Ok(Attachment {
+            mime: mime_for(&name).to_string(),
+            name,
+            modified,
+            content: std::fs::read(path)?,
+        })
+    }
+
+    /// u16 name length, name, u8 MIME length, MIME type, u64 mtime, content
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = (self.name.len() as u16).to_be_bytes().to_vec();
+        bytes.extend_from_slice(self.name.as_bytes());
+        bytes.push(self.mime.len() as u8);
+        bytes.extend_from_slice(self.mime.as_bytes());
+        bytes.extend_from_slice(&self.modified.to_be_bytes());
+        bytes.extend_from_slice(&self.content);
+        bytes
+    }
+
+    pub fn parse(bytes: &[u8]) -> Result<Attachment, AttachmentError> {
+        let (name_len, rest) = bytes
+            .split_at_checked(2)
+            .ok_or(AttachmentError::Truncated)?;
+        let name_len = u16::from_be_bytes(name_len.try_into().unwrap()) as usize;
+        let (name, rest) = rest
+            .split_at_checked(name_len)
+            .ok_or(AttachmentError::Truncated)?;
+        let (&mime_len, rest) = rest.split_first().ok_or(AttachmentError::Truncated)?;
+        let (mime, rest) = rest
+            .split_at_checked(mime_len as usize)
+            .ok_or(AttachmentError::Truncated)?;
+        let (modified, content) = rest.split_at_checked(8).ok_or(AttachmentError::Truncated)?;
+        let text =
+            |b: &[u8]| String::from_utf8(b.to_vec()).map_err(|_| AttachmentError::InvalidName);
+        Ok(Attachment {
+            name: text(name)?,
+            mime: text(mime)?,
+            modified: u64::from_be_bytes(modified.try_into().unwrap()),
+            content: content.to_vec(),
+        })
+    }
+
+    /// Where to restore the file in `dir`: the recorded name without any
+    /// directory parts, given an extension matching its MIME type if it
+    /// has none
+    pub fn restore_path(&self, dir: &Path) -> Result<PathBuf, AttachmentError> {
+        let name = Path::new(&self.name)
+            .file_name()
+            .and_then(|n| n.to_str())
+            .filter(|n| !n.starts_with('.'))
+            .ok_or(AttachmentError::InvalidName)?;
+        let mut path = dir.join(name);
+        if path.extension().is_none() {
+            if let Some((ext, _)) = MIME_TYPES.iter().find(|(_, mime)| *mime == self.mime) {
+                path.set_extension(ext);
+            }
+        }
+        Ok(path)
+    }
+
+    pub fn modified_time(&self) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(self.modified)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn testing_attachment(name: &str) -> Attachment {
+        Attachment {
+            name: name.to_string(),
+            mime: mime_for(name).to_string(),
+            modified: 1_700_000_000,
+            content: b"%PDF-1.7".to_vec(),
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let attachment = testing_attachment("report.pdf");
+        assert_eq!(attachment.mime, "application/pdf");
+        assert_eq!(Attachment::parse(&attachment.to_bytes()), Ok(attachment));
+    }
+
+    #[test]
+    fn test_truncated() {
+        let bytes = testing_attachment("report.pdf").to_bytes();
+        assert_eq!(
+            Attachment::parse(&bytes[..12]),
+            Err(AttachmentError::Truncated)
+        );
+    }
+
+    #[test]
+    fn test_mime_for() {
+        assert_eq!(mime_for("photo.JPG"), "image/jpeg");
+        assert_eq!(mime_for("notes"), DEFAULT_MIME);
+    }
+
+    #[test]
+    fn test_restore_path() {
+        let dir = Path::new("out");
+        assert_eq!(
+            testing_attachment("../../etc/report.pdf").restore_path(dir),
+            Ok(dir.join("report.pdf"))
+        );
+        let mut unnamed = testing_attachment("report");
+        unnamed.mime = "application/pdf".to_string();
+        assert_eq!(unnamed.restore_path(dir), Ok(dir.join("report.pdf")));
+        assert_eq!(
+            testing_attachment("..").restore_path(dir),
+            Err(AttachmentError::InvalidName)
+        );
+    }
+}
@@ -0,0 +1,925 @@
+//! One operation over many files on a thread pool. Every file succeeds or
+//! fails on its own, so a bad file, even one that trips a bug, never
+//! stops the rest. Files are read, processed and written in stages with
+//! bounded queues between them, so a slow disk holds the others back
+//! rather than piling files up in memory. Read and write buffers are
+//! pooled across files, so a run over many large images doesn't allocate
+//! two fresh ones per file
+
+#[cfg(feature = "crypto")]
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::fmt::Display;
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Read};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+#[cfg(feature = "crypto")]
+use std::sync::Arc;
+use std::sync::{mpsc, Mutex};
+use std::thread;
+
+use rayon::prelude::*;
+use rayon::{ThreadPoolBuildError, ThreadPoolBuilder};
+
+use crate::cache::{self, CacheError, Identity, ValidationCache};
+use crate::chunk::Chunk;
+#[cfg(feature = "crypto")]
+use crate::chunk_type::ChunkType;
+use crate::message;
+use crate::perf;
+use crate::png::Png;
+use crate::streaming;
+use crate::validate::{validate, ValidateOptions};
+
+#[derive(Debug)]
+pub enum BatchError {
+    Pool(ThreadPoolBuildError),
+    Cache(CacheError),
+    /// The payload to encode couldn't be prepared
+    #[cfg(feature = "crypto")]
+    Payload(crate::Error),
+    /// A memory bound for an operation that needs whole files in memory
+    NotStreamable,
+    /// More than one input would be written to this file in the output
+    /// directory
+    SameOutput(PathBuf),
+}
+
+impl Display for BatchError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BatchError::Pool(e) => write!(f, "can't start the thread pool: {}", e),
+            BatchError::Cache(e) => write!(f, "{}", e),
+            #[cfg(feature = "crypto")]
+            BatchError::Payload(e) => write!(f, "can't prepare the payload: {}", e),
+            BatchError::NotStreamable => write!(
+                f,
+                "only validate, strip, encode and append run in bounded memory"
+            ),
+            BatchError::SameOutput(path) => write!(
+                f,
+                "more than one input would be written to {}",
+                path.display()
+            ),
+        }
+    }
+}
+
+impl std::error::Error for BatchError {}
+
+#[derive(Debug, Clone)]
+pub enum Operation {
+    /// Check the file as [`validate`] does, its CRCs in parallel
+    Validate,
+    /// Drop the chunks [`message::strip`] does
+    Strip,
+    /// Add a message as [`message::embed`] does. The payload is prepared
+    /// once, through [`Options::payloads`], and added to every file as an
+    /// [`Append`](Operation::Append)
+    #[cfg(feature = "crypto")]
+    Encode {
+        chunk_type: ChunkType,
+        message: Vec<u8>,
+        password: Option<String>,
+    },
+    /// Add these chunks before IEND as they are
+    Append(Vec<Chunk>),
+    /// Apply a [`Script`](crate::script::Script)'s operations
+    #[cfg(feature = "script")]
+    Script {
+        script: crate::script::Script,
+        password: Option<String>,
+    },
+}
+
+impl Operation {
+    fn writes(&self) -> bool {
+        !matches!(self, Operation::Validate)
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Options {
+    /// Files processed at once; `None` for one per core
+    pub jobs: Option<usize>,
+    /// Files read and waiting to be processed, and processed and waiting
+    /// to be written, at most; `None` for twice the jobs. Through io_uring,
+    /// the files read at a time
+    pub queue_depth: Option<usize>,
+    /// Threads reading files, and as many writing them; `None` for two
+    pub io_threads: Option<usize>,
+    /// Write changed files here, under their own names, instead of over
+    /// the originals; inputs sharing a name are refused
+    pub output_dir: Option<PathBuf>,
+    /// Stream each file through in at most this many bytes, rather than
+    /// reading it whole; see [`streaming`]. Encoding then adds the message
+    /// as a streamed payload
+    pub max_memory: Option<usize>,
+    /// Validation results kept here between runs, so only files whose
+    /// identity changed are validated again; see [`cache`]
+    pub cache: Option<PathBuf>,
+    pub cache_identity: Identity,
+    /// Where encoded payloads are prepared, to share them between runs;
+    /// `None` for one of the run's own
+    #[cfg(feature = "crypto")]
+    pub payloads: Option<Arc<Payloads>>,
+}
+
+/// Payload chunks prepared for [`Operation::Encode`], by a BLAKE3 hash of
+/// the chunk type, message and password, so the same message is
+/// compressed and encrypted only once however many files it goes into
+#[cfg(feature = "crypto")]
+#[derive(Debug, Default)]
+pub struct Payloads(Mutex<HashMap<[u8; 32], Chunk>>);
+
+#[cfg(feature = "crypto")]
+impl Payloads {
+    /// The chunk [`message::prepare`] makes, prepared the first time it's
+    /// asked for
+    pub fn prepare(
+        &self,
+        chunk_type: ChunkType,
+        message: &[u8],
+        password: Option<&str>,
+    ) -> crate::Result<Chunk> {
+        let mut hasher = blake3::Hasher::new();
+        hasher.update(&chunk_type.bytes());
+        hasher.update(&(message.len() as u64).to_le_bytes());
+        hasher.update(message);
+        if let Some(password) = password {
+            hasher.update(b"\x01").update(password.as_bytes());
+        }
+        let key = *hasher.finalize().as_bytes();
+        if let Some(chunk) = self.0.lock().unwrap().get(&key) {
+            return Ok(chunk.clone());
+        }
+        let chunk = message::prepare(chunk_type, message, password)?;
+        self.0.lock().unwrap().insert(key, chunk.clone());
+        Ok(chunk)
+    }
+
+    /// How many payloads have been prepared
+    pub fn len(&self) -> usize {
+        self.0.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// What happened to one file that went through
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FileReport {
+    pub bytes_in: usize,
+    /// Size of the file written, if the operation writes one
+    pub bytes_out: Option<usize>,
+    pub chunks_removed: usize,
+}
+
+#[derive(Debug)]
+pub struct Outcome {
+    pub path: PathBuf,
+    /// The error's description on failure, since errors can't all cross
+    /// threads
+    pub result: Result<FileReport, String>,
+}
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Summary {
+    pub succeeded: usize,
+    pub failed: usize,
+    pub bytes_in: usize,
+    pub bytes_out: usize,
+    pub chunks_removed: usize,
+}
+
+impl Summary {
+    pub fn of(outcomes: &[Outcome]) -> Summary {
+        let mut summary = Summary::default();
+        for outcome in outcomes {
+            match &outcome.result {
+                Ok(report) => {
+                    summary.succeeded += 1;
+                    summary.bytes_in += report.bytes_in;
+                    summary.bytes_out += report.bytes_out.unwrap_or(0);
+                    summary.chunks_removed += report.chunks_removed;
+                }
+                Err(_) => summary.failed += 1,
+            }
+        }
+        summary
+    }
+}
+
+/// Buffers handed back by finished files for the next ones to fill. Each
+/// file holds at most two at once, so there are never more than twice as
+/// many as there are files in flight
+#[derive(Default)]
+struct Pool(Mutex<Vec<Vec<u8>>>);
+
+impl Pool {
+    fn take(&self) -> Vec<u8> {
+        self.0.lock().unwrap().pop().unwrap_or_default()
+    }
+
+    fn give(&self, mut buffer: Vec<u8>) {
+        buffer.clear();
+        self.0.lock().unwrap().push(buffer);
+    }
+}
+
+/// Apply `operation` to every file, returning the outcomes in the order of
+/// `paths`
+pub fn run(
+    paths: &[PathBuf],
+    operation: &Operation,
+    options: &Options,
+) -> Result<Vec<Outcome>, BatchError> {
+    #[cfg(feature = "script")]
+    if options.max_memory.is_some() && matches!(operation, Operation::Script { .. }) {
+        return Err(BatchError::NotStreamable);
+    }
+    if options.output_dir.is_some() && !matches!(operation, Operation::Validate) {
+        let mut names = HashSet::new();
+        if let Some(path) = paths.iter().find(|path| !names.insert(path.file_name())) {
+            return Err(BatchError::SameOutput(output_path(path, options)));
+        }
+    }
+    let pool = ThreadPoolBuilder::new()
+        .num_threads(options.jobs.unwrap_or(0))
+        .build()
+        .map_err(BatchError::Pool)?;
+    if let (Operation::Validate, Some(cache)) = (operation, &options.cache) {
+        return run_cached(&pool, paths, options, cache);
+    }
+    #[cfg(feature = "crypto")]
+    let prepared;
+    #[cfg(feature = "crypto")]
+    let operation = match operation {
+        Operation::Encode {
+            chunk_type,
+            message,
+            password,
+        } if options.max_memory.is_none() => {
+            let payloads = options.payloads.clone().unwrap_or_default();
+            let chunk = payloads
+                .prepare(*chunk_type, message, password.as_deref())
+                .map_err(BatchError::Payload)?;
+            prepared = Operation::Append(vec![chunk]);
+            &prepared
+        }
+        operation => operation,
+    };
+    let buffers = Pool::default();
+    if options.max_memory.is_some() {
+        return Ok(pool.install(|| {
+            paths
+                .par_iter()
+                .map(|path| Outcome {
+                    path: path.clone(),
+                    result: isolate(|| process(path, operation, options, &buffers)),
+                })
+                .collect()
+        }));
+    }
+    #[cfg(all(feature = "io-uring", target_os = "linux"))]
+    if let Ok(mut reader) = crate::uring::Reader::new() {
+        return Ok(run_uring(
+            &mut reader,
+            &pool,
+            paths,
+            operation,
+            options,
+            &buffers,
+        ));
+    }
+    Ok(run_staged(&pool, paths, operation, options, &buffers))
+}
+
+/// [`run`] validating only the files `cache` has no result for under
+/// their current identity, and remembering the results
+fn run_cached(
+    pool: &rayon::ThreadPool,
+    paths: &[PathBuf],
+    options: &Options,
+    cache: &Path,
+) -> Result<Vec<Outcome>, BatchError> {
+    let mut cache = ValidationCache::open(cache).map_err(BatchError::Cache)?;
+    let identities: Vec<Option<(String, u64)>> = pool.install(|| {
+        paths
+            .par_iter()
+            .map(|path| options.cache_identity.of(path).ok())
+            .collect()
+    });
+    let mut outcomes: Vec<Option<Outcome>> = paths
+        .iter()
+        .zip(&identities)
+        .map(|(path, identity)| {
+            let (identity, _) = identity.as_ref()?;
+            let entry = cache.get(path, identity)?;
+            Some(Outcome {
+                path: path.clone(),
+                result: entry.result.clone().map(|()| FileReport {
+                    bytes_in: entry.size as usize,
+                    ..FileReport::default()
+                }),
+            })
+        })
+        .collect();
+    let missed: Vec<PathBuf> = paths
+        .iter()
+        .zip(&outcomes)
+        .filter(|(_, outcome)| outcome.is_none())
+        .map(|(path, _)| path.clone())
+        .collect();
+    let uncached = Options {
+        cache: None,
+        ..options.clone()
+    };
+    let mut validated = run(&missed, &Operation::Validate, &uncached)?.into_iter();
+    for (outcome, identity) in outcomes.iter_mut().zip(identities) {
+        if outcome.is_some() {
+            continue;
+        }
+        let fresh = validated.next().expect("a result per file missed");
+        if let Some((identity, size)) = identity {
+            let result = fresh.result.as_ref().map(|_| ()).map_err(String::clone);
+            let entry = cache::Entry {
+                identity,
+                size,
+                result,
+            };
+            cache.insert(&fresh.path, entry);
+        }
+        *outcome = Some(fresh);
+    }
+    cache
+        .save()
+        .map_err(|e| BatchError::Cache(CacheError::Io(e)))?;
+    Ok(outcomes.into_iter().flatten().collect())
+}
+
+/// A file's bytes, or why they couldn't be read
+type Loaded = (usize, Result<Vec<u8>, String>);
+
+/// A file's report and what to write for it, or why it failed
+type Processed = (usize, Result<(FileReport, Option<Vec<u8>>), String>);
+
+/// [`run`] in three stages: `io_threads` reading files into a queue, the
+/// pool's threads taking them off it and queueing what to write, and
+/// `io_threads` writing that. Each queue holds at most `queue_depth`
+/// files, so whichever stage is slowest sets the pace
+fn run_staged(
+    pool: &rayon::ThreadPool,
+    paths: &[PathBuf],
+    operation: &Operation,
+    options: &Options,
+    buffers: &Pool,
+) -> Vec<Outcome> {
+    let depth = options
+        .queue_depth
+        .unwrap_or(2 * pool.current_num_threads())
+        .max(1);
+    let io_threads = options.io_threads.unwrap_or(2).max(1);
+    let (read_tx, read_rx) = mpsc::sync_channel::<Loaded>(depth);
+    let (done_tx, done_rx) = mpsc::sync_channel::<Processed>(depth);
+    let (read_rx, done_rx) = (Mutex::new(read_rx), Mutex::new(done_rx));
+    let next = AtomicUsize::new(0);
+    let outcomes: Mutex<Vec<Option<Outcome>>> = Mutex::new(paths.iter().map(|_| None).collect());
+
+    thread::scope(|scope| {
+        for _ in 0..io_threads {
+            let read_tx = read_tx.clone();
+            let next = &next;
+            scope.spawn(move || loop {
+                let index = next.fetch_add(1, Ordering::Relaxed);
+                let Some(path) = paths.get(index) else {
+                    break;
+                };
+                let mut bytes = buffers.take();
+                let read = match read_into(path, &mut bytes) {
+                    Ok(()) => Ok(bytes),
+                    Err(e) => {
+                        buffers.give(bytes);
+                        Err(e.to_string())
+                    }
+                };
+                if read_tx.send((index, read)).is_err() {
+                    break;
+                }
+            });
+        }
+        drop(read_tx);
+
+        let read_rx = &read_rx;
+        scope.spawn(move || {
+            pool.broadcast(|_| loop {
+                let Ok((index, read)) = read_rx.lock().unwrap().recv() else {
+                    break;
+                };
+                let processed = read.and_then(|bytes| {
+                    let processed = isolate_with(|| transform(&bytes, operation, buffers));
+                    buffers.give(bytes);
+                    processed
+                });
+                if done_tx.send((index, processed)).is_err() {
+                    break;
+                }
+            });
+            // Every sender is gone once this returns, which ends the writers
+            drop(done_tx);
+        });
+
+        for _ in 0..io_threads {
+            let (done_rx, outcomes) = (&done_rx, &outcomes);
+            scope.spawn(move || loop {
+                let Ok((index, processed)) = done_rx.lock().unwrap().recv() else {
+                    break;
+                };
+                let path = &paths[index];
+                let result = processed.and_then(|(report, out)| {
+                    write(path, report, out, options, buffers).map_err(|e| e.to_string())
+                });
+                outcomes.lock().unwrap()[index] = Some(Outcome {
+                    path: path.clone(),
+                    result,
+                });
+            });
+        }
+    });
+    outcomes
+        .into_inner()
+        .unwrap()
+        .into_iter()
+        .map(|outcome| outcome.expect("every file goes through every stage"))
+        .collect()
+}
+
+/// [`run`], reading a window of files through the ring while the pool
+/// works on the one before
+#[cfg(all(feature = "io-uring", target_os = "linux"))]
+fn run_uring(
+    reader: &mut crate::uring::Reader,
+    pool: &rayon::ThreadPool,
+    paths: &[PathBuf],
+    operation: &Operation,
+    options: &Options,
+    buffers: &Pool,
+) -> Vec<Outcome> {
+    let window = options
+        .queue_depth
+        .unwrap_or(crate::uring::DEPTH * 4)
+        .max(1);
+    let read = |reader: &mut crate::uring::Reader, paths: &[PathBuf]| {
+        let _stage = perf::stage("read");
+        let mut bytes: Vec<Vec<u8>> = paths.iter().map(|_| buffers.take()).collect();
+        let results = reader.read_all(paths, &mut bytes);
+        (bytes, results)
+    };
+    let mut outcomes = Vec::with_capacity(paths.len());
+    let mut windows = paths.chunks(window);
+    let mut next = windows.next().map(|paths| (paths, read(reader, paths)));
+    while let Some((paths, (bytes, reads))) = next.take() {
+        let (done, following) = pool.join(
+            || {
+                paths
+                    .par_iter()
+                    .zip(&bytes)
+                    .zip(reads)
+                    .map(|((path, bytes), read)| Outcome {
+                        path: path.clone(),
+                        result: isolate(|| {
+                            read?;
+                            apply(path, bytes, operation, options, buffers)
+                        }),
+                    })
+                    .collect::<Vec<_>>()
+            },
+            || windows.next().map(|paths| (paths, read(reader, paths))),
+        );
+        outcomes.extend(done);
+        bytes.into_iter().for_each(|b| buffers.give(b));
+        next = following;
+    }
+    outcomes
+}
+
+fn isolate(call: impl FnOnce() -> crate::Result<FileReport>) -> Result<FileReport, String> {
+    isolate_with(call)
+}
+
+/// [`isolate`] for any result
+fn isolate_with<T>(call: impl FnOnce() -> crate::Result<T>) -> Result<T, String> {
+    match panic::catch_unwind(AssertUnwindSafe(call)) {
+        Ok(result) => result.map_err(|e| e.to_string()),
+        Err(_) => Err("internal error: panicked".to_string()),
+    }
+}
+
+fn process(
+    path: &Path,
+    operation: &Operation,
+    options: &Options,
+    buffers: &Pool,
+) -> crate::Result<FileReport> {
+    if let Some(max_memory) = options.max_memory {
+        return stream(path, operation, options, max_memory);
+    }
+    let mut bytes = buffers.take();
+    let result =
+        read_into(path, &mut bytes).and_then(|()| apply(path, &bytes, operation, options, buffers));
+    buffers.give(bytes);
+    result
+}
+
+/// [`process`] through [`streaming`], never holding more than `max_memory`
+/// bytes of the file. Changed files are written beside their destination
+/// and renamed over it once complete
+fn stream(
+    path: &Path,
+    operation: &Operation,
+    options: &Options,
+    max_memory: usize,
+) -> crate::Result<FileReport> {
+    let limits = streaming::Options { max_memory };
+    let input = BufReader::new(File::open(path)?);
+    let mut report = FileReport {
+        bytes_in: fs::metadata(path)?.len() as usize,
+        ..FileReport::default()
+    };
+    if let Operation::Validate = operation {
+        streaming::validate(input, &limits)?;
+        return Ok(report);
+    }
+    let output = output_path(path, options);
+    let mut temp = output.clone().into_os_string();
+    temp.push(".tmp");
+    let temp = PathBuf::from(temp);
+    let written = (|| -> crate::Result<()> {
+        let out = BufWriter::new(File::create(&temp)?);
+        match operation {
+            Operation::Strip => report.chunks_removed = streaming::strip(input, out, &limits)?,
+            #[cfg(feature = "crypto")]
+            Operation::Encode {
+                chunk_type,
+                message,
+                password,
+            } => {
+                let secret = password
+                    .as_deref()
+                    .map(|password| crate::crypto::secret(Some(password), None));
+                let params = crate::crypto::KdfParams::default();
+                let encryption = secret.as_deref().map(|secret| (secret, &params));
+                let codec = crate::compress::Codec::None;
+                streaming::encode(
+                    input,
+                    out,
+                    *chunk_type,
+                    &mut message.as_slice(),
+                    codec,
+                    encryption,
+                    &limits,
+                )?
+            }
+            Operation::Append(chunks) => streaming::append(input, out, chunks)?,
+            _ => unreachable!("only validate, strip, encode and append stream"),
+        }
+        Ok(())
+    })();
+    if let Err(e) = written {
+        let _ = fs::remove_file(&temp);
+        return Err(e);
+    }
+    report.bytes_out = Some(fs::metadata(&temp)?.len() as usize);
+    fs::rename(&temp, output)?;
+    Ok(report)
+}
+
+/// Where the changed version of `path` goes
+fn output_path(path: &Path, options: &Options) -> PathBuf {
+    match (&options.output_dir, path.file_name()) {
+        (Some(dir), Some(name)) => dir.join(name),
+        _ => path.to_path_buf(),
+    }
+}
+
+fn read_into(path: &Path, buffer: &mut Vec<u8>) -> crate::Result<()> {
+    let _stage = perf::stage("read");
+    let mut file = File::open(path)?;
+    buffer.reserve(file.metadata()?.len() as usize);
+    file.read_to_end(buffer)?;
+    Ok(())
+}
+
+fn apply(
+    path: &Path,
+    bytes: &[u8],
+    operation: &Operation,
+    options: &Options,
+    buffers: &Pool,
+) -> crate::Result<FileReport> {
+    let (report, out) = transform(bytes, operation, buffers)?;
+    write(path, report, out, options, buffers)
+}
+
+/// Write `out`, if the operation made one, where `path`'s changed version
+/// goes, giving the buffer back
+fn write(
+    path: &Path,
+    mut report: FileReport,
+    out: Option<Vec<u8>>,
+    options: &Options,
+    buffers: &Pool,
+) -> crate::Result<FileReport> {
+    let _stage = perf::stage("write");
+    if let Some(out) = out {
+        let written = fs::write(output_path(path, options), &out);
+        report.bytes_out = Some(out.len());
+        buffers.give(out);
+        written?;
+    }
+    Ok(report)
+}
+
+/// Apply `operation` to a file's `bytes`, returning its report and the
+/// bytes to write, if the operation writes
+fn transform(
+    bytes: &[u8],
+    operation: &Operation,
+    buffers: &Pool,
+) -> crate::Result<(FileReport, Option<Vec<u8>>)> {
+    let _stage = perf::stage("process");
+    let mut report = FileReport {
+        bytes_in: bytes.len(),
+        ..FileReport::default()
+    };
+    if let Operation::Validate = operation {
+        let options = ValidateOptions {
+            parallel: true,
+            keywords: true,
+            unique: true,
+            placement: true,
+            header: true,
+            indices: true,
+        };
+        validate(bytes, &options)?;
+        return Ok((report, None));
+    }
+    let mut png = Png::try_from(bytes)?;
+    match operation {
+        Operation::Validate => unreachable!("validated without parsing"),
+        Operation::Strip => report.chunks_removed = message::strip(&mut png),
+        #[cfg(feature = "crypto")]
+        Operation::Encode {
+            chunk_type,
+            message,
+            password,
+        } => message::embed(&mut png, *chunk_type, message, password.as_deref())?,
+        Operation::Append(chunks) => {
+            for chunk in chunks {
+                png.append_chunk(chunk.clone());
+            }
+        }
+        #[cfg(feature = "script")]
+        Operation::Script { script, password } => {
+            report.chunks_removed = script.apply(&mut png, password.as_deref())?
+        }
+    }
+    if !operation.writes() {
+        return Ok((report, None));
+    }
+    let mut out = buffers.take();
+    png.write_into(&mut out);
+    Ok((report, Some(out)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk_type::ChunkType;
+    use crate::pixels::tests::testing_png;
+    use std::str::FromStr;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("ping-batch-{}-{}", name, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn write_files(dir: &Path) -> Vec<PathBuf> {
+        let mut png = testing_png(4, 4);
+        png.append_chunk(Chunk::new(
+            ChunkType::from_str("tEXt").unwrap(),
+            b"Comment\0x".to_vec(),
+        ));
+        let files = vec![dir.join("a.png"), dir.join("bad.png"), dir.join("c.png")];
+        fs::write(&files[0], png.as_bytes()).unwrap();
+        fs::write(&files[1], b"not a png").unwrap();
+        fs::write(&files[2], png.as_bytes()).unwrap();
+        files
+    }
+
+    #[test]
+    fn test_errors_are_isolated() {
+        let dir = temp_dir("strip");
+        let files = write_files(&dir);
+        let out = dir.join("out");
+        fs::create_dir_all(&out).unwrap();
+        let options = Options {
+            jobs: Some(2),
+            output_dir: Some(out.clone()),
+            ..Options::default()
+        };
+        let outcomes = run(&files, &Operation::Strip, &options).unwrap();
+        assert_eq!(outcomes[1].path, files[1]);
+        assert_eq!(
+            outcomes[1].result,
+            Err("invalid png signature: byte 0 is 0x6e, not 0x89".to_string())
+        );
+        let summary = Summary::of(&outcomes);
+        assert_eq!((summary.succeeded, summary.failed), (2, 1));
+        assert_eq!(summary.chunks_removed, 2);
+        assert!(summary.bytes_out < summary.bytes_in);
+        let stripped = Png::try_from(fs::read(out.join("c.png")).unwrap().as_slice()).unwrap();
+        assert_eq!(stripped.chunks().len(), 3);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_same_output() {
+        let dir = temp_dir("same-output");
+        let mut files = write_files(&dir);
+        fs::create_dir_all(dir.join("sub")).unwrap();
+        files.push(dir.join("sub").join("a.png"));
+        fs::copy(&files[0], &files[3]).unwrap();
+        let out = dir.join("out");
+        let options = Options {
+            output_dir: Some(out.clone()),
+            ..Options::default()
+        };
+        assert!(matches!(
+            run(&files, &Operation::Strip, &options),
+            Err(BatchError::SameOutput(path)) if path == out.join("a.png")
+        ));
+        assert!(!out.exists());
+        assert!(run(&files, &Operation::Validate, &options).is_ok());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_validate() {
+        let dir = temp_dir("validate");
+        let files = write_files(&dir);
+        let outcomes = run(&files, &Operation::Validate, &Options::default()).unwrap();
+        assert_eq!(Summary::of(&outcomes).failed, 1);
+        assert_eq!(outcomes[0].result.as_ref().unwrap().bytes_out, None);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    #[cfg(feature = "crypto")]
+    fn test_encode() {
+        let dir = temp_dir("encode");
+        let files = write_files(&dir);
+        let chunk_type = ChunkType::from_str("ruSt").unwrap();
+        let operation = Operation::Encode {
+            chunk_type,
+            message: b"hello".to_vec(),
+            password: None,
+        };
+        run(&files[..1], &operation, &Options::default()).unwrap();
+        let png = Png::try_from(fs::read(&files[0]).unwrap().as_slice()).unwrap();
+        assert_eq!(message::extract(&png, &chunk_type, None).unwrap(), b"hello");
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    #[cfg(feature = "crypto")]
+    fn test_payload_prepared_once() {
+        let dir = temp_dir("prepared");
+        let files = write_files(&dir);
+        let chunk_type = ChunkType::from_str("ruSt").unwrap();
+        let operation = Operation::Encode {
+            chunk_type,
+            message: b"licensed under MIT".to_vec(),
+            password: Some("hunter2".to_string()),
+        };
+        let options = Options {
+            payloads: Some(Arc::default()),
+            ..Options::default()
+        };
+        let good = [files[0].clone(), files[2].clone()];
+        run(&good[..1], &operation, &options).unwrap();
+        run(&good[1..], &operation, &options).unwrap();
+        assert_eq!(options.payloads.as_ref().unwrap().len(), 1);
+        let payloads: Vec<Vec<u8>> = good
+            .iter()
+            .map(|file| {
+                let png = Png::try_from(fs::read(file).unwrap().as_slice()).unwrap();
+                let message = message::extract(&png, &chunk_type, Some("hunter2")).unwrap();
+                assert_eq!(message, b"licensed under MIT");
+                png.chunk_by_type("ruSt").unwrap().data().to_vec()
+            })
+            .collect();
+        assert_eq!(payloads[0], payloads[1]);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_bounded_memory() {
+        let dir = temp_dir("streaming");
+        let files = write_files(&dir);
+        let options = Options {
+            max_memory: Some(streaming::MIN_MEMORY),
+            ..Options::default()
+        };
+        let outcomes = run(&files, &Operation::Validate, &options).unwrap();
+        assert_eq!(Summary::of(&outcomes).failed, 1);
+
+        let expected = {
+            let mut png = Png::try_from(fs::read(&files[0]).unwrap().as_slice()).unwrap();
+            message::strip(&mut png);
+            png.as_bytes()
+        };
+        let outcomes = run(&files, &Operation::Strip, &options).unwrap();
+        assert_eq!(Summary::of(&outcomes).chunks_removed, 2);
+        assert_eq!(fs::read(&files[0]).unwrap(), expected);
+        assert!(!dir.join("a.png.tmp").exists() && !dir.join("bad.png.tmp").exists());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_buffers_are_reused() {
+        let dir = temp_dir("pool");
+        let files = write_files(&dir);
+        let buffers = Pool::default();
+        let options = Options::default();
+        for path in [&files[0], &files[2]] {
+            process(path, &Operation::Strip, &options, &buffers).unwrap();
+        }
+        let pooled = buffers.0.into_inner().unwrap();
+        assert_eq!(pooled.len(), 2);
+        assert!(pooled.iter().all(|b| b.is_empty() && b.capacity() > 0));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_queues_are_bounded() {
+        let dir = temp_dir("staged");
+        let mut files = write_files(&dir);
+        for i in 0..12 {
+            let copy = dir.join(format!("{}.png", i));
+            fs::copy(&files[0], &copy).unwrap();
+            files.push(copy);
+        }
+        let options = Options {
+            queue_depth: Some(1),
+            io_threads: Some(1),
+            ..Options::default()
+        };
+        let pool = ThreadPoolBuilder::new().num_threads(1).build().unwrap();
+        let buffers = Pool::default();
+        let outcomes = run_staged(&pool, &files, &Operation::Strip, &options, &buffers);
+        assert!(outcomes.iter().zip(&files).all(|(o, path)| o.path == *path));
+        assert_eq!(Summary::of(&outcomes).failed, 1);
+        // one being read, one queued, two being processed, one queued and
+        // one being written
+        assert!(buffers.0.into_inner().unwrap().len() <= 6);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_cached_validation() {
+        let dir = temp_dir("cached");
+        let files = write_files(&dir);
+        let options = Options {
+            cache: Some(dir.join("cache")),
+            ..Options::default()
+        };
+        let first = run(&files, &Operation::Validate, &options).unwrap();
+        // A cached result stands for the file until it changes
+        let cache = dir.join("cache");
+        let text = fs::read_to_string(&cache).unwrap();
+        fs::write(&cache, text.replace("\tok\t", "\terror stale\t")).unwrap();
+        let second = run(&files, &Operation::Validate, &options).unwrap();
+        assert_eq!(second[0].result, Err("stale".to_string()));
+        assert_eq!(second[1].result, first[1].result);
+
+        fs::write(&files[0], testing_png(4, 4).as_bytes()).unwrap();
+        let third = run(&files, &Operation::Validate, &options).unwrap();
+        assert!(third[0].result.is_ok());
+        assert_eq!(third[2].result, Err("stale".to_string()));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_panics_are_isolated() {
+        assert_eq!(
+            isolate(|| panic!("bug")),
+            Err("internal error: panicked".to_string())
+        );
+    }
+}
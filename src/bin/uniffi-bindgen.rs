@@ -0,0 +1,7 @@
+//! Generates the Kotlin and Swift bindings, e.g.
+//! `cargo run --features uniffi-cli --bin uniffi-bindgen -- generate
+//! --library target/debug/libping.so --language kotlin --out-dir out`
+
+fn main() {
+    uniffi::uniffi_bindgen_main()
+}
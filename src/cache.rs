@@ -0,0 +1,257 @@
+//! Validation results remembered between runs, so checking a large tree
+//! of files that rarely change only validates the ones that did. A file is
+//! known by its path and its [`Identity`], one
+//! `<identity>\t<size>\t<result>\t<path>` line each
+
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::fs;
+use std::io::{self, ErrorKind};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+/// What tells that a file changed since it was validated
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum Identity {
+    /// Size and modification time, read without opening the file
+    #[default]
+    Metadata,
+    /// A BLAKE3 hash of the contents, which holds across fresh checkouts
+    /// and copies that reset modification times
+    Content,
+}
+
+impl Identity {
+    /// `file`'s identity and size
+    pub fn of(&self, file: &Path) -> io::Result<(String, u64)> {
+        let metadata = fs::metadata(file)?;
+        let identity = match self {
+            Identity::Metadata => {
+                let modified = metadata.modified()?.duration_since(UNIX_EPOCH);
+                let nanos = modified.map_or(0, |since| since.as_nanos());
+                format!("m{}-{}", metadata.len(), nanos)
+            }
+            Identity::Content => {
+                let mut hasher = blake3::Hasher::new();
+                hasher.update_reader(fs::File::open(file)?)?;
+                format!("b{}", hasher.finalize().to_hex())
+            }
+        };
+        Ok((identity, metadata.len()))
+    }
+}
+
+#[derive(Debug)]
+pub enum CacheError {
+    Io(io::Error),
+    InvalidLine(usize),
+}
+
+impl Display for CacheError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CacheError::Io(e) => write!(f, "can't read the validation cache: {}", e),
+            CacheError::InvalidLine(line) => {
+                write!(
+                    f,
+                    "invalid validation cache line {}; delete the file to start over",
+                    line
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for CacheError {}
+
+impl From<io::Error> for CacheError {
+    fn from(e: io::Error) -> Self {
+        CacheError::Io(e)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    pub identity: String,
+    pub size: u64,
+    /// The reason validation failed, if it did
+    pub result: Result<(), String>,
+}
+
+/// Where the cache goes if not told otherwise: `ping/validate` under
+/// `$XDG_CACHE_HOME`, or else under `~/.cache`
+pub fn default_path() -> Option<PathBuf> {
+    let base = match std::env::var_os("XDG_CACHE_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(std::env::var_os("HOME")?).join(".cache"),
+    };
+    Some(base.join("ping").join("validate"))
+}
+
+/// The results saved at one path, by the canonical path of each file
+#[derive(Debug, Default)]
+pub struct ValidationCache {
+    path: PathBuf,
+    entries: HashMap<PathBuf, Entry>,
+}
+
+impl ValidationCache {
+    /// Read the cache at `path`; a missing file is an empty cache
+    pub fn open(path: &Path) -> Result<ValidationCache, CacheError> {
+        let text = match fs::read_to_string(path) {
+            Err(e) if e.kind() == ErrorKind::NotFound => String::new(),
+            text => text?,
+        };
+        let mut entries = HashMap::new();
+        for (number, line) in text.lines().enumerate() {
+            let mut fields = line.splitn(4, '\t');
+            let (Some(identity), Some(size), Some(result), Some(file)) =
+                (fields.next(), fields.next(), fields.next(), fields.next())
+            else {
+                return Err(CacheError::InvalidLine(number + 1));
+            };
+            let result = match result.strip_prefix("error ") {
+                Some(reason) => Err(reason.to_string()),
+                None if result == "ok" => Ok(()),
+                None => return Err(CacheError::InvalidLine(number + 1)),
+            };
+            let entry = Entry {
+                identity: identity.to_string(),
+                size: size
+                    .parse()
+                    .map_err(|_| CacheError::InvalidLine(number + 1))?,
+                result,
+            };
+            entries.insert(PathBuf::from(file), entry);
+        }
+        Ok(ValidationCache {
+            path: path.to_path_buf(),
+            entries,
+        })
+    }
+
+    fn key(file: &Path) -> PathBuf {
+        fs::canonicalize(file).unwrap_or_else(|_| file.to_path_buf())
+    }
+
+    /// The result for `file` if it was validated with this identity
+    pub fn get(&self, file: &Path, identity: &str) -> Option<&Entry> {
+        self.entries
+            .get(&Self::key(file))
+            .filter(|entry| entry.identity == identity)
+    }
+
+    pub fn insert(&mut self, file: &Path, entry: Entry) {
+        self.entries.insert(Self::key(file), entry);
+    }
+
+    /// Write the cache back, leaving out files that are gone and paths a
+    /// line can't hold
+    pub fn save(&self) -> io::Result<()> {
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let mut temp = self.path.clone().into_os_string();
+        temp.push(".tmp");
+        fs::write(&temp, self.to_string())?;
+        fs::rename(&temp, &self.path)
+    }
+}
+
+impl Display for ValidationCache {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut files: Vec<(&str, &Entry)> = self
+            .entries
+            .iter()
+            .filter(|(file, _)| file.exists())
+            .filter_map(|(file, entry)| Some((file.to_str()?, entry)))
+            .filter(|(file, _)| !file.contains('\n'))
+            .collect();
+        files.sort_unstable_by_key(|(file, _)| *file);
+        for (file, entry) in files {
+            let result = match &entry.result {
+                Ok(()) => "ok".to_string(),
+                Err(reason) => format!("error {}", reason.replace(['\t', '\n'], " ")),
+            };
+            writeln!(
+                f,
+                "{}\t{}\t{}\t{}",
+                entry.identity, entry.size, result, file
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("ping-cache-{}-{}", name, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let dir = temp_dir("round-trip");
+        let (good, bad) = (dir.join("good.png"), dir.join("bad\tname.png"));
+        fs::write(&good, b"one").unwrap();
+        fs::write(&bad, b"two").unwrap();
+        let path = dir.join("cache").join("validate");
+        let mut cache = ValidationCache::open(&path).unwrap();
+        for (file, result) in [(&good, Ok(())), (&bad, Err("bad\tCRC".to_string()))] {
+            let (identity, size) = Identity::Content.of(file).unwrap();
+            cache.insert(
+                file,
+                Entry {
+                    identity,
+                    size,
+                    result,
+                },
+            );
+        }
+        cache.insert(
+            &dir.join("gone.png"),
+            Entry {
+                identity: "m1-1".to_string(),
+                size: 1,
+                result: Ok(()),
+            },
+        );
+        cache.save().unwrap();
+
+        let cache = ValidationCache::open(&path).unwrap();
+        assert_eq!(cache.entries.len(), 2);
+        let (identity, _) = Identity::Content.of(&bad).unwrap();
+        assert_eq!(
+            cache.get(&bad, &identity).unwrap().result,
+            Err("bad CRC".to_string())
+        );
+        assert!(cache.get(&good, &identity).is_none());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_identity_changes() {
+        let dir = temp_dir("identity");
+        let file = dir.join("a.png");
+        fs::write(&file, b"before").unwrap();
+        let before = [Identity::Metadata, Identity::Content].map(|i| i.of(&file).unwrap());
+        fs::write(&file, b"after!!").unwrap();
+        let after = [Identity::Metadata, Identity::Content].map(|i| i.of(&file).unwrap());
+        assert_ne!(before[0], after[0]);
+        assert_ne!(before[1], after[1]);
+        assert_eq!(after[1].1, 7);
+
+        let invalid = dir.join("invalid");
+        fs::write(&invalid, "b00\t3\tok\n").unwrap();
+        assert!(matches!(
+            ValidationCache::open(&invalid),
+            Err(CacheError::InvalidLine(1))
+        ));
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
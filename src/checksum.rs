@@ -0,0 +1,145 @@
+use sha2::{Digest, Sha256};
+
+use crate::pixels::Pixels;
+use crate::png::Png;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum Algorithm {
+    Sha256,
+    Blake3,
+}
+
+impl Algorithm {
+    /// Byte recording the algorithm where a digest is stored
+    pub fn id(&self) -> u8 {
+        match self {
+            Algorithm::Sha256 => 1,
+            Algorithm::Blake3 => 2,
+        }
+    }
+
+    pub fn from_id(id: u8) -> Option<Algorithm> {
+        [Algorithm::Sha256, Algorithm::Blake3]
+            .into_iter()
+            .find(|a| a.id() == id)
+    }
+
+    pub fn digest(&self, data: &[u8]) -> Vec<u8> {
+        match self {
+            Algorithm::Sha256 => Sha256::digest(data).to_vec(),
+            Algorithm::Blake3 => blake3::hash(data).as_bytes().to_vec(),
+        }
+    }
+}
+
+/// Digests of a file as a whole, of its image data and of every chunk
+#[derive(Debug)]
+pub struct Checksums {
+    pub file: Vec<u8>,
+    pub image: Vec<u8>,
+    pub chunks: Vec<(String, Vec<u8>)>,
+}
+
+impl Checksums {
+    /// The image digest covers the unfiltered pixels and the dimensions,
+    /// bit depth, colour type and interlacing they're read by, so it only
+    /// changes when the image does, not when metadata chunks are edited or
+    /// the data is filtered or compressed differently
+    pub fn new(bytes: &[u8], algo: Algorithm) -> crate::Result<Checksums> {
+        let png = Png::try_from(bytes)?;
+        let pixels = Pixels::read(&png)?;
+        let ihdr = &pixels.ihdr;
+        let mut image = Vec::with_capacity(11 + pixels.data.len());
+        image.extend_from_slice(&ihdr.width.to_be_bytes());
+        image.extend_from_slice(&ihdr.height.to_be_bytes());
+        image.extend_from_slice(&[ihdr.bit_depth, ihdr.color_type, ihdr.interlace]);
+        image.extend_from_slice(&pixels.data);
+
+        Ok(Checksums {
+            file: algo.digest(bytes),
+            image: algo.digest(&image),
+            chunks: png
+                .chunks()
+                .iter()
+                .map(|c| (c.chunk_type().to_string(), algo.digest(&c.as_bytes())))
+                .collect(),
+        })
+    }
+}
+
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::Chunk;
+    use crate::chunk_type::ChunkType;
+    use flate2::write::ZlibEncoder;
+    use flate2::Compression;
+    use std::io::Write;
+    use std::str::FromStr;
+
+    fn chunk(chunk_type: &str, data: &[u8]) -> Chunk {
+        Chunk::new(ChunkType::from_str(chunk_type).unwrap(), data.to_vec())
+    }
+
+    /// An 8-bit greyscale image `width` pixels wide of `scanlines`
+    fn image(width: u32, scanlines: &[u8], level: u32, comment: &str) -> Vec<u8> {
+        let height = scanlines.len() as u32 / (width + 1);
+        let mut ihdr = [width.to_be_bytes(), height.to_be_bytes()].concat();
+        ihdr.extend_from_slice(&[8, 0, 0, 0, 0]);
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::new(level));
+        encoder.write_all(scanlines).unwrap();
+        Png::from_chunks(vec![
+            chunk("IHDR", &ihdr),
+            chunk("tEXt", comment.as_bytes()),
+            chunk("IDAT", &encoder.finish().unwrap()),
+            chunk("IEND", b""),
+        ])
+        .as_bytes()
+    }
+
+    fn testing_png(level: u32, comment: &str) -> Vec<u8> {
+        image(3, &[0, 1, 2, 3, 0, 4, 5, 6], level, comment)
+    }
+
+    #[test]
+    fn test_digest() {
+        assert_eq!(
+            to_hex(&Algorithm::Sha256.digest(b"abc")),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        assert_eq!(Algorithm::Blake3.digest(b"abc").len(), 32);
+    }
+
+    #[test]
+    fn test_image_digest_ignores_metadata_and_compression() {
+        let a = Checksums::new(&testing_png(1, "Comment\0a"), Algorithm::Sha256).unwrap();
+        let b = Checksums::new(&testing_png(9, "Comment\0b"), Algorithm::Sha256).unwrap();
+        assert_eq!(a.image, b.image);
+        assert_ne!(a.file, b.file);
+    }
+
+    #[test]
+    fn test_image_digest_is_of_pixels() {
+        let digest = |width, scanlines: &[u8]| {
+            Checksums::new(&image(width, scanlines, 6, "Comment\0a"), Algorithm::Sha256)
+                .unwrap()
+                .image
+        };
+        // The Sub filter storing the same row as differences
+        assert_eq!(digest(3, &[0, 1, 2, 3]), digest(3, &[1, 1, 1, 1]));
+        // The same bytes as one row or two
+        assert_ne!(digest(4, &[0, 1, 2, 3, 4]), digest(2, &[0, 1, 2, 0, 3, 4]));
+    }
+
+    #[test]
+    fn test_chunk_digests() {
+        let sums = Checksums::new(&testing_png(6, "Comment\0a"), Algorithm::Blake3).unwrap();
+        let types: Vec<&str> = sums.chunks.iter().map(|(t, _)| t.as_str()).collect();
+        assert_eq!(types, ["IHDR", "tEXt", "IDAT", "IEND"]);
+    }
+}
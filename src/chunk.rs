@@ -1,12 +1,207 @@
 #![allow(unused_variables)]
+#![deny(clippy::unwrap_used, clippy::expect_used)]
 
-#[derive(Debug, PartialEq, Eq)]
-pub struct Chunk;
+use alloc::string::{FromUtf8Error, String};
+use alloc::vec::Vec;
+use core::fmt::Display;
+
+use crate::chunk_type::{ChunkError, ChunkType};
+use crate::crc;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Chunk {
+    chunk_type: ChunkType,
+    data: Vec<u8>,
+    crc: u32,
+}
+
+impl TryFrom<&[u8]> for Chunk {
+    type Error = ChunkError;
+
+    fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
+        let (Some((length, chunk_type)), Some((rest, crc))) =
+            (Chunk::read_head(value), value.split_last_chunk::<4>())
+        else {
+            return Err(ChunkError::InvalidLength(value.len()));
+        };
+        let length = length as usize;
+        if value.len() < Chunk::OVERHEAD || value.len() - Chunk::OVERHEAD != length {
+            return Err(ChunkError::InvalidLength(length));
+        }
+
+        let chunk_type = ChunkType::try_from(chunk_type)?;
+        let data = &rest[8..];
+        let crc = u32::from_be_bytes(*crc);
+
+        let chunk = Chunk::new(chunk_type, data.to_vec());
+        if chunk.crc != crc {
+            #[cfg(feature = "tracing")]
+            tracing::debug!(chunk_type = %chunk.chunk_type, expected = crc, actual = chunk.crc, "crc mismatch");
+            return Err(ChunkError::CrcMismatch {
+                expected: crc,
+                actual: chunk.crc,
+            });
+        }
+        Ok(chunk)
+    }
+}
+
+impl Display for Chunk {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "{}\t{}\t{:08x}",
+            self.chunk_type,
+            self.length(),
+            self.crc
+        )
+    }
+}
 
 impl Chunk {
-    pub fn length() -> usize {
-        todo!()
+    /// Bytes taken by the length, type and CRC fields around the data
+    pub const OVERHEAD: usize = 12;
+    /// Bytes of data the streaming readers take at a time, checking the
+    /// CRC as they go and never allocating a claimed length up front
+    #[cfg(feature = "std")]
+    pub(crate) const READ_BLOCK: usize = 64 << 10;
+
+    pub fn new(chunk_type: ChunkType, data: Vec<u8>) -> Chunk {
+        let mut hasher = crc::CrcHasher::new();
+        hasher.update(&chunk_type.bytes());
+        hasher.update(&data);
+        let crc = hasher.finalize();
+        Chunk {
+            chunk_type,
+            data,
+            crc,
+        }
+    }
+    /// The length and type fields of a chunk's first eight bytes; the type
+    /// isn't checked
+    pub(crate) fn split_head(head: [u8; 8]) -> (u32, [u8; 4]) {
+        let [a, b, c, d, e, f, g, h] = head;
+        (u32::from_be_bytes([a, b, c, d]), [e, f, g, h])
+    }
+    /// [`Chunk::split_head`] of the start of `bytes`, or `None` if they're
+    /// too short to hold the fields
+    pub(crate) fn read_head(bytes: &[u8]) -> Option<(u32, [u8; 4])> {
+        bytes
+            .first_chunk::<8>()
+            .map(|head| Chunk::split_head(*head))
+    }
+    /// The big-endian CRC field of a chunk with `length` bytes of data whose
+    /// bytes start `bytes`, or `None` if they don't reach it
+    pub(crate) fn read_crc(bytes: &[u8], length: usize) -> Option<u32> {
+        let field = bytes.get(length.checked_add(8)?..)?.first_chunk::<4>()?;
+        Some(u32::from_be_bytes(*field))
+    }
+    /// A chunk whose CRC the caller has already computed and checked, as
+    /// streaming readers do while the data arrives
+    #[cfg(any(feature = "net", feature = "tokio"))]
+    pub(crate) fn from_checked(chunk_type: ChunkType, data: Vec<u8>, crc: u32) -> Chunk {
+        Chunk {
+            chunk_type,
+            data,
+            crc,
+        }
+    }
+    /// Overwrite the data from `offset` on with `bytes`, updating the CRC
+    /// from the bytes changed rather than rehashing all of it
+    pub fn patch(&mut self, offset: usize, bytes: &[u8]) -> Result<(), ChunkError> {
+        let end = offset
+            .checked_add(bytes.len())
+            .filter(|&end| end <= self.data.len());
+        let Some(end) = end else {
+            let end = offset.saturating_add(bytes.len());
+            return Err(ChunkError::OutOfRange {
+                end,
+                length: self.data.len(),
+            });
+        };
+        let trailing = (self.data.len() - end) as u64;
+        self.crc = crc::patch(self.crc, &self.data[offset..end], bytes, trailing);
+        self.data[offset..end].copy_from_slice(bytes);
+        Ok(())
+    }
+    /// The same data under another type, with the CRC recomputed
+    pub fn with_type(self, chunk_type: ChunkType) -> Chunk {
+        Chunk::new(chunk_type, self.data)
+    }
+    pub fn length(&self) -> u32 {
+        self.data.len() as u32
+    }
+    pub fn chunk_type(&self) -> &ChunkType {
+        &self.chunk_type
+    }
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+    pub fn crc(&self) -> u32 {
+        self.crc
+    }
+    /// The type, data and CRC, giving up the data without a copy
+    #[cfg(feature = "bytes")]
+    pub(crate) fn into_parts(self) -> (ChunkType, Vec<u8>, u32) {
+        (self.chunk_type, self.data, self.crc)
     }
+    pub fn data_as_string(&self) -> Result<String, FromUtf8Error> {
+        String::from_utf8(self.data.clone())
+    }
+    /// The length and type fields that come before the data
+    #[cfg(feature = "std")]
+    pub(crate) fn head(&self) -> [u8; 8] {
+        let mut head = [0; 8];
+        head[..4].copy_from_slice(&self.length().to_be_bytes());
+        head[4..].copy_from_slice(&self.chunk_type.bytes());
+        head
+    }
+    /// Write the chunk as [`Chunk::as_bytes`] serializes it, in vectored
+    /// writes straight from the data rather than through a copy of it
+    #[cfg(feature = "std")]
+    pub fn write_to(&self, mut writer: impl std::io::Write) -> std::io::Result<()> {
+        let (head, crc) = (self.head(), self.crc.to_be_bytes());
+        let mut slices = [&head[..], &self.data, &crc].map(std::io::IoSlice::new);
+        write_all_vectored(&mut writer, &mut slices)
+    }
+    /// Serialize the chunk as length, type, data and CRC
+    pub fn as_bytes(&self) -> Vec<u8> {
+        self.length()
+            .to_be_bytes()
+            .iter()
+            .chain(self.chunk_type.bytes().iter())
+            .chain(self.data.iter())
+            .chain(self.crc.to_be_bytes().iter())
+            .copied()
+            .collect()
+    }
+}
+
+/// Slices handed to one `write_vectored` call: what Linux's `writev` takes,
+/// and few enough that writers summing their lengths each call stay quick
+#[cfg(feature = "std")]
+pub(crate) const MAX_SLICES: usize = 1024;
+
+/// Write all of `slices`, as `write_all` does for one buffer
+#[cfg(feature = "std")]
+pub(crate) fn write_all_vectored(
+    writer: &mut impl std::io::Write,
+    mut slices: &mut [std::io::IoSlice],
+) -> std::io::Result<()> {
+    use std::io::{ErrorKind, IoSlice};
+
+    // Skip leading empty slices, which writers may report writing as zero
+    // bytes, so that a zero-byte write only ever means the writer is stuck
+    IoSlice::advance_slices(&mut slices, 0);
+    while !slices.is_empty() {
+        match writer.write_vectored(&slices[..slices.len().min(MAX_SLICES)]) {
+            Ok(0) => return Err(ErrorKind::WriteZero.into()),
+            Ok(n) => IoSlice::advance_slices(&mut slices, n),
+            Err(e) if e.kind() == ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
 }
 
 #[cfg(test)]
@@ -36,12 +231,79 @@ mod tests {
     #[test]
     fn test_new_chunk() {
         let chunk_type = ChunkType::from_str("RuSt").unwrap();
-        let data = "This is where your secret message will be!".as_bytes().to_vec();
+        let data = "This is where your secret message will be!"
+            .as_bytes()
+            .to_vec();
         let chunk = Chunk::new(chunk_type, data);
         assert_eq!(chunk.length(), 42);
         assert_eq!(chunk.crc(), 2882656334);
     }
 
+    #[test]
+    fn test_chunk_with_type() {
+        let chunk = testing_chunk().with_type(ChunkType::from_str("ruSt").unwrap());
+        assert_eq!(chunk.chunk_type().to_string(), "ruSt");
+        assert_eq!(
+            chunk.data_as_string().unwrap(),
+            "This is where your secret message will be!"
+        );
+        assert_eq!(
+            chunk.crc(),
+            Chunk::new(*chunk.chunk_type(), chunk.data().to_vec()).crc()
+        );
+        assert_ne!(chunk.crc(), 2882656334);
+    }
+
+    #[test]
+    fn test_chunk_patch() {
+        let mut chunk = testing_chunk();
+        chunk.patch(14, b"THERE").unwrap();
+        assert_eq!(
+            chunk.data_as_string().unwrap(),
+            "This is where THEREsecret message will be!"
+        );
+        assert_eq!(
+            chunk.crc(),
+            Chunk::new(*chunk.chunk_type(), chunk.data().to_vec()).crc()
+        );
+        assert_eq!(
+            chunk.patch(40, b"abc"),
+            Err(ChunkError::OutOfRange {
+                end: 43,
+                length: 42
+            })
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn test_write_to() {
+        let empty = Chunk::new(ChunkType::from_str("IEND").unwrap(), Vec::new());
+        for chunk in [testing_chunk(), empty] {
+            let mut out = Vec::new();
+            chunk.write_to(&mut out).unwrap();
+            assert_eq!(out, chunk.as_bytes());
+        }
+    }
+
+    #[test]
+    fn test_empty_chunk() {
+        let iend = Chunk::new(ChunkType::from_str("IEND").unwrap(), Vec::new());
+        assert_eq!(iend.length(), 0);
+        assert_eq!(iend.crc(), 0xAE42_6082);
+        assert_eq!(iend.data_as_string().unwrap(), "");
+        let bytes = iend.as_bytes();
+        assert_eq!(
+            bytes,
+            [0, 0, 0, 0, b'I', b'E', b'N', b'D', 0xAE, 0x42, 0x60, 0x82]
+        );
+        assert_eq!(Chunk::try_from(bytes.as_slice()).unwrap(), iend);
+        assert!(Chunk::try_from(&bytes[..11]).is_err());
+        let mut corrupt = bytes.clone();
+        corrupt[11] ^= 1;
+        assert!(Chunk::try_from(corrupt.as_slice()).is_err());
+    }
+
     #[test]
     fn test_chunk_length() {
         let chunk = testing_chunk();
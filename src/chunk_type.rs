@@ -1,27 +1,59 @@
-#![allow(unused_variables)]
+#![deny(clippy::unwrap_used, clippy::expect_used)]
 
-use std::fmt::Display;
-use std::str::FromStr;
+use core::fmt::Display;
+use core::str::FromStr;
 
-use crate::chunk::Chunk;
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ChunkType([u8; 4]);
 
 #[derive(Debug, PartialEq, Eq)]
-pub struct ChunkType(Chunk);
-
-#[derive(Debug)]
 pub enum ChunkError {
-    ErrorConvertFromString(Chunk),
     InvalidChunk,
+    InvalidLength(usize),
+    CrcMismatch {
+        expected: u32,
+        actual: u32,
+    },
+    /// A patch ending at `end`, past the chunk's `length` bytes of data
+    OutOfRange {
+        end: usize,
+        length: usize,
+    },
 }
 
-impl TryFrom<Chunk> for ChunkType {
+impl Display for ChunkError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            ChunkError::InvalidChunk => write!(f, "invalid chunk type"),
+            ChunkError::InvalidLength(len) => write!(f, "invalid chunk length: {}", len),
+            ChunkError::CrcMismatch { expected, actual } => {
+                write!(
+                    f,
+                    "crc mismatch: expected {:#010x}, got {:#010x}",
+                    expected, actual
+                )
+            }
+            ChunkError::OutOfRange { end, length } => {
+                write!(
+                    f,
+                    "patch ends at byte {}, past the {} bytes of data",
+                    end, length
+                )
+            }
+        }
+    }
+}
+
+impl core::error::Error for ChunkError {}
+
+impl TryFrom<[u8; 4]> for ChunkType {
     type Error = ChunkError;
 
-    fn try_from(value: Chunk) -> Result<Self, Self::Error> {
-        if value.is_ascii() {
-            return Ok(ChunkType(value))
+    fn try_from(value: [u8; 4]) -> Result<Self, Self::Error> {
+        if ChunkType::is_letters(&value) {
+            Ok(ChunkType(value))
         } else {
-            return Err(ChunkError::InvalidChunk)
+            Err(ChunkError::InvalidChunk)
         }
     }
 }
@@ -30,62 +62,73 @@ impl FromStr for ChunkType {
     type Err = ChunkError;
 
     fn from_str(value: &str) -> Result<Self, Self::Err> {
-        let alpha_only = value.chars().all(char::is_alphabetic);
-        let parseable = Self::from_slice(value.as_bytes());
-
-        match (alpha_only, parseable) {
-            (true, Some(x)) => Ok(x),
-            _ => Err(ChunkError::InvalidChunk)
-        }
+        let bytes: [u8; 4] = value
+            .as_bytes()
+            .try_into()
+            .map_err(|_| ChunkError::InvalidChunk)?;
+        Self::try_from(bytes)
     }
 }
 
 impl Display for ChunkType {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        f.write_str(&String::from_utf8(Vec::from(self.0)).unwrap())
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_str(self.as_str())
     }
 }
 
 impl ChunkType {
-    /// Parse a slice into [u8; 4]
-    fn from_slice(array: &[u8]) -> Option<Self> {
-        match array.len() {
-            4 => {
-                let mut temp: [u8; 4] = [0,0,0,0];
-                for i in 0..array.len() {
-                    temp[i] = array[i] as u8;
-                }
-                Some(ChunkType(temp))
-            },
-            _ => None
+    /// A type known to be valid, for constants: `ChunkType::new(*b"IEND")`.
+    /// Panics, at compile time in a constant, unless every byte is an ASCII
+    /// letter
+    pub const fn new(bytes: [u8; 4]) -> ChunkType {
+        assert!(
+            ChunkType::is_letters(&bytes),
+            "chunk types are four ASCII letters"
+        );
+        ChunkType(bytes)
+    }
+    const fn is_letters(bytes: &[u8; 4]) -> bool {
+        let mut i = 0;
+        while i < bytes.len() {
+            if !bytes[i].is_ascii_alphabetic() {
+                return false;
+            }
+            i += 1;
         }
+        true
     }
-    pub fn bytes(self) -> Chunk {
+    pub fn bytes(&self) -> [u8; 4] {
         self.0
     }
-    pub fn is_critical(self) -> bool {
+    /// The type as text, borrowed rather than allocated like `to_string`
+    pub fn as_str(&self) -> &str {
+        // Every byte was checked to be an ASCII letter when it was made, so
+        // this is never the empty default
+        core::str::from_utf8(&self.0).unwrap_or_default()
+    }
+    pub fn is_critical(&self) -> bool {
         self.0[0].is_ascii_uppercase()
     }
-    pub fn is_public(self) -> bool {
+    pub fn is_public(&self) -> bool {
         self.0[1].is_ascii_uppercase()
     }
-    pub fn is_reserved_bit_valid(self) -> bool {
+    pub fn is_reserved_bit_valid(&self) -> bool {
         self.0[2].is_ascii_uppercase()
     }
-    pub fn is_safe_to_copy(self) -> bool {
+    pub fn is_safe_to_copy(&self) -> bool {
         self.0[3].is_ascii_lowercase()
     }
-    pub fn is_valid(self) -> bool {
+    pub fn is_valid(&self) -> bool {
         self.is_reserved_bit_valid()
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use std::{assert_eq, format};
     use super::*;
     use std::convert::TryFrom;
     use std::str::FromStr;
+    use std::{assert_eq, format};
 
     #[test]
     pub fn test_chunk_type_from_bytes() {
@@ -102,6 +145,15 @@ mod tests {
         assert_eq!(expected, actual);
     }
 
+    #[test]
+    pub fn test_chunk_type_new() {
+        const RUST: ChunkType = ChunkType::new(*b"RuSt");
+        assert_eq!(RUST, ChunkType::from_str("RuSt").unwrap());
+        assert!(ChunkType::from_str("RuS").is_err());
+        assert!(ChunkType::from_str("RuStt").is_err());
+        assert!(std::panic::catch_unwind(|| ChunkType::new(*b"Ru1t")).is_err());
+    }
+
     #[test]
     pub fn test_chunk_type_is_critical() {
         let chunk = ChunkType::from_str("RuSt").unwrap();
@@ -169,6 +221,7 @@ mod tests {
     pub fn test_chunk_type_string() {
         let chunk = ChunkType::from_str("RuSt").unwrap();
         assert_eq!(&chunk.to_string(), "RuSt");
+        assert_eq!(chunk.as_str(), "RuSt");
     }
 
     #[test]
@@ -0,0 +1,1262 @@
+use std::fs;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+use clap::ValueEnum;
+use ping::analyze;
+use ping::attachment::Attachment;
+use ping::batch::{self, Operation, Summary};
+use ping::cache;
+use ping::checksum::{to_hex, Checksums};
+use ping::chunk::Chunk;
+use ping::chunk_type::ChunkType;
+use ping::container::{self, Container};
+use ping::crypto::{self, CryptoSuite, KdfParams};
+#[cfg(feature = "exiftool")]
+use ping::exiftool;
+use ping::grep::Matcher;
+use ping::lazy::LazyPng;
+#[cfg(feature = "net")]
+use ping::net;
+use ping::payload;
+use ping::perf;
+use ping::png::{Mode, ParseOptions, Png, PngError};
+use ping::recipients;
+use ping::replay::{Direction, NonceLog};
+use ping::reproducible;
+#[cfg(feature = "script")]
+use ping::script::Script;
+#[cfg(feature = "serve")]
+use ping::server;
+use ping::shred;
+use ping::sign;
+use ping::split;
+use ping::stats::{self, Histogram};
+use ping::stego::{self, Method};
+use ping::stream;
+use ping::streaming;
+use ping::strength;
+use ping::validate::{self, ValidateOptions};
+use ping::Result;
+
+#[cfg(feature = "script")]
+use crate::args::ScriptArgs;
+#[cfg(feature = "serve")]
+use crate::args::ServeArgs;
+use crate::args::{
+    AnalyzeArgs, BatchArgs, BatchOperation, CapacityArgs, CheckArgs, ChecksumArgs, DecodeArgs,
+    EncodeArgs, GrepArgs, JoinArgs, KeygenArgs, PrintArgs, RemoveArgs, RenameChunkArgs,
+    ReorderArgs, ScanArgs, SignArgs, SplitArgs, StatsArgs, VerifyArgs,
+};
+#[cfg(feature = "exiftool")]
+use crate::args::{MetadataArgs, MetadataCommand};
+
+/// `path` as an http(s) URL, if it is one
+#[cfg(feature = "net")]
+fn url(path: &Path) -> Option<&str> {
+    path.to_str().filter(|path| net::is_url(path))
+}
+
+/// The contents of `path`, downloading it if it's a URL
+fn read_input(path: &Path) -> Result<Vec<u8>> {
+    let _stage = perf::stage("read");
+    #[cfg(feature = "net")]
+    if let Some(url) = url(path) {
+        return Ok(net::fetch(url, &net::Options::default())?);
+    }
+    Ok(fs::read(path)?)
+}
+
+fn read_png(path: &Path) -> Result<Png> {
+    #[cfg(feature = "net")]
+    if let Some(url) = url(path) {
+        return Ok(Png::from_url(url, &net::Options::default())?);
+    }
+    let bytes = read_input(path)?;
+    let _stage = perf::stage("parse");
+    let png = Png::try_from(bytes.as_slice())?;
+    warn(&png);
+    Ok(png)
+}
+
+/// Print what parsing accepted that might be corruption or hidden data
+fn warn(png: &Png) {
+    for warning in png.warnings() {
+        eprintln!("warning: {}", warning);
+    }
+}
+
+/// A file to embed in or extract from: a PNG, or a file of another format
+/// a built-in container holds payloads in. The PNG is boxed, as its
+/// inline chunk storage dwarfs the other variant
+enum Carrier {
+    Png(Box<Png>),
+    Other(&'static dyn Container, Vec<u8>),
+}
+
+impl Carrier {
+    /// Read `path`. With `damaged` set, PNG chunks failing their CRC check
+    /// are kept, with a warning, so error-corrected payloads in them can be
+    /// repaired
+    fn read(path: &Path, damaged: bool) -> Result<Carrier> {
+        let bytes = read_input(path)?;
+        if let Some(container) = container::detect(&bytes) {
+            return Ok(Carrier::Other(container, bytes));
+        }
+        let _stage = perf::stage("parse");
+        if !damaged {
+            let png = Png::try_from(bytes.as_slice())?;
+            warn(&png);
+            return Ok(Carrier::Png(Box::new(png)));
+        }
+        let (png, mismatches) = Png::parse_ignoring_crc(&bytes)?;
+        for mismatch in mismatches {
+            eprintln!("warning: {}", mismatch);
+        }
+        warn(&png);
+        Ok(Carrier::Png(Box::new(png)))
+    }
+
+    /// Fail unless payloads can go in this file by `method`; other formats
+    /// only have the one place to put them
+    fn check(&self, method: Method) -> Result<()> {
+        match self {
+            Carrier::Other(container, _) if method != Method::Chunk => {
+                Err(format!("{} files only take the chunk method", container.name()).into())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        match self {
+            Carrier::Png(png) => png.as_bytes(),
+            Carrier::Other(_, bytes) => bytes.clone(),
+        }
+    }
+}
+
+/// Where to write the edited `file`: `output` if given, else over `file`,
+/// which a URL can't be
+fn destination<'a>(file: &'a Path, output: Option<&'a Path>) -> Result<&'a Path> {
+    #[cfg(feature = "net")]
+    if output.is_none() && url(file).is_some() {
+        return Err("can't write back to a URL; pass an output path".into());
+    }
+    Ok(output.unwrap_or(file))
+}
+
+/// Check an encrypted payload against the nonce log at `path` and add it
+fn log_nonce(path: Option<&Path>, direction: Direction, data: &[u8]) -> Result<()> {
+    let (Some(path), Some(nonce)) = (path, payload::nonce_id(data)) else {
+        return Ok(());
+    };
+    let mut log = NonceLog::open(path)?;
+    log.record(direction, &nonce, data)?;
+    Ok(log.save()?)
+}
+
+/// Password from PING_PASSWORD, or prompted for on the terminal
+fn password() -> Result<String> {
+    read_password("PING_PASSWORD", "Password")
+}
+
+fn read_password(var: &str, prompt: &str) -> Result<String> {
+    match std::env::var(var) {
+        Ok(password) => Ok(password),
+        Err(_) => Ok(rpassword::prompt_password(format!("{}: ", prompt))?),
+    }
+}
+
+/// A password to encrypt with, confirmed if typed in. A weak one typed in
+/// is refused unless `allow_weak`; from the environment, where scripts set
+/// it, it only gets a warning
+fn new_password(var: &str, prompt: &str, allow_weak: bool) -> Result<String> {
+    if let Ok(password) = std::env::var(var) {
+        if let Some(weakness) = strength::weakness(&password) {
+            eprintln!("warning: {}", weakness);
+        }
+        return Ok(password);
+    }
+    let password = rpassword::prompt_password(format!("{}: ", prompt))?;
+    if rpassword::prompt_password(format!("Confirm {}: ", prompt.to_lowercase()))? != password {
+        return Err("passwords do not match".into());
+    }
+    match strength::weakness(&password) {
+        Some(weakness) if !allow_weak => {
+            Err(format!("{}\npass --allow-weak-password to use it anyway", weakness).into())
+        }
+        Some(weakness) => {
+            eprintln!("warning: {}", weakness);
+            Ok(password)
+        }
+        None => Ok(password),
+    }
+}
+
+/// Key material from a file, or the empty key
+fn read_key(path: Option<&Path>) -> Result<Vec<u8>> {
+    match path {
+        Some(path) => Ok(fs::read(path)?),
+        None => Ok(Vec::new()),
+    }
+}
+
+/// The time reproducible output records, if SOURCE_DATE_EPOCH sets one
+fn source_date_epoch() -> Result<Option<u64>> {
+    let Ok(epoch) = std::env::var(reproducible::SOURCE_DATE_EPOCH) else {
+        return Ok(None);
+    };
+    match epoch.trim().parse() {
+        Ok(epoch) => Ok(Some(epoch)),
+        Err(_) => Err(format!(
+            "{} must be seconds since 1970, not {:?}",
+            reproducible::SOURCE_DATE_EPOCH,
+            epoch
+        )
+        .into()),
+    }
+}
+
+pub fn encode(args: EncodeArgs) -> Result<()> {
+    if let Some(threads) = args.threads.or(args.deterministic.then_some(1)) {
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build_global()?;
+    }
+    if args.stream {
+        return encode_stream(&args);
+    }
+    let epoch = match args.deterministic {
+        true => source_date_epoch()?,
+        false => None,
+    };
+    // Chunks added to a PNG on disk go in without parsing the rest of it,
+    // unless it's to be normalized
+    let appending = args.method == Method::Chunk && !args.deterministic;
+    let mut carrier = match appending && is_png_file(&args.file) {
+        true => None,
+        false => {
+            let carrier = Carrier::read(&args.file, false)?;
+            carrier.check(args.method)?;
+            Some(carrier)
+        }
+    };
+    let chunk_type = ChunkType::from_str(&args.chunk_type)?;
+    // Version 1 payloads have no header, so compression is a layer of its own
+    let compress = |message: &[u8]| match args.payload_version {
+        1 => payload::compress(message, args.compress),
+        _ => args.compress.compress(message),
+    };
+    let message = match args.from_file {
+        true if args.payload_version < 2 => {
+            return Err("embedding a file needs payload version 2 or later".into())
+        }
+        true => {
+            let mut attachment = Attachment::read(Path::new(&args.message))?;
+            if args.deterministic {
+                attachment.modified = epoch.unwrap_or(0);
+            }
+            attachment.to_bytes()
+        }
+        false => args.message.clone().into_bytes(),
+    };
+    let data = compress(&message)?;
+    let keyfile = match &args.keyfile {
+        Some(path) => Some(fs::read(path)?),
+        None => None,
+    };
+    let password = match args.encrypt {
+        true => Some(new_password(
+            "PING_PASSWORD",
+            "Password",
+            args.allow_weak_password,
+        )?),
+        false => None,
+    };
+    let encrypting = perf::stage("encrypt");
+    let data = match args.encrypt || keyfile.is_some() {
+        true => {
+            let params = KdfParams {
+                memory_kib: args.kdf_memory,
+                time_cost: args.kdf_time,
+                ..KdfParams::default()
+            };
+            let secret = crypto::secret(password.as_deref(), keyfile.as_deref());
+            match (args.deniable, &args.decoy) {
+                (true, Some(decoy)) => {
+                    let decoy = compress(decoy.as_bytes())?;
+                    let decoy_password = new_password(
+                        "PING_DECOY_PASSWORD",
+                        "Decoy password",
+                        args.allow_weak_password,
+                    )?;
+                    if Some(&decoy_password) == password.as_ref() {
+                        return Err("decoy password must differ from the password".into());
+                    }
+                    let decoy_secret = crypto::secret(Some(&decoy_password), keyfile.as_deref());
+                    payload::encrypt_deniable(
+                        &data,
+                        &secret,
+                        Some((&decoy, &decoy_secret)),
+                        &params,
+                    )?
+                }
+                (true, None) => payload::encrypt_deniable(&data, &secret, None, &params)?,
+                (false, _) => {
+                    let suite = CryptoSuite {
+                        kdf: args.kdf,
+                        cipher: args.cipher,
+                    };
+                    payload::encrypt(&data, &secret, &params, suite)?
+                }
+            }
+        }
+        false if !args.recipient.is_empty() => {
+            let recipients = args
+                .recipient
+                .iter()
+                .map(|path| recipients::read_public_key(path))
+                .collect::<Result<Vec<_>>>()?;
+            payload::encrypt_to(&data, &recipients)?
+        }
+        false if !args.age_recipient.is_empty() => encrypt_age(&data, &args.age_recipient)?,
+        false => data,
+    };
+    drop(encrypting);
+    let signing_key = match &args.sign {
+        Some(path) => Some(sign::read_signing_key(path)?),
+        None => None,
+    };
+    let data = match &signing_key {
+        Some(key) => payload::sign(&data, &chunk_type, key),
+        None => data,
+    };
+    let integrity_key = read_key(args.integrity_key.as_deref())?;
+    let data = match args.integrity {
+        true => payload::tag(&data, &integrity_key),
+        false => data,
+    };
+    let data = match args.payload_version {
+        1 => data,
+        _ => {
+            let flags = [
+                (args.encrypt, payload::Header::ENCRYPTED),
+                (args.integrity, payload::Header::TAGGED),
+                (args.sign.is_some(), payload::Header::SIGNED),
+                (args.deniable, payload::Header::DENIABLE),
+                (args.from_file, payload::Header::ATTACHMENT),
+                (!args.recipient.is_empty(), payload::Header::RECIPIENTS),
+                (args.keyfile.is_some(), payload::Header::KEYFILE),
+                (!args.age_recipient.is_empty(), payload::Header::AGE),
+            ];
+            let header = payload::Header {
+                version: payload::VERSION,
+                flags: flags
+                    .iter()
+                    .filter(|(set, _)| *set)
+                    .fold(0, |f, (_, b)| f | b),
+                codec: args.compress,
+                length: message.len() as u32,
+            };
+            payload::seal(header, &data)
+        }
+    };
+    log_nonce(args.nonce_log.as_deref(), Direction::Encoded, &data)?;
+    let digest = blake3::hash(&data);
+    let protect = |data: &[u8]| match args.ecc {
+        Some(parity) => payload::protect(data, parity),
+        None => Ok(data.to_vec()),
+    };
+    let output = destination(&args.file, args.output.as_deref())?;
+    let chunks = |data: Vec<u8>| {
+        payload::split(data, args.max_chunk_size)
+            .into_iter()
+            .map(|part| Ok(Chunk::new(chunk_type, protect(&part)?)))
+            .collect::<Result<Vec<_>>>()
+    };
+    match (&mut carrier, args.method) {
+        (None, _) => append_chunks(&args.file, output, &chunks(data)?)?,
+        (Some(Carrier::Png(png)), Method::Chunk) => {
+            for chunk in chunks(data)? {
+                png.append_chunk(chunk);
+            }
+        }
+        (Some(Carrier::Png(png)), Method::Lsb) => {
+            for chunk in stego::embed(png, &protect(&data)?)? {
+                eprintln!(
+                    "warning: dropped {} chunk, which isn't safe to copy into rewritten image data",
+                    chunk.chunk_type()
+                );
+            }
+        }
+        (Some(Carrier::Png(png)), Method::Profile) => stego::embed_profile(png, &protect(&data)?)?,
+        (Some(Carrier::Png(png)), Method::Trailer) => stego::embed_trailer(png, &protect(&data)?)?,
+        (Some(Carrier::Other(container, bytes)), _) => {
+            *bytes = container.embed(bytes, &protect(&data)?)?
+        }
+    }
+    if let (true, Some(Carrier::Png(png))) = (args.deterministic, &mut carrier) {
+        reproducible::normalize(png, epoch)?;
+    }
+    if let Some(carrier) = &carrier {
+        let _stage = perf::stage("write");
+        fs::write(output, carrier.to_bytes())?;
+    }
+    if !args.verify {
+        return Ok(());
+    }
+
+    let (written, _) = extract(
+        &Carrier::read(output, false)?,
+        args.method,
+        &args.chunk_type,
+    )?;
+    if blake3::hash(&written) != digest {
+        return Err("verify failed: the payload read back differs from the one written".into());
+    }
+    // without the recipients' secret keys only the payload bytes can be checked
+    if args.recipient.is_empty() && args.age_recipient.is_empty() {
+        let keys = payload::Keys {
+            password,
+            integrity_key: Some(integrity_key),
+            verifying_key: signing_key.map(|key| key.verifying_key()),
+            keyfile,
+            ..payload::Keys::default()
+        };
+        if payload::decode(&written, &chunk_type, &keys)? != message {
+            return Err("verify failed: the payload decodes to a different message".into());
+        }
+    }
+    eprintln!(
+        "verified: payload {} reads back intact",
+        &digest.to_hex()[..16]
+    );
+    Ok(())
+}
+
+/// Whether `path` names a PNG on disk rather than a URL or a file of
+/// another format
+fn is_png_file(path: &Path) -> bool {
+    #[cfg(feature = "net")]
+    if url(path).is_some() {
+        return false;
+    }
+    let mut header = [0; 8];
+    let read = fs::File::open(path).and_then(|mut file| file.read_exact(&mut header));
+    read.is_ok() && header == Png::STANDARD_HEADER
+}
+
+/// Copy `file` to a temporary copy of `output` with `chunks` before IEND,
+/// renamed into place once complete
+fn append_chunks(file: &Path, output: &Path, chunks: &[Chunk]) -> Result<()> {
+    let _stage = perf::stage("write");
+    let mut temp = output.as_os_str().to_os_string();
+    temp.push(".tmp");
+    let mut out = fs::File::create(&temp)?;
+    streaming::append(fs::File::open(file)?, &mut out, chunks)?;
+    out.sync_all()?;
+    fs::rename(&temp, output)?;
+    Ok(())
+}
+
+#[cfg(feature = "age")]
+fn encrypt_age(data: &[u8], recipients: &[String]) -> Result<Vec<u8>> {
+    Ok(payload::encrypt_age(data, recipients)?)
+}
+
+#[cfg(not(feature = "age"))]
+fn encrypt_age(_data: &[u8], _recipients: &[String]) -> Result<Vec<u8>> {
+    Err(payload::PayloadError::AgeUnsupported.into())
+}
+
+/// Stream the file named by MESSAGE into chunks written straight to a
+/// temporary copy of the output, renamed into place once complete
+fn encode_stream(args: &EncodeArgs) -> Result<()> {
+    if args.method != Method::Chunk {
+        return Err("--stream only works with the chunk method".into());
+    }
+    let png = read_png(&args.file)?;
+    let chunk_type = ChunkType::from_str(&args.chunk_type)?;
+    let keyfile = match &args.keyfile {
+        Some(path) => Some(fs::read(path)?),
+        None => None,
+    };
+    let password = match args.encrypt {
+        true => Some(new_password(
+            "PING_PASSWORD",
+            "Password",
+            args.allow_weak_password,
+        )?),
+        false => None,
+    };
+    let secret = match args.encrypt || keyfile.is_some() {
+        true => Some(crypto::secret(password.as_deref(), keyfile.as_deref())),
+        false => None,
+    };
+    let params = KdfParams {
+        memory_kib: args.kdf_memory,
+        time_cost: args.kdf_time,
+        ..KdfParams::default()
+    };
+
+    let output = destination(&args.file, args.output.as_deref())?;
+    let mut temp = output.as_os_str().to_os_string();
+    temp.push(".tmp");
+    let mut out = BufWriter::new(fs::File::create(&temp)?);
+    out.write_all(png.header())?;
+    let (iend, chunks) = match png.chunks().split_last() {
+        Some((last, rest)) if last.chunk_type().as_str() == "IEND" => (Some(last), rest),
+        _ => (None, png.chunks()),
+    };
+    for chunk in chunks {
+        chunk.write_to(&mut out)?;
+    }
+    stream::encode(
+        &mut fs::File::open(&args.message)?,
+        args.compress,
+        secret.as_deref().map(|secret| (secret, &params)),
+        args.max_chunk_size,
+        |data| Chunk::new(chunk_type, data).write_to(&mut out),
+    )?;
+    if let Some(iend) = iend {
+        iend.write_to(&mut out)?;
+    }
+    out.write_all(png.trailing())?;
+    out.into_inner()?.sync_all()?;
+    fs::rename(&temp, output)?;
+    Ok(())
+}
+
+/// The payload embedded by `method`, error-corrected and reassembled, and
+/// the number of bytes corrected
+fn extract(carrier: &Carrier, method: Method, chunk_type: &str) -> Result<(Vec<u8>, usize)> {
+    let png = match carrier {
+        Carrier::Png(png) => png,
+        Carrier::Other(container, bytes) => {
+            return Ok(payload::correct(&container.extract(bytes)?)?)
+        }
+    };
+    Ok(match method {
+        Method::Chunk => {
+            if png.chunk_by_type(chunk_type).is_none() {
+                return Err(PngError::ChunkNotFound(chunk_type.to_string()).into());
+            }
+            let parts = png
+                .chunks_by_type(chunk_type)
+                .map(|c| payload::correct(c.data()))
+                .collect::<std::result::Result<Vec<_>, _>>()?;
+            let corrected = parts.iter().map(|(_, n)| n).sum();
+            let data = payload::reassemble(parts.iter().map(|(part, _)| part.as_slice()))?;
+            (data, corrected)
+        }
+        Method::Lsb => payload::correct(&stego::extract(png)?)?,
+        Method::Profile => payload::correct(&stego::extract_profile(png)?)?,
+        Method::Trailer => payload::correct(&stego::extract_trailer(png)?)?,
+    })
+}
+
+/// The key derivation cost decoding accepts, unless --no-kdf-limit
+fn kdf_limit(args: &DecodeArgs) -> Option<KdfParams> {
+    (!args.no_kdf_limit).then_some(KdfParams::DECODE_LIMIT)
+}
+
+/// Decode a streamed payload straight to the output file or stdout
+/// Decode the stream in `chunks` with `secret` to `output`, or stdout
+fn decode_stream_to(
+    chunks: &[&[u8]],
+    secret: Option<&[u8]>,
+    limit: Option<&KdfParams>,
+    output: Option<&Path>,
+) -> Result<()> {
+    let chunks = || chunks.iter().copied();
+    let Some(path) = output else {
+        return stream::decode(chunks(), secret, limit, std::io::stdout().lock());
+    };
+    // Renamed into place only once the whole stream has decoded
+    let mut temp = path.as_os_str().to_os_string();
+    temp.push(".tmp");
+    let mut out = BufWriter::new(fs::File::create(&temp)?);
+    let decoded = stream::decode(chunks(), secret, limit, &mut out)
+        .and_then(|()| Ok(out.into_inner().map_err(|e| e.into_error())?.sync_all()?));
+    match decoded {
+        Ok(()) => Ok(fs::rename(&temp, path)?),
+        Err(e) => {
+            let _ = fs::remove_file(&temp);
+            Err(e)
+        }
+    }
+}
+
+fn decode_stream(args: &DecodeArgs, png: &Png) -> Result<()> {
+    let chunks: Vec<&[u8]> = png
+        .chunks_by_type(&args.chunk_type)
+        .map(|c| c.data())
+        .collect();
+    let output = match args.output.as_deref() {
+        Some(path) if path == Path::new("auto") => {
+            return Err("streamed payloads keep no file name; pass -o PATH".into())
+        }
+        output => output,
+    };
+    let limit = kdf_limit(args);
+    let limit = limit.as_ref();
+    if !chunks.first().is_some_and(|c| stream::is_encrypted(c)) {
+        return decode_stream_to(&chunks, None, limit, output);
+    }
+    let Some(keyfile) = &args.keyfile else {
+        let secret = crypto::secret(Some(&password()?), None);
+        return decode_stream_to(&chunks, Some(&secret), limit, output);
+    };
+    // Streams don't record whether a password went with the keyfile, so
+    // the keyfile alone is tried before asking for one
+    let keyfile = fs::read(keyfile)?;
+    if std::env::var("PING_PASSWORD").is_err() {
+        let secret = crypto::secret(None, Some(&keyfile));
+        match decode_stream_to(&chunks, Some(&secret), limit, output) {
+            Err(e) if e.downcast_ref() == Some(&stream::StreamError::Decrypt) => {}
+            decoded => return decoded,
+        }
+    }
+    let secret = crypto::secret(Some(&password()?), Some(&keyfile));
+    decode_stream_to(&chunks, Some(&secret), limit, output)
+}
+
+pub fn decode(args: DecodeArgs) -> Result<()> {
+    let carrier = Carrier::read(&args.file, true)?;
+    carrier.check(args.method)?;
+    let chunk_type = ChunkType::from_str(&args.chunk_type)?;
+    if let Carrier::Png(png) = &carrier {
+        if args.method == Method::Chunk
+            && png
+                .chunk_by_type(&args.chunk_type)
+                .is_some_and(|c| stream::is_stream(c.data()))
+        {
+            return decode_stream(&args, png);
+        }
+    }
+    let (data, corrected) = extract(&carrier, args.method, &args.chunk_type)?;
+    if corrected > 0 {
+        eprintln!("corrected {} damaged payload bytes", corrected);
+    }
+    let version = payload::version(&data)?;
+    if version > payload::VERSION {
+        return Err(format!(
+            "payload format version {} needs a newer release (this one reads up to {})",
+            version,
+            payload::VERSION
+        )
+        .into());
+    }
+    let keys = payload::Keys {
+        password: match payload::is_encrypted(&data) {
+            true => Some(password()?),
+            false => None,
+        },
+        integrity_key: Some(read_key(args.integrity_key.as_deref())?),
+        verifying_key: match &args.verify {
+            Some(path) => Some(sign::read_verifying_key(path)?),
+            None => None,
+        },
+        keyfile: match &args.keyfile {
+            Some(path) => Some(fs::read(path)?),
+            None => None,
+        },
+        identity: match &args.identity {
+            Some(path) => Some(recipients::read_secret_key(path)?),
+            None => None,
+        },
+        #[cfg(feature = "age")]
+        age_identities: match &args.age_identity {
+            Some(path) => Some(fs::read_to_string(path)?),
+            None => None,
+        },
+        kdf_limit: kdf_limit(&args),
+    };
+    if payload::needs_keyfile(&data) && keys.keyfile.is_none() {
+        return Err("payload is encrypted with a keyfile, pass --keyfile".into());
+    }
+    if payload::is_for_recipients(&data) && keys.identity.is_none() {
+        return Err("payload is encrypted to recipients, pass --identity".into());
+    }
+    if payload::is_age(&data) && args.age_identity.is_none() {
+        return Err("payload is encrypted with age, pass --age-identity".into());
+    }
+    match (payload::is_signed(&data), keys.verifying_key.is_some()) {
+        (false, true) => return Err("payload is not signed".into()),
+        (true, false) => eprintln!("warning: payload is signed, pass --verify to check it"),
+        _ => {}
+    }
+    let message = payload::decode(&data, &chunk_type, &keys)?;
+    log_nonce(args.nonce_log.as_deref(), Direction::Decoded, &data)?;
+    if !payload::is_attachment(&data) {
+        match &args.output {
+            Some(path) => fs::write(path, message)?,
+            None => println!("{}", String::from_utf8(message)?),
+        }
+        return Ok(());
+    }
+    let attachment = Attachment::parse(&message)?;
+    let path = match args.output.as_deref() {
+        Some(path) if path == Path::new("auto") => attachment.restore_path(Path::new("."))?,
+        Some(path) => path.to_path_buf(),
+        None => {
+            return Err(format!(
+                "payload is the file {} ({}); pass -o auto or -o PATH to save it",
+                attachment.name, attachment.mime
+            )
+            .into())
+        }
+    };
+    fs::write(&path, &attachment.content)?;
+    fs::File::options()
+        .write(true)
+        .open(&path)?
+        .set_modified(attachment.modified_time())?;
+    eprintln!("wrote {} ({})", path.display(), attachment.mime);
+    Ok(())
+}
+
+pub fn remove(args: RemoveArgs) -> Result<()> {
+    let mut png = read_png(&args.file)?;
+    let before = args.shred.map(|_| png.clone());
+    let chunk = png.remove_first_chunk(&args.chunk_type)?;
+    let (manifest, _) = payload::correct(chunk.data())?;
+    if payload::is_manifest(&manifest) {
+        png.retain_chunks(|c| {
+            c.chunk_type() != chunk.chunk_type()
+                || !payload::correct(c.data())
+                    .is_ok_and(|(part, _)| payload::is_part_of(&manifest, &part))
+        });
+    }
+    let output = destination(&args.file, None)?;
+    let _stage = perf::stage("write");
+    match (before, args.shred) {
+        (Some(before), Some(fill)) => shred::shred(output, &before, &png, fill)?,
+        _ => fs::write(output, png.as_bytes())?,
+    }
+    println!("removed {}", chunk);
+    Ok(())
+}
+
+pub fn print(args: PrintArgs) -> Result<()> {
+    if args.lazy {
+        return print_lazy(&args);
+    }
+    // Offsets in the file as read, which lenient parsing's skips make
+    // differ from those of the chunks written back out
+    let (png, offsets) = match args.lenient {
+        true => {
+            let options = ParseOptions {
+                mode: Mode::Lenient,
+                ..ParseOptions::default()
+            };
+            let (png, offsets, warnings) =
+                Png::parse_with_offsets(&read_input(&args.file)?, &options)?;
+            for warning in warnings {
+                eprintln!("warning: {}", warning);
+            }
+            (png, offsets)
+        }
+        false => {
+            let png = read_png(&args.file)?;
+            let offsets = png.chunk_offsets();
+            (png, offsets)
+        }
+    };
+    match args.format {
+        Some(template) => {
+            for (index, (chunk, &offset)) in png.chunks().iter().zip(&offsets).enumerate() {
+                println!("{}", template.render(index, offset, chunk));
+            }
+        }
+        None => print!("{}", png),
+    }
+    Ok(())
+}
+
+fn print_lazy(args: &PrintArgs) -> Result<()> {
+    let file = BufReader::new(fs::File::open(&args.file)?);
+    let mut png = LazyPng::new(file)?;
+    for index in 0..png.entries().len() {
+        let entry = png.entries()[index];
+        match &args.format {
+            Some(template) => {
+                let keyword = || png.keyword(index).ok().flatten().unwrap_or_default();
+                println!("{}", template.render_entry(index, &entry, keyword));
+            }
+            None => println!("{}", entry),
+        }
+    }
+    Ok(())
+}
+
+pub fn grep(args: GrepArgs) -> Result<()> {
+    let matcher = Matcher::new(&args.pattern, args.regex, args.chunk_type, args.raw)?;
+    for file in &args.files {
+        let png = match read_png(file) {
+            Ok(png) => png,
+            Err(e) => {
+                eprintln!("{}: {}", file.display(), e);
+                continue;
+            }
+        };
+        for m in matcher.find(&png) {
+            match m.keyword {
+                Some(keyword) => println!(
+                    "{}: {}[{}] {}: {}",
+                    file.display(),
+                    m.chunk_type,
+                    m.index,
+                    keyword,
+                    m.context
+                ),
+                None => println!(
+                    "{}: {}[{}] @{}: {}",
+                    file.display(),
+                    m.chunk_type,
+                    m.index,
+                    m.offset,
+                    m.context
+                ),
+            }
+        }
+    }
+    Ok(())
+}
+
+pub fn sign(args: SignArgs) -> Result<()> {
+    let key = sign::read_signing_key(&args.key)?;
+    let mut png = read_png(&args.file)?;
+    sign::sign_with(&mut png, &key, args.digest);
+    fs::write(
+        destination(&args.file, args.output.as_deref())?,
+        png.as_bytes(),
+    )?;
+    Ok(())
+}
+
+pub fn verify(args: VerifyArgs) -> Result<()> {
+    let key = sign::read_verifying_key(&args.pubkey)?;
+    let png = read_png(&args.file)?;
+    sign::verify(&png, &key)?;
+    println!("{}: signature OK", args.file.display());
+    Ok(())
+}
+
+pub fn checksum(args: ChecksumArgs) -> Result<()> {
+    let sums = Checksums::new(&read_input(&args.file)?, args.algo)?;
+    println!("{}  file", to_hex(&sums.file));
+    println!("{}  image data", to_hex(&sums.image));
+    for (index, (chunk_type, digest)) in sums.chunks.iter().enumerate() {
+        println!("{}  {}[{}]", to_hex(digest), chunk_type, index);
+    }
+    Ok(())
+}
+
+pub fn check(args: CheckArgs) -> Result<()> {
+    let options = ValidateOptions {
+        parallel: false,
+        keywords: true,
+        unique: true,
+        placement: true,
+        header: true,
+        indices: true,
+    };
+    let report = validate::report(&read_input(&args.file)?, &options);
+    match args.json {
+        true => println!("{}", report.to_json()),
+        false => {
+            for finding in report.findings() {
+                println!("{}: {}", args.file.display(), finding);
+            }
+        }
+    }
+    match report.errors.len() {
+        0 => Ok(()),
+        n => Err(format!("{} errors in {}", n, args.file.display()).into()),
+    }
+}
+
+pub fn rename_chunk(args: RenameChunkArgs) -> Result<()> {
+    let old = ChunkType::from_str(&args.old_type)?;
+    let new = ChunkType::from_str(&args.new_type)?;
+    if !args.force {
+        for chunk_type in [old, new] {
+            if chunk_type.is_critical() || chunk_type.is_public() {
+                return Err(PngError::ProtectedChunk(chunk_type.to_string()).into());
+            }
+        }
+    }
+    let mut png = read_png(&args.file)?;
+    let renamed = png.rename_chunks(&args.old_type, new)?;
+    fs::write(
+        destination(&args.file, args.output.as_deref())?,
+        png.as_bytes(),
+    )?;
+    println!("renamed {} {} chunk(s) to {}", renamed, old, new);
+    Ok(())
+}
+
+pub fn reorder(args: ReorderArgs) -> Result<()> {
+    let mut png = read_png(&args.file)?;
+    png.reorder()?;
+    fs::write(
+        destination(&args.file, args.output.as_deref())?,
+        png.as_bytes(),
+    )?;
+    Ok(())
+}
+
+pub fn split(args: SplitArgs) -> Result<()> {
+    let png = read_png(&args.file)?;
+    split::split(&png, &args.output)
+}
+
+pub fn join(args: JoinArgs) -> Result<()> {
+    let png = split::join(&args.dir)?;
+    fs::write(&args.output, png.as_bytes())?;
+    Ok(())
+}
+
+pub fn capacity(args: CapacityArgs) -> Result<()> {
+    let png = read_png(&args.file)?;
+    let Some(payload_len) = args.size else {
+        println!("{}", stego::capacity(&png)?);
+        return Ok(());
+    };
+    let options = stego::EstimateOptions {
+        payload_len,
+        chunk_type: ChunkType::from_str(&args.chunk_type)?,
+        max_chunk_size: args.max_chunk_size,
+    };
+    println!(
+        "{:<8} {:>10} {:>10} {:>8} {:>13}",
+        "method", "capacity", "growth", "entropy", "detectability"
+    );
+    for method in Method::value_variants() {
+        let name = method.to_possible_value().unwrap();
+        let estimate = match stego::estimate(&png, *method, &options) {
+            Ok(estimate) => estimate,
+            Err(e) => {
+                println!("{:<8} {}", name.get_name(), e);
+                continue;
+            }
+        };
+        println!(
+            "{:<8} {:>10} {:>10} {:>+8.4} {:>13.2}",
+            name.get_name(),
+            estimate
+                .capacity
+                .map_or("unlimited".to_string(), |c| c.to_string()),
+            estimate
+                .growth
+                .map_or("too large".to_string(), |g| format!("{:+}", g)),
+            estimate.entropy_delta,
+            estimate.detectability
+        );
+    }
+    Ok(())
+}
+
+/// Write `contents` to a new file at `path`, failing if one is there, only
+/// readable by its owner if `private` is set
+fn write_new(path: &Path, contents: &str, private: bool) -> Result<()> {
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    if private {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    #[cfg(not(unix))]
+    let _ = private;
+    let mut file = options
+        .open(path)
+        .map_err(|e| format!("can't create {}: {}", path.display(), e))?;
+    file.write_all(contents.as_bytes())?;
+    Ok(())
+}
+
+pub fn keygen(args: KeygenArgs) -> Result<()> {
+    let secret = recipients::generate()?;
+    let public = x25519_dalek::PublicKey::from(&secret);
+    let mut public_path = args.output.clone().into_os_string();
+    public_path.push(".pub");
+    let public_path = Path::new(&public_path);
+    if public_path.exists() {
+        return Err(format!("{} already exists", public_path.display()).into());
+    }
+    write_new(
+        &args.output,
+        &(recipients::to_hex(secret.as_bytes()) + "\n"),
+        true,
+    )?;
+    write_new(
+        public_path,
+        &(recipients::to_hex(public.as_bytes()) + "\n"),
+        false,
+    )?;
+    println!("{}", recipients::to_hex(public.as_bytes()));
+    Ok(())
+}
+
+pub fn scan(args: ScanArgs) -> Result<()> {
+    let mut found = 0;
+    for file in &args.files {
+        let parsed = read_input(file).and_then(|bytes| Ok(Png::try_from(bytes.as_slice())?));
+        let mut png = match parsed {
+            Ok(png) => png,
+            Err(e) => {
+                eprintln!("{}: {}", file.display(), e);
+                continue;
+            }
+        };
+        if let Some(trailer) = stego::scan(&png) {
+            found += 1;
+            println!(
+                "{}: {} bytes after IEND @{}: {}",
+                file.display(),
+                trailer.length,
+                trailer.offset,
+                trailer.preview
+            );
+            if args.strip {
+                png.take_trailing();
+                fs::write(file, png.as_bytes())?;
+            }
+        }
+    }
+    match found {
+        n if n > 0 && args.strict => Err(format!("{} files have data after IEND", n).into()),
+        _ => Ok(()),
+    }
+}
+
+pub fn analyze(args: AnalyzeArgs) -> Result<()> {
+    let png = read_png(&args.file)?;
+    let analysis = analyze::analyze(&png);
+    match &analysis.lsb {
+        Ok(stats) => println!(
+            "lsb: chi-square p {:.3}, RS estimate {:.1}% embedded",
+            stats.chi_square,
+            stats.rs_rate * 100.0
+        ),
+        Err(e) => println!("lsb: not analyzed: {}", e),
+    }
+    if analysis.findings.is_empty() {
+        println!("no signs of hidden data");
+    }
+    for finding in &analysis.findings {
+        println!("{}", finding);
+    }
+    Ok(())
+}
+
+pub fn stats(args: StatsArgs) -> Result<()> {
+    let mut histogram = Histogram::default();
+    for file in &args.files {
+        let png = match read_png(file) {
+            Ok(png) => png,
+            Err(e) => {
+                eprintln!("{}: {}", file.display(), e);
+                continue;
+            }
+        };
+        let file_stats = stats::file_stats(&png);
+        println!("{}", file.display());
+        println!(
+            "{:>5} {:<4} {:>10} {:>10} {:>8} {:>6}",
+            "index", "type", "offset", "length", "entropy", "ratio"
+        );
+        for (index, chunk) in file_stats.chunks.iter().enumerate() {
+            println!(
+                "{:>5} {:<4} {:>10} {:>10} {:>8.3} {:>6}",
+                index,
+                chunk.chunk_type,
+                chunk.offset,
+                chunk.length,
+                chunk.entropy,
+                chunk.ratio.map_or("-".to_string(), |r| format!("{:.3}", r))
+            );
+        }
+        let percentiles: Vec<String> = stats::PERCENTILES
+            .iter()
+            .zip(&file_stats.percentiles)
+            .map(|(p, length)| format!("p{} {}", p, length))
+            .collect();
+        println!("lengths: {}", percentiles.join(", "));
+        histogram.add(&file_stats);
+    }
+    if histogram.files > 1 {
+        println!("\n{} files", histogram.files);
+        println!(
+            "{:<4} {:>8} {:>8} {:>12}",
+            "type", "chunks", "files", "bytes"
+        );
+        for (chunk_type, count) in &histogram.types {
+            println!(
+                "{:<4} {:>8} {:>8} {:>12}",
+                chunk_type, count.chunks, count.files, count.bytes
+            );
+        }
+    }
+    Ok(())
+}
+
+pub fn batch(args: BatchArgs) -> Result<()> {
+    let operation = match args.operation {
+        BatchOperation::Validate => Operation::Validate,
+        BatchOperation::Strip => Operation::Strip,
+        BatchOperation::Encode => Operation::Encode {
+            chunk_type: ChunkType::from_str(args.chunk_type.as_deref().unwrap_or_default())?,
+            message: args.message.unwrap_or_default().into_bytes(),
+            password: match args.encrypt {
+                true => Some(new_password(
+                    "PING_PASSWORD",
+                    "Password",
+                    args.allow_weak_password,
+                )?),
+                false => None,
+            },
+        },
+    };
+    let cache = match args.cache {
+        Some(Some(path)) => Some(path),
+        Some(None) => Some(
+            cache::default_path()
+                .ok_or("no cache directory in the environment; give --cache a file")?,
+        ),
+        None => None,
+    };
+    let options = batch::Options {
+        jobs: args.jobs,
+        queue_depth: args.queue_depth,
+        io_threads: args.io_threads,
+        output_dir: args.output_dir,
+        max_memory: args.max_memory.map(|mib| mib << 20),
+        cache,
+        cache_identity: args.cache_identity,
+        payloads: None,
+    };
+    let outcomes = batch::run(&args.files, &operation, &options)?;
+    report_batch(&operation, &outcomes)
+}
+
+/// Print the failures and a summary, failing if any file did
+fn report_batch(operation: &Operation, outcomes: &[batch::Outcome]) -> Result<()> {
+    for outcome in outcomes {
+        if let Err(e) = &outcome.result {
+            eprintln!("{}: {}", outcome.path.display(), e);
+        }
+    }
+    let summary = Summary::of(outcomes);
+    print!("{} ok, {} failed", summary.succeeded, summary.failed);
+    match operation {
+        Operation::Validate => println!(),
+        _ => println!(
+            "; {} bytes in, {} out, {} chunks removed",
+            summary.bytes_in, summary.bytes_out, summary.chunks_removed
+        ),
+    }
+    match summary.failed {
+        0 => Ok(()),
+        n => Err(format!("{} of {} files failed", n, outcomes.len()).into()),
+    }
+}
+
+#[cfg(feature = "exiftool")]
+pub fn metadata(args: MetadataArgs) -> Result<()> {
+    match args.command {
+        MetadataCommand::Export {
+            files,
+            exiftool_json,
+        } => {
+            let pngs = files
+                .iter()
+                .map(|file| Ok((file.display().to_string(), read_png(file)?)))
+                .collect::<Result<Vec<_>>>()?;
+            if exiftool_json {
+                let named = pngs.iter().map(|(name, png)| (name.as_str(), png));
+                print!("{}", exiftool::export(named));
+                return Ok(());
+            }
+            for (name, png) in &pngs {
+                if pngs.len() > 1 {
+                    println!("======== {}", name);
+                }
+                for (tag, value) in exiftool::tags(png) {
+                    match value.as_str() {
+                        Some(text) => println!("{:<32}: {}", tag, text),
+                        None => println!("{:<32}: {}", tag, value),
+                    }
+                }
+            }
+            Ok(())
+        }
+        MetadataCommand::Import {
+            json,
+            files,
+            output_dir,
+        } => {
+            let json = match json.as_os_str() == "-" {
+                true => std::io::read_to_string(std::io::stdin())?,
+                false => fs::read_to_string(&json)?,
+            };
+            let entries = exiftool::parse(&json)?;
+            for file in &files {
+                let entry = exiftool::entry_for(&entries, &file.display().to_string())?;
+                let mut png = read_png(file)?;
+                let written = exiftool::import(&mut png, entry);
+                let output = match (&output_dir, file.file_name()) {
+                    (Some(dir), Some(name)) => dir.join(name),
+                    _ => destination(file, None)?.to_path_buf(),
+                };
+                fs::write(output, png.as_bytes())?;
+                println!("{}: {} tags written", file.display(), written);
+            }
+            Ok(())
+        }
+    }
+}
+
+#[cfg(feature = "script")]
+pub fn script(args: ScriptArgs) -> Result<()> {
+    let json = match args.script.as_os_str() == "-" {
+        true => std::io::read_to_string(std::io::stdin())?,
+        false => fs::read_to_string(&args.script)?,
+    };
+    let script = Script::from_json(&json)?;
+    let password = match script.needs_password() {
+        true => Some(new_password(
+            "PING_PASSWORD",
+            "Password",
+            args.allow_weak_password,
+        )?),
+        false => None,
+    };
+    let operation = Operation::Script { script, password };
+    let options = batch::Options {
+        jobs: args.jobs,
+        output_dir: args.output_dir,
+        ..batch::Options::default()
+    };
+    let outcomes = batch::run(&args.files, &operation, &options)?;
+    report_batch(&operation, &outcomes)
+}
+
+#[cfg(feature = "serve")]
+pub fn serve(args: ServeArgs) -> Result<()> {
+    let mut options = server::Options {
+        max_size: args.max_size,
+        timeout: std::time::Duration::from_secs(args.timeout),
+        ..server::Options::default()
+    };
+    if let Some(jobs) = args.jobs {
+        options.threads = jobs;
+    }
+    eprintln!("listening on http://{}:{}", args.bind, args.port);
+    server::serve((args.bind.as_str(), args.port), &options)?;
+    Ok(())
+}
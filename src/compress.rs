@@ -0,0 +1,150 @@
+use std::io::{self, Read, Write};
+
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+
+use crate::deflate;
+use crate::perf;
+
+/// Compression applied to a message before it's encrypted and embedded
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum Codec {
+    None = 0,
+    Zlib = 1,
+    Zstd = 2,
+}
+
+impl TryFrom<u8> for Codec {
+    type Error = u8;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(Codec::None),
+            1 => Ok(Codec::Zlib),
+            2 => Ok(Codec::Zstd),
+            _ => Err(value),
+        }
+    }
+}
+
+/// A zstd encoder at the level payloads use, with a worker per thread of
+/// the current rayon pool when it has more than one
+fn zstd_encoder<W: Write>(output: W) -> io::Result<zstd::Encoder<'static, W>> {
+    #[allow(unused_mut)]
+    let mut encoder = zstd::Encoder::new(output, 19)?;
+    #[cfg(not(target_arch = "wasm32"))]
+    if rayon::current_num_threads() > 1 {
+        encoder.multithread(rayon::current_num_threads() as u32)?;
+    }
+    Ok(encoder)
+}
+
+impl Codec {
+    /// Compress on the current rayon pool's threads; see
+    /// [`deflate::compress_blocks`] for zlib
+    pub fn compress(&self, data: &[u8]) -> io::Result<Vec<u8>> {
+        perf::compress(|| match self {
+            Codec::None => Ok(data.to_vec()),
+            Codec::Zlib => Ok(deflate::backend().compress(data, deflate::BEST_LEVEL)),
+            Codec::Zstd => {
+                let mut encoder = zstd_encoder(Vec::new())?;
+                encoder.write_all(data)?;
+                encoder.finish()
+            }
+        })
+    }
+
+    /// Decompress at most `limit` bytes, truncating or failing beyond that,
+    /// so a crafted stream can't expand past the length recorded next to it
+    pub fn decompress(&self, data: &[u8], limit: u64) -> io::Result<Vec<u8>> {
+        let mut out = Vec::new();
+        match self {
+            Codec::None => return Ok(data.to_vec()),
+            Codec::Zlib => return deflate::backend().decompress(data, limit),
+            Codec::Zstd => zstd::Decoder::new(data)?
+                .take(limit)
+                .read_to_end(&mut out)?,
+        };
+        Ok(out)
+    }
+
+    /// Compress everything read from `input` into `output`, without holding
+    /// more than the codec's window in memory
+    pub fn compress_stream(&self, input: &mut impl Read, output: impl Write) -> io::Result<()> {
+        // Counts the time reading `input` as well
+        perf::compress(|| match self {
+            Codec::None => {
+                let mut output = output;
+                io::copy(input, &mut output).map(drop)
+            }
+            Codec::Zlib => {
+                let mut encoder = ZlibEncoder::new(output, Compression::best());
+                io::copy(input, &mut encoder)?;
+                encoder.finish().map(drop)
+            }
+            Codec::Zstd => {
+                let mut encoder = zstd_encoder(output)?;
+                io::copy(input, &mut encoder)?;
+                encoder.finish().map(drop)
+            }
+        })
+    }
+
+    /// A writer decompressing what's written to it into `output`; flush it
+    /// once everything is written
+    pub fn decompressor<'a>(&self, output: impl Write + 'a) -> io::Result<Box<dyn Write + 'a>> {
+        Ok(match self {
+            Codec::None => Box::new(output),
+            Codec::Zlib => Box::new(flate2::write::ZlibDecoder::new(output)),
+            Codec::Zstd => Box::new(zstd::stream::write::Decoder::new(output)?),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let data = "all work and no play ".repeat(100);
+        for codec in [Codec::None, Codec::Zlib, Codec::Zstd] {
+            let compressed = codec.compress(data.as_bytes()).unwrap();
+            if codec != Codec::None {
+                assert!(compressed.len() < data.len() / 10);
+            }
+            let decompressed = codec.decompress(&compressed, u64::MAX).unwrap();
+            assert_eq!(decompressed, data.as_bytes());
+        }
+    }
+
+    #[test]
+    fn test_decompress_limit() {
+        let compressed = Codec::Zstd.compress(&[0; 10_000]).unwrap();
+        assert_eq!(Codec::Zstd.decompress(&compressed, 100).unwrap().len(), 100);
+    }
+
+    #[test]
+    fn test_codec_from_u8() {
+        assert_eq!(Codec::try_from(2), Ok(Codec::Zstd));
+        assert_eq!(Codec::try_from(9), Err(9));
+    }
+
+    #[test]
+    fn test_stream_round_trip() {
+        let data = "streamed ".repeat(1000).into_bytes();
+        for codec in [Codec::None, Codec::Zlib, Codec::Zstd] {
+            let mut compressed = Vec::new();
+            codec
+                .compress_stream(&mut data.as_slice(), &mut compressed)
+                .unwrap();
+            let mut out = Vec::new();
+            let mut decompressor = codec.decompressor(&mut out).unwrap();
+            decompressor.write_all(&compressed).unwrap();
+            decompressor.flush().unwrap();
+            drop(decompressor);
+            assert_eq!(out, data);
+        }
+    }
+}
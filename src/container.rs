@@ -0,0 +1,547 @@
+//! Payloads in image formats other than PNG, each behind a feature of its
+//! own: `jpeg` keeps them in APP15 (or COM) segments, `gif` in an
+//! application extension and `webp` in a RIFF chunk. Containers carry the
+//! payload bytes as they are, so the header, encryption, signing and error
+//! correction `ping encode` applies work the same in every format
+
+use std::fmt::Display;
+
+/// A file format a payload can be embedded in
+pub trait Container: Send + Sync {
+    fn name(&self) -> &'static str;
+    /// Whether `file` starts like a file of this format
+    fn recognizes(&self, file: &[u8]) -> bool;
+    /// `file` with `payload` in it, in place of any payload already there
+    fn embed(&self, file: &[u8], payload: &[u8]) -> Result<Vec<u8>, ContainerError>;
+    /// The payload embedded in `file`
+    fn extract(&self, file: &[u8]) -> Result<Vec<u8>, ContainerError>;
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ContainerError {
+    /// The file's structure is broken where it was being read
+    Malformed {
+        format: &'static str,
+        reason: &'static str,
+    },
+    NoPayload,
+    /// The payload is larger than the format's size fields can describe
+    TooLarge {
+        format: &'static str,
+        length: usize,
+    },
+}
+
+impl Display for ContainerError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ContainerError::Malformed { format, reason } => {
+                write!(f, "malformed {} file: {}", format, reason)
+            }
+            ContainerError::NoPayload => write!(f, "file has no embedded payload"),
+            ContainerError::TooLarge { format, length } => {
+                write!(f, "a payload of {} bytes doesn't fit in {}", length, format)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ContainerError {}
+
+/// The containers built in
+pub fn containers() -> &'static [&'static dyn Container] {
+    &[
+        #[cfg(feature = "jpeg")]
+        &Jpeg::APP15,
+        #[cfg(feature = "gif")]
+        &Gif,
+        #[cfg(feature = "webp")]
+        &Webp,
+    ]
+}
+
+/// The built-in container for `file`'s format, if there is one
+pub fn detect(file: &[u8]) -> Option<&'static dyn Container> {
+    containers().iter().copied().find(|c| c.recognizes(file))
+}
+
+/// The `len` bytes at `start` of `file`, or a malformed error
+#[cfg(any(feature = "jpeg", feature = "gif", feature = "webp"))]
+fn slice<'a>(
+    file: &'a [u8],
+    start: usize,
+    len: usize,
+    format: &'static str,
+    reason: &'static str,
+) -> Result<&'a [u8], ContainerError> {
+    start
+        .checked_add(len)
+        .and_then(|end| file.get(start..end))
+        .ok_or(ContainerError::Malformed { format, reason })
+}
+
+/// JPEG, keeping the payload in segments of one marker, each starting
+/// with [`Jpeg::IDENTIFIER`] and split where a segment would overflow
+#[cfg(feature = "jpeg")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Jpeg {
+    pub marker: u8,
+}
+
+/// A JPEG segment's marker and byte range
+#[cfg(feature = "jpeg")]
+type Segment = (u8, usize, usize);
+
+#[cfg(feature = "jpeg")]
+impl Jpeg {
+    /// Application segment 15, which no common format claims
+    pub const APP15: Jpeg = Jpeg { marker: 0xef };
+    /// A comment segment, which editors are more likely to keep
+    pub const COMMENT: Jpeg = Jpeg { marker: 0xfe };
+    /// Start of the data of every payload segment
+    pub const IDENTIFIER: &'static [u8] = b"ping\0";
+
+    /// Payload bytes in one segment, after its length and identifier
+    const SEGMENT_PAYLOAD: usize = u16::MAX as usize - 2 - Self::IDENTIFIER.len();
+
+    fn malformed(reason: &'static str) -> ContainerError {
+        ContainerError::Malformed {
+            format: "JPEG",
+            reason,
+        }
+    }
+
+    /// The marker and byte range of each segment ahead of the scan data,
+    /// and where the rest of the file starts
+    fn segments(file: &[u8]) -> Result<(Vec<Segment>, usize), ContainerError> {
+        if !file.starts_with(&[0xff, 0xd8]) {
+            return Err(Self::malformed("no start of image marker"));
+        }
+        let mut segments = Vec::new();
+        let mut pos = 2;
+        loop {
+            if file.get(pos) != Some(&0xff) {
+                return Err(Self::malformed("expected a marker"));
+            }
+            let fill = pos;
+            // a marker may be preceded by any number of fill bytes, which
+            // are dropped along with the segment ranges
+            while file.get(pos + 1) == Some(&0xff) {
+                pos += 1;
+            }
+            let start = pos;
+            let marker = *file
+                .get(pos + 1)
+                .ok_or(Self::malformed("file ends inside a marker"))?;
+            if marker == 0xda || marker == 0xd9 {
+                return Ok((segments, fill));
+            }
+            let end = match marker {
+                0x01 | 0xd0..=0xd7 => pos + 2,
+                _ => {
+                    let length = slice(file, pos + 2, 2, "JPEG", "file ends inside a segment")?;
+                    let length = u16::from_be_bytes([length[0], length[1]]) as usize;
+                    if length < 2 {
+                        return Err(Self::malformed("segment length too short"));
+                    }
+                    slice(file, pos + 2, length, "JPEG", "segment runs past the end")?;
+                    pos + 2 + length
+                }
+            };
+            segments.push((marker, start, end));
+            pos = end;
+        }
+    }
+
+    /// Whether the segment at `start..end` is a payload segment of any
+    /// marker this container writes
+    fn is_payload(file: &[u8], marker: u8, start: usize, end: usize) -> bool {
+        let data = &file[start..end];
+        [Self::APP15.marker, Self::COMMENT.marker].contains(&marker)
+            && data.len() >= 4
+            && data[4..].starts_with(Self::IDENTIFIER)
+    }
+}
+
+#[cfg(feature = "jpeg")]
+impl Container for Jpeg {
+    fn name(&self) -> &'static str {
+        "JPEG"
+    }
+
+    fn recognizes(&self, file: &[u8]) -> bool {
+        file.starts_with(&[0xff, 0xd8, 0xff])
+    }
+
+    /// Payload segments go after the leading application segments, so
+    /// JFIF and EXIF headers stay first
+    fn embed(&self, file: &[u8], payload: &[u8]) -> Result<Vec<u8>, ContainerError> {
+        let (segments, rest) = Self::segments(file)?;
+        let segments: Vec<_> = segments
+            .into_iter()
+            .filter(|&(marker, start, end)| !Self::is_payload(file, marker, start, end))
+            .collect();
+        let at = segments
+            .iter()
+            .position(|(marker, _, _)| !(0xe0..=0xef).contains(marker))
+            .unwrap_or(segments.len());
+        let mut out = Vec::with_capacity(file.len() + payload.len() + 16);
+        out.extend_from_slice(&file[..2]);
+        for &(_, start, end) in &segments[..at] {
+            out.extend_from_slice(&file[start..end]);
+        }
+        let mut parts: Vec<&[u8]> = payload.chunks(Self::SEGMENT_PAYLOAD).collect();
+        if parts.is_empty() {
+            parts.push(&[]);
+        }
+        for part in parts {
+            out.extend_from_slice(&[0xff, self.marker]);
+            let length = 2 + Self::IDENTIFIER.len() + part.len();
+            out.extend_from_slice(&(length as u16).to_be_bytes());
+            out.extend_from_slice(Self::IDENTIFIER);
+            out.extend_from_slice(part);
+        }
+        for &(_, start, end) in &segments[at..] {
+            out.extend_from_slice(&file[start..end]);
+        }
+        out.extend_from_slice(&file[rest..]);
+        Ok(out)
+    }
+
+    fn extract(&self, file: &[u8]) -> Result<Vec<u8>, ContainerError> {
+        let (segments, _) = Self::segments(file)?;
+        let mut parts = segments
+            .into_iter()
+            .filter(|&(marker, start, end)| Self::is_payload(file, marker, start, end))
+            .peekable();
+        parts.peek().ok_or(ContainerError::NoPayload)?;
+        Ok(parts
+            .flat_map(|(_, start, end)| &file[start + 4 + Self::IDENTIFIER.len()..end])
+            .copied()
+            .collect())
+    }
+}
+
+/// GIF, keeping the payload in an application extension ahead of the
+/// trailer. GIF87a files are rewritten as GIF89a, the version with
+/// extensions
+#[cfg(feature = "gif")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Gif;
+
+/// A GIF block's byte range
+#[cfg(feature = "gif")]
+type Block = (usize, usize);
+
+#[cfg(feature = "gif")]
+impl Gif {
+    /// Application identifier and authentication code of the extension
+    pub const APPLICATION: &'static [u8; 11] = b"PINGDATA1.0";
+
+    fn malformed(reason: &'static str) -> ContainerError {
+        ContainerError::Malformed {
+            format: "GIF",
+            reason,
+        }
+    }
+
+    /// Where the data sub-blocks starting at `pos` end, past their
+    /// terminator
+    fn skip_blocks(file: &[u8], mut pos: usize) -> Result<usize, ContainerError> {
+        loop {
+            let size = *file
+                .get(pos)
+                .ok_or(Self::malformed("file ends inside a block"))?
+                as usize;
+            pos += 1 + size;
+            if size == 0 {
+                return Ok(pos);
+            }
+        }
+    }
+
+    /// The size of a colour table given the packed fields byte describing it
+    fn color_table(packed: u8) -> usize {
+        match packed & 0x80 {
+            0 => 0,
+            _ => 3 << ((packed & 0x07) + 1),
+        }
+    }
+
+    /// Where the blocks start, the byte range of each block, and where the
+    /// trailer is
+    fn blocks(file: &[u8]) -> Result<(usize, Vec<Block>, usize), ContainerError> {
+        if !Gif.recognizes(file) {
+            return Err(Self::malformed("no GIF signature"));
+        }
+        let screen = slice(file, 6, 7, "GIF", "file ends inside the screen descriptor")?;
+        let first = 13 + Self::color_table(screen[4]);
+        let mut blocks = Vec::new();
+        let mut pos = first;
+        loop {
+            let start = pos;
+            let end = match file.get(pos) {
+                Some(0x3b) => return Ok((first, blocks, pos)),
+                Some(0x21) => Self::skip_blocks(file, pos + 2)?,
+                Some(0x2c) => {
+                    let descriptor = slice(
+                        file,
+                        pos + 1,
+                        9,
+                        "GIF",
+                        "file ends inside an image descriptor",
+                    )?;
+                    let data = pos + 10 + Self::color_table(descriptor[8]);
+                    // the LZW code size comes ahead of the image data
+                    Self::skip_blocks(file, data + 1)?
+                }
+                Some(_) => return Err(Self::malformed("unknown block")),
+                None => return Err(Self::malformed("no trailer")),
+            };
+            blocks.push((start, end));
+            pos = end;
+        }
+    }
+
+    fn is_payload(block: &[u8]) -> bool {
+        block.len() > 14 && block[..3] == [0x21, 0xff, 11] && &block[3..14] == Self::APPLICATION
+    }
+}
+
+#[cfg(feature = "gif")]
+impl Container for Gif {
+    fn name(&self) -> &'static str {
+        "GIF"
+    }
+
+    fn recognizes(&self, file: &[u8]) -> bool {
+        file.starts_with(b"GIF87a") || file.starts_with(b"GIF89a")
+    }
+
+    fn embed(&self, file: &[u8], payload: &[u8]) -> Result<Vec<u8>, ContainerError> {
+        let (first, blocks, trailer) = Self::blocks(file)?;
+        let mut out = Vec::with_capacity(file.len() + payload.len() + payload.len() / 255 + 16);
+        out.extend_from_slice(b"GIF89a");
+        out.extend_from_slice(&file[6..first]);
+        for &(start, end) in &blocks {
+            if !Self::is_payload(&file[start..end]) {
+                out.extend_from_slice(&file[start..end]);
+            }
+        }
+        out.extend_from_slice(&[0x21, 0xff, 11]);
+        out.extend_from_slice(Self::APPLICATION);
+        for block in payload.chunks(255) {
+            out.push(block.len() as u8);
+            out.extend_from_slice(block);
+        }
+        out.push(0);
+        out.extend_from_slice(&file[trailer..]);
+        Ok(out)
+    }
+
+    fn extract(&self, file: &[u8]) -> Result<Vec<u8>, ContainerError> {
+        let (_, blocks, _) = Self::blocks(file)?;
+        let (start, end) = blocks
+            .into_iter()
+            .find(|&(start, end)| Self::is_payload(&file[start..end]))
+            .ok_or(ContainerError::NoPayload)?;
+        let mut payload = Vec::new();
+        let mut pos = start + 14;
+        while pos < end {
+            let size = file[pos] as usize;
+            payload.extend_from_slice(&file[pos + 1..pos + 1 + size]);
+            pos += 1 + size;
+        }
+        Ok(payload)
+    }
+}
+
+/// WebP, keeping the payload in a chunk of its own at the end of the RIFF
+/// container. Simple lossy and lossless files gain the VP8X header of the
+/// extended format, the one that allows extra chunks
+#[cfg(feature = "webp")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Webp;
+
+#[cfg(feature = "webp")]
+impl Webp {
+    /// FourCC of the payload chunk
+    pub const FOURCC: &'static [u8; 4] = b"PING";
+
+    fn malformed(reason: &'static str) -> ContainerError {
+        ContainerError::Malformed {
+            format: "WebP",
+            reason,
+        }
+    }
+
+    /// The FourCC and byte range, padding included, of each chunk
+    fn chunks(file: &[u8]) -> Result<Vec<([u8; 4], usize, usize)>, ContainerError> {
+        if !Webp.recognizes(file) {
+            return Err(Self::malformed("no RIFF WEBP signature"));
+        }
+        let size = u32::from_le_bytes(file[4..8].try_into().unwrap()) as usize;
+        let end = size.saturating_add(8).min(file.len());
+        let mut chunks = Vec::new();
+        let mut pos = 12;
+        while pos + 8 <= end {
+            let fourcc: [u8; 4] = file[pos..pos + 4].try_into().unwrap();
+            let length = u32::from_le_bytes(file[pos + 4..pos + 8].try_into().unwrap()) as usize;
+            slice(file, pos + 8, length, "WebP", "chunk runs past the end")?;
+            let next = (pos + 8 + length + length % 2).min(end);
+            chunks.push((fourcc, pos, next));
+            pos = next;
+        }
+        Ok(chunks)
+    }
+
+    /// A VP8X chunk for the simple-format image chunk `chunk`, with the
+    /// canvas size and alpha flag read from its bitstream header
+    fn vp8x(chunk: &[u8]) -> Result<Vec<u8>, ContainerError> {
+        let data = &chunk[8..];
+        let (width, height, alpha) = match &chunk[..4] {
+            b"VP8 " if data.len() >= 10 && data[3..6] == [0x9d, 0x01, 0x2a] => {
+                let width = u16::from_le_bytes([data[6], data[7]]) & 0x3fff;
+                let height = u16::from_le_bytes([data[8], data[9]]) & 0x3fff;
+                (width as u32, height as u32, false)
+            }
+            b"VP8L" if data.len() >= 5 && data[0] == 0x2f => {
+                let bits = u32::from_le_bytes(data[1..5].try_into().unwrap());
+                let width = (bits & 0x3fff) + 1;
+                let height = ((bits >> 14) & 0x3fff) + 1;
+                (width, height, bits & (1 << 28) != 0)
+            }
+            _ => return Err(Self::malformed("no image chunk to size the canvas by")),
+        };
+        let mut vp8x = b"VP8X".to_vec();
+        vp8x.extend_from_slice(&10u32.to_le_bytes());
+        vp8x.extend_from_slice(&[if alpha { 0x10 } else { 0 }, 0, 0, 0]);
+        vp8x.extend_from_slice(&width.saturating_sub(1).to_le_bytes()[..3]);
+        vp8x.extend_from_slice(&height.saturating_sub(1).to_le_bytes()[..3]);
+        Ok(vp8x)
+    }
+}
+
+#[cfg(feature = "webp")]
+impl Container for Webp {
+    fn name(&self) -> &'static str {
+        "WebP"
+    }
+
+    fn recognizes(&self, file: &[u8]) -> bool {
+        file.len() >= 12 && file.starts_with(b"RIFF") && &file[8..12] == b"WEBP"
+    }
+
+    fn embed(&self, file: &[u8], payload: &[u8]) -> Result<Vec<u8>, ContainerError> {
+        let chunks = Self::chunks(file)?;
+        let too_large = |_| ContainerError::TooLarge {
+            format: "WebP",
+            length: payload.len(),
+        };
+        let length = u32::try_from(payload.len()).map_err(too_large)?;
+        let mut out = Vec::with_capacity(file.len() + payload.len() + 32);
+        out.extend_from_slice(&file[..12]);
+        let &(first, start, end) = chunks.first().ok_or(Self::malformed("no chunks"))?;
+        if &first != b"VP8X" {
+            out.extend_from_slice(&Self::vp8x(&file[start..end])?);
+        }
+        for &(fourcc, start, end) in &chunks {
+            if &fourcc != Self::FOURCC {
+                out.extend_from_slice(&file[start..end]);
+            }
+        }
+        out.extend_from_slice(Self::FOURCC);
+        out.extend_from_slice(&length.to_le_bytes());
+        out.extend_from_slice(payload);
+        if payload.len() % 2 == 1 {
+            out.push(0);
+        }
+        let size = u32::try_from(out.len() - 8).map_err(too_large)?;
+        out[4..8].copy_from_slice(&size.to_le_bytes());
+        Ok(out)
+    }
+
+    fn extract(&self, file: &[u8]) -> Result<Vec<u8>, ContainerError> {
+        let (_, start, _) = Self::chunks(file)?
+            .into_iter()
+            .find(|(fourcc, _, _)| fourcc == Self::FOURCC)
+            .ok_or(ContainerError::NoPayload)?;
+        let length = u32::from_le_bytes(file[start + 4..start + 8].try_into().unwrap()) as usize;
+        Ok(file[start + 8..start + 8 + length].to_vec())
+    }
+}
+
+#[cfg(all(test, any(feature = "jpeg", feature = "gif", feature = "webp")))]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[cfg(feature = "jpeg")]
+    fn test_jpeg() {
+        let mut file = vec![0xff, 0xd8];
+        file.extend_from_slice(b"\xff\xe0\x00\x10JFIF\x00\x01\x01\x00\x00\x01\x00\x01\x00\x00");
+        file.extend_from_slice(b"\xff\xdb\x00\x04\x01\x02");
+        file.extend_from_slice(b"\xff\xda\x00\x03\x01\x12\x34\xff\x00\x56\xff\xd9");
+        assert_eq!(detect(&file).unwrap().name(), "JPEG");
+        assert_eq!(Jpeg::APP15.extract(&file), Err(ContainerError::NoPayload));
+
+        let payload: Vec<u8> = (0..100_000).map(|i| i as u8).collect();
+        let embedded = Jpeg::APP15.embed(&file, &payload).unwrap();
+        assert!(embedded[2..].starts_with(&file[2..20]));
+        assert!(embedded.ends_with(&file[20..]));
+        assert_eq!(embedded[20..22], [0xff, 0xef]);
+        assert_eq!(Jpeg::APP15.extract(&embedded).unwrap(), payload);
+
+        let replaced = Jpeg::COMMENT.embed(&embedded, b"short").unwrap();
+        assert_eq!(replaced.len(), file.len() + 4 + Jpeg::IDENTIFIER.len() + 5);
+        assert_eq!(Jpeg::APP15.extract(&replaced).unwrap(), b"short");
+        assert!(matches!(
+            Jpeg::APP15.extract(&file[..24]),
+            Err(ContainerError::Malformed { .. })
+        ));
+    }
+
+    #[test]
+    #[cfg(feature = "gif")]
+    fn test_gif() {
+        let mut file = b"GIF87a\x01\x00\x01\x00\x80\x00\x00".to_vec();
+        file.extend_from_slice(&[0, 0, 0, 0xff, 0xff, 0xff]);
+        file.extend_from_slice(b"\x2c\x00\x00\x00\x00\x01\x00\x01\x00\x00\x02\x02\x4c\x01\x00\x3b");
+        assert_eq!(detect(&file).unwrap().name(), "GIF");
+
+        let payload: Vec<u8> = (0..600).map(|i| i as u8).collect();
+        let embedded = Gif.embed(&file, &payload).unwrap();
+        assert!(embedded.starts_with(b"GIF89a"));
+        assert_eq!(embedded[6..file.len() - 1], file[6..file.len() - 1]);
+        assert_eq!(embedded.last(), Some(&0x3b));
+        assert_eq!(Gif.extract(&embedded).unwrap(), payload);
+        let replaced = Gif.embed(&embedded, b"").unwrap();
+        assert_eq!(replaced.len(), file.len() + 15);
+        assert_eq!(Gif.extract(&replaced).unwrap(), b"");
+        assert_eq!(Gif.extract(&file), Err(ContainerError::NoPayload));
+    }
+
+    #[test]
+    #[cfg(feature = "webp")]
+    fn test_webp() {
+        // a 3x2 lossless image with alpha
+        let image = [0x2f, 0x02, 0x40, 0x00, 0x10, 0x07];
+        let mut file = b"RIFF\x12\x00\x00\x00WEBPVP8L\x06\x00\x00\x00".to_vec();
+        file.extend_from_slice(&image);
+        assert_eq!(detect(&file).unwrap().name(), "WebP");
+
+        let embedded = Webp.embed(&file, b"odd").unwrap();
+        let size = u32::from_le_bytes(embedded[4..8].try_into().unwrap()) as usize;
+        assert_eq!(size, embedded.len() - 8);
+        assert_eq!(&embedded[12..16], b"VP8X");
+        assert_eq!(embedded[20..30], [0x10, 0, 0, 0, 2, 0, 0, 1, 0, 0]);
+        assert_eq!(embedded[30..44], file[12..]);
+        assert_eq!(embedded.len() % 2, 0);
+        assert_eq!(Webp.extract(&embedded).unwrap(), b"odd");
+
+        let replaced = Webp.embed(&embedded, b"even").unwrap();
+        assert_eq!(replaced.len(), embedded.len());
+        assert_eq!(Webp.extract(&replaced).unwrap(), b"even");
+        assert_eq!(Webp.extract(&file), Err(ContainerError::NoPayload));
+    }
+}
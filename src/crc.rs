@@ -1,27 +1,237 @@
+//! CRC-32 as PNG uses it, behind [`Crc32`] so callers can pick an
+//! implementation: crc32fast's by default, or the plain table below
+
+/// A CRC-32 implementation
+pub trait Crc32: Sync {
+    fn name(&self) -> &'static str;
+    fn checksum(&self, buffer: &[u8]) -> u32;
+}
+
+/// A byte-at-a-time lookup table, small and portable
 pub struct Crc([u32; 256]);
 
 impl Crc {
-    pub fn new(&mut self) {
-        for n in 0..256 {
+    pub const fn new() -> Self {
+        let mut table = [0u32; 256];
+        let mut n = 0;
+        while n < 256 {
             let mut c = n as u32;
-            for _k in 0..8 {
+            let mut k = 0;
+            while k < 8 {
                 if c & 1 == 1 {
                     c = 0xedb88320 ^ (c >> 1);
                 } else {
-                    c = c >> 1;
+                    c >>= 1;
                 }
+                k += 1;
             }
-            self.0[n] = c;
+            table[n] = c;
+            n += 1;
         }
+        Crc(table)
     }
 
-    pub fn update(&mut self, buffer: &[u8]) -> u32 {
+    pub fn update(&self, buffer: &[u8]) -> u32 {
         let mut c = 0xffffffff;
-        buffer.iter().for_each(|b| {
-            c = self.0[((c ^ *b as u32) & 0xff) as usize] ^ (c >> 8)
-        });
+        buffer
+            .iter()
+            .for_each(|b| c = self.0[((c ^ *b as u32) & 0xff) as usize] ^ (c >> 8));
         c ^ 0xffffffff
     }
 }
 
+impl Default for Crc {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Crc32 for Crc {
+    fn name(&self) -> &'static str {
+        "table"
+    }
+    fn checksum(&self, buffer: &[u8]) -> u32 {
+        self.update(buffer)
+    }
+}
+
+/// crc32fast, which with std checks the CPU at runtime and folds with
+/// PCLMULQDQ on x86 or the CRC instructions on ARM, falling back to
+/// slicing by 16 tables
+pub struct Fast;
+
+impl Fast {
+    /// Whether this CPU has the instructions crc32fast speeds up with
+    pub fn accelerated() -> bool {
+        #[cfg(all(feature = "std", any(target_arch = "x86", target_arch = "x86_64")))]
+        return std::is_x86_feature_detected!("pclmulqdq")
+            && std::is_x86_feature_detected!("sse4.1");
+        #[cfg(all(feature = "std", target_arch = "aarch64"))]
+        return std::arch::is_aarch64_feature_detected!("crc");
+        #[allow(unreachable_code)]
+        false
+    }
+}
+
+impl Crc32 for Fast {
+    fn name(&self) -> &'static str {
+        match Fast::accelerated() {
+            true => "crc32fast (hardware)",
+            false => "crc32fast (tables)",
+        }
+    }
+    fn checksum(&self, buffer: &[u8]) -> u32 {
+        crc32fast::hash(buffer)
+    }
+}
+
+/// CRC-32 of data fed in pieces, so a chunk's type and data can be
+/// checked without joining them and a large chunk as it's read
+#[derive(Debug, Clone, Default)]
+pub struct CrcHasher(crc32fast::Hasher);
+
+impl CrcHasher {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn update(&mut self, buffer: &[u8]) {
+        #[cfg(feature = "std")]
+        return crate::perf::crc(|| self.0.update(buffer));
+        #[allow(unreachable_code)]
+        self.0.update(buffer);
+    }
+    pub fn finalize(self) -> u32 {
+        self.0.finalize()
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::io::Write for CrcHasher {
+    fn write(&mut self, buffer: &[u8]) -> std::io::Result<usize> {
+        self.update(buffer);
+        Ok(buffer.len())
+    }
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// The reflected CRC-32 polynomial
+const POLYNOMIAL: u32 = 0xedb88320;
+
+/// `a` times `b` modulo the polynomial, both reflected as CRCs are
+const fn multiply(a: u32, mut b: u32) -> u32 {
+    let mut product = 0;
+    let mut bit = 1 << 31;
+    while bit != 0 {
+        if a & bit != 0 {
+            product ^= b;
+        }
+        bit >>= 1;
+        b = match b & 1 {
+            1 => (b >> 1) ^ POLYNOMIAL,
+            _ => b >> 1,
+        };
+    }
+    product
+}
+
+/// x to the power of 8n modulo the polynomial, which is what appending `n`
+/// zero bytes multiplies a CRC's linear part by; found by repeated
+/// squaring in O(log n) multiplications
+fn zeros(mut n: u64) -> u32 {
+    let mut power = 1 << 31;
+    // x^8, the effect of one zero byte
+    let mut square = 1 << 23;
+    while n != 0 {
+        if n & 1 == 1 {
+            power = multiply(square, power);
+        }
+        square = multiply(square, square);
+        n >>= 1;
+    }
+    power
+}
+
+/// CRC-32 of `buffer` without the initial register or final inversion,
+/// the part that's linear in the bits
+fn linear(buffer: &[u8]) -> u32 {
+    let mut hasher = crc32fast::Hasher::new_with_initial(!0);
+    hasher.update(buffer);
+    !hasher.finalize()
+}
+
+/// The CRC of a message, given its CRC `crc`, after the bytes `old`
+/// followed by `trailing` more are replaced with `new`, of the same length.
+/// CRC-32 is affine, so the change depends only on what changed and how
+/// far it sits from the end, and the rest of the message is never read
+pub fn patch(crc: u32, old: &[u8], new: &[u8], trailing: u64) -> u32 {
+    assert_eq!(old.len(), new.len(), "a patch can't change the length");
+    crc ^ multiply(zeros(trailing), linear(old) ^ linear(new))
+}
+
+/// The implementations there are, the default first
+pub fn backends() -> [&'static dyn Crc32; 2] {
+    static TABLE: Crc = Crc::new();
+    [&Fast, &TABLE]
+}
+
+/// CRC-32 of `buffer` with the default implementation
+pub fn checksum(buffer: &[u8]) -> u32 {
+    #[cfg(feature = "std")]
+    return crate::perf::crc(|| crc32fast::hash(buffer));
+    #[allow(unreachable_code)]
+    crc32fast::hash(buffer)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backends_agree() {
+        let data: Vec<u8> = (0..10_000u32).map(|i| (i * 31 % 251) as u8).collect();
+        // CRC of "IEND", the same in every PNG
+        assert_eq!(checksum(b"IEND"), 0xae426082);
+        for backend in backends() {
+            assert_eq!(backend.checksum(b"IEND"), 0xae426082, "{}", backend.name());
+            assert_eq!(
+                backend.checksum(&data),
+                checksum(&data),
+                "{}",
+                backend.name()
+            );
+            assert_eq!(backend.checksum(&[]), 0);
+        }
+    }
 
+    #[test]
+    fn test_patch() {
+        let mut data: Vec<u8> = (0..100_000u32).map(|i| (i % 241) as u8).collect();
+        for (at, new) in [(0, &b"IEND"[..]), (50_000, b"patched"), (99_990, &[0; 10])] {
+            let crc = checksum(&data);
+            let old = data[at..at + new.len()].to_vec();
+            data[at..at + new.len()].copy_from_slice(new);
+            let trailing = (data.len() - at - new.len()) as u64;
+            assert_eq!(patch(crc, &old, new, trailing), checksum(&data));
+        }
+        assert_eq!(patch(0xae426082, b"", b"", 4), 0xae426082);
+    }
+
+    #[test]
+    fn test_hasher_in_pieces() {
+        let data: Vec<u8> = (0..100_000u32).map(|i| (i % 253) as u8).collect();
+        let mut hasher = CrcHasher::new();
+        for piece in data.chunks(4099) {
+            hasher.update(piece);
+        }
+        assert_eq!(hasher.finalize(), checksum(&data));
+        assert_eq!(CrcHasher::new().finalize(), 0);
+        #[cfg(feature = "std")]
+        {
+            let mut hasher = CrcHasher::new();
+            std::io::copy(&mut &data[..], &mut hasher).unwrap();
+            assert_eq!(hasher.finalize(), checksum(&data));
+        }
+    }
+}
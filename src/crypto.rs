@@ -0,0 +1,491 @@
+use std::fmt::Display;
+
+use aes_gcm::aead::{Aead, KeyInit, Payload};
+use aes_gcm::{Aes256Gcm, Nonce};
+use argon2::{Algorithm, Argon2, Params, Version};
+use chacha20poly1305::ChaCha20Poly1305;
+
+pub const KEY_LEN: usize = 32;
+pub const SALT_LEN: usize = 16;
+pub const NONCE_LEN: usize = 12;
+/// Length of the AES-GCM authentication tag appended to ciphertexts
+pub const TAG_LEN: usize = 16;
+
+/// Key derivation context for keyfiles
+const KEYFILE_CONTEXT: &str = "ping 2023 keyfile";
+
+/// Argon2id cost parameters, stored in the payload next to the salt
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KdfParams {
+    pub memory_kib: u32,
+    pub time_cost: u32,
+    pub parallelism: u32,
+}
+
+impl Default for KdfParams {
+    fn default() -> Self {
+        KdfParams {
+            memory_kib: Params::DEFAULT_M_COST,
+            time_cost: Params::DEFAULT_T_COST,
+            parallelism: Params::DEFAULT_P_COST,
+        }
+    }
+}
+
+impl KdfParams {
+    pub const LEN: usize = 12;
+    /// Refuse to derive with more memory than this, so a crafted payload
+    /// can't make decoding allocate without bound
+    pub const MAX_MEMORY_KIB: u32 = 2 * 1024 * 1024;
+    /// Refuse more passes than this, which for scrypt is also p and the
+    /// KiB of its working buffer, so a crafted payload can't cost without
+    /// bound in time or memory
+    pub const MAX_TIME_COST: u32 = 64;
+    /// The most decoding derives at unless the caller opts out: well past
+    /// what encoding uses by default, well short of the hard caps
+    pub const DECODE_LIMIT: KdfParams = KdfParams {
+        memory_kib: 1024 * 1024,
+        time_cost: 16,
+        parallelism: 16,
+    };
+
+    pub fn to_bytes(self) -> [u8; Self::LEN] {
+        let mut bytes = [0u8; Self::LEN];
+        bytes[..4].copy_from_slice(&self.memory_kib.to_be_bytes());
+        bytes[4..8].copy_from_slice(&self.time_cost.to_be_bytes());
+        bytes[8..].copy_from_slice(&self.parallelism.to_be_bytes());
+        bytes
+    }
+
+    /// Whether deriving with these costs no more than `limit` in memory,
+    /// time and parallelism alike
+    pub fn within(&self, limit: &KdfParams) -> bool {
+        self.memory_kib <= limit.memory_kib
+            && self.time_cost <= limit.time_cost
+            && self.parallelism <= limit.parallelism
+    }
+
+    pub fn from_bytes(bytes: &[u8; Self::LEN]) -> KdfParams {
+        let word = |i: usize| u32::from_be_bytes(bytes[i..i + 4].try_into().unwrap());
+        KdfParams {
+            memory_kib: word(0),
+            time_cost: word(4),
+            parallelism: word(8),
+        }
+    }
+
+    fn argon2(&self) -> Result<Argon2<'static>, CryptoError> {
+        if self.memory_kib > Self::MAX_MEMORY_KIB || self.time_cost > Self::MAX_TIME_COST {
+            return Err(CryptoError::InvalidKdfParams);
+        }
+        let params = Params::new(self.memory_kib, self.time_cost, self.parallelism, None)
+            .map_err(|_| CryptoError::InvalidKdfParams)?;
+        Ok(Argon2::new(Algorithm::Argon2id, Version::V0x13, params))
+    }
+
+    /// scrypt with N the largest power of two for which N KiB fit in
+    /// `memory_kib`, r = 8, which makes each unit of N 1 KiB, and
+    /// p = `time_cost`; scrypt has no parallelism of its own
+    fn scrypt(&self) -> Result<scrypt::Params, CryptoError> {
+        if self.memory_kib > Self::MAX_MEMORY_KIB
+            || self.memory_kib < 2
+            || self.time_cost > Self::MAX_TIME_COST
+        {
+            return Err(CryptoError::InvalidKdfParams);
+        }
+        scrypt::Params::new(self.memory_kib.ilog2() as u8, 8, self.time_cost)
+            .map_err(|_| CryptoError::InvalidKdfParams)
+    }
+}
+
+/// Password hashing function deriving the key
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum Kdf {
+    #[default]
+    Argon2id = 0,
+    Scrypt = 1,
+}
+
+/// AEAD sealing the message
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum Cipher {
+    #[default]
+    #[cfg_attr(feature = "cli", value(name = "aes-256-gcm"))]
+    Aes256Gcm = 0,
+    #[cfg_attr(feature = "cli", value(name = "chacha20-poly1305"))]
+    ChaCha20Poly1305 = 1,
+}
+
+/// Key derivation function and cipher a payload is encrypted with. The
+/// default suite is the one releases without suites read
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CryptoSuite {
+    pub kdf: Kdf,
+    pub cipher: Cipher,
+}
+
+impl CryptoSuite {
+    pub const LEN: usize = 2;
+
+    pub fn to_bytes(self) -> [u8; Self::LEN] {
+        [self.kdf as u8, self.cipher as u8]
+    }
+
+    pub fn from_bytes(bytes: [u8; Self::LEN]) -> Result<CryptoSuite, CryptoError> {
+        let kdf = match bytes[0] {
+            0 => Kdf::Argon2id,
+            1 => Kdf::Scrypt,
+            _ => return Err(CryptoError::UnknownSuite(bytes)),
+        };
+        let cipher = match bytes[1] {
+            0 => Cipher::Aes256Gcm,
+            1 => Cipher::ChaCha20Poly1305,
+            _ => return Err(CryptoError::UnknownSuite(bytes)),
+        };
+        Ok(CryptoSuite { kdf, cipher })
+    }
+
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip_all, fields(kdf = ?self.kdf), err)
+    )]
+    pub fn derive_key(
+        &self,
+        password: &[u8],
+        salt: &[u8],
+        params: &KdfParams,
+    ) -> Result<[u8; KEY_LEN], CryptoError> {
+        match self.kdf {
+            Kdf::Argon2id => derive_key(password, salt, params),
+            Kdf::Scrypt => {
+                let mut key = [0u8; KEY_LEN];
+                scrypt::scrypt(password, salt, &params.scrypt()?, &mut key)
+                    .map_err(|_| CryptoError::KeyDerivation)?;
+                Ok(key)
+            }
+        }
+    }
+
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip_all, fields(cipher = ?self.cipher, len = plaintext.len()), err)
+    )]
+    pub fn encrypt(
+        &self,
+        key: &[u8; KEY_LEN],
+        nonce: &[u8; NONCE_LEN],
+        plaintext: &[u8],
+        aad: &[u8],
+    ) -> Result<Vec<u8>, CryptoError> {
+        let payload = Payload {
+            msg: plaintext,
+            aad,
+        };
+        match self.cipher {
+            Cipher::Aes256Gcm => encrypt(key, nonce, plaintext, aad),
+            Cipher::ChaCha20Poly1305 => ChaCha20Poly1305::new(key.into())
+                .encrypt(nonce.into(), payload)
+                .map_err(|_| CryptoError::Encrypt),
+        }
+    }
+
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            level = "debug",
+            skip_all,
+            fields(cipher = ?self.cipher, len = ciphertext.len()),
+            err(level = "debug")
+        )
+    )]
+    pub fn decrypt(
+        &self,
+        key: &[u8; KEY_LEN],
+        nonce: &[u8; NONCE_LEN],
+        ciphertext: &[u8],
+        aad: &[u8],
+    ) -> Result<Vec<u8>, CryptoError> {
+        let payload = Payload {
+            msg: ciphertext,
+            aad,
+        };
+        match self.cipher {
+            Cipher::Aes256Gcm => decrypt(key, nonce, ciphertext, aad),
+            Cipher::ChaCha20Poly1305 => ChaCha20Poly1305::new(key.into())
+                .decrypt(nonce.into(), payload)
+                .map_err(|_| CryptoError::Decrypt),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum CryptoError {
+    KeyDerivation,
+    InvalidKdfParams,
+    /// The payload's key derivation costs more than the decoder allows
+    KdfTooCostly(KdfParams),
+    Random,
+    Encrypt,
+    /// Wrong password or tampered ciphertext; AES-GCM can't tell them apart
+    Decrypt,
+    /// Key derivation function and cipher ids this release doesn't know
+    UnknownSuite([u8; CryptoSuite::LEN]),
+}
+
+impl Display for CryptoError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CryptoError::KeyDerivation => write!(f, "key derivation failed"),
+            CryptoError::InvalidKdfParams => write!(f, "invalid argon2 parameters"),
+            CryptoError::KdfTooCostly(params) => write!(
+                f,
+                "key derivation needs {} KiB, {} passes and {} lanes, more than allowed",
+                params.memory_kib, params.time_cost, params.parallelism
+            ),
+            CryptoError::Random => write!(f, "system random number generator failed"),
+            CryptoError::Encrypt => write!(f, "encryption failed"),
+            CryptoError::Decrypt => {
+                write!(f, "decryption failed: wrong password or corrupted data")
+            }
+            CryptoError::UnknownSuite([kdf, cipher]) => {
+                write!(f, "unknown kdf {} or cipher {}", kdf, cipher)
+            }
+        }
+    }
+}
+
+impl std::error::Error for CryptoError {}
+
+pub fn random<const N: usize>() -> Result<[u8; N], CryptoError> {
+    let mut bytes = [0u8; N];
+    getrandom::fill(&mut bytes).map_err(|_| CryptoError::Random)?;
+    Ok(bytes)
+}
+
+pub fn random_bytes(len: usize) -> Result<Vec<u8>, CryptoError> {
+    let mut bytes = vec![0u8; len];
+    getrandom::fill(&mut bytes).map_err(|_| CryptoError::Random)?;
+    Ok(bytes)
+}
+
+/// Key derivation input from a password, a keyfile or both. The keyfile is
+/// hashed to a fixed length first, so the combination is unambiguous
+pub fn secret(password: Option<&str>, keyfile: Option<&[u8]>) -> Vec<u8> {
+    let mut secret = Vec::new();
+    if let Some(keyfile) = keyfile {
+        secret.extend_from_slice(&blake3::derive_key(KEYFILE_CONTEXT, keyfile));
+    }
+    if let Some(password) = password {
+        secret.extend_from_slice(password.as_bytes());
+    }
+    secret
+}
+
+/// Derive an AES key from a password with Argon2id
+#[cfg_attr(
+    feature = "tracing",
+    tracing::instrument(level = "debug", skip_all, fields(memory_kib = params.memory_kib), err)
+)]
+pub fn derive_key(
+    password: &[u8],
+    salt: &[u8],
+    params: &KdfParams,
+) -> Result<[u8; KEY_LEN], CryptoError> {
+    let mut key = [0u8; KEY_LEN];
+    params
+        .argon2()?
+        .hash_password_into(password, salt, &mut key)
+        .map_err(|_| CryptoError::KeyDerivation)?;
+    Ok(key)
+}
+
+/// AES-256-GCM encrypt, authenticating `aad` alongside the plaintext
+#[cfg_attr(
+    feature = "tracing",
+    tracing::instrument(level = "trace", skip_all, fields(len = plaintext.len()), err)
+)]
+pub fn encrypt(
+    key: &[u8; KEY_LEN],
+    nonce: &[u8; NONCE_LEN],
+    plaintext: &[u8],
+    aad: &[u8],
+) -> Result<Vec<u8>, CryptoError> {
+    let cipher = Aes256Gcm::new(key.into());
+    cipher
+        .encrypt(
+            &Nonce::from(*nonce),
+            Payload {
+                msg: plaintext,
+                aad,
+            },
+        )
+        .map_err(|_| CryptoError::Encrypt)
+}
+
+// Failures are routine when trying each deniable slot, hence debug
+#[cfg_attr(
+    feature = "tracing",
+    tracing::instrument(level = "trace", skip_all, fields(len = ciphertext.len()), err(level = "debug"))
+)]
+pub fn decrypt(
+    key: &[u8; KEY_LEN],
+    nonce: &[u8; NONCE_LEN],
+    ciphertext: &[u8],
+    aad: &[u8],
+) -> Result<Vec<u8>, CryptoError> {
+    let cipher = Aes256Gcm::new(key.into());
+    cipher
+        .decrypt(
+            &Nonce::from(*nonce),
+            Payload {
+                msg: ciphertext,
+                aad,
+            },
+        )
+        .map_err(|_| CryptoError::Decrypt)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let key = [1; KEY_LEN];
+        let nonce = [2; NONCE_LEN];
+        let ciphertext = encrypt(&key, &nonce, b"secret", b"header").unwrap();
+        assert_ne!(&ciphertext[..6], b"secret");
+        assert_eq!(
+            decrypt(&key, &nonce, &ciphertext, b"header").unwrap(),
+            b"secret"
+        );
+    }
+
+    #[test]
+    fn test_tampering_detected() {
+        let key = [1; KEY_LEN];
+        let nonce = [2; NONCE_LEN];
+        let mut ciphertext = encrypt(&key, &nonce, b"secret", b"header").unwrap();
+        assert_eq!(
+            decrypt(&key, &nonce, &ciphertext, b"other"),
+            Err(CryptoError::Decrypt)
+        );
+        ciphertext[0] ^= 1;
+        assert_eq!(
+            decrypt(&key, &nonce, &ciphertext, b"header"),
+            Err(CryptoError::Decrypt)
+        );
+    }
+
+    #[test]
+    fn test_derive_key() {
+        let salt = [3; SALT_LEN];
+        let params = KdfParams::default();
+        let key = derive_key(b"hunter2", &salt, &params).unwrap();
+        assert_eq!(key, derive_key(b"hunter2", &salt, &params).unwrap());
+        assert_ne!(key, derive_key(b"hunter3", &salt, &params).unwrap());
+        assert_ne!(
+            key,
+            derive_key(b"hunter2", &[4; SALT_LEN], &params).unwrap()
+        );
+
+        let cheaper = KdfParams {
+            memory_kib: 1024,
+            time_cost: 1,
+            ..params
+        };
+        assert_ne!(key, derive_key(b"hunter2", &salt, &cheaper).unwrap());
+    }
+
+    #[test]
+    fn test_kdf_params_bytes() {
+        let params = KdfParams {
+            memory_kib: 65536,
+            time_cost: 3,
+            parallelism: 4,
+        };
+        assert_eq!(KdfParams::from_bytes(&params.to_bytes()), params);
+    }
+
+    #[test]
+    fn test_invalid_kdf_params() {
+        let huge = KdfParams {
+            memory_kib: u32::MAX,
+            ..KdfParams::default()
+        };
+        let zero = KdfParams {
+            time_cost: 0,
+            ..KdfParams::default()
+        };
+        let endless = KdfParams {
+            memory_kib: 64,
+            time_cost: KdfParams::MAX_TIME_COST + 1,
+            ..KdfParams::default()
+        };
+        for params in [huge, zero, endless] {
+            assert_eq!(
+                derive_key(b"pw", &[0; SALT_LEN], &params),
+                Err(CryptoError::InvalidKdfParams)
+            );
+        }
+        let scrypt = CryptoSuite {
+            kdf: Kdf::Scrypt,
+            ..CryptoSuite::default()
+        };
+        assert_eq!(
+            scrypt.derive_key(b"pw", &[0; SALT_LEN], &endless),
+            Err(CryptoError::InvalidKdfParams)
+        );
+    }
+
+    #[test]
+    fn test_suites() {
+        let params = KdfParams {
+            memory_kib: 1024,
+            time_cost: 1,
+            ..KdfParams::default()
+        };
+        let argon2 = CryptoSuite::default();
+        let scrypt = CryptoSuite {
+            kdf: Kdf::Scrypt,
+            cipher: Cipher::ChaCha20Poly1305,
+        };
+        let key = scrypt.derive_key(b"pw", &[0; SALT_LEN], &params).unwrap();
+        assert_ne!(
+            key,
+            argon2.derive_key(b"pw", &[0; SALT_LEN], &params).unwrap()
+        );
+
+        let nonce = [2; NONCE_LEN];
+        let ciphertext = scrypt.encrypt(&key, &nonce, b"secret", b"aad").unwrap();
+        assert_ne!(
+            ciphertext,
+            argon2.encrypt(&key, &nonce, b"secret", b"aad").unwrap()
+        );
+        assert_eq!(
+            scrypt.decrypt(&key, &nonce, &ciphertext, b"aad").unwrap(),
+            b"secret"
+        );
+        assert_eq!(
+            argon2.decrypt(&key, &nonce, &ciphertext, b"aad"),
+            Err(CryptoError::Decrypt)
+        );
+
+        assert_eq!(CryptoSuite::from_bytes(scrypt.to_bytes()), Ok(scrypt));
+        assert_eq!(
+            CryptoSuite::from_bytes([0, 9]),
+            Err(CryptoError::UnknownSuite([0, 9]))
+        );
+    }
+
+    #[test]
+    fn test_secret() {
+        assert_eq!(secret(Some("pw"), None), b"pw");
+        let both = secret(Some("pw"), Some(b"key"));
+        assert_eq!(both.len(), 34);
+        assert_eq!(&both[32..], b"pw");
+        assert_eq!(&both[..32], &secret(None, Some(b"key"))[..]);
+    }
+}
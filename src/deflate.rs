@@ -0,0 +1,392 @@
+//! The zlib implementation behind IDAT recompression and zlib payloads.
+//! Speeds differ several times over between them, so which is built in is
+//! up to features: flate2 on miniz_oxide by default, on zlib-rs or zlib-ng
+//! with the features of those names, or libdeflate with `libdeflate`.
+//! flate2 compresses large inputs a block per thread, as pigz does
+
+use std::io::{self, Read, Write};
+
+use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+use flate2::{Compress, Compression, FlushCompress, Status};
+use rayon::prelude::*;
+
+/// Default compression level, as zlib's
+pub const DEFAULT_LEVEL: u32 = 6;
+
+/// Highest compression level every backend supports
+pub const BEST_LEVEL: u32 = 9;
+
+/// Input compressed as one block when compressing in parallel. Each block
+/// starts without the window of the one before, which at this size costs
+/// well under a percent
+pub const PARALLEL_BLOCK: usize = 1 << 20;
+
+/// A zlib (RFC 1950) compressor and decompressor
+pub trait Deflate: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    /// Compress at `level`, from 0 for none to [`BEST_LEVEL`]
+    fn compress(&self, data: &[u8], level: u32) -> Vec<u8>;
+
+    /// Decompress, failing rather than produce more than `limit` bytes so a
+    /// crafted stream can't expand without bound
+    fn decompress(&self, data: &[u8], limit: u64) -> io::Result<Vec<u8>>;
+}
+
+/// The error a backend's decompress fails with past its limit
+#[derive(Debug)]
+struct TooLong(u64);
+
+impl std::fmt::Display for TooLong {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "decompressed data is longer than {} bytes", self.0)
+    }
+}
+
+impl std::error::Error for TooLong {}
+
+fn too_long(limit: u64) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, TooLong(limit))
+}
+
+/// How far data inflated from an untrusted file may expand before it's
+/// taken for a decompression bomb
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Limits {
+    pub max_output: u64,
+    /// Output bytes per input byte, though inputs too small for the ratio
+    /// to mean much may always reach [`Limits::MIN_OUTPUT`]
+    pub max_ratio: u64,
+}
+
+impl Limits {
+    pub const MIN_OUTPUT: u64 = 1 << 20;
+
+    /// zTXt, iTXt and iCCP data, which real files don't compress past a
+    /// few times over
+    pub const METADATA: Limits = Limits {
+        max_output: 64 << 20,
+        max_ratio: 100,
+    };
+
+    /// Compressed payloads, whose recorded length is the sender's to
+    /// forge. Deflate can't pass about 1032:1, and a message zstd packs
+    /// tighter than that is one nobody needs to hide
+    pub const PAYLOAD: Limits = Limits {
+        max_output: 1 << 30,
+        max_ratio: 1032,
+    };
+
+    /// IDAT data, already bounded by the size IHDR gives. Blank images
+    /// reach deflate's own limit of about 1032:1, so the ratio isn't capped
+    pub const IMAGE: Limits = Limits {
+        max_output: 4 << 30,
+        max_ratio: u64::MAX,
+    };
+
+    /// The most `input` bytes may inflate to
+    pub fn output_for(&self, input: usize) -> u64 {
+        (input as u64)
+            .saturating_mul(self.max_ratio)
+            .max(Self::MIN_OUTPUT)
+            .min(self.max_output)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InflateError {
+    Corrupt,
+    /// The data inflates to more than this many bytes
+    DecompressionLimitExceeded(u64),
+}
+
+impl std::fmt::Display for InflateError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            InflateError::Corrupt => write!(f, "corrupt deflate stream"),
+            InflateError::DecompressionLimitExceeded(limit) => write!(
+                f,
+                "data inflates to more than {} bytes, past the decompression limit",
+                limit
+            ),
+        }
+    }
+}
+
+impl std::error::Error for InflateError {}
+
+/// Inflate `data` from a file with the fastest backend, to at most `limit`
+/// bytes
+pub fn inflate(data: &[u8], limit: u64) -> Result<Vec<u8>, InflateError> {
+    backend().decompress(data, limit).map_err(|e| {
+        match e.get_ref().is_some_and(|inner| inner.is::<TooLong>()) {
+            true => InflateError::DecompressionLimitExceeded(limit),
+            false => InflateError::Corrupt,
+        }
+    })
+}
+
+/// flate2, on whichever backend its features select
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Flate2;
+
+impl Deflate for Flate2 {
+    fn name(&self) -> &'static str {
+        "flate2"
+    }
+
+    /// On more than one thread of the current rayon pool, inputs of two
+    /// blocks or more are compressed a block per thread
+    fn compress(&self, data: &[u8], level: u32) -> Vec<u8> {
+        if rayon::current_num_threads() > 1 && data.len() >= 2 * PARALLEL_BLOCK {
+            return compress_blocks(data, level);
+        }
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::new(level.min(BEST_LEVEL)));
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    fn decompress(&self, data: &[u8], limit: u64) -> io::Result<Vec<u8>> {
+        let mut out = Vec::new();
+        ZlibDecoder::new(data)
+            .take(limit.saturating_add(1))
+            .read_to_end(&mut out)?;
+        match out.len() as u64 > limit {
+            true => Err(too_long(limit)),
+            false => Ok(out),
+        }
+    }
+}
+
+/// Raw deflate of one block, ending on a byte boundary with a sync flush
+/// so the next block's stream can follow, or with the final block if it's
+/// the `last`
+fn deflate_block(block: &[u8], level: Compression, last: bool) -> Vec<u8> {
+    let flush = match last {
+        true => FlushCompress::Finish,
+        false => FlushCompress::Sync,
+    };
+    let mut compress = Compress::new(level, false);
+    let mut out = Vec::with_capacity(block.len() / 2 + 1024);
+    loop {
+        let read = compress.total_in() as usize;
+        let status = compress
+            .compress_vec(&block[read..], &mut out, flush)
+            .unwrap();
+        // A flush is complete once it leaves room in the output
+        let flushed = compress.total_in() as usize == block.len() && out.len() < out.capacity();
+        match status {
+            Status::StreamEnd => return out,
+            _ if !last && flushed => return out,
+            _ => out.reserve(out.capacity()),
+        }
+    }
+}
+
+const ADLER_MOD: u64 = 65521;
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u64, 0u64);
+    // The most bytes summed before `b` could overflow a u32, as zlib does
+    for run in data.chunks(5552) {
+        for &byte in run {
+            a += byte as u64;
+            b += a;
+        }
+        (a, b) = (a % ADLER_MOD, b % ADLER_MOD);
+    }
+    ((b << 16) | a) as u32
+}
+
+/// The Adler-32 of two runs of bytes, given each one's and the second's
+/// length
+fn adler32_combine(first: u32, second: u32, second_len: usize) -> u32 {
+    let (a1, b1) = ((first & 0xffff) as u64, (first >> 16) as u64);
+    let (a2, b2) = ((second & 0xffff) as u64, (second >> 16) as u64);
+    let len = second_len as u64 % ADLER_MOD;
+    let a = (a1 + a2 + ADLER_MOD - 1) % ADLER_MOD;
+    let b = (b1 + b2 + len * (a1 + ADLER_MOD - 1)) % ADLER_MOD;
+    ((b << 16) | a) as u32
+}
+
+/// A zlib stream of `data` compressed [`PARALLEL_BLOCK`] bytes at a time on
+/// the current rayon pool
+pub fn compress_blocks(data: &[u8], level: u32) -> Vec<u8> {
+    let level = level.min(BEST_LEVEL);
+    let blocks: Vec<&[u8]> = data.chunks(PARALLEL_BLOCK).collect();
+    let last = blocks.len().saturating_sub(1);
+    let deflated: Vec<(Vec<u8>, u32)> = blocks
+        .par_iter()
+        .enumerate()
+        .map(|(i, block)| {
+            let deflated = deflate_block(block, Compression::new(level), i == last);
+            (deflated, adler32(block))
+        })
+        .collect();
+
+    // The header's level hint, as zlib writes it
+    let flags = match level {
+        0 | 1 => 0x01,
+        2..=5 => 0x5e,
+        6 => 0x9c,
+        _ => 0xda,
+    };
+    let mut out = Vec::with_capacity(6 + deflated.iter().map(|(d, _)| d.len()).sum::<usize>());
+    out.extend_from_slice(&[0x78, flags]);
+    let mut adler = 1;
+    for ((deflated, block_adler), block) in deflated.iter().zip(&blocks) {
+        out.extend_from_slice(deflated);
+        adler = adler32_combine(adler, *block_adler, block.len());
+    }
+    if blocks.is_empty() {
+        out.extend_from_slice(&deflate_block(&[], Compression::new(level), true));
+    }
+    out.extend_from_slice(&adler.to_be_bytes());
+    out
+}
+
+/// libdeflate, which works on whole buffers only
+#[cfg(feature = "libdeflate")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Libdeflate;
+
+#[cfg(feature = "libdeflate")]
+impl Deflate for Libdeflate {
+    fn name(&self) -> &'static str {
+        "libdeflate"
+    }
+
+    fn compress(&self, data: &[u8], level: u32) -> Vec<u8> {
+        let level = libdeflater::CompressionLvl::new(level.min(BEST_LEVEL) as i32).unwrap();
+        let mut compressor = libdeflater::Compressor::new(level);
+        let mut out = vec![0; compressor.zlib_compress_bound(data.len())];
+        let len = compressor.zlib_compress(data, &mut out).unwrap();
+        out.truncate(len);
+        out
+    }
+
+    fn decompress(&self, data: &[u8], limit: u64) -> io::Result<Vec<u8>> {
+        let limit = usize::try_from(limit).unwrap_or(usize::MAX);
+        let mut decompressor = libdeflater::Decompressor::new();
+        // The output size isn't known up front, so grow until it fits
+        let mut size = data.len().saturating_mul(4).clamp(1, limit.max(1));
+        loop {
+            let mut out = vec![0; size];
+            match decompressor.zlib_decompress(data, &mut out) {
+                Ok(len) if len <= limit => {
+                    out.truncate(len);
+                    return Ok(out);
+                }
+                Ok(_) => return Err(too_long(limit as u64)),
+                Err(libdeflater::DecompressionError::InsufficientSpace) if size < limit => {
+                    size = size.saturating_mul(2).min(limit);
+                }
+                Err(libdeflater::DecompressionError::InsufficientSpace) => {
+                    return Err(too_long(limit as u64))
+                }
+                Err(libdeflater::DecompressionError::BadData) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "corrupt deflate stream",
+                    ))
+                }
+            }
+        }
+    }
+}
+
+/// The fastest backend built in
+pub fn backend() -> &'static dyn Deflate {
+    #[cfg(feature = "libdeflate")]
+    return &Libdeflate;
+    #[cfg(not(feature = "libdeflate"))]
+    return &Flate2;
+}
+
+/// Every backend built in, for comparing them
+pub fn backends() -> Vec<&'static dyn Deflate> {
+    vec![
+        &Flate2,
+        #[cfg(feature = "libdeflate")]
+        &Libdeflate,
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backends_agree() {
+        let data = "all work and no play ".repeat(100);
+        for backend in backends() {
+            for level in [0, DEFAULT_LEVEL, BEST_LEVEL] {
+                let compressed = backend.compress(data.as_bytes(), level);
+                for other in backends() {
+                    let limit = data.len() as u64;
+                    assert_eq!(
+                        other.decompress(&compressed, limit).unwrap(),
+                        data.as_bytes(),
+                        "{} at level {} read by {}",
+                        backend.name(),
+                        level,
+                        other.name()
+                    );
+                    assert!(other.decompress(&compressed, limit - 1).is_err());
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_compress_blocks() {
+        let data: Vec<u8> = (0..5 * PARALLEL_BLOCK / 2)
+            .map(|i| (i as u32).wrapping_mul(2654435761).rotate_left(7) as u8 % 16)
+            .collect();
+        assert_eq!(
+            adler32_combine(
+                adler32(&data[..1000]),
+                adler32(&data[1000..]),
+                data.len() - 1000
+            ),
+            adler32(&data)
+        );
+        for level in [1, DEFAULT_LEVEL, BEST_LEVEL] {
+            let compressed = compress_blocks(&data, level);
+            assert!(compressed.len() < data.len() * 2 / 3);
+            for backend in backends() {
+                let limit = data.len() as u64;
+                assert_eq!(backend.decompress(&compressed, limit).unwrap(), data);
+            }
+        }
+        assert_eq!(Flate2.decompress(&compress_blocks(b"", 6), 0).unwrap(), b"");
+    }
+
+    #[test]
+    fn test_inflate_limits() {
+        let bomb = Flate2.compress(&vec![0; 8 << 20], BEST_LEVEL);
+        assert!(bomb.len() < 16 << 10);
+        let limit = Limits::METADATA.output_for(bomb.len());
+        assert_eq!(limit, Limits::MIN_OUTPUT);
+        assert_eq!(
+            inflate(&bomb, limit),
+            Err(InflateError::DecompressionLimitExceeded(limit))
+        );
+        assert_eq!(inflate(&bomb, 8 << 20).unwrap().len(), 8 << 20);
+        assert_eq!(inflate(b"not zlib", limit), Err(InflateError::Corrupt));
+        assert_eq!(Limits::METADATA.output_for(1 << 30), 64 << 20);
+        assert_eq!(Limits::IMAGE.output_for(1 << 20), 4 << 30);
+    }
+
+    #[test]
+    fn test_corrupt() {
+        for backend in backends() {
+            assert!(backend.decompress(b"not zlib", 100).is_err());
+            assert_eq!(
+                backend.decompress(&backend.compress(b"", 6), 0).unwrap(),
+                b""
+            );
+        }
+    }
+}
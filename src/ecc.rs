@@ -0,0 +1,168 @@
+use std::fmt::Display;
+
+use reed_solomon::{Decoder, Encoder};
+
+/// Length of a Reed-Solomon codeword over GF(2^8)
+const CODEWORD_LEN: usize = 255;
+
+/// Most parity bytes per codeword the decoder can correct with; beyond
+/// this its locator polynomials outgrow its fixed buffers
+pub const MAX_PARITY: u8 = 127;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum EccError {
+    /// Parity must leave room for at least one data byte per codeword
+    InvalidParity(u8),
+    /// More damage in some codeword than its parity can repair
+    Uncorrectable,
+}
+
+impl Display for EccError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EccError::InvalidParity(parity) => write!(f, "invalid parity length {}", parity),
+            EccError::Uncorrectable => write!(f, "payload is too damaged to correct"),
+        }
+    }
+}
+
+impl std::error::Error for EccError {}
+
+fn codewords(data_len: usize, parity: usize) -> usize {
+    data_len.div_ceil(CODEWORD_LEN - parity).max(1)
+}
+
+/// Add `parity` Reed-Solomon bytes per codeword, interleaving the codewords
+/// byte by byte so a burst of damage, like a truncated tail, is spread
+/// thinly across all of them
+pub fn encode(data: &[u8], parity: u8) -> Result<Vec<u8>, EccError> {
+    if parity == 0 || parity > MAX_PARITY {
+        return Err(EccError::InvalidParity(parity));
+    }
+    let parity = parity as usize;
+    let encoder = Encoder::new(parity);
+    let count = codewords(data.len(), parity);
+    let mut padded = data.to_vec();
+    padded.resize(count * (CODEWORD_LEN - parity), 0);
+
+    let mut out = vec![0u8; count * CODEWORD_LEN];
+    for (i, block) in padded.chunks(CODEWORD_LEN - parity).enumerate() {
+        for (j, byte) in encoder.encode(block).iter().enumerate() {
+            out[j * count + i] = *byte;
+        }
+    }
+    Ok(out)
+}
+
+/// Recover `data_len` bytes from `encoded`, which may be damaged or cut
+/// short; returns the data and how many bytes needed correcting. Payloads
+/// written with more than [`MAX_PARITY`] bytes, as older releases allowed,
+/// are only read back undamaged
+pub fn decode(encoded: &[u8], data_len: usize, parity: u8) -> Result<(Vec<u8>, usize), EccError> {
+    let parity = parity as usize;
+    if parity == 0 || parity >= CODEWORD_LEN {
+        return Err(EccError::InvalidParity(parity as u8));
+    }
+    let decoder = Decoder::new(parity);
+    let count = codewords(data_len, parity);
+    // Every missing byte is an erasure, and a codeword can only repair as
+    // many as it has parity bytes; checking up front also keeps a forged
+    // length from sizing the buffer below
+    let missing = (count * CODEWORD_LEN).saturating_sub(encoded.len());
+    if missing > count * parity {
+        return Err(EccError::Uncorrectable);
+    }
+
+    let mut data = Vec::with_capacity(count * (CODEWORD_LEN - parity));
+    let mut corrected = 0;
+    for i in 0..count {
+        let mut codeword = [0u8; CODEWORD_LEN];
+        let mut erasures = Vec::new();
+        for (j, byte) in codeword.iter_mut().enumerate() {
+            match encoded.get(j * count + i) {
+                Some(b) => *byte = *b,
+                None => erasures.push(j as u8),
+            }
+        }
+        if erasures.len() > parity {
+            return Err(EccError::Uncorrectable);
+        }
+        if parity > MAX_PARITY as usize {
+            if !erasures.is_empty() || decoder.is_corrupted(&codeword) {
+                return Err(EccError::Uncorrectable);
+            }
+            data.extend_from_slice(&codeword[..CODEWORD_LEN - parity]);
+            continue;
+        }
+        let (buffer, fixed) = decoder
+            .correct_err_count(&codeword, Some(&erasures))
+            .map_err(|_| EccError::Uncorrectable)?;
+        data.extend_from_slice(buffer.data());
+        corrected += fixed;
+    }
+    data.truncate(data_len);
+    Ok((data, corrected))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn testing_data() -> Vec<u8> {
+        (0..1000).map(|i| (i * 13 % 256) as u8).collect()
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let data = testing_data();
+        let encoded = encode(&data, 16).unwrap();
+        // 239 data bytes per codeword
+        assert_eq!(encoded.len(), 5 * CODEWORD_LEN);
+        assert_eq!(decode(&encoded, data.len(), 16), Ok((data, 0)));
+    }
+
+    #[test]
+    fn test_corrects_flipped_bytes() {
+        let data = testing_data();
+        let mut encoded = encode(&data, 16).unwrap();
+        for i in [0, 17, 400, 401, 402, 999] {
+            encoded[i] ^= 0xff;
+        }
+        let (decoded, corrected) = decode(&encoded, data.len(), 16).unwrap();
+        assert_eq!(decoded, data);
+        assert_eq!(corrected, 6);
+    }
+
+    #[test]
+    fn test_corrects_truncation() {
+        let data = testing_data();
+        let encoded = encode(&data, 16).unwrap();
+        // five codewords, so 60 bytes cut is 12 erasures each
+        let cut = &encoded[..encoded.len() - 60];
+        assert_eq!(decode(cut, data.len(), 16).unwrap().0, data);
+    }
+
+    #[test]
+    fn test_uncorrectable() {
+        let data = testing_data();
+        let encoded = encode(&data, 4).unwrap();
+        let cut = &encoded[..encoded.len() / 2];
+        assert_eq!(decode(cut, data.len(), 4), Err(EccError::Uncorrectable));
+        assert_eq!(encode(&data, 0), Err(EccError::InvalidParity(0)));
+        // a forged length mustn't size the output
+        assert_eq!(
+            decode(&encoded, u32::MAX as usize, 4),
+            Err(EccError::Uncorrectable)
+        );
+    }
+
+    #[test]
+    fn test_high_parity() {
+        assert_eq!(encode(b"data", 128), Err(EccError::InvalidParity(128)));
+        let data = [7u8; 127];
+        let mut encoded = Encoder::new(128).encode(&data).to_vec();
+        assert_eq!(decode(&encoded, 127, 128), Ok((data.to_vec(), 0)));
+        encoded[3] ^= 1;
+        assert_eq!(decode(&encoded, 127, 128), Err(EccError::Uncorrectable));
+    }
+}
@@ -0,0 +1,286 @@
+//! Metadata in the JSON exiftool reads and writes with `-j` and `-json=`:
+//! an array with one object per file, keyed by `SourceFile`, mapping tag
+//! names to values. Text chunks are the tags carried across; the image
+//! header's tags are exported for reference but never imported
+
+use std::fmt::Display;
+
+use serde_json::{Map, Value};
+
+use crate::pixels::Ihdr;
+use crate::png::Png;
+use crate::text::TextChunk;
+
+/// Tag names of the keywords the PNG specification defines, where they
+/// differ from the keyword
+const STANDARD: [(&str, &str); 1] = [("Creation Time", "CreationTime")];
+
+/// Tags exiftool reports about the file or works out from other tags,
+/// which aren't metadata to copy
+const COMPUTED: [&str; 16] = [
+    "SourceFile",
+    "ExifToolVersion",
+    "FileName",
+    "Directory",
+    "FileSize",
+    "FileModifyDate",
+    "FileAccessDate",
+    "FileInodeChangeDate",
+    "FilePermissions",
+    "FileType",
+    "FileTypeExtension",
+    "MIMEType",
+    "ImageSize",
+    "Megapixels",
+    "Warning",
+    "Error",
+];
+
+const HEADER: [&str; 7] = [
+    "ImageWidth",
+    "ImageHeight",
+    "BitDepth",
+    "ColorType",
+    "Compression",
+    "Filter",
+    "Interlace",
+];
+
+#[derive(Debug)]
+pub enum ExiftoolError {
+    Json(serde_json::Error),
+    NoEntry(String),
+}
+
+impl Display for ExiftoolError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ExiftoolError::Json(e) => write!(f, "invalid exiftool JSON: {}", e),
+            ExiftoolError::NoEntry(file) => write!(f, "no metadata for {}", file),
+        }
+    }
+}
+
+impl std::error::Error for ExiftoolError {}
+
+impl From<serde_json::Error> for ExiftoolError {
+    fn from(e: serde_json::Error) -> Self {
+        ExiftoolError::Json(e)
+    }
+}
+
+/// exiftool's name for a text keyword: its words capitalized and joined
+pub fn tag_name(keyword: &str) -> String {
+    if let Some((_, tag)) = STANDARD.iter().find(|(k, _)| *k == keyword) {
+        return tag.to_string();
+    }
+    keyword
+        .split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(|word| {
+            let mut chars = word.chars();
+            let first = chars.next().unwrap().to_ascii_uppercase();
+            std::iter::once(first).chain(chars).collect::<String>()
+        })
+        .collect()
+}
+
+/// The keyword to store a tag under; tags other than the standard ones
+/// keep their name
+pub fn keyword(tag: &str) -> String {
+    match STANDARD.iter().find(|(_, t)| *t == tag) {
+        Some((keyword, _)) => keyword.to_string(),
+        None => tag.to_string(),
+    }
+}
+
+fn color_type(color_type: u8) -> String {
+    match color_type {
+        0 => "Grayscale".to_string(),
+        2 => "RGB".to_string(),
+        3 => "Palette".to_string(),
+        4 => "Grayscale with Alpha".to_string(),
+        6 => "RGB with Alpha".to_string(),
+        other => format!("Unknown ({})", other),
+    }
+}
+
+/// A text chunk exiftool decodes into other tags rather than showing as
+/// text: XMP and raw profiles
+fn decoded_elsewhere(keyword: &str) -> bool {
+    keyword == "XML:com.adobe.xmp" || keyword.starts_with("Raw profile type ")
+}
+
+/// The file's tags in the order exiftool lists them: the header's, then
+/// one per text keyword, later chunks' text winning
+pub fn tags(png: &Png) -> Vec<(String, Value)> {
+    let mut tags: Vec<(String, Value)> = Vec::new();
+    if let Some(ihdr) = png
+        .chunk_by_type("IHDR")
+        .and_then(|c| Ihdr::parse(c.data()).ok())
+    {
+        let interlace = match ihdr.interlace {
+            0 => "Noninterlaced",
+            _ => "Adam7 Interlace",
+        };
+        let values = [
+            Value::from(ihdr.width),
+            Value::from(ihdr.height),
+            Value::from(ihdr.bit_depth),
+            Value::from(color_type(ihdr.color_type)),
+            Value::from("Deflate/Inflate"),
+            Value::from("Adaptive"),
+            Value::from(interlace),
+        ];
+        tags.extend(HEADER.iter().map(|t| t.to_string()).zip(values));
+    }
+    for text in png
+        .chunks()
+        .iter()
+        .filter_map(|c| TextChunk::parse(c)?.ok())
+    {
+        if decoded_elsewhere(&text.keyword) {
+            continue;
+        }
+        let tag = tag_name(&text.keyword);
+        match tags.iter_mut().find(|(t, _)| *t == tag) {
+            Some((_, value)) => *value = Value::from(text.text),
+            None => tags.push((tag, Value::from(text.text))),
+        }
+    }
+    tags
+}
+
+/// exiftool `-j` output for `files`, each a name and its parsed file
+pub fn export<'a>(files: impl IntoIterator<Item = (&'a str, &'a Png)>) -> String {
+    let entries: Vec<String> = files
+        .into_iter()
+        .map(|(name, png)| {
+            let source = (String::from("SourceFile"), Value::from(name));
+            let lines: Vec<String> = std::iter::once(source)
+                .chain(tags(png))
+                .map(|(tag, value)| format!("  {}: {}", Value::from(tag), value))
+                .collect();
+            format!("{{\n{}\n}}", lines.join(",\n"))
+        })
+        .collect();
+    format!("[{}]\n", entries.join(",\n"))
+}
+
+/// The objects of exiftool JSON
+pub fn parse(json: &str) -> Result<Vec<Map<String, Value>>, ExiftoolError> {
+    Ok(serde_json::from_str(json)?)
+}
+
+/// The entry for `file` among `entries`: the one whose SourceFile it is,
+/// or the only one there is
+pub fn entry_for<'a>(
+    entries: &'a [Map<String, Value>],
+    file: &str,
+) -> Result<&'a Map<String, Value>, ExiftoolError> {
+    let named = entries
+        .iter()
+        .find(|e| e.get("SourceFile").and_then(Value::as_str) == Some(file));
+    match (named, entries) {
+        (Some(entry), _) | (None, [entry]) => Ok(entry),
+        _ => Err(ExiftoolError::NoEntry(file.to_string())),
+    }
+}
+
+/// Write `entry`'s tags into `png` as text chunks, replacing text with the
+/// same keyword, and return how many were written. Group prefixes like
+/// `PNG:` from `-G` are dropped; header and file tags, and values that
+/// aren't text, numbers or booleans, are skipped
+pub fn import(png: &mut Png, entry: &Map<String, Value>) -> usize {
+    let mut written = 0;
+    for (tag, value) in entry {
+        let tag = tag.rsplit(':').next().unwrap_or(tag);
+        if COMPUTED.contains(&tag) || HEADER.contains(&tag) {
+            continue;
+        }
+        let text = match value {
+            Value::String(text) => text.clone(),
+            Value::Number(_) | Value::Bool(_) => value.to_string(),
+            _ => continue,
+        };
+        let text = TextChunk {
+            keyword: keyword(tag),
+            text,
+        };
+        if png.set_text(&text).is_ok() {
+            written += 1;
+        }
+    }
+    written
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::Chunk;
+    use crate::pixels::tests::testing_png;
+
+    fn text(png: &Png) -> Vec<(String, String)> {
+        png.chunks()
+            .iter()
+            .filter_map(|c| TextChunk::parse(c)?.ok())
+            .map(|t| (t.keyword, t.text))
+            .collect()
+    }
+
+    #[test]
+    fn test_tag_names() {
+        assert_eq!(tag_name("Author"), "Author");
+        assert_eq!(tag_name("Creation Time"), "CreationTime");
+        assert_eq!(tag_name("my render-settings"), "MyRenderSettings");
+        assert_eq!(keyword("CreationTime"), "Creation Time");
+        assert_eq!(keyword("Comment"), "Comment");
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let mut png = testing_png(4, 4);
+        for data in [
+            &b"Author\0Ferris"[..],
+            b"Creation Time\x002024-01-01",
+            b"Raw profile type exif\0x",
+        ] {
+            png.append_chunk(Chunk::new("tEXt".parse().unwrap(), data.to_vec()));
+        }
+        let json = export([("a.png", &png)]);
+        assert!(json.starts_with("[{\n  \"SourceFile\": \"a.png\",\n  \"ImageWidth\": 4,"));
+        assert!(json.contains("\"ColorType\": \"RGB\""));
+        assert!(!json.contains("Raw"));
+
+        let entries = parse(&json).unwrap();
+        let mut copy = testing_png(4, 4);
+        assert_eq!(import(&mut copy, entry_for(&entries, "b.png").unwrap()), 2);
+        assert_eq!(
+            text(&copy),
+            [
+                ("Author".to_string(), "Ferris".to_string()),
+                ("Creation Time".to_string(), "2024-01-01".to_string())
+            ]
+        );
+
+        let exiftool = r#"[{"SourceFile": "x.png", "PNG:Author": "Corro", "Rating": 5, "FileSize": "1 kB", "Subject": ["a"]}]"#;
+        let entries = parse(exiftool).unwrap();
+        assert_eq!(import(&mut copy, &entries[0]), 2);
+        assert_eq!(
+            text(&copy),
+            [
+                ("Author".to_string(), "Corro".to_string()),
+                ("Creation Time".to_string(), "2024-01-01".to_string()),
+                ("Rating".to_string(), "5".to_string())
+            ]
+        );
+
+        let two = parse(r#"[{"SourceFile": "x.png"}, {"SourceFile": "y.png"}]"#).unwrap();
+        assert!(entry_for(&two, "y.png").is_ok());
+        assert!(matches!(
+            entry_for(&two, "z.png"),
+            Err(ExiftoolError::NoEntry(_))
+        ));
+        assert!(matches!(parse("{}"), Err(ExiftoolError::Json(_))));
+    }
+}
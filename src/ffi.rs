@@ -0,0 +1,364 @@
+//! C API for the `cdylib` build; `include/ping.h` declares it. Every call
+//! returns a [`PingStatus`], leaves a description of any failure for
+//! [`ping_last_error`] and hands results back in a [`PingBuffer`] the caller
+//! releases with [`ping_free`]. A failed call leaves an empty buffer, so
+//! freeing it either way is safe
+
+use std::cell::RefCell;
+use std::ffi::{c_char, CStr, CString};
+use std::fmt::Display;
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+use std::str::FromStr;
+
+use crate::chunk_type::{ChunkError, ChunkType};
+use crate::message;
+use crate::png::{Png, PngError};
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PingStatus {
+    PingOk = 0,
+    /// A null pointer, invalid UTF-8 or an invalid chunk type
+    PingInvalidArgument = 1,
+    /// The input isn't a PNG file
+    PingInvalidPng = 2,
+    PingChunkNotFound = 3,
+    /// Anything else, such as a wrong password
+    PingFailed = 4,
+    /// A bug; the library caught a panic rather than unwind into C
+    PingPanicked = 5,
+}
+
+/// Bytes allocated by the library, to be released with [`ping_free`]
+#[repr(C)]
+#[derive(Debug)]
+pub struct PingBuffer {
+    pub data: *mut u8,
+    pub len: usize,
+}
+
+impl PingBuffer {
+    fn new(bytes: Vec<u8>) -> PingBuffer {
+        let len = bytes.len();
+        PingBuffer {
+            data: Box::into_raw(bytes.into_boxed_slice()).cast(),
+            len,
+        }
+    }
+
+    fn empty() -> PingBuffer {
+        PingBuffer {
+            data: ptr::null_mut(),
+            len: 0,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct InvalidArgument(&'static str);
+
+impl Display for InvalidArgument {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "invalid argument: {}", self.0)
+    }
+}
+
+impl std::error::Error for InvalidArgument {}
+
+thread_local! {
+    static LAST_ERROR: RefCell<CString> = RefCell::new(CString::default());
+}
+
+fn status_of(error: &crate::Error) -> PingStatus {
+    if error.is::<InvalidArgument>() || error.is::<ChunkError>() {
+        return PingStatus::PingInvalidArgument;
+    }
+    match error.downcast_ref::<PngError>() {
+        Some(PngError::ChunkNotFound(_)) => PingStatus::PingChunkNotFound,
+        Some(_) => PingStatus::PingInvalidPng,
+        None => PingStatus::PingFailed,
+    }
+}
+
+fn set_last_error(message: String) {
+    let message = CString::new(message.replace('\0', " ")).unwrap_or_default();
+    LAST_ERROR.with(|last| *last.borrow_mut() = message);
+}
+
+/// Run `call`, storing its result in `out`, or an empty buffer and its
+/// failure in the last error
+fn run(out: *mut PingBuffer, call: impl FnOnce() -> crate::Result<Vec<u8>>) -> PingStatus {
+    if out.is_null() {
+        set_last_error(InvalidArgument("out").to_string());
+        return PingStatus::PingInvalidArgument;
+    }
+    let (buffer, status) = match panic::catch_unwind(AssertUnwindSafe(call)) {
+        Ok(Ok(bytes)) => {
+            set_last_error(String::new());
+            (PingBuffer::new(bytes), PingStatus::PingOk)
+        }
+        Ok(Err(e)) => {
+            set_last_error(e.to_string());
+            (PingBuffer::empty(), status_of(&e))
+        }
+        Err(_) => {
+            set_last_error("internal error: the library panicked".to_string());
+            (PingBuffer::empty(), PingStatus::PingPanicked)
+        }
+    };
+    // SAFETY: checked non-null; the caller promises it's writable
+    unsafe { out.write(buffer) };
+    status
+}
+
+/// # Safety
+///
+/// `data` must be null or point to `len` readable bytes that outlive `'a`
+unsafe fn bytes<'a>(data: *const u8, len: usize, name: &'static str) -> crate::Result<&'a [u8]> {
+    match data.is_null() {
+        true if len == 0 => Ok(&[]),
+        true => Err(InvalidArgument(name).into()),
+        false => Ok(std::slice::from_raw_parts(data, len)),
+    }
+}
+
+/// # Safety
+///
+/// `string` must be null or a NUL-terminated string that outlives `'a`
+unsafe fn string<'a>(string: *const c_char, name: &'static str) -> crate::Result<Option<&'a str>> {
+    match string.is_null() {
+        true => Ok(None),
+        false => Ok(Some(
+            CStr::from_ptr(string)
+                .to_str()
+                .map_err(|_| InvalidArgument(name))?,
+        )),
+    }
+}
+
+/// # Safety
+///
+/// As for [`string`]
+unsafe fn chunk_type(chunk_type: *const c_char) -> crate::Result<ChunkType> {
+    let name = string(chunk_type, "chunk_type")?.ok_or(InvalidArgument("chunk_type"))?;
+    Ok(ChunkType::from_str(name)?)
+}
+
+/// Parse the `len` bytes at `data` as a PNG file and write its chunk
+/// listing, one line per chunk and not NUL-terminated, to `out`
+///
+/// # Safety
+///
+/// `data` must point to `len` readable bytes and `out` to a writable
+/// [`PingBuffer`]
+#[no_mangle]
+pub unsafe extern "C" fn ping_parse(
+    data: *const u8,
+    len: usize,
+    out: *mut PingBuffer,
+) -> PingStatus {
+    run(out, || {
+        let png = Png::try_from(bytes(data, len, "data")?)?;
+        Ok(png.to_string().into_bytes())
+    })
+}
+
+/// Add `message_len` bytes at `message` in a new chunk of `chunk_type`,
+/// encrypted under `password` unless it's null, and write the new file to
+/// `out`
+///
+/// # Safety
+///
+/// `data` and `message` must point to that many readable bytes,
+/// `chunk_type` and `password` must be NUL-terminated strings, `password`
+/// may be null, and `out` must point to a writable [`PingBuffer`]
+#[no_mangle]
+pub unsafe extern "C" fn ping_encode(
+    data: *const u8,
+    len: usize,
+    chunk_type: *const c_char,
+    message: *const u8,
+    message_len: usize,
+    password: *const c_char,
+    out: *mut PingBuffer,
+) -> PingStatus {
+    run(out, || {
+        let mut png = Png::try_from(bytes(data, len, "data")?)?;
+        let message_bytes = bytes(message, message_len, "message")?;
+        message::embed(
+            &mut png,
+            self::chunk_type(chunk_type)?,
+            message_bytes,
+            string(password, "password")?,
+        )?;
+        Ok(png.as_bytes())
+    })
+}
+
+/// Write the message in the chunks of `chunk_type` to `out`, decrypting it
+/// with `password` if it's encrypted
+///
+/// # Safety
+///
+/// As for [`ping_encode`]
+#[no_mangle]
+pub unsafe extern "C" fn ping_decode(
+    data: *const u8,
+    len: usize,
+    chunk_type: *const c_char,
+    password: *const c_char,
+    out: *mut PingBuffer,
+) -> PingStatus {
+    run(out, || {
+        let png = Png::try_from(bytes(data, len, "data")?)?;
+        message::extract(
+            &png,
+            &self::chunk_type(chunk_type)?,
+            string(password, "password")?,
+        )
+    })
+}
+
+/// Release a buffer filled in by the library; an empty buffer is ignored
+///
+/// # Safety
+///
+/// `buffer` must have come from this library and not been freed before
+#[no_mangle]
+pub unsafe extern "C" fn ping_free(buffer: PingBuffer) {
+    if !buffer.data.is_null() {
+        drop(Box::from_raw(ptr::slice_from_raw_parts_mut(
+            buffer.data,
+            buffer.len,
+        )));
+    }
+}
+
+/// Description of the last failure on this thread, empty after a success.
+/// Valid until the next call on the same thread
+#[no_mangle]
+pub extern "C" fn ping_last_error() -> *const c_char {
+    LAST_ERROR.with(|last| last.borrow().as_ptr())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pixels::tests::testing_png;
+
+    fn empty() -> PingBuffer {
+        PingBuffer::empty()
+    }
+
+    fn last_error() -> String {
+        unsafe { CStr::from_ptr(ping_last_error()) }
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let file = testing_png(4, 4).as_bytes();
+        let chunk_type = c"ruSt";
+        let password = c"hunter2";
+        let (mut encoded, mut decoded, mut listing) = (empty(), empty(), empty());
+        unsafe {
+            let status = ping_encode(
+                file.as_ptr(),
+                file.len(),
+                chunk_type.as_ptr(),
+                b"attack at dawn".as_ptr(),
+                14,
+                password.as_ptr(),
+                &mut encoded,
+            );
+            assert_eq!(status, PingStatus::PingOk);
+            assert_eq!(
+                ping_decode(
+                    encoded.data,
+                    encoded.len,
+                    chunk_type.as_ptr(),
+                    password.as_ptr(),
+                    &mut decoded,
+                ),
+                PingStatus::PingOk
+            );
+            assert_eq!(
+                std::slice::from_raw_parts(decoded.data, decoded.len),
+                b"attack at dawn"
+            );
+            assert_eq!(
+                ping_parse(encoded.data, encoded.len, &mut listing),
+                PingStatus::PingOk
+            );
+            assert_eq!(
+                String::from_utf8_lossy(std::slice::from_raw_parts(listing.data, listing.len))
+                    .lines()
+                    .count(),
+                4
+            );
+            ping_free(encoded);
+            ping_free(decoded);
+            ping_free(listing);
+        }
+    }
+
+    #[test]
+    fn test_errors() {
+        // Whatever the caller left in it, a failure empties the buffer
+        let mut out = PingBuffer {
+            data: ptr::NonNull::dangling().as_ptr(),
+            len: 1,
+        };
+        unsafe {
+            assert_eq!(
+                ping_parse(b"nope".as_ptr(), 4, &mut out),
+                PingStatus::PingInvalidPng
+            );
+            assert!(out.data.is_null() && out.len == 0);
+            assert_eq!(
+                last_error(),
+                "invalid png signature: byte 0 is 0x6e, not 0x89"
+            );
+
+            let file = testing_png(4, 4).as_bytes();
+            assert_eq!(
+                ping_decode(
+                    file.as_ptr(),
+                    file.len(),
+                    c"ruSt".as_ptr(),
+                    ptr::null(),
+                    &mut out
+                ),
+                PingStatus::PingChunkNotFound
+            );
+            assert_eq!(
+                ping_decode(
+                    file.as_ptr(),
+                    file.len(),
+                    ptr::null(),
+                    ptr::null(),
+                    &mut out
+                ),
+                PingStatus::PingInvalidArgument
+            );
+            assert_eq!(
+                ping_parse(file.as_ptr(), file.len(), ptr::null_mut()),
+                PingStatus::PingInvalidArgument
+            );
+            ping_free(empty());
+        }
+        assert!(out.data.is_null());
+    }
+
+    #[test]
+    fn test_header_is_current() {
+        let generated = include_str!(concat!(env!("OUT_DIR"), "/ping.h"));
+        assert!(
+            generated == include_str!("../include/ping.h"),
+            "include/ping.h is out of date; regenerate it with \
+             cbindgen --config cbindgen.toml --output include/ping.h"
+        );
+    }
+}
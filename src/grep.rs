@@ -0,0 +1,201 @@
+use regex::{bytes, Regex};
+
+use crate::png::Png;
+use crate::text::TextChunk;
+
+/// Characters of surrounding text printed on each side of a match
+const CONTEXT: usize = 32;
+
+/// A match found inside one chunk of a file
+#[derive(Debug, PartialEq, Eq)]
+pub struct Match {
+    pub index: usize,
+    pub chunk_type: String,
+    pub keyword: Option<String>,
+    pub offset: usize,
+    pub context: String,
+}
+
+pub struct Matcher {
+    text: Regex,
+    bytes: bytes::Regex,
+    chunk_type: Option<String>,
+    raw: bool,
+}
+
+impl Matcher {
+    /// Build a matcher; without `regex` the pattern is matched literally
+    pub fn new(
+        pattern: &str,
+        regex: bool,
+        chunk_type: Option<String>,
+        raw: bool,
+    ) -> Result<Matcher, regex::Error> {
+        let pattern = if regex {
+            pattern.to_string()
+        } else {
+            regex::escape(pattern)
+        };
+        Ok(Matcher {
+            text: Regex::new(&pattern)?,
+            bytes: bytes::Regex::new(&pattern)?,
+            chunk_type,
+            raw,
+        })
+    }
+
+    /// Search text chunks by keyword and decoded text, and the payload of
+    /// every other chunk when raw matching is enabled
+    pub fn find(&self, png: &Png) -> Vec<Match> {
+        let mut matches = Vec::new();
+        for (index, chunk) in png.chunks().iter().enumerate() {
+            let chunk_type = chunk.chunk_type().to_string();
+            if self.chunk_type.as_deref().is_some_and(|t| t != chunk_type) {
+                continue;
+            }
+            match TextChunk::parse(chunk) {
+                Some(Ok(text)) => {
+                    for haystack in [&text.keyword, &text.text] {
+                        for m in self.text.find_iter(haystack) {
+                            matches.push(Match {
+                                index,
+                                chunk_type: chunk_type.clone(),
+                                keyword: Some(text.keyword.clone()),
+                                offset: m.start(),
+                                context: context(haystack, m.start(), m.end()),
+                            });
+                        }
+                    }
+                }
+                _ if self.raw => {
+                    let printable = printable(chunk.data());
+                    for m in self.bytes.find_iter(chunk.data()) {
+                        matches.push(Match {
+                            index,
+                            chunk_type: chunk_type.clone(),
+                            keyword: None,
+                            offset: m.start(),
+                            context: context(&printable, m.start(), m.end()),
+                        });
+                    }
+                }
+                _ => {}
+            }
+        }
+        matches
+    }
+}
+
+/// One character per byte, with anything unprintable shown as '.'
+pub fn printable(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .map(|b| {
+            if b.is_ascii_graphic() || *b == b' ' {
+                *b as char
+            } else {
+                '.'
+            }
+        })
+        .collect()
+}
+
+/// Window of `text` around the byte range `start..end`, on one line
+fn context(text: &str, start: usize, end: usize) -> String {
+    let from = text[..start]
+        .char_indices()
+        .rev()
+        .nth(CONTEXT - 1)
+        .map_or(0, |(i, _)| i);
+    let to = text[end..]
+        .char_indices()
+        .nth(CONTEXT)
+        .map_or(text.len(), |(i, _)| end + i);
+    text[from..to].replace(['\n', '\r', '\t'], " ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::Chunk;
+    use crate::chunk_type::ChunkType;
+    use std::str::FromStr;
+
+    fn testing_png() -> Png {
+        let chunk = |t: &str, d: &[u8]| Chunk::new(ChunkType::from_str(t).unwrap(), d.to_vec());
+        Png::from_chunks(vec![
+            chunk("tEXt", b"Copyright\0(c) 2023 Acme Corp"),
+            chunk("ruSt", b"\x01\x02hidden marker\xff"),
+            chunk("IEND", b""),
+        ])
+    }
+
+    #[test]
+    fn test_literal_text_match() {
+        let matcher = Matcher::new("Acme", false, None, false).unwrap();
+        let matches = matcher.find(&testing_png());
+        assert_eq!(
+            matches,
+            [Match {
+                index: 0,
+                chunk_type: "tEXt".to_string(),
+                keyword: Some("Copyright".to_string()),
+                offset: 9,
+                context: "(c) 2023 Acme Corp".to_string(),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_keyword_match() {
+        let matcher = Matcher::new("Copyright", false, None, false).unwrap();
+        assert_eq!(matcher.find(&testing_png()).len(), 1);
+    }
+
+    #[test]
+    fn test_literal_escapes_pattern() {
+        let matcher = Matcher::new("(c)", false, None, false).unwrap();
+        assert_eq!(matcher.find(&testing_png())[0].offset, 0);
+    }
+
+    #[test]
+    fn test_regex_match() {
+        let matcher = Matcher::new(r"\d{4}", true, None, false).unwrap();
+        assert_eq!(
+            matcher.find(&testing_png())[0].context,
+            "(c) 2023 Acme Corp"
+        );
+    }
+
+    #[test]
+    fn test_raw_match() {
+        let without_raw = Matcher::new("marker", false, None, false).unwrap();
+        assert!(without_raw.find(&testing_png()).is_empty());
+
+        let with_raw = Matcher::new("marker", false, None, true).unwrap();
+        let matches = with_raw.find(&testing_png());
+        assert_eq!(matches.len(), 1);
+        assert_eq!(matches[0].chunk_type, "ruSt");
+        assert_eq!(matches[0].offset, 9);
+        assert_eq!(matches[0].context, "..hidden marker.");
+    }
+
+    #[test]
+    fn test_chunk_type_filter() {
+        let matcher = Matcher::new("e", false, Some("ruSt".to_string()), true).unwrap();
+        assert!(matcher
+            .find(&testing_png())
+            .iter()
+            .all(|m| m.chunk_type == "ruSt"));
+    }
+
+    #[test]
+    fn test_context_window() {
+        let text = "a".repeat(100) + "needle" + &"b".repeat(100);
+        let window = context(&text, 100, 106);
+        assert_eq!(
+            window,
+            "a".repeat(CONTEXT) + "needle" + &"b".repeat(CONTEXT)
+        );
+    }
+}
@@ -0,0 +1,316 @@
+//! Typed access to application-specific chunks. A [`ChunkHandler`] turns
+//! the data of one private chunk type into a value and back; a [`Registry`]
+//! holds an application's handlers so whole files can be checked against
+//! them
+
+use std::any::Any;
+use std::fmt::Display;
+
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::png::Png;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum HandlerError {
+    /// Handlers are for private chunk types; public ones belong to the
+    /// PNG specification and its registered extensions
+    NotPrivate(ChunkType),
+    AlreadyRegistered(ChunkType),
+    NotRegistered(String),
+    /// The handler for the chunk type produces some other type of value
+    WrongValueType(ChunkType),
+    /// The handler rejected the chunk's data
+    Invalid {
+        chunk_type: ChunkType,
+        reason: String,
+    },
+}
+
+impl Display for HandlerError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            HandlerError::NotPrivate(chunk_type) => {
+                write!(f, "{} is a public chunk type", chunk_type)
+            }
+            HandlerError::AlreadyRegistered(chunk_type) => {
+                write!(f, "a handler for {} is already registered", chunk_type)
+            }
+            HandlerError::NotRegistered(chunk_type) => {
+                write!(f, "no handler is registered for {}", chunk_type)
+            }
+            HandlerError::WrongValueType(chunk_type) => {
+                write!(
+                    f,
+                    "the handler for {} produces a different type",
+                    chunk_type
+                )
+            }
+            HandlerError::Invalid { chunk_type, reason } => {
+                write!(f, "invalid {} chunk: {}", chunk_type, reason)
+            }
+        }
+    }
+}
+
+impl std::error::Error for HandlerError {}
+
+/// Reads and writes the data of one private chunk type
+pub trait ChunkHandler {
+    type Value;
+    type Error: Display;
+
+    fn chunk_type(&self) -> ChunkType;
+
+    fn parse(&self, data: &[u8]) -> Result<Self::Value, Self::Error>;
+
+    fn serialize(&self, value: &Self::Value) -> Vec<u8>;
+
+    /// Check a parsed value, for rules `parse` doesn't enforce; accepts
+    /// everything unless overridden
+    fn validate(&self, _value: &Self::Value) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    /// Parse and validate
+    fn read(&self, data: &[u8]) -> Result<Self::Value, HandlerError> {
+        let invalid = |e: Self::Error| HandlerError::Invalid {
+            chunk_type: self.chunk_type(),
+            reason: e.to_string(),
+        };
+        let value = self.parse(data).map_err(invalid)?;
+        self.validate(&value).map_err(invalid)?;
+        Ok(value)
+    }
+
+    /// Validate and serialize into a chunk
+    fn write(&self, value: &Self::Value) -> Result<Chunk, HandlerError> {
+        self.validate(value).map_err(|e| HandlerError::Invalid {
+            chunk_type: self.chunk_type(),
+            reason: e.to_string(),
+        })?;
+        Ok(Chunk::new(self.chunk_type(), self.serialize(value)))
+    }
+}
+
+/// A handler with its value type erased, so handlers for different types
+/// can share a registry
+trait AnyHandler: Send + Sync {
+    fn read_any(&self, data: &[u8]) -> Result<Box<dyn Any>, HandlerError>;
+}
+
+impl<H> AnyHandler for H
+where
+    H: ChunkHandler + Send + Sync,
+    H::Value: 'static,
+{
+    fn read_any(&self, data: &[u8]) -> Result<Box<dyn Any>, HandlerError> {
+        Ok(Box::new(self.read(data)?))
+    }
+}
+
+/// An application's chunk handlers, at most one per chunk type
+#[derive(Default)]
+pub struct Registry {
+    handlers: Vec<(ChunkType, Box<dyn AnyHandler>)>,
+}
+
+impl Registry {
+    pub fn new() -> Registry {
+        Registry::default()
+    }
+
+    pub fn register<H>(&mut self, handler: H) -> Result<(), HandlerError>
+    where
+        H: ChunkHandler + Send + Sync + 'static,
+        H::Value: 'static,
+    {
+        let chunk_type = handler.chunk_type();
+        if chunk_type.is_public() {
+            return Err(HandlerError::NotPrivate(chunk_type));
+        }
+        if self.find(chunk_type.as_str()).is_some() {
+            return Err(HandlerError::AlreadyRegistered(chunk_type));
+        }
+        self.handlers.push((chunk_type, Box::new(handler)));
+        Ok(())
+    }
+
+    pub fn handles(&self, chunk_type: &str) -> bool {
+        self.find(chunk_type).is_some()
+    }
+
+    /// The chunk types with handlers, in the order they were registered
+    pub fn chunk_types(&self) -> impl Iterator<Item = ChunkType> + '_ {
+        self.handlers.iter().map(|(chunk_type, _)| *chunk_type)
+    }
+
+    fn find(&self, chunk_type: &str) -> Option<(ChunkType, &dyn AnyHandler)> {
+        self.handlers
+            .iter()
+            .find(|(t, _)| t.as_str() == chunk_type)
+            .map(|(t, handler)| (*t, handler.as_ref()))
+    }
+
+    /// The value of the first `chunk_type` chunk in `png`, or `None` if it
+    /// has none
+    pub fn get<V: 'static>(&self, png: &Png, chunk_type: &str) -> Option<Result<V, HandlerError>> {
+        let (found, handler) = match self.find(chunk_type) {
+            Some(found) => found,
+            None => return Some(Err(HandlerError::NotRegistered(chunk_type.to_string()))),
+        };
+        let chunk = png.chunk_by_type(chunk_type)?;
+        Some(handler.read_any(chunk.data()).and_then(|value| {
+            value
+                .downcast()
+                .map(|value| *value)
+                .map_err(|_| HandlerError::WrongValueType(found))
+        }))
+    }
+
+    /// Check every chunk of `png` that has a handler, stopping at the first
+    /// one rejected
+    pub fn validate(&self, png: &Png) -> Result<(), HandlerError> {
+        for chunk in png.chunks() {
+            if let Some((_, handler)) = self.find(chunk.chunk_type().as_str()) {
+                handler.read_any(chunk.data())?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Png {
+    /// The value of the first chunk `handler` handles, or `None` if there's
+    /// none
+    pub fn typed_chunk<H: ChunkHandler>(
+        &self,
+        handler: &H,
+    ) -> Option<Result<H::Value, HandlerError>> {
+        let chunk = self.chunk_by_type(handler.chunk_type().as_str())?;
+        Some(handler.read(chunk.data()))
+    }
+
+    /// Store `value` in place of any chunks `handler` handles, where the
+    /// first of them was or else before IEND
+    pub fn set_typed_chunk<H: ChunkHandler>(
+        &mut self,
+        handler: &H,
+        value: &H::Value,
+    ) -> Result<(), HandlerError> {
+        let chunk = handler.write(value)?;
+        self.replace_chunks(handler.chunk_type().as_str(), vec![chunk]);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pixels::tests::testing_png;
+    use std::str::FromStr;
+
+    /// Rendering progress as a percentage in one byte
+    struct Progress;
+
+    impl ChunkHandler for Progress {
+        type Value = u8;
+        type Error = String;
+
+        fn chunk_type(&self) -> ChunkType {
+            ChunkType::from_str("prGs").unwrap()
+        }
+
+        fn parse(&self, data: &[u8]) -> Result<u8, String> {
+            match data {
+                [percent] => Ok(*percent),
+                _ => Err(format!("expected 1 byte, found {}", data.len())),
+            }
+        }
+
+        fn serialize(&self, value: &u8) -> Vec<u8> {
+            vec![*value]
+        }
+
+        fn validate(&self, value: &u8) -> Result<(), String> {
+            match *value <= 100 {
+                true => Ok(()),
+                false => Err(format!("{}% is over 100", value)),
+            }
+        }
+    }
+
+    struct Label(&'static str);
+
+    impl ChunkHandler for Label {
+        type Value = String;
+        type Error = std::string::FromUtf8Error;
+
+        fn chunk_type(&self) -> ChunkType {
+            ChunkType::from_str(self.0).unwrap()
+        }
+
+        fn parse(&self, data: &[u8]) -> Result<String, Self::Error> {
+            String::from_utf8(data.to_vec())
+        }
+
+        fn serialize(&self, value: &String) -> Vec<u8> {
+            value.as_bytes().to_vec()
+        }
+    }
+
+    #[test]
+    fn test_typed_chunk() {
+        let mut png = testing_png(4, 4);
+        assert_eq!(png.typed_chunk(&Progress), None);
+        png.set_typed_chunk(&Progress, &40).unwrap();
+        png.set_typed_chunk(&Progress, &75).unwrap();
+        assert_eq!(png.chunks_by_type("prGs").count(), 1);
+        assert_eq!(png.typed_chunk(&Progress), Some(Ok(75)));
+
+        let error = png.set_typed_chunk(&Progress, &101).unwrap_err();
+        assert_eq!(error.to_string(), "invalid prGs chunk: 101% is over 100");
+        png.replace_chunks("prGs", vec![Chunk::new(Progress.chunk_type(), vec![1, 2])]);
+        assert!(matches!(
+            png.typed_chunk(&Progress),
+            Some(Err(HandlerError::Invalid { .. }))
+        ));
+    }
+
+    #[test]
+    fn test_registry() {
+        let mut registry = Registry::new();
+        registry.register(Progress).unwrap();
+        registry.register(Label("lbEl")).unwrap();
+        assert_eq!(
+            registry.register(Label("lbEl")),
+            Err(HandlerError::AlreadyRegistered(Label("lbEl").chunk_type()))
+        );
+        assert_eq!(
+            registry.register(Label("tEXt")),
+            Err(HandlerError::NotPrivate(Label("tEXt").chunk_type()))
+        );
+        assert!(registry.handles("lbEl") && !registry.handles("tEXt"));
+
+        let mut png = testing_png(4, 4);
+        png.set_typed_chunk(&Progress, &50).unwrap();
+        png.set_typed_chunk(&Label("lbEl"), &"cover".to_string())
+            .unwrap();
+        assert_eq!(registry.get::<u8>(&png, "prGs"), Some(Ok(50)));
+        assert_eq!(
+            registry.get::<String>(&png, "lbEl"),
+            Some(Ok("cover".to_string()))
+        );
+        assert_eq!(
+            registry.get::<String>(&png, "prGs"),
+            Some(Err(HandlerError::WrongValueType(Progress.chunk_type())))
+        );
+        assert_eq!(
+            registry.get::<u8>(&png, "nOne"),
+            Some(Err(HandlerError::NotRegistered("nOne".to_string())))
+        );
+        assert_eq!(registry.validate(&png), Ok(()));
+
+        png.append_chunk(Chunk::new(Progress.chunk_type(), vec![200]));
+        assert!(registry.validate(&png).is_err());
+    }
+}
@@ -0,0 +1,272 @@
+//! Parsing that reads only chunk headers, seeking past each chunk's data
+//! until it's asked for, so listing a huge file reads a few bytes per
+//! chunk. CRCs are checked when data is loaded, not when listing
+
+#![deny(clippy::unwrap_used, clippy::expect_used)]
+
+use std::fmt::Display;
+use std::io::{self, Read, Seek, SeekFrom};
+
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::crc::CrcHasher;
+use crate::png::{CrcMismatch, Png, PngError};
+
+/// Bytes read from a text chunk for its keyword: the longest keyword and
+/// its terminator
+const KEYWORD_LEN: usize = 80;
+
+#[derive(Debug)]
+pub enum LazyError {
+    Io(io::Error),
+    Png(PngError),
+    /// There's no chunk at this index
+    NoChunk(usize),
+}
+
+impl Display for LazyError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LazyError::Io(e) => write!(f, "{}", e),
+            LazyError::Png(e) => write!(f, "{}", e),
+            LazyError::NoChunk(index) => write!(f, "no chunk at index {}", index),
+        }
+    }
+}
+
+impl std::error::Error for LazyError {}
+
+impl From<io::Error> for LazyError {
+    fn from(e: io::Error) -> Self {
+        LazyError::Io(e)
+    }
+}
+
+impl From<PngError> for LazyError {
+    fn from(e: PngError) -> Self {
+        LazyError::Png(e)
+    }
+}
+
+/// A chunk's header, where its data is in the file and its stored CRC
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChunkEntry {
+    pub chunk_type: ChunkType,
+    pub length: u32,
+    pub crc: u32,
+    /// File offset of the chunk's length field
+    pub offset: u64,
+}
+
+impl Display for ChunkEntry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}\t{}\t{:08x}", self.chunk_type, self.length, self.crc)
+    }
+}
+
+/// A file whose chunk headers have been read, loading data on demand
+pub struct LazyPng<R> {
+    reader: R,
+    entries: Vec<ChunkEntry>,
+    /// File offset of the data after IEND
+    trailing_offset: u64,
+    len: u64,
+}
+
+impl<R: Read + Seek> LazyPng<R> {
+    /// Read the signature and every chunk header, failing on a chunk that
+    /// runs past the end of the file
+    pub fn new(mut reader: R) -> Result<LazyPng<R>, LazyError> {
+        let len = reader.seek(SeekFrom::End(0))?;
+        reader.seek(SeekFrom::Start(0))?;
+        let mut header = vec![0; len.min(8) as usize];
+        reader.read_exact(&mut header)?;
+        Png::check_signature(&header)?;
+        let mut entries = Vec::new();
+        let mut offset = header.len() as u64;
+        while offset < len {
+            let truncated = PngError::Truncated(offset as usize);
+            if len - offset < Chunk::OVERHEAD as u64 {
+                return Err(truncated.into());
+            }
+            let mut head = [0; 8];
+            reader.seek(SeekFrom::Start(offset))?;
+            reader.read_exact(&mut head)?;
+            let (length, chunk_type) = Chunk::split_head(head);
+            if len - offset - (Chunk::OVERHEAD as u64) < length as u64 {
+                return Err(truncated.into());
+            }
+            let chunk_type =
+                ChunkType::try_from(chunk_type).map_err(|e| PngError::Chunk(offset as usize, e))?;
+            let mut crc = [0; 4];
+            reader.seek(SeekFrom::Start(offset + 8 + length as u64))?;
+            reader.read_exact(&mut crc)?;
+            entries.push(ChunkEntry {
+                chunk_type,
+                length,
+                crc: u32::from_be_bytes(crc),
+                offset,
+            });
+            offset += length as u64 + Chunk::OVERHEAD as u64;
+            if &chunk_type.bytes() == b"IEND" {
+                break;
+            }
+        }
+        Ok(LazyPng {
+            reader,
+            entries,
+            trailing_offset: offset,
+            len,
+        })
+    }
+
+    pub fn entries(&self) -> &[ChunkEntry] {
+        &self.entries
+    }
+
+    fn entry(&self, index: usize) -> Result<ChunkEntry, LazyError> {
+        self.entries
+            .get(index)
+            .copied()
+            .ok_or(LazyError::NoChunk(index))
+    }
+
+    /// Load the data of the `index`th chunk, checking its CRC
+    pub fn data(&mut self, index: usize) -> Result<Vec<u8>, LazyError> {
+        let entry = self.entry(index)?;
+        let mut data = vec![0; entry.length as usize];
+        self.reader.seek(SeekFrom::Start(entry.offset + 8))?;
+        self.reader.read_exact(&mut data)?;
+        let mut hasher = CrcHasher::new();
+        hasher.update(&entry.chunk_type.bytes());
+        hasher.update(&data);
+        let actual = hasher.finalize();
+        if actual != entry.crc {
+            return Err(PngError::Crc(CrcMismatch {
+                index,
+                offset: entry.offset as usize,
+                chunk_type: entry.chunk_type,
+                length: entry.length,
+                stored: entry.crc,
+                computed: actual,
+            })
+            .into());
+        }
+        Ok(data)
+    }
+
+    /// Load the `index`th chunk
+    pub fn chunk(&mut self, index: usize) -> Result<Chunk, LazyError> {
+        let chunk_type = self.entry(index)?.chunk_type;
+        Ok(Chunk::new(chunk_type, self.data(index)?))
+    }
+
+    /// The keyword of the `index`th chunk if it's a text chunk, read from
+    /// the start of its data alone and so unchecked by its CRC
+    pub fn keyword(&mut self, index: usize) -> Result<Option<String>, LazyError> {
+        let entry = self.entry(index)?;
+        if ![b"tEXt", b"zTXt", b"iTXt"].contains(&&entry.chunk_type.bytes()) {
+            return Ok(None);
+        }
+        let mut start = vec![0; KEYWORD_LEN.min(entry.length as usize)];
+        self.reader.seek(SeekFrom::Start(entry.offset + 8))?;
+        self.reader.read_exact(&mut start)?;
+        Ok(start
+            .iter()
+            .position(|&b| b == 0)
+            .map(|end| start[..end].iter().map(|&b| b as char).collect()))
+    }
+
+    /// Bytes after IEND
+    pub fn trailing_len(&self) -> u64 {
+        self.len - self.trailing_offset
+    }
+
+    /// Load every chunk and the trailing data
+    pub fn into_png(mut self) -> Result<Png, LazyError> {
+        let chunks = (0..self.entries.len())
+            .map(|index| self.chunk(index))
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut png = Png::from_chunks(chunks);
+        let mut trailing = Vec::new();
+        self.reader.seek(SeekFrom::Start(self.trailing_offset))?;
+        self.reader.read_to_end(&mut trailing)?;
+        png.set_trailing(trailing);
+        Ok(png)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pixels::tests::testing_png;
+    use std::io::Cursor;
+
+    /// A reader counting the bytes read from it
+    struct Counting<R>(R, usize);
+
+    impl<R: Read> Read for Counting<R> {
+        fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+            let n = self.0.read(buffer)?;
+            self.1 += n;
+            Ok(n)
+        }
+    }
+
+    impl<R: Seek> Seek for Counting<R> {
+        fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+            self.0.seek(pos)
+        }
+    }
+
+    #[test]
+    fn test_headers_only() {
+        let mut png = testing_png(4, 4);
+        png.append_chunk(Chunk::new("raIn".parse().unwrap(), vec![7; 1 << 20]));
+        png.append_chunk(Chunk::new("tEXt".parse().unwrap(), b"Author\0me".to_vec()));
+        png.set_trailing(b"after".to_vec());
+        let bytes = png.as_bytes();
+
+        let mut lazy = LazyPng::new(Counting(Cursor::new(bytes.clone()), 0)).unwrap();
+        assert!(lazy.reader.1 < 100);
+        let listed: Vec<String> = lazy.entries().iter().map(|e| e.to_string()).collect();
+        let parsed: Vec<String> = png.chunks().iter().map(|c| c.to_string()).collect();
+        assert_eq!(listed, parsed);
+        let offsets: Vec<u64> = lazy.entries().iter().map(|e| e.offset).collect();
+        let expected: Vec<u64> = png.chunk_offsets().iter().map(|&o| o as u64).collect();
+        assert_eq!(offsets, expected);
+        assert_eq!(lazy.keyword(3).unwrap().as_deref(), Some("Author"));
+        assert_eq!(lazy.keyword(2).unwrap(), None);
+        assert_eq!(lazy.trailing_len(), 5);
+        assert_eq!(lazy.data(2).unwrap().len(), 1 << 20);
+        assert!(matches!(lazy.data(9), Err(LazyError::NoChunk(9))));
+        assert_eq!(lazy.into_png().unwrap().as_bytes(), bytes);
+    }
+
+    #[test]
+    fn test_errors() {
+        let png = testing_png(4, 4);
+        let mut bytes = png.as_bytes();
+        let idat = png.chunk_offsets()[1];
+        bytes[idat + 8] ^= 1;
+        let mut lazy = LazyPng::new(Cursor::new(bytes.clone())).unwrap();
+        assert!(matches!(
+            lazy.data(1),
+            Err(LazyError::Png(PngError::Crc(CrcMismatch { offset, .. }))) if offset == idat
+        ));
+
+        let error = LazyPng::new(Cursor::new(&bytes[..bytes.len() - 3]))
+            .err()
+            .unwrap();
+        let last = png.chunk_offsets().pop().unwrap();
+        assert!(matches!(error, LazyError::Png(PngError::Truncated(at)) if at == last));
+        let error = LazyPng::new(Cursor::new(b"nope")).err().unwrap();
+        assert!(matches!(
+            error,
+            LazyError::Png(PngError::Signature {
+                offset: 0,
+                found: b'n'
+            })
+        ));
+    }
+}
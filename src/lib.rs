@@ -0,0 +1,125 @@
+//! Read, edit and hide messages in PNG chunks
+//!
+//! The default `cli` feature builds the `ping` binary. As a dependency,
+//! turn default features off and enable `crypto` for encrypted, signed and
+//! recipient payloads, or just `std` for parsing, editing, compression,
+//! error correction and steganography without cipher or CLI dependencies.
+//!
+//! Without `std` only the chunk, CRC and parsing core is built, on `core`
+//! and `alloc`, for embedded and sandboxed use. The library is built as an
+//! rlib; the C, wasm and language bindings build their cdylib with std
+
+#![cfg_attr(all(not(feature = "std"), not(test)), no_std)]
+
+extern crate alloc;
+
+#[cfg(feature = "age")]
+pub mod age_format;
+#[cfg(feature = "std")]
+pub mod analyze;
+pub mod arena;
+#[cfg(feature = "tokio")]
+pub mod async_io;
+#[cfg(feature = "std")]
+pub mod attachment;
+#[cfg(feature = "std")]
+pub mod batch;
+#[cfg(feature = "std")]
+pub mod cache;
+#[cfg(feature = "std")]
+pub mod checksum;
+pub mod chunk;
+pub mod chunk_type;
+#[cfg(feature = "std")]
+pub mod compress;
+#[cfg(feature = "std")]
+pub mod container;
+pub mod crc;
+#[cfg(feature = "crypto")]
+pub mod crypto;
+#[cfg(feature = "std")]
+pub mod deflate;
+#[cfg(feature = "std")]
+pub mod ecc;
+#[cfg(feature = "exiftool")]
+pub mod exiftool;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+#[cfg(feature = "std")]
+pub mod grep;
+#[cfg(feature = "std")]
+pub mod handler;
+#[cfg(feature = "std")]
+pub mod lazy;
+#[cfg(feature = "std")]
+pub mod message;
+#[cfg(feature = "uniffi")]
+pub mod mobile;
+#[cfg(feature = "serde")]
+pub mod model;
+#[cfg(feature = "net")]
+pub mod net;
+#[cfg(feature = "node")]
+pub mod node;
+pub mod order;
+#[cfg(feature = "crypto")]
+pub mod payload;
+#[cfg(feature = "std")]
+pub mod perf;
+#[cfg(feature = "crypto")]
+pub mod pipeline;
+#[cfg(feature = "std")]
+pub mod pixels;
+pub mod png;
+#[cfg(feature = "std")]
+pub mod profile;
+#[cfg(feature = "python")]
+pub mod python;
+#[cfg(feature = "image")]
+pub mod raster;
+#[cfg(feature = "crypto")]
+pub mod recipients;
+#[cfg(feature = "crypto")]
+pub mod replay;
+#[cfg(feature = "std")]
+pub mod reproducible;
+#[cfg(feature = "script")]
+pub mod script;
+#[cfg(feature = "serve")]
+pub mod server;
+#[cfg(feature = "bytes")]
+pub mod shared;
+#[cfg(feature = "crypto")]
+pub mod shred;
+#[cfg(feature = "crypto")]
+pub mod sign;
+#[cfg(feature = "std")]
+pub mod split;
+#[cfg(feature = "std")]
+pub mod stats;
+#[cfg(feature = "std")]
+pub mod stego;
+#[cfg(feature = "crypto")]
+pub mod stream;
+#[cfg(feature = "std")]
+pub mod streaming;
+#[cfg(feature = "cli")]
+pub mod strength;
+#[cfg(feature = "std")]
+pub mod template;
+#[cfg(feature = "std")]
+pub mod text;
+#[cfg(all(feature = "io-uring", target_os = "linux"))]
+pub mod uring;
+#[cfg(feature = "std")]
+pub mod validate;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+#[cfg(feature = "xmp")]
+pub mod xmp;
+
+#[cfg(feature = "uniffi")]
+uniffi::setup_scaffolding!();
+
+pub type Error = alloc::boxed::Box<dyn core::error::Error>;
+pub type Result<T> = core::result::Result<T, Error>;
@@ -1,14 +1,47 @@
-//mod args;
-mod chunk;
-mod chunk_type;
-//mod commands;
-//mod png;
-mod crc;
-
-pub type Error = Box<dyn std::error::Error>;
-pub type Result<T> = std::result::Result<T, Error>;
-
-fn main() -> Result<()> {
-    todo!()
+use clap::Parser;
+
+mod args;
+mod commands;
+
+use args::{Cli, Command};
+use ping::perf;
+
+fn main() -> ping::Result<()> {
+    let cli = Cli::parse();
+    if !cli.timings {
+        return run(cli.command);
+    }
+    let (result, stats) = perf::measure(|| run(cli.command));
+    eprintln!("{}", stats);
+    result
 }
 
+fn run(command: Command) -> ping::Result<()> {
+    match command {
+        Command::Encode(args) => commands::encode(args),
+        Command::Decode(args) => commands::decode(args),
+        Command::Remove(args) => commands::remove(args),
+        Command::Print(args) => commands::print(args),
+        Command::Grep(args) => commands::grep(args),
+        Command::Sign(args) => commands::sign(args),
+        Command::Verify(args) => commands::verify(args),
+        Command::Checksum(args) => commands::checksum(args),
+        Command::Check(args) => commands::check(args),
+        Command::RenameChunk(args) => commands::rename_chunk(args),
+        Command::Reorder(args) => commands::reorder(args),
+        Command::Split(args) => commands::split(args),
+        Command::Join(args) => commands::join(args),
+        Command::Capacity(args) => commands::capacity(args),
+        Command::Keygen(args) => commands::keygen(args),
+        Command::Scan(args) => commands::scan(args),
+        Command::Analyze(args) => commands::analyze(args),
+        Command::Stats(args) => commands::stats(args),
+        Command::Batch(args) => commands::batch(args),
+        #[cfg(feature = "exiftool")]
+        Command::Metadata(args) => commands::metadata(args),
+        #[cfg(feature = "script")]
+        Command::Script(args) => commands::script(args),
+        #[cfg(feature = "serve")]
+        Command::Serve(args) => commands::serve(args),
+    }
+}
@@ -0,0 +1,179 @@
+//! Chunk-method embedding with only a password for keys, the subset of
+//! `ping encode` and `ping decode` the language bindings expose
+
+use crate::chunk_type::ChunkType;
+use crate::png::Png;
+#[cfg(feature = "crypto")]
+use crate::{
+    chunk::Chunk,
+    compress::Codec,
+    crypto::{self, CryptoSuite, KdfParams},
+    payload::{self, Header, Keys},
+    pipeline::PayloadCodec,
+    png::{OnDuplicate, PngError},
+    Result,
+};
+
+/// Ancillary chunks that change how the image is displayed or animated
+const RENDERING: [&str; 12] = [
+    "tRNS", "gAMA", "cHRM", "sRGB", "iCCP", "sBIT", "cICP", "mDCv", "cLLI", "acTL", "fcTL", "fdAT",
+];
+
+/// Whether [`strip`] keeps chunks of this type
+pub fn keeps(chunk_type: &ChunkType) -> bool {
+    chunk_type.is_critical() || RENDERING.contains(&chunk_type.as_str())
+}
+
+/// Drop the ancillary chunks that don't affect how the image looks, such
+/// as text, timestamps, EXIF and private chunks, and any data after IEND.
+/// Returns how many chunks were removed
+pub fn strip(png: &mut Png) -> usize {
+    let before = png.chunks().len();
+    png.retain_chunks(|c| keeps(c.chunk_type()));
+    png.set_trailing(Vec::new());
+    before - png.chunks().len()
+}
+
+#[cfg(feature = "crypto")]
+/// Add `message` in a new chunk, encrypted under `password` if one is given
+/// with the default key derivation cost
+pub fn embed(
+    png: &mut Png,
+    chunk_type: ChunkType,
+    message: &[u8],
+    password: Option<&str>,
+) -> Result<()> {
+    png.add_chunk(prepare(chunk_type, message, password)?, OnDuplicate::Refuse)?;
+    Ok(())
+}
+
+#[cfg(feature = "crypto")]
+/// The chunk [`embed`] adds, to add to any number of files. Encrypted, they
+/// then share a ciphertext, which shows they carry the same message
+pub fn prepare(chunk_type: ChunkType, message: &[u8], password: Option<&str>) -> Result<Chunk> {
+    let (body, flags) = match password {
+        Some(password) => (
+            payload::encrypt(
+                message,
+                &crypto::secret(Some(password), None),
+                &KdfParams::default(),
+                CryptoSuite::default(),
+            )?,
+            Header::ENCRYPTED,
+        ),
+        None => (message.to_vec(), 0),
+    };
+    let header = Header {
+        version: payload::VERSION,
+        flags,
+        codec: Codec::None,
+        length: message.len() as u32,
+    };
+    Ok(Chunk::new(chunk_type, payload::seal(header, &body)))
+}
+
+#[cfg(feature = "crypto")]
+/// Read the message in the chunks of `chunk_type`, reassembling split
+/// payloads and correcting error-corrected ones
+pub fn extract(png: &Png, chunk_type: &ChunkType, password: Option<&str>) -> Result<Vec<u8>> {
+    let keys = Keys {
+        password: password.map(str::to_string),
+        ..Keys::default()
+    };
+    extract_keyed(png, chunk_type, &keys)
+}
+
+#[cfg(feature = "crypto")]
+/// [`extract`] with any of the keys [`payload::decode`] takes
+pub fn extract_keyed(png: &Png, chunk_type: &ChunkType, keys: &Keys) -> Result<Vec<u8>> {
+    let name = chunk_type.to_string();
+    if png.chunk_by_type(&name).is_none() {
+        return Err(PngError::ChunkNotFound(name).into());
+    }
+    let parts = png
+        .chunks_by_type(&name)
+        .map(|c| payload::correct(c.data()).map(|(part, _)| part))
+        .collect::<std::result::Result<Vec<_>, _>>()?;
+    let data = payload::reassemble(parts.iter().map(Vec::as_slice))?;
+    Ok(payload::decode(&data, chunk_type, keys)?)
+}
+
+/// Add `message` in a new chunk, transformed by `codec` rather than framed
+/// as a payload; see [`extract_with`]
+#[cfg(feature = "crypto")]
+pub fn embed_with(
+    png: &mut Png,
+    chunk_type: ChunkType,
+    message: &[u8],
+    codec: &dyn PayloadCodec,
+) -> Result<()> {
+    png.append_chunk(Chunk::new(chunk_type, codec.encode(message)?));
+    Ok(())
+}
+
+/// Read a message added by [`embed_with`] with the same `codec`
+#[cfg(feature = "crypto")]
+pub fn extract_with(
+    png: &Png,
+    chunk_type: &ChunkType,
+    codec: &dyn PayloadCodec,
+) -> Result<Vec<u8>> {
+    let name = chunk_type.to_string();
+    let chunk = png
+        .chunk_by_type(&name)
+        .ok_or(PngError::ChunkNotFound(name))?;
+    codec.decode(chunk.data())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::Chunk;
+    use crate::chunk_type::ChunkType;
+    use crate::pixels::tests::testing_png;
+    use std::str::FromStr;
+
+    #[test]
+    #[cfg(feature = "crypto")]
+    fn test_round_trip() {
+        let chunk_type = ChunkType::from_str("ruSt").unwrap();
+        let mut png = testing_png(4, 4);
+        embed(&mut png, chunk_type, b"attack at dawn", None).unwrap();
+        assert_eq!(extract(&png, &chunk_type, None).unwrap(), b"attack at dawn");
+
+        let other = ChunkType::from_str("raIn").unwrap();
+        assert!(extract(&png, &other, None).is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "crypto")]
+    fn test_codec_round_trip() {
+        use crate::pipeline::{Chain, Compress, Ecc};
+        let chain = Chain::new().then(Compress(Codec::Zlib)).then(Ecc(8));
+        let chunk_type = ChunkType::from_str("ruSt").unwrap();
+        let mut png = testing_png(4, 4);
+        embed_with(&mut png, chunk_type, b"attack at dawn", &chain).unwrap();
+        assert_eq!(
+            extract_with(&png, &chunk_type, &chain).unwrap(),
+            b"attack at dawn"
+        );
+    }
+
+    #[test]
+    fn test_strip() {
+        let mut png = testing_png(4, 4);
+        let chunk = |t: &str| Chunk::new(ChunkType::from_str(t).unwrap(), b"data".to_vec());
+        for chunk_type in ["tEXt", "gAMA", "eXIf", "ruSt"] {
+            png.append_chunk(chunk(chunk_type));
+        }
+        png.set_trailing(b"after the end".to_vec());
+        assert_eq!(strip(&mut png), 3);
+        let types: Vec<String> = png
+            .chunks()
+            .iter()
+            .map(|c| c.chunk_type().to_string())
+            .collect();
+        assert_eq!(types, ["IHDR", "IDAT", "gAMA", "IEND"]);
+        assert!(png.trailing().is_empty());
+    }
+}
@@ -0,0 +1,165 @@
+//! UniFFI exports for Kotlin and Swift apps. A `Png` object wraps a parsed
+//! file, and `scrub` covers the common case of cleaning an image before
+//! it's shared; failures are thrown as `PingException` / `PingError`
+
+use std::fmt::Display;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+
+use crate::chunk_type::ChunkType;
+use crate::message;
+use crate::png;
+
+#[derive(Debug, uniffi::Error)]
+#[uniffi(flat_error)]
+pub enum PingError {
+    InvalidPng(String),
+    InvalidChunkType(String),
+    Message(String),
+}
+
+impl Display for PingError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PingError::InvalidPng(e) => write!(f, "invalid PNG: {}", e),
+            PingError::InvalidChunkType(e) => write!(f, "invalid chunk type: {}", e),
+            PingError::Message(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for PingError {}
+
+fn chunk_type(name: &str) -> Result<ChunkType, PingError> {
+    ChunkType::from_str(name).map_err(|e| PingError::InvalidChunkType(e.to_string()))
+}
+
+fn read(bytes: &[u8]) -> Result<png::Png, PingError> {
+    png::Png::try_from(bytes).map_err(|e| PingError::InvalidPng(e.to_string()))
+}
+
+/// One chunk of a parsed file
+#[derive(Debug, Clone, PartialEq, Eq, uniffi::Record)]
+pub struct ChunkInfo {
+    pub chunk_type: String,
+    pub offset: u64,
+    pub length: u32,
+    pub crc: u32,
+}
+
+/// A parsed file; methods lock it, so one object can be shared across
+/// threads
+#[derive(uniffi::Object)]
+pub struct Png(Mutex<png::Png>);
+
+impl Png {
+    fn lock(&self) -> std::sync::MutexGuard<'_, png::Png> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+#[uniffi::export]
+impl Png {
+    #[uniffi::constructor]
+    pub fn new(bytes: Vec<u8>) -> Result<Arc<Png>, PingError> {
+        Ok(Arc::new(Png(Mutex::new(read(&bytes)?))))
+    }
+
+    pub fn chunks(&self) -> Vec<ChunkInfo> {
+        let png = self.lock();
+        png.chunks()
+            .iter()
+            .zip(png.chunk_offsets())
+            .map(|(chunk, offset)| ChunkInfo {
+                chunk_type: chunk.chunk_type().to_string(),
+                offset: offset as u64,
+                length: chunk.length(),
+                crc: chunk.crc(),
+            })
+            .collect()
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.lock().as_bytes()
+    }
+
+    /// Add `message` in a new chunk, encrypted under `password` if one is
+    /// given with the default key derivation cost
+    pub fn encode(
+        &self,
+        chunk_type: String,
+        message: Vec<u8>,
+        password: Option<String>,
+    ) -> Result<(), PingError> {
+        let chunk_type = self::chunk_type(&chunk_type)?;
+        message::embed(&mut self.lock(), chunk_type, &message, password.as_deref())
+            .map_err(|e| PingError::Message(e.to_string()))
+    }
+
+    /// Read the message in the chunks of `chunk_type`
+    pub fn decode(
+        &self,
+        chunk_type: String,
+        password: Option<String>,
+    ) -> Result<Vec<u8>, PingError> {
+        let chunk_type = self::chunk_type(&chunk_type)?;
+        message::extract(&self.lock(), &chunk_type, password.as_deref())
+            .map_err(|e| PingError::Message(e.to_string()))
+    }
+
+    /// Drop ancillary chunks that don't affect rendering and any trailing
+    /// data, returning how many chunks went
+    pub fn strip(&self) -> u32 {
+        message::strip(&mut self.lock()) as u32
+    }
+}
+
+/// The file in `bytes` without text, timestamps, EXIF, private chunks or
+/// trailing data, for sharing a screenshot without what it carries
+#[uniffi::export]
+pub fn scrub(bytes: Vec<u8>) -> Result<Vec<u8>, PingError> {
+    let mut png = read(&bytes)?;
+    message::strip(&mut png);
+    Ok(png.as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::Chunk;
+    use crate::pixels::tests::testing_png;
+
+    #[test]
+    fn test_round_trip() {
+        let png = Png::new(testing_png(4, 4).as_bytes()).unwrap();
+        png.encode("ruSt".to_string(), b"attack at dawn".to_vec(), None)
+            .unwrap();
+        let chunks = png.chunks();
+        assert_eq!(chunks.len(), 4);
+        assert_eq!(chunks[2].chunk_type, "ruSt");
+        assert_eq!(
+            png.decode("ruSt".to_string(), None).unwrap(),
+            b"attack at dawn"
+        );
+        assert!(matches!(
+            png.decode("ru5t".to_string(), None),
+            Err(PingError::InvalidChunkType(_))
+        ));
+        assert_eq!(png.strip(), 1);
+        assert!(matches!(
+            Png::new(b"not a png".to_vec()),
+            Err(PingError::InvalidPng(_))
+        ));
+    }
+
+    #[test]
+    fn test_scrub() {
+        let mut png = testing_png(4, 4);
+        let text = Chunk::new(ChunkType::from_str("tEXt").unwrap(), b"Author\0me".to_vec());
+        png.append_chunk(text);
+        png.set_trailing(b"after the end".to_vec());
+        let scrubbed = png::Png::try_from(scrub(png.as_bytes()).unwrap().as_slice()).unwrap();
+        assert!(scrubbed.chunk_by_type("tEXt").is_none());
+        assert_eq!(scrubbed.as_bytes(), testing_png(4, 4).as_bytes());
+    }
+}
@@ -0,0 +1,379 @@
+//! Structured form of a parsed file for serde, so `Png` round-trips
+//! through JSON, YAML and the like. Chunks whose layout is known become
+//! fields; everything else, and any known chunk whose fields wouldn't
+//! rebuild its exact bytes, keeps its data as base64
+
+#![deny(clippy::unwrap_used, clippy::expect_used)]
+
+use std::fmt::Display;
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+use crate::chunk::Chunk;
+use crate::chunk_type::{ChunkError, ChunkType};
+use crate::png::Png;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ModelError {
+    Chunk(String, ChunkError),
+    /// A tEXt keyword or text, or an iTXt keyword, outside Latin-1
+    NotLatin1(String),
+}
+
+impl Display for ModelError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ModelError::Chunk(chunk_type, e) => write!(f, "{}: {}", chunk_type, e),
+            ModelError::NotLatin1(text) => write!(f, "{:?} isn't latin-1", text),
+        }
+    }
+}
+
+impl std::error::Error for ModelError {}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PngModel {
+    pub chunks: Vec<ChunkModel>,
+    /// Bytes after IEND
+    #[serde(with = "base64_data", default, skip_serializing_if = "Vec::is_empty")]
+    pub trailing: Vec<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum ChunkModel {
+    Known(KnownChunk),
+    Raw {
+        #[serde(rename = "type")]
+        chunk_type: String,
+        #[serde(with = "base64_data")]
+        data: Vec<u8>,
+    },
+}
+
+/// Field by field layouts of the chunks the model understands
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", deny_unknown_fields)]
+pub enum KnownChunk {
+    #[serde(rename = "IHDR")]
+    Ihdr {
+        width: u32,
+        height: u32,
+        bit_depth: u8,
+        color_type: u8,
+        compression: u8,
+        filter: u8,
+        interlace: u8,
+    },
+    #[serde(rename = "PLTE")]
+    Plte { entries: Vec<[u8; 3]> },
+    /// Gamma times 100000
+    #[serde(rename = "gAMA")]
+    Gama { gamma: u32 },
+    #[serde(rename = "sRGB")]
+    Srgb { rendering_intent: u8 },
+    #[serde(rename = "pHYs")]
+    Phys { x: u32, y: u32, unit: u8 },
+    #[serde(rename = "tIME")]
+    Time {
+        year: u16,
+        month: u8,
+        day: u8,
+        hour: u8,
+        minute: u8,
+        second: u8,
+    },
+    #[serde(rename = "tEXt")]
+    Text { keyword: String, text: String },
+    /// Uncompressed iTXt only; compressed ones stay raw
+    #[serde(rename = "iTXt")]
+    Itxt {
+        keyword: String,
+        language: String,
+        translated_keyword: String,
+        text: String,
+    },
+    #[serde(rename = "IEND")]
+    Iend,
+}
+
+impl KnownChunk {
+    fn parse(chunk_type: &str, data: &[u8]) -> Option<KnownChunk> {
+        let word = |i: usize| {
+            let bytes = data.get(i..).and_then(|rest| rest.first_chunk::<4>());
+            bytes.map_or(0, |bytes| u32::from_be_bytes(*bytes))
+        };
+        Some(match (chunk_type, data.len()) {
+            ("IHDR", 13) => KnownChunk::Ihdr {
+                width: word(0),
+                height: word(4),
+                bit_depth: data[8],
+                color_type: data[9],
+                compression: data[10],
+                filter: data[11],
+                interlace: data[12],
+            },
+            ("PLTE", len) if len % 3 == 0 => KnownChunk::Plte {
+                entries: data
+                    .chunks_exact(3)
+                    .map(|rgb| [rgb[0], rgb[1], rgb[2]])
+                    .collect(),
+            },
+            ("gAMA", 4) => KnownChunk::Gama { gamma: word(0) },
+            ("sRGB", 1) => KnownChunk::Srgb {
+                rendering_intent: data[0],
+            },
+            ("pHYs", 9) => KnownChunk::Phys {
+                x: word(0),
+                y: word(4),
+                unit: data[8],
+            },
+            ("tIME", 7) => KnownChunk::Time {
+                year: u16::from_be_bytes([data[0], data[1]]),
+                month: data[2],
+                day: data[3],
+                hour: data[4],
+                minute: data[5],
+                second: data[6],
+            },
+            ("tEXt", _) => {
+                let (keyword, text) = split_nul(data)?;
+                KnownChunk::Text {
+                    keyword: latin1(keyword),
+                    text: latin1(text),
+                }
+            }
+            ("iTXt", _) => {
+                let (keyword, rest) = split_nul(data)?;
+                let [0, 0, rest @ ..] = rest else {
+                    return None;
+                };
+                let (language, rest) = split_nul(rest)?;
+                let (translated, text) = split_nul(rest)?;
+                KnownChunk::Itxt {
+                    keyword: latin1(keyword),
+                    language: String::from_utf8(language.to_vec()).ok()?,
+                    translated_keyword: String::from_utf8(translated.to_vec()).ok()?,
+                    text: String::from_utf8(text.to_vec()).ok()?,
+                }
+            }
+            ("IEND", 0) => KnownChunk::Iend,
+            _ => return None,
+        })
+    }
+
+    fn chunk_type(&self) -> &'static str {
+        match self {
+            KnownChunk::Ihdr { .. } => "IHDR",
+            KnownChunk::Plte { .. } => "PLTE",
+            KnownChunk::Gama { .. } => "gAMA",
+            KnownChunk::Srgb { .. } => "sRGB",
+            KnownChunk::Phys { .. } => "pHYs",
+            KnownChunk::Time { .. } => "tIME",
+            KnownChunk::Text { .. } => "tEXt",
+            KnownChunk::Itxt { .. } => "iTXt",
+            KnownChunk::Iend => "IEND",
+        }
+    }
+
+    fn to_data(&self) -> Result<Vec<u8>, ModelError> {
+        Ok(match self {
+            KnownChunk::Ihdr {
+                width,
+                height,
+                bit_depth,
+                color_type,
+                compression,
+                filter,
+                interlace,
+            } => [
+                &width.to_be_bytes()[..],
+                &height.to_be_bytes(),
+                &[*bit_depth, *color_type, *compression, *filter, *interlace],
+            ]
+            .concat(),
+            KnownChunk::Plte { entries } => entries.concat(),
+            KnownChunk::Gama { gamma } => gamma.to_be_bytes().to_vec(),
+            KnownChunk::Srgb { rendering_intent } => vec![*rendering_intent],
+            KnownChunk::Phys { x, y, unit } => {
+                [&x.to_be_bytes()[..], &y.to_be_bytes(), &[*unit]].concat()
+            }
+            KnownChunk::Time {
+                year,
+                month,
+                day,
+                hour,
+                minute,
+                second,
+            } => [
+                &year.to_be_bytes()[..],
+                &[*month, *day, *hour, *minute, *second],
+            ]
+            .concat(),
+            KnownChunk::Text { keyword, text } => {
+                [&to_latin1(keyword)?[..], &[0], &to_latin1(text)?].concat()
+            }
+            KnownChunk::Itxt {
+                keyword,
+                language,
+                translated_keyword,
+                text,
+            } => [
+                &to_latin1(keyword)?[..],
+                &[0, 0, 0],
+                language.as_bytes(),
+                &[0],
+                translated_keyword.as_bytes(),
+                &[0],
+                text.as_bytes(),
+            ]
+            .concat(),
+            KnownChunk::Iend => Vec::new(),
+        })
+    }
+}
+
+impl From<&Chunk> for ChunkModel {
+    fn from(chunk: &Chunk) -> Self {
+        let chunk_type = chunk.chunk_type().to_string();
+        match KnownChunk::parse(&chunk_type, chunk.data()) {
+            Some(known) if known.to_data().as_deref() == Ok(chunk.data()) => {
+                ChunkModel::Known(known)
+            }
+            _ => ChunkModel::Raw {
+                chunk_type,
+                data: chunk.data().to_vec(),
+            },
+        }
+    }
+}
+
+impl TryFrom<ChunkModel> for Chunk {
+    type Error = ModelError;
+
+    fn try_from(model: ChunkModel) -> Result<Self, Self::Error> {
+        let (chunk_type, data) = match model {
+            ChunkModel::Known(known) => (known.chunk_type().to_string(), known.to_data()?),
+            ChunkModel::Raw { chunk_type, data } => (chunk_type, data),
+        };
+        match ChunkType::from_str(&chunk_type) {
+            Ok(parsed) => Ok(Chunk::new(parsed, data)),
+            Err(e) => Err(ModelError::Chunk(chunk_type, e)),
+        }
+    }
+}
+
+impl From<Png> for PngModel {
+    fn from(png: Png) -> Self {
+        PngModel {
+            chunks: png.chunks().iter().map(ChunkModel::from).collect(),
+            trailing: png.trailing().to_vec(),
+        }
+    }
+}
+
+impl TryFrom<PngModel> for Png {
+    type Error = ModelError;
+
+    fn try_from(model: PngModel) -> Result<Self, Self::Error> {
+        let chunks = model
+            .chunks
+            .into_iter()
+            .map(Chunk::try_from)
+            .collect::<Result<_, _>>()?;
+        let mut png = Png::from_chunks(chunks);
+        png.set_trailing(model.trailing);
+        Ok(png)
+    }
+}
+
+fn split_nul(data: &[u8]) -> Option<(&[u8], &[u8])> {
+    let at = data.iter().position(|b| *b == 0)?;
+    Some((&data[..at], &data[at + 1..]))
+}
+
+fn latin1(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| *b as char).collect()
+}
+
+fn to_latin1(text: &str) -> Result<Vec<u8>, ModelError> {
+    text.chars()
+        .map(u8::try_from)
+        .collect::<Result<_, _>>()
+        .map_err(|_| ModelError::NotLatin1(text.to_string()))
+}
+
+mod base64_data {
+    use base64::engine::general_purpose::STANDARD;
+    use base64::Engine;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(data: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&STANDARD.encode(data))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        let text = String::deserialize(deserializer)?;
+        STANDARD.decode(text).map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chunk(chunk_type: &str, data: &[u8]) -> Chunk {
+        Chunk::new(ChunkType::from_str(chunk_type).unwrap(), data.to_vec())
+    }
+
+    fn testing_png() -> Png {
+        let mut png = Png::from_chunks(vec![
+            chunk("IHDR", &[0, 0, 0, 4, 0, 0, 0, 2, 8, 2, 0, 0, 0]),
+            chunk("tIME", &[7, 234, 10, 14, 12, 30, 0]),
+            chunk("tEXt", b"Comment\0caf\xe9"),
+            chunk("iTXt", b"Title\0\0\0en\0Titel\0\xe2\x9c\x93"),
+            chunk("zTXt", b"Author\0\0x\x9c"),
+            chunk("gAMA", &[0, 0, 177]),
+            chunk("IDAT", &[1, 2, 3]),
+            chunk("ruSt", b"hidden"),
+            chunk("IEND", b""),
+        ]);
+        png.set_trailing(b"after the end".to_vec());
+        png
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let png = testing_png();
+        let json = serde_json::to_string(&png).unwrap();
+        let read: Png = serde_json::from_str(&json).unwrap();
+        assert_eq!(read.as_bytes(), png.as_bytes());
+    }
+
+    #[test]
+    fn test_known_chunks_become_fields() {
+        let value = serde_json::to_value(testing_png()).unwrap();
+        let chunks = &value["chunks"];
+        assert_eq!(chunks[0]["width"], 4);
+        assert_eq!(chunks[1]["year"], 2026);
+        assert_eq!(chunks[2]["text"], "café");
+        assert_eq!(chunks[3]["translated_keyword"], "Titel");
+        assert_eq!(chunks[3]["text"], "✓");
+        // gAMA is one byte short, so it can't be rebuilt from a field
+        assert_eq!(chunks[5]["data"], "AACx");
+        assert_eq!(chunks[7]["data"], "aGlkZGVu");
+        assert_eq!(chunks[8], serde_json::json!({"type": "IEND"}));
+        assert_eq!(value["trailing"], "YWZ0ZXIgdGhlIGVuZA==");
+    }
+
+    #[test]
+    fn test_invalid_model() {
+        let bad_type = r#"{"chunks": [{"type": "ru5t", "data": ""}]}"#;
+        assert!(serde_json::from_str::<Png>(bad_type).is_err());
+        let not_latin1 = r#"{"chunks": [{"type": "tEXt", "keyword": "✓", "text": ""}]}"#;
+        assert!(serde_json::from_str::<Png>(not_latin1).is_err());
+        let bad_base64 = r#"{"chunks": [{"type": "ruSt", "data": "!"}]}"#;
+        assert!(serde_json::from_str::<Png>(bad_base64).is_err());
+    }
+}
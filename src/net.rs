@@ -0,0 +1,543 @@
+//! Reading PNGs over HTTP(S), parsing each chunk as it arrives so a file
+//! that isn't a PNG fails before the rest of it downloads. Requests time
+//! out and bodies are capped, so a stalled or endless server returns an
+//! error rather than hang or fill memory
+
+#![deny(clippy::unwrap_used, clippy::expect_used)]
+
+use std::fmt::Display;
+use std::io::{self, Read, Seek, SeekFrom, Take};
+use std::time::Duration;
+
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::crc::CrcHasher;
+use crate::png::{CrcMismatch, Png, PngError};
+
+#[derive(Debug)]
+pub enum NetError {
+    Http(ureq::Error),
+    Io(io::Error),
+    Png(PngError),
+    /// The body ran past this many bytes
+    TooLarge(u64),
+}
+
+impl Display for NetError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            NetError::Http(e) => write!(f, "{}", e),
+            NetError::Io(e) => write!(f, "{}", e),
+            NetError::Png(e) => write!(f, "{}", e),
+            NetError::TooLarge(max) => write!(f, "response is larger than {} bytes", max),
+        }
+    }
+}
+
+impl std::error::Error for NetError {}
+
+impl From<ureq::Error> for NetError {
+    fn from(e: ureq::Error) -> Self {
+        NetError::Http(e)
+    }
+}
+
+impl From<io::Error> for NetError {
+    fn from(e: io::Error) -> Self {
+        match e.get_ref().and_then(|e| e.downcast_ref::<NetError>()) {
+            Some(&NetError::TooLarge(max)) => NetError::TooLarge(max),
+            _ => NetError::Io(e),
+        }
+    }
+}
+
+impl From<PngError> for NetError {
+    fn from(e: PngError) -> Self {
+        NetError::Png(e)
+    }
+}
+
+/// Whether `input` names an http or https URL rather than a file
+pub fn is_url(input: &str) -> bool {
+    ["http://", "https://"].iter().any(|scheme| {
+        input.len() > scheme.len()
+            && input
+                .get(..scheme.len())
+                .is_some_and(|s| s.eq_ignore_ascii_case(scheme))
+    })
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Options {
+    /// Largest body read, in bytes; a longer one fails with
+    /// [`NetError::TooLarge`]
+    pub max_size: u64,
+    /// Longest wait to connect to the server
+    pub connect_timeout: Duration,
+    /// Longest wait for the response, and again for its whole body
+    pub read_timeout: Duration,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            max_size: 256 << 20,
+            connect_timeout: Duration::from_secs(10),
+            read_timeout: Duration::from_secs(60),
+        }
+    }
+}
+
+/// A body that fails once it passes `max` bytes rather than end early
+struct Limited<R> {
+    reader: Take<R>,
+    max: u64,
+}
+
+impl<R: Read> Read for Limited<R> {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        let n = self.reader.read(buffer)?;
+        // Reading is limited to one byte past the maximum, so running out
+        // means the body went past it
+        if self.reader.limit() == 0 {
+            return Err(io::Error::other(NetError::TooLarge(self.max)));
+        }
+        Ok(n)
+    }
+}
+
+/// The body of a GET request for `url`, as it arrives
+pub fn open(url: &str, options: &Options) -> Result<impl Read, NetError> {
+    let agent = ureq::Agent::config_builder()
+        .timeout_connect(Some(options.connect_timeout))
+        .timeout_recv_response(Some(options.read_timeout))
+        .timeout_recv_body(Some(options.read_timeout))
+        .build()
+        .new_agent();
+    let reader = agent.get(url).call()?.into_body().into_reader();
+    Ok(Limited {
+        reader: reader.take(options.max_size.saturating_add(1)),
+        max: options.max_size,
+    })
+}
+
+/// The whole body of a GET request for `url`
+pub fn fetch(url: &str, options: &Options) -> Result<Vec<u8>, NetError> {
+    let mut bytes = Vec::new();
+    open(url, options)?.read_to_end(&mut bytes)?;
+    Ok(bytes)
+}
+
+/// Reads a file one chunk at a time, stopping after IEND; the blocking
+/// counterpart of `async_io::ChunkReader`
+pub struct ChunkReader<R> {
+    reader: R,
+    /// Offset in the file of the next chunk
+    offset: usize,
+    /// Position of the next chunk in the file's chunk list
+    index: usize,
+    done: bool,
+}
+
+impl<R: Read> ChunkReader<R> {
+    /// Read and check the signature
+    pub fn new(mut reader: R) -> Result<ChunkReader<R>, NetError> {
+        let mut header = Vec::with_capacity(8);
+        (&mut reader).take(8).read_to_end(&mut header)?;
+        Png::check_signature(&header)?;
+        Ok(ChunkReader {
+            reader,
+            offset: header.len(),
+            index: 0,
+            done: false,
+        })
+    }
+
+    /// The next chunk, or `None` after IEND or at the end of a file that
+    /// lacks one
+    pub fn next_chunk(&mut self) -> Result<Option<Chunk>, NetError> {
+        let mut data = Vec::new();
+        Ok(self
+            .read_chunk(Some(&mut data))?
+            .map(|(chunk_type, crc)| Chunk::from_checked(chunk_type, data, crc)))
+    }
+
+    /// Check the next chunk's CRC without keeping its data, returning its
+    /// type and length, or `None` where [`next_chunk`](Self::next_chunk)
+    /// would
+    pub fn skip_chunk(&mut self) -> Result<Option<(ChunkType, u32)>, NetError> {
+        let start = self.offset;
+        Ok(self.read_chunk(None)?.map(|(chunk_type, _)| {
+            let length = self.offset - start - Chunk::OVERHEAD;
+            (chunk_type, length as u32)
+        }))
+    }
+
+    /// Read the next chunk's length and type, or `None` where
+    /// [`next_chunk`](Self::next_chunk) would return it
+    fn read_head(&mut self) -> Result<Option<(usize, ChunkType)>, NetError> {
+        if self.done {
+            return Ok(None);
+        }
+        let mut head = Vec::with_capacity(8);
+        (&mut self.reader).take(8).read_to_end(&mut head)?;
+        if head.is_empty() {
+            self.done = true;
+            return Ok(None);
+        }
+        let Some((length, chunk_type)) = Chunk::read_head(&head) else {
+            return Err(PngError::Truncated(self.offset).into());
+        };
+        let length = length as usize;
+        let chunk_type =
+            ChunkType::try_from(chunk_type).map_err(|e| PngError::Chunk(self.offset, e))?;
+        Ok(Some((length, chunk_type)))
+    }
+
+    /// Read one chunk, hashing its data as it arrives and appending it to
+    /// `keep` if given, and return its type and checked CRC
+    fn read_chunk(
+        &mut self,
+        keep: Option<&mut Vec<u8>>,
+    ) -> Result<Option<(ChunkType, u32)>, NetError> {
+        match self.read_head()? {
+            Some((length, chunk_type)) => self.read_body(length, chunk_type, keep).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// The rest of the chunk [`read_head`](Self::read_head) started, as
+    /// [`read_chunk`](Self::read_chunk) reads it
+    fn read_body(
+        &mut self,
+        length: usize,
+        chunk_type: ChunkType,
+        mut keep: Option<&mut Vec<u8>>,
+    ) -> Result<(ChunkType, u32), NetError> {
+        let mut hasher = CrcHasher::new();
+        hasher.update(&chunk_type.bytes());
+        // Read what's there a block at a time rather than allocate the
+        // claimed length up front
+        let mut block = Vec::new();
+        let mut left = length;
+        while left > 0 {
+            let n = left.min(Chunk::READ_BLOCK);
+            let buffer = match keep.as_deref_mut() {
+                Some(data) => data,
+                None => {
+                    block.clear();
+                    &mut block
+                }
+            };
+            let start = buffer.len();
+            buffer.resize(start + n, 0);
+            self.fill(&mut buffer[start..])?;
+            hasher.update(&buffer[start..]);
+            left -= n;
+        }
+        let mut crc = [0; 4];
+        self.fill(&mut crc)?;
+        let (expected, actual) = (u32::from_be_bytes(crc), hasher.finalize());
+        if expected != actual {
+            return Err(PngError::Crc(CrcMismatch {
+                index: self.index,
+                offset: self.offset,
+                chunk_type,
+                length: length as u32,
+                stored: expected,
+                computed: actual,
+            })
+            .into());
+        }
+        self.offset += length + Chunk::OVERHEAD;
+        self.index += 1;
+        self.done = &chunk_type.bytes() == b"IEND";
+        Ok((chunk_type, expected))
+    }
+
+    /// Fill `buffer` from the reader, a short read being a truncated chunk
+    fn fill(&mut self, buffer: &mut [u8]) -> Result<(), NetError> {
+        match self.reader.read_exact(buffer) {
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                Err(PngError::Truncated(self.offset).into())
+            }
+            result => Ok(result?),
+        }
+    }
+
+    /// Offset in the file of the next chunk
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    /// The underlying reader, positioned after the last chunk read
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+}
+
+impl<R: Read + Seek> ChunkReader<R> {
+    /// [`skip_chunk`](Self::skip_chunk), seeking past the data instead of
+    /// reading it, so a chunk of any size costs a read of its header. The
+    /// CRC goes unchecked
+    pub fn seek_chunk(&mut self) -> Result<Option<(ChunkType, u32)>, NetError> {
+        let Some((length, chunk_type)) = self.read_head()? else {
+            return Ok(None);
+        };
+        self.seek_body(length, chunk_type)?;
+        Ok(Some((chunk_type, length as u32)))
+    }
+
+    /// Seek past the rest of the chunk [`read_head`](Self::read_head)
+    /// started
+    fn seek_body(&mut self, length: usize, chunk_type: ChunkType) -> Result<(), NetError> {
+        // Seeking past the end succeeds, so a short file is caught here
+        let here = self.reader.stream_position()?;
+        let end = self.reader.seek(SeekFrom::End(0))?;
+        let skip = length as u64 + 4;
+        if end.saturating_sub(here) < skip {
+            return Err(PngError::Truncated(self.offset).into());
+        }
+        self.reader.seek(SeekFrom::Start(here + skip))?;
+        self.offset += length + Chunk::OVERHEAD;
+        self.index += 1;
+        self.done = &chunk_type.bytes() == b"IEND";
+        Ok(())
+    }
+
+    /// The next chunk of type `chunk_type`, checked by its CRC, seeking
+    /// past the chunks before it; `None` if there's none before IEND
+    pub fn find_chunk(&mut self, chunk_type: ChunkType) -> Result<Option<Chunk>, NetError> {
+        while let Some((length, found)) = self.read_head()? {
+            if found != chunk_type {
+                self.seek_body(length, found)?;
+                continue;
+            }
+            let mut data = Vec::new();
+            let (_, crc) = self.read_body(length, found, Some(&mut data))?;
+            return Ok(Some(Chunk::from_checked(found, data, crc)));
+        }
+        Ok(None)
+    }
+}
+
+impl Png {
+    /// Parse a file from `reader`, keeping whatever follows IEND as
+    /// trailing data
+    pub fn from_reader(reader: impl Read) -> Result<Png, NetError> {
+        let mut chunks = ChunkReader::new(reader)?;
+        let mut png = Png::from_chunks(Vec::new());
+        while let Some(chunk) = chunks.next_chunk()? {
+            png.append_chunk(chunk);
+        }
+        let mut trailing = Vec::new();
+        chunks.into_inner().read_to_end(&mut trailing)?;
+        png.set_trailing(trailing);
+        Ok(png)
+    }
+
+    /// Download and parse the file at `url`
+    pub fn from_url(url: &str, options: &Options) -> Result<Png, NetError> {
+        Png::from_reader(open(url, options)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pixels::tests::testing_png;
+    use std::io::Write;
+    use std::net::TcpListener;
+    use std::thread;
+
+    /// Answer one request on a local port with `body`, returning its URL
+    fn serve(status: &'static str, body: Vec<u8>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/image.png", listener.local_addr().unwrap());
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = [0; 1024];
+            let _ = stream.read(&mut request).unwrap();
+            let head = format!(
+                "HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                status,
+                body.len()
+            );
+            stream.write_all(head.as_bytes()).unwrap();
+            let _ = stream.write_all(&body);
+        });
+        url
+    }
+
+    #[test]
+    fn test_is_url() {
+        assert!(is_url("https://example.com/image.png"));
+        assert!(is_url("HTTP://example.com"));
+        assert!(!is_url("https://"));
+        assert!(!is_url("image.png"));
+        assert!(!is_url("ftp://example.com/image.png"));
+        assert!(!is_url("httpé://"));
+    }
+
+    #[test]
+    fn test_from_reader() {
+        let mut png = testing_png(4, 4);
+        png.set_trailing(b"after the end".to_vec());
+        let bytes = png.as_bytes();
+        let read = Png::from_reader(bytes.as_slice()).unwrap();
+        assert_eq!(read.as_bytes(), bytes);
+
+        let error = Png::from_reader(&bytes[..bytes.len() - 20]).unwrap_err();
+        let last = png.chunk_offsets().pop().unwrap();
+        assert!(matches!(error, NetError::Png(PngError::Truncated(at)) if at == last));
+    }
+
+    #[test]
+    fn test_streamed_crc_checks() {
+        let mut png = testing_png(4, 4);
+        let big: Vec<u8> = (0..3 * Chunk::READ_BLOCK + 5).map(|i| i as u8).collect();
+        png.append_chunk(Chunk::new("raIn".parse().unwrap(), big.clone()));
+        let bytes = png.as_bytes();
+        let read = Png::from_reader(bytes.as_slice()).unwrap();
+        assert_eq!(read.chunk_by_type("raIn").unwrap().data(), big);
+
+        let mut chunks = ChunkReader::new(bytes.as_slice()).unwrap();
+        let mut skipped = Vec::new();
+        while let Some((chunk_type, length)) = chunks.skip_chunk().unwrap() {
+            skipped.push((chunk_type.to_string(), length));
+        }
+        assert_eq!(skipped[2], ("raIn".to_string(), big.len() as u32));
+        assert_eq!(skipped.last().unwrap().0, "IEND");
+
+        let at = png.chunk_offsets()[2];
+        let mut corrupt = bytes.clone();
+        corrupt[at + 8 + Chunk::READ_BLOCK] ^= 1;
+        let mut chunks = ChunkReader::new(corrupt.as_slice()).unwrap();
+        chunks.skip_chunk().unwrap();
+        chunks.skip_chunk().unwrap();
+        let error = chunks.skip_chunk().unwrap_err();
+        assert!(matches!(
+            error,
+            NetError::Png(PngError::Crc(CrcMismatch { offset, .. })) if offset == at
+        ));
+    }
+
+    /// A reader counting the bytes read from it
+    struct Counting<R>(R, usize);
+
+    impl<R: Read> Read for Counting<R> {
+        fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+            let n = self.0.read(buffer)?;
+            self.1 += n;
+            Ok(n)
+        }
+    }
+
+    impl<R: Seek> Seek for Counting<R> {
+        fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+            self.0.seek(pos)
+        }
+    }
+
+    #[test]
+    fn test_seek_chunk() {
+        let mut png = testing_png(4, 4);
+        png.insert_chunk(1, Chunk::new("raIn".parse().unwrap(), vec![7; 1 << 22]));
+        png.append_chunk(Chunk::new("tEXt".parse().unwrap(), b"Author\0me".to_vec()));
+        let bytes = png.as_bytes();
+        let reader = Counting(io::Cursor::new(bytes.as_slice()), 0);
+        let mut chunks = ChunkReader::new(reader).unwrap();
+        let text = chunks.find_chunk("tEXt".parse().unwrap()).unwrap().unwrap();
+        assert_eq!(text.data(), b"Author\0me");
+        assert!(chunks.into_inner().1 < 1000);
+
+        let mut chunks = ChunkReader::new(bytes.as_slice()).unwrap();
+        let mut skipped = Vec::new();
+        while let Some((chunk_type, length)) = chunks.skip_chunk().unwrap() {
+            skipped.push((chunk_type, length));
+        }
+        let mut chunks = ChunkReader::new(io::Cursor::new(bytes.as_slice())).unwrap();
+        for expected in &skipped {
+            assert_eq!(chunks.seek_chunk().unwrap().as_ref(), Some(expected));
+        }
+        assert!(chunks.seek_chunk().unwrap().is_none());
+        let mut chunks = ChunkReader::new(io::Cursor::new(bytes.as_slice())).unwrap();
+        assert!(chunks
+            .find_chunk("tIME".parse().unwrap())
+            .unwrap()
+            .is_none());
+
+        let at = png.chunk_offsets()[1];
+        let mut chunks = ChunkReader::new(io::Cursor::new(&bytes[..at + 100])).unwrap();
+        chunks.seek_chunk().unwrap();
+        assert!(matches!(
+            chunks.seek_chunk(),
+            Err(NetError::Png(PngError::Truncated(offset))) if offset == at
+        ));
+    }
+
+    #[test]
+    fn test_from_url() {
+        let png = testing_png(4, 4);
+        let url = serve("200 OK", png.as_bytes());
+        assert_eq!(
+            Png::from_url(&url, &Options::default()).unwrap().as_bytes(),
+            png.as_bytes()
+        );
+
+        let url = serve("200 OK", b"<html>not an image</html>".to_vec());
+        let error = Png::from_url(&url, &Options::default()).unwrap_err();
+        assert!(matches!(
+            error,
+            NetError::Png(PngError::Signature {
+                offset: 0,
+                found: b'<'
+            })
+        ));
+
+        let url = serve("404 Not Found", Vec::new());
+        let error = Png::from_url(&url, &Options::default()).unwrap_err();
+        assert!(matches!(
+            error,
+            NetError::Http(ureq::Error::StatusCode(404))
+        ));
+    }
+
+    #[test]
+    fn test_limits() {
+        let png = testing_png(4, 4);
+        let bytes = png.as_bytes();
+        let exact = Options {
+            max_size: bytes.len() as u64,
+            ..Options::default()
+        };
+        let url = serve("200 OK", bytes.clone());
+        assert_eq!(fetch(&url, &exact).unwrap(), bytes);
+
+        let short = Options {
+            max_size: bytes.len() as u64 - 1,
+            ..Options::default()
+        };
+        let url = serve("200 OK", bytes.clone());
+        let error = fetch(&url, &short).unwrap_err();
+        assert!(matches!(error, NetError::TooLarge(max) if max == short.max_size));
+        let url = serve("200 OK", bytes.clone());
+        let error = Png::from_url(&url, &short).unwrap_err();
+        assert!(matches!(error, NetError::TooLarge(_)));
+
+        // A server that accepts the connection and never answers
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/image.png", listener.local_addr().unwrap());
+        thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            thread::sleep(Duration::from_secs(5));
+            drop(stream);
+        });
+        let quick = Options {
+            read_timeout: Duration::from_millis(200),
+            ..Options::default()
+        };
+        let error = fetch(&url, &quick).unwrap_err();
+        assert!(matches!(error, NetError::Http(ureq::Error::Timeout(_))));
+    }
+}
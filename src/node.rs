@@ -0,0 +1,103 @@
+//! Node.js addon exports, built with napi-rs. Every function takes the
+//! file as a `Buffer` and returns a new one rather than editing in place,
+//! and failures are thrown as `Error`s
+
+use std::str::FromStr;
+
+use napi::bindgen_prelude::Buffer;
+use napi::{Error, Result};
+use napi_derive::napi;
+
+use crate::chunk_type::ChunkType;
+use crate::message;
+use crate::png::Png;
+
+fn error(e: impl ToString) -> Error {
+    Error::from_reason(e.to_string())
+}
+
+fn read(bytes: &[u8]) -> Result<Png> {
+    Png::try_from(bytes).map_err(error)
+}
+
+fn chunk_type(name: &str) -> Result<ChunkType> {
+    ChunkType::from_str(name).map_err(error)
+}
+
+/// One chunk of a parsed file
+#[napi(object)]
+pub struct ChunkInfo {
+    pub chunk_type: String,
+    pub offset: i64,
+    pub length: u32,
+    pub crc: u32,
+}
+
+/// The chunks of the file in `bytes`
+#[napi]
+pub fn parse(bytes: Buffer) -> Result<Vec<ChunkInfo>> {
+    let png = read(&bytes)?;
+    Ok(png
+        .chunks()
+        .iter()
+        .zip(png.chunk_offsets())
+        .map(|(chunk, offset)| ChunkInfo {
+            chunk_type: chunk.chunk_type().to_string(),
+            offset: offset as i64,
+            length: chunk.length(),
+            crc: chunk.crc(),
+        })
+        .collect())
+}
+
+/// The file with `message` added in a new chunk, encrypted under
+/// `password` if one is given
+#[napi]
+pub fn encode(
+    bytes: Buffer,
+    chunk_type: String,
+    message: Buffer,
+    password: Option<String>,
+) -> Result<Buffer> {
+    let mut png = read(&bytes)?;
+    let chunk_type = self::chunk_type(&chunk_type)?;
+    message::embed(&mut png, chunk_type, &message, password.as_deref()).map_err(error)?;
+    Ok(png.as_bytes().into())
+}
+
+/// The message in the chunks of `chunk_type`
+#[napi]
+pub fn decode(bytes: Buffer, chunk_type: String, password: Option<String>) -> Result<Buffer> {
+    let png = read(&bytes)?;
+    let chunk_type = self::chunk_type(&chunk_type)?;
+    let message = message::extract(&png, &chunk_type, password.as_deref()).map_err(error)?;
+    Ok(message.into())
+}
+
+/// The file without text, timestamps, EXIF, private chunks or trailing
+/// data
+#[napi]
+pub fn strip(bytes: Buffer) -> Result<Buffer> {
+    let mut png = read(&bytes)?;
+    message::strip(&mut png);
+    Ok(png.as_bytes().into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pixels::tests::testing_png;
+
+    #[test]
+    fn test_round_trip() {
+        let bytes = Buffer::from(testing_png(4, 4).as_bytes());
+        let encoded = encode(bytes, "ruSt".to_string(), b"hi".to_vec().into(), None).unwrap();
+        let chunks = parse(encoded.to_vec().into()).unwrap();
+        assert_eq!(chunks[2].chunk_type, "ruSt");
+        let message = decode(encoded.to_vec().into(), "ruSt".to_string(), None).unwrap();
+        assert_eq!(message.to_vec(), b"hi");
+        let stripped = strip(encoded).unwrap();
+        assert_eq!(stripped.to_vec(), testing_png(4, 4).as_bytes());
+        assert!(decode(stripped, "ru5t".to_string(), None).is_err());
+    }
+}
@@ -0,0 +1,435 @@
+use alloc::vec::Vec;
+use core::fmt::Display;
+
+#[cfg(feature = "std")]
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+#[cfg(feature = "std")]
+use crate::text::TextChunk;
+
+/// Position of a chunk type in the canonical layout: IHDR, the colour
+/// space chunks, PLTE, the palette dependents, the other pre-IDAT chunks,
+/// tIME, text, IDAT, anything unknown and finally IEND
+pub fn rank(chunk_type: &str) -> u8 {
+    match chunk_type {
+        "IHDR" => 0,
+        "cHRM" | "gAMA" | "iCCP" | "sBIT" | "sRGB" | "cICP" | "mDCv" | "cLLI" => 1,
+        "PLTE" => 2,
+        "bKGD" | "hIST" | "tRNS" => 3,
+        "pHYs" | "sPLT" | "oFFs" | "pCAL" | "sCAL" | "eXIf" => 4,
+        "tIME" => 5,
+        "tEXt" | "zTXt" | "iTXt" => 6,
+        "IDAT" => 7,
+        "IEND" => 9,
+        _ => 8,
+    }
+}
+
+/// Whether the PNG specification defines this chunk type
+pub fn is_standard(chunk_type: &str) -> bool {
+    // every type rank doesn't know shares the rank of a nonsense one
+    rank(chunk_type) != rank("")
+}
+
+/// Whether a file may hold at most one chunk of this type, as it may of
+/// every standard type but IDAT, sPLT, the text chunks and the APNG frames
+pub fn is_unique(chunk_type: &str) -> bool {
+    let repeatable = matches!(
+        chunk_type,
+        "IDAT" | "sPLT" | "tEXt" | "zTXt" | "iTXt" | "fcTL" | "fdAT"
+    );
+    (is_standard(chunk_type) || chunk_type == "acTL") && !repeatable
+}
+
+/// A placement rule of the spec's
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rule {
+    IhdrFirst,
+    IendLast,
+    /// The colour space chunks come before PLTE
+    BeforePlte,
+    /// bKGD, hIST and tRNS come after PLTE, if there is one
+    AfterPlte,
+    /// PLTE and the chunks describing the image come before the first IDAT
+    BeforeIdat,
+    ConsecutiveIdat,
+}
+
+impl Display for Rule {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Rule::IhdrFirst => write!(f, "must be first"),
+            Rule::IendLast => write!(f, "must be last"),
+            Rule::BeforePlte => write!(f, "must come before PLTE"),
+            Rule::AfterPlte => write!(f, "must come after PLTE"),
+            Rule::BeforeIdat => write!(f, "must come before IDAT"),
+            Rule::ConsecutiveIdat => write!(f, "must follow the other IDAT chunks"),
+        }
+    }
+}
+
+/// A chunk breaking a [`Rule`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Misplaced {
+    pub index: usize,
+    /// File offset of the chunk's length field
+    pub offset: usize,
+    pub chunk_type: ChunkType,
+    pub rule: Rule,
+}
+
+impl Display for Misplaced {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "{} chunk {} at offset {} {}",
+            self.chunk_type, self.index, self.offset, self.rule
+        )
+    }
+}
+
+/// Every chunk breaking a placement rule, given each chunk's offset and
+/// type in file order. A file without IHDR or IEND breaks no rule here
+pub fn misplaced(chunks: &[(usize, ChunkType)]) -> Vec<Misplaced> {
+    let position = |name: &str| chunks.iter().position(|(_, t)| t.as_str() == name);
+    let (plte, idat) = (position("PLTE"), position("IDAT"));
+    let after_idat = |index: usize| idat.is_some_and(|idat| index > idat);
+    let mut misplaced = Vec::new();
+    for (index, &(offset, chunk_type)) in chunks.iter().enumerate() {
+        let name = chunk_type.as_str();
+        let rule = match name {
+            "IHDR" if index > 0 => Some(Rule::IhdrFirst),
+            "IEND" if index + 1 < chunks.len() => Some(Rule::IendLast),
+            "IDAT" if after_idat(index) && chunks[index - 1].1.as_str() != "IDAT" => {
+                Some(Rule::ConsecutiveIdat)
+            }
+            "PLTE" | "acTL" if after_idat(index) => Some(Rule::BeforeIdat),
+            _ => match rank(name) {
+                1 if plte.is_some_and(|plte| index > plte) => Some(Rule::BeforePlte),
+                3 if plte.is_some_and(|plte| index < plte) => Some(Rule::AfterPlte),
+                1 | 3 | 4 if after_idat(index) => Some(Rule::BeforeIdat),
+                _ => None,
+            },
+        };
+        if let Some(rule) = rule {
+            misplaced.push(Misplaced {
+                index,
+                offset,
+                chunk_type,
+                rule,
+            });
+        }
+    }
+    misplaced
+}
+
+/// [`misplaced`] for chunks met one at a time, as streaming readers meet
+/// them, in constant memory however many there are. It finds whether any
+/// chunk is misplaced, though not always the first [`misplaced`] would
+#[derive(Debug, Clone, Default)]
+pub struct Placement {
+    index: usize,
+    plte: bool,
+    idat: bool,
+    /// Whether the last chunk was an IDAT
+    in_idat: bool,
+    iend: Option<(usize, usize)>,
+    /// The first chunk that must follow PLTE, met before any PLTE
+    before_plte: Option<(usize, usize, ChunkType)>,
+}
+
+impl Placement {
+    /// Place the chunk at `offset` after those before it, returning it if
+    /// it breaks a rule, or the one before it that it shows is misplaced
+    pub fn push(&mut self, offset: usize, chunk_type: ChunkType) -> Option<Misplaced> {
+        let index = self.index;
+        self.index += 1;
+        let name = chunk_type.as_str();
+        let misplaced = |index, offset, chunk_type, rule| {
+            Some(Misplaced {
+                index,
+                offset,
+                chunk_type,
+                rule,
+            })
+        };
+        if let Some((index, offset)) = self.iend {
+            return misplaced(index, offset, ChunkType::new(*b"IEND"), Rule::IendLast);
+        }
+        if name == "PLTE" {
+            if let Some((index, offset, chunk_type)) = self.before_plte {
+                return misplaced(index, offset, chunk_type, Rule::AfterPlte);
+            }
+        }
+        let rule = match name {
+            "IHDR" if index > 0 => Some(Rule::IhdrFirst),
+            "IDAT" if self.idat && !self.in_idat => Some(Rule::ConsecutiveIdat),
+            "PLTE" | "acTL" if self.idat => Some(Rule::BeforeIdat),
+            _ => match rank(name) {
+                1 if self.plte => Some(Rule::BeforePlte),
+                1 | 3 | 4 if self.idat => Some(Rule::BeforeIdat),
+                _ => None,
+            },
+        };
+        if let Some(rule) = rule {
+            return misplaced(index, offset, chunk_type, rule);
+        }
+        if rank(name) == 3 && !self.plte && self.before_plte.is_none() {
+            self.before_plte = Some((index, offset, chunk_type));
+        }
+        self.plte |= name == "PLTE";
+        self.idat |= name == "IDAT";
+        self.in_idat = name == "IDAT";
+        if name == "IEND" {
+            self.iend = Some((index, offset));
+        }
+        None
+    }
+}
+
+/// Sort chunks into canonical order, text chunks by keyword, keeping the
+/// relative order of chunks that compare equal (e.g. the IDAT sequence).
+/// Unknown chunks may depend on what's around them, so as the spec asks
+/// they stay on the same side of PLTE and IDAT: with the colour space
+/// chunks before PLTE, with the text chunks before IDAT, or after it
+#[cfg(feature = "std")]
+pub fn canonicalize(chunks: &mut [Chunk]) {
+    let position = |name: &str| chunks.iter().position(|c| c.chunk_type().as_str() == name);
+    let (plte, idat) = (position("PLTE"), position("IDAT"));
+    let mut order: Vec<(u8, String, usize)> = chunks
+        .iter()
+        .enumerate()
+        .map(|(index, chunk)| {
+            let name = chunk.chunk_type().as_str();
+            let rank = match is_standard(name) {
+                true => rank(name),
+                false if plte.is_some_and(|plte| index < plte) => rank("gAMA"),
+                false if idat.is_none_or(|idat| index < idat) => rank("tEXt"),
+                false => rank(""),
+            };
+            let keyword = match TextChunk::parse(chunk) {
+                Some(Ok(text)) => text.keyword,
+                _ => String::new(),
+            };
+            (rank, keyword, index)
+        })
+        .collect();
+    order.sort();
+    // Each cycle of the permutation walked once, swapping every chunk in
+    let mut placed = vec![false; order.len()];
+    for start in 0..order.len() {
+        let mut to = start;
+        while !placed[to] {
+            placed[to] = true;
+            let from = order[to].2;
+            if from == start {
+                break;
+            }
+            chunks.swap(to, from);
+            to = from;
+        }
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use crate::chunk_type::ChunkType;
+    use std::str::FromStr;
+
+    fn chunk(chunk_type: &str, data: &[u8]) -> Chunk {
+        Chunk::new(ChunkType::from_str(chunk_type).unwrap(), data.to_vec())
+    }
+
+    fn types(chunks: &[Chunk]) -> Vec<String> {
+        chunks.iter().map(|c| c.chunk_type().to_string()).collect()
+    }
+
+    #[test]
+    fn test_is_unique() {
+        for chunk_type in ["IHDR", "PLTE", "tIME", "gAMA", "acTL", "IEND"] {
+            assert!(is_unique(chunk_type), "{}", chunk_type);
+        }
+        for chunk_type in ["IDAT", "sPLT", "iTXt", "fdAT", "ruSt"] {
+            assert!(!is_unique(chunk_type), "{}", chunk_type);
+        }
+    }
+
+    #[test]
+    fn test_misplaced() {
+        let chunks: Vec<(usize, ChunkType)> = [
+            "IHDR", "tRNS", "PLTE", "gAMA", "IDAT", "tEXt", "IDAT", "pHYs", "IEND",
+        ]
+        .iter()
+        .enumerate()
+        .map(|(i, t)| (i * 100, ChunkType::from_str(t).unwrap()))
+        .collect();
+        let found: Vec<(usize, Rule)> = misplaced(&chunks)
+            .iter()
+            .map(|m| (m.index, m.rule))
+            .collect();
+        assert_eq!(
+            found,
+            [
+                (1, Rule::AfterPlte),
+                (3, Rule::BeforePlte),
+                (6, Rule::ConsecutiveIdat),
+                (7, Rule::BeforeIdat)
+            ]
+        );
+        assert_eq!(
+            misplaced(&chunks)[3].to_string(),
+            "pHYs chunk 7 at offset 700 must come before IDAT"
+        );
+
+        let mut canonical: Vec<Chunk> = chunks
+            .iter()
+            .map(|(_, t)| Chunk::new(*t, Vec::new()))
+            .collect();
+        canonicalize(&mut canonical);
+        let chunks: Vec<(usize, ChunkType)> =
+            canonical.iter().map(|c| (0, *c.chunk_type())).collect();
+        assert!(misplaced(&chunks).is_empty());
+    }
+
+    #[test]
+    fn test_placement() {
+        let place = |types: &[&str]| {
+            let mut placement = Placement::default();
+            let chunks: Vec<(usize, ChunkType)> = types
+                .iter()
+                .enumerate()
+                .map(|(i, t)| (i * 100, ChunkType::from_str(t).unwrap()))
+                .collect();
+            let found = chunks
+                .iter()
+                .find_map(|&(offset, chunk_type)| placement.push(offset, chunk_type));
+            // Misplaced by one exactly when by the other
+            assert_eq!(
+                found.is_some(),
+                !misplaced(&chunks).is_empty(),
+                "{:?}",
+                types
+            );
+            found.map(|m| (m.index, m.rule))
+        };
+        assert_eq!(
+            place(&["IHDR", "tRNS", "PLTE", "gAMA", "IDAT", "IEND"]),
+            Some((1, Rule::AfterPlte))
+        );
+        assert_eq!(
+            place(&["IHDR", "PLTE", "gAMA", "IDAT", "IEND"]),
+            Some((2, Rule::BeforePlte))
+        );
+        assert_eq!(
+            place(&["IHDR", "IDAT", "tEXt", "IDAT", "IEND"]),
+            Some((3, Rule::ConsecutiveIdat))
+        );
+        assert_eq!(
+            place(&["IHDR", "IDAT", "pHYs", "IEND"]),
+            Some((2, Rule::BeforeIdat))
+        );
+        assert_eq!(place(&["IHDR", "IEND", "tEXt"]), Some((1, Rule::IendLast)));
+        assert_eq!(
+            place(&["IHDR", "tEXt", "IHDR", "IEND"]),
+            Some((2, Rule::IhdrFirst))
+        );
+        assert_eq!(
+            place(&["IHDR", "tRNS", "IDAT", "IDAT", "ruSt", "IEND"]),
+            None
+        );
+        assert_eq!(
+            place(&["IHDR", "gAMA", "PLTE", "bKGD", "IDAT", "IEND"]),
+            None
+        );
+    }
+
+    #[test]
+    fn test_canonical_order() {
+        let mut chunks = vec![
+            chunk("IHDR", &[0; 13]),
+            chunk("tEXt", b"Title\0x"),
+            chunk("IDAT", b"1"),
+            chunk("ruSt", b"hidden"),
+            chunk("IDAT", b"2"),
+            chunk("pHYs", &[0; 9]),
+            chunk("gAMA", &[0; 4]),
+            chunk("IEND", b""),
+        ];
+        canonicalize(&mut chunks);
+        assert_eq!(
+            types(&chunks),
+            ["IHDR", "gAMA", "pHYs", "tEXt", "IDAT", "IDAT", "ruSt", "IEND"]
+        );
+        assert_eq!(chunks[4].data(), b"1");
+        assert_eq!(chunks[5].data(), b"2");
+    }
+
+    #[test]
+    fn test_unknown_chunks_keep_their_side() {
+        let mut chunks = vec![
+            chunk("IHDR", &[0; 13]),
+            chunk("prVa", b"before PLTE"),
+            chunk("gAMA", &[0; 4]),
+            chunk("PLTE", &[0; 3]),
+            chunk("prVb", b"before IDAT"),
+            chunk("bKGD", &[0]),
+            chunk("tIME", &[0; 7]),
+            chunk("IDAT", b"1"),
+            chunk("prVc", b"after IDAT"),
+            chunk("pHYs", &[0; 9]),
+            chunk("IEND", b""),
+        ];
+        canonicalize(&mut chunks);
+        assert_eq!(
+            types(&chunks),
+            [
+                "IHDR", "prVa", "gAMA", "PLTE", "bKGD", "pHYs", "tIME", "prVb", "IDAT", "prVc",
+                "IEND"
+            ]
+        );
+
+        // Without PLTE, everything before IDAT is one side
+        let mut chunks = vec![
+            chunk("IHDR", &[0; 13]),
+            chunk("prVb", b""),
+            chunk("tEXt", b"Title\0x"),
+            chunk("gAMA", &[0; 4]),
+            chunk("IDAT", b"1"),
+            chunk("IEND", b""),
+        ];
+        canonicalize(&mut chunks);
+        assert_eq!(
+            types(&chunks),
+            ["IHDR", "gAMA", "prVb", "tEXt", "IDAT", "IEND"]
+        );
+    }
+
+    #[test]
+    fn test_text_sorted_by_keyword() {
+        let mut chunks = vec![
+            chunk("tEXt", b"Title\0x"),
+            chunk("iTXt", b"Author\0\0\0\0\0y"),
+            chunk("tEXt", b"Comment\0z"),
+        ];
+        canonicalize(&mut chunks);
+        let keywords: Vec<String> = chunks
+            .iter()
+            .map(|c| TextChunk::parse(c).unwrap().unwrap().keyword)
+            .collect();
+        assert_eq!(keywords, ["Author", "Comment", "Title"]);
+    }
+
+    #[test]
+    fn test_canonicalize_is_idempotent() {
+        let mut chunks = vec![
+            chunk("IEND", b""),
+            chunk("tIME", &[0; 7]),
+            chunk("IHDR", &[0; 13]),
+        ];
+        canonicalize(&mut chunks);
+        let once = chunks.clone();
+        canonicalize(&mut chunks);
+        assert_eq!(chunks, once);
+    }
+}
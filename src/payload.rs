@@ -0,0 +1,1713 @@
+#![deny(clippy::unwrap_used, clippy::expect_used)]
+
+use std::fmt::Display;
+use std::io;
+
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use x25519_dalek::{PublicKey, StaticSecret};
+
+#[cfg(feature = "age")]
+use crate::age_format::{self, AgeError};
+use crate::chunk_type::ChunkType;
+use crate::compress::Codec;
+use crate::crypto::{self, CryptoError, CryptoSuite, KdfParams, KEY_LEN, NONCE_LEN, SALT_LEN};
+use crate::deflate::Limits;
+use crate::ecc::{self, EccError};
+use crate::recipients::{self, STANZA_LEN};
+
+/// Prefix of payloads that carry a header; plain messages are stored as-is.
+/// The leading 0x89 can't start valid UTF-8, so text is never mistaken for it
+pub const MAGIC: [u8; 4] = [0x89, b'P', b'A', b'Y'];
+
+/// Newest payload version this build reads and writes. Version 0 is a plain
+/// message and version 1 a bare stack of format layers, from before
+/// payloads started with a [`Header`]
+pub const VERSION: u8 = 2;
+
+/// Length of the BLAKE3 integrity tag
+pub const TAG_LEN: usize = 32;
+
+/// Length of an Ed25519 payload signature
+pub const SIGNATURE_LEN: usize = 64;
+
+/// Key derivation context for integrity tags
+const TAG_CONTEXT: &str = "ping 2023 payload integrity tag";
+
+/// Format tag stored after the magic
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// Versioned [`Header`] in front of the other layers
+    Header = 0,
+    /// Argon2id-derived key, AES-256-GCM: kdf params, salt, nonce, ciphertext
+    Aes256Gcm = 1,
+    /// Keyed BLAKE3 tag around another payload: u32 length, inner, tag
+    Tagged = 2,
+    /// Ed25519 signature over the chunk type and the payload: u32 length,
+    /// inner, signature
+    Signed = 3,
+    /// First chunk of a split payload: u32 part count, u64 total length,
+    /// BLAKE3 hash of the whole payload
+    Manifest = 4,
+    /// One piece of a split payload: 8-byte id from the manifest hash, u32
+    /// index, data
+    Part = 5,
+    /// Compressed message: codec id, u64 uncompressed length, compressed data
+    Compressed = 6,
+    /// Two equal-sized encrypted slots under one set of kdf params: u32 slot
+    /// length, then each slot's salt, nonce and ciphertext. A slot holding
+    /// no message is random bytes, indistinguishable from ciphertext
+    Deniable = 7,
+    /// Random file key wrapped to each X25519 recipient: ephemeral public
+    /// key, u8 count, that many wrapped keys, nonce, ciphertext
+    Recipients = 8,
+    /// Reed-Solomon parity around another payload: u8 parity bytes per
+    /// 255-byte codeword, u32 inner length, interleaved codewords
+    Ecc = 9,
+    /// Message encrypted with age, in age's binary format
+    Age = 10,
+    /// Header of a payload streamed in segments: codec, encrypted flag, kdf
+    /// params, salt, nonce prefix and stream id; see [`crate::stream`]
+    Stream = 11,
+    /// One segment of a streamed payload: stream id, u32 index, last flag,
+    /// data
+    Segment = 12,
+    /// Like Aes256Gcm under another [`CryptoSuite`]: kdf id, cipher id, kdf
+    /// params, salt, nonce, ciphertext
+    Suite = 13,
+}
+
+impl TryFrom<u8> for Format {
+    type Error = PayloadError;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(Format::Header),
+            1 => Ok(Format::Aes256Gcm),
+            2 => Ok(Format::Tagged),
+            3 => Ok(Format::Signed),
+            4 => Ok(Format::Manifest),
+            5 => Ok(Format::Part),
+            6 => Ok(Format::Compressed),
+            7 => Ok(Format::Deniable),
+            8 => Ok(Format::Recipients),
+            9 => Ok(Format::Ecc),
+            10 => Ok(Format::Age),
+            11 => Ok(Format::Stream),
+            12 => Ok(Format::Segment),
+            13 => Ok(Format::Suite),
+            _ => Err(PayloadError::UnknownFormat(value)),
+        }
+    }
+}
+
+/// Versioned payload header: magic, format 0, version, flags, codec and the
+/// u32 length of the decoded message, followed by the layered payload
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Header {
+    pub version: u8,
+    pub flags: u8,
+    /// Applied to the message before any other layer
+    pub codec: Codec,
+    /// Written as 0 for deniable payloads, where comparing it with the
+    /// slots would reveal which holds the real message
+    pub length: u32,
+}
+
+impl Header {
+    pub const LEN: usize = MAGIC.len() + 4 + 4;
+
+    pub const ENCRYPTED: u8 = 1;
+    pub const TAGGED: u8 = 2;
+    pub const SIGNED: u8 = 4;
+    pub const DENIABLE: u8 = 8;
+    /// The message is an [`Attachment`](crate::attachment::Attachment)
+    pub const ATTACHMENT: u8 = 16;
+    pub const RECIPIENTS: u8 = 32;
+    /// The key derivation input includes a keyfile; without ENCRYPTED it's
+    /// the keyfile alone
+    pub const KEYFILE: u8 = 64;
+    /// Encrypted with age rather than the native scheme
+    pub const AGE: u8 = 128;
+
+    /// Parse the header at the start of `data`; a newer version is refused
+    /// before anything else is read, since its layout may have changed
+    pub fn parse(data: &[u8]) -> Result<Header, PayloadError> {
+        if !data.starts_with(&MAGIC) || data.get(MAGIC.len()) != Some(&(Format::Header as u8)) {
+            return Err(PayloadError::UnknownFormat(
+                data.get(MAGIC.len()).copied().unwrap_or_default(),
+            ));
+        }
+        let header = data.get(..Self::LEN).ok_or(PayloadError::Truncated)?;
+        let [version, flags, codec] = field(header, MAGIC.len() + 1)?;
+        if version > VERSION {
+            return Err(PayloadError::UnsupportedVersion(version));
+        }
+        Ok(Header {
+            version,
+            flags,
+            codec: Codec::try_from(codec).map_err(PayloadError::UnknownCodec)?,
+            length: u32::from_be_bytes(field(header, MAGIC.len() + 4)?),
+        })
+    }
+
+    fn to_bytes(self) -> Vec<u8> {
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend_from_slice(&[
+            Format::Header as u8,
+            self.version,
+            self.flags,
+            self.codec as u8,
+        ]);
+        let length = match self.flags & Self::DENIABLE {
+            0 => self.length,
+            _ => 0,
+        };
+        bytes.extend_from_slice(&length.to_be_bytes());
+        bytes
+    }
+}
+
+/// Version of the payload format `data` was written with, so callers can
+/// tell payloads they can't read from corrupt ones before decoding
+pub fn version(data: &[u8]) -> Result<u8, PayloadError> {
+    if !data.starts_with(&MAGIC) {
+        return Ok(0);
+    }
+    match data.get(MAGIC.len()) {
+        Some(0) => data
+            .get(MAGIC.len() + 1)
+            .copied()
+            .ok_or(PayloadError::Truncated),
+        Some(_) => Ok(1),
+        None => Err(PayloadError::Truncated),
+    }
+}
+
+/// Whether the message is an embedded file rather than text
+pub fn is_attachment(data: &[u8]) -> bool {
+    Header::parse(data).is_ok_and(|h| h.flags & Header::ATTACHMENT != 0)
+}
+
+/// Put a header in front of a payload; `body` is the already-compressed
+/// message with its other layers applied
+pub fn seal(header: Header, body: &[u8]) -> Vec<u8> {
+    let mut payload = header.to_bytes();
+    payload.extend_from_slice(body);
+    payload
+}
+
+/// Secrets available while decoding
+pub struct Keys {
+    pub password: Option<String>,
+    /// Key material for integrity tags; none means the empty key
+    pub integrity_key: Option<Vec<u8>>,
+    /// Key to check signed payloads against; none skips the check
+    pub verifying_key: Option<VerifyingKey>,
+    pub keyfile: Option<Vec<u8>>,
+    /// X25519 secret key for payloads encrypted to recipients
+    pub identity: Option<StaticSecret>,
+    /// Contents of an age identity file
+    #[cfg(feature = "age")]
+    pub age_identities: Option<String>,
+    /// Refuse payloads whose key derivation costs more than this, by
+    /// default [`KdfParams::DECODE_LIMIT`]; none allows up to the hard
+    /// caps, [`KdfParams::MAX_MEMORY_KIB`] and [`KdfParams::MAX_TIME_COST`]
+    pub kdf_limit: Option<KdfParams>,
+}
+
+impl Default for Keys {
+    fn default() -> Self {
+        Keys {
+            password: None,
+            integrity_key: None,
+            verifying_key: None,
+            keyfile: None,
+            identity: None,
+            #[cfg(feature = "age")]
+            age_identities: None,
+            kdf_limit: Some(KdfParams::DECODE_LIMIT),
+        }
+    }
+}
+
+impl Keys {
+    /// Key derivation input for password-encrypted payloads
+    fn secret(&self) -> Option<Vec<u8>> {
+        match (&self.password, &self.keyfile) {
+            (None, None) => None,
+            (password, keyfile) => Some(crypto::secret(password.as_deref(), keyfile.as_deref())),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum PayloadError {
+    Truncated,
+    UnknownFormat(u8),
+    /// Written by a newer release with a format this one can't read
+    UnsupportedVersion(u8),
+    PasswordRequired,
+    /// The integrity tag doesn't match: tampered data or the wrong key
+    IntegrityMismatch,
+    InvalidSignature,
+    IdentityRequired,
+    /// None of the payload's recipients match the identity
+    NotARecipient,
+    /// Encrypting to this many recipients rather than 1 to 255
+    RecipientCount(usize),
+    /// A split payload was decoded without reassembling its parts
+    Unassembled,
+    MissingParts {
+        expected: u32,
+        found: u32,
+    },
+    /// The reassembled parts don't hash to the manifest's content hash
+    ContentMismatch,
+    UnknownCodec(u8),
+    /// Wrapped in more than [`MAX_LAYERS`] layers
+    TooManyLayers,
+    /// The compressed data is corrupt or doesn't match its recorded length
+    Decompress,
+    /// The payload uses age but this build was made without it
+    #[cfg(not(feature = "age"))]
+    AgeUnsupported,
+    #[cfg(feature = "age")]
+    Age(AgeError),
+    Ecc(EccError),
+    Crypto(CryptoError),
+}
+
+impl Display for PayloadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PayloadError::Truncated => write!(f, "payload is truncated"),
+            PayloadError::UnknownFormat(tag) => write!(f, "unknown payload format {}", tag),
+            PayloadError::UnsupportedVersion(version) => write!(
+                f,
+                "payload version {} is newer than the supported version {}",
+                version, VERSION
+            ),
+            PayloadError::PasswordRequired => {
+                write!(f, "payload is encrypted, a password is required")
+            }
+            PayloadError::IntegrityMismatch => {
+                write!(
+                    f,
+                    "payload integrity check failed: tampered data or wrong key"
+                )
+            }
+            PayloadError::InvalidSignature => write!(f, "payload signature does not match"),
+            PayloadError::IdentityRequired => {
+                write!(
+                    f,
+                    "payload is encrypted to recipients, an identity is required"
+                )
+            }
+            PayloadError::NotARecipient => write!(f, "payload is not encrypted to this identity"),
+            PayloadError::RecipientCount(count) => write!(
+                f,
+                "payloads are encrypted to 1 to {} recipients, not {}",
+                u8::MAX,
+                count
+            ),
+            PayloadError::Unassembled => write!(f, "payload is split and must be reassembled"),
+            PayloadError::MissingParts { expected, found } => {
+                write!(f, "found {} of {} payload parts", found, expected)
+            }
+            PayloadError::ContentMismatch => {
+                write!(f, "reassembled payload does not match its manifest hash")
+            }
+            PayloadError::UnknownCodec(id) => write!(f, "unknown compression codec {}", id),
+            PayloadError::TooManyLayers => {
+                write!(f, "payload is nested more than {} layers deep", MAX_LAYERS)
+            }
+            PayloadError::Decompress => write!(f, "payload failed to decompress"),
+            #[cfg(not(feature = "age"))]
+            PayloadError::AgeUnsupported => {
+                write!(
+                    f,
+                    "age support is not built in, rebuild with --features age"
+                )
+            }
+            #[cfg(feature = "age")]
+            PayloadError::Age(e) => write!(f, "{}", e),
+            PayloadError::Ecc(e) => write!(f, "{}", e),
+            PayloadError::Crypto(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for PayloadError {}
+
+#[cfg(feature = "age")]
+impl From<AgeError> for PayloadError {
+    fn from(value: AgeError) -> Self {
+        PayloadError::Age(value)
+    }
+}
+
+impl From<EccError> for PayloadError {
+    fn from(value: EccError) -> Self {
+        PayloadError::Ecc(value)
+    }
+}
+
+impl From<CryptoError> for PayloadError {
+    fn from(value: CryptoError) -> Self {
+        PayloadError::Crypto(value)
+    }
+}
+
+/// Whether decoding needs a password, looking through integrity tags
+pub fn is_encrypted(data: &[u8]) -> bool {
+    if !data.starts_with(&MAGIC) {
+        return false;
+    }
+    match data.get(MAGIC.len()).map(|tag| Format::try_from(*tag)) {
+        Some(Ok(Format::Aes256Gcm | Format::Suite | Format::Deniable)) => true,
+        Some(Ok(Format::Header)) => {
+            Header::parse(data).is_ok_and(|h| h.flags & Header::ENCRYPTED != 0)
+        }
+        Some(Ok(Format::Tagged | Format::Signed)) => {
+            is_encrypted(data.get(WRAPPED_START..).unwrap_or(&[]))
+        }
+        _ => false,
+    }
+}
+
+/// Whether decoding needs a keyfile; only versioned payloads record it
+pub fn needs_keyfile(data: &[u8]) -> bool {
+    Header::parse(data).is_ok_and(|h| h.flags & Header::KEYFILE != 0)
+}
+
+/// Whether decoding needs an identity, looking through integrity tags
+pub fn is_for_recipients(data: &[u8]) -> bool {
+    if !data.starts_with(&MAGIC) {
+        return false;
+    }
+    match data.get(MAGIC.len()).map(|tag| Format::try_from(*tag)) {
+        Some(Ok(Format::Recipients)) => true,
+        Some(Ok(Format::Header)) => {
+            Header::parse(data).is_ok_and(|h| h.flags & Header::RECIPIENTS != 0)
+        }
+        Some(Ok(Format::Tagged | Format::Signed)) => {
+            is_for_recipients(data.get(WRAPPED_START..).unwrap_or(&[]))
+        }
+        _ => false,
+    }
+}
+
+/// Whether decoding needs an age identity, looking through integrity tags
+pub fn is_age(data: &[u8]) -> bool {
+    if !data.starts_with(&MAGIC) {
+        return false;
+    }
+    match data.get(MAGIC.len()).map(|tag| Format::try_from(*tag)) {
+        Some(Ok(Format::Age)) => true,
+        Some(Ok(Format::Header)) => Header::parse(data).is_ok_and(|h| h.flags & Header::AGE != 0),
+        Some(Ok(Format::Tagged | Format::Signed)) => {
+            is_age(data.get(WRAPPED_START..).unwrap_or(&[]))
+        }
+        _ => false,
+    }
+}
+
+/// Whether the payload carries a signature, looking through integrity tags
+pub fn is_signed(data: &[u8]) -> bool {
+    if !data.starts_with(&MAGIC) {
+        return false;
+    }
+    match data.get(MAGIC.len()).map(|tag| Format::try_from(*tag)) {
+        Some(Ok(Format::Signed)) => true,
+        Some(Ok(Format::Header)) => {
+            Header::parse(data).is_ok_and(|h| h.flags & Header::SIGNED != 0)
+        }
+        Some(Ok(Format::Tagged)) => is_signed(data.get(WRAPPED_START..).unwrap_or(&[])),
+        _ => false,
+    }
+}
+
+/// Hash of what fixes the key and nonce an encrypted payload was sealed
+/// with: kdf params, salts and nonces, or the recipients header. `None` if
+/// the payload isn't encrypted natively
+pub fn nonce_id(data: &[u8]) -> Option<[u8; 32]> {
+    if !data.starts_with(&MAGIC) {
+        return None;
+    }
+    let prefix_len = match Format::try_from(*data.get(MAGIC.len())?).ok()? {
+        Format::Header => return nonce_id(data.get(Header::LEN..)?),
+        Format::Tagged | Format::Signed => return nonce_id(data.get(WRAPPED_START..)?),
+        Format::Aes256Gcm => MAGIC.len() + 1 + KdfParams::LEN + SALT_LEN + NONCE_LEN,
+        Format::Suite => MAGIC.len() + 1 + CryptoSuite::LEN + KdfParams::LEN + SALT_LEN + NONCE_LEN,
+        Format::Recipients => {
+            let count = *data.get(MAGIC.len() + 33)? as usize;
+            MAGIC.len() + 34 + count * STANZA_LEN + NONCE_LEN
+        }
+        Format::Deniable => {
+            let header = data.get(..DENIABLE_START)?;
+            let slot_len = u32::from_be_bytes(field(header, DENIABLE_START - 4).ok()?) as usize;
+            let mut hasher = blake3::Hasher::new();
+            hasher.update(header);
+            for slot in data[DENIABLE_START..].chunks(slot_len.max(1)) {
+                hasher.update(slot.get(..SALT_LEN + NONCE_LEN)?);
+            }
+            return Some(*hasher.finalize().as_bytes());
+        }
+        _ => return None,
+    };
+    Some(*blake3::hash(data.get(..prefix_len)?).as_bytes())
+}
+
+/// The `N` bytes at `at`, for the fixed-size fields of a header
+fn field<const N: usize>(bytes: &[u8], at: usize) -> Result<[u8; N], PayloadError> {
+    let field = bytes.get(at..).and_then(|rest| rest.first_chunk::<N>());
+    field.copied().ok_or(PayloadError::Truncated)
+}
+
+/// Offset of the inner payload in a tagged or signed payload
+const WRAPPED_START: usize = MAGIC.len() + 5;
+
+/// Header and inner payload of a wrapping format, ready for a trailer
+fn wrap(format: Format, inner: &[u8]) -> Vec<u8> {
+    let mut payload = MAGIC.to_vec();
+    payload.push(format as u8);
+    payload.extend_from_slice(&(inner.len() as u32).to_be_bytes());
+    payload.extend_from_slice(inner);
+    payload
+}
+
+/// Split a wrapped payload into the covered bytes and the trailer
+fn unwrap(data: &[u8], trailer_len: usize) -> Result<(&[u8], &[u8]), PayloadError> {
+    let length = u32::from_be_bytes(field(data, MAGIC.len() + 1)?) as usize;
+    // Space for the covered data, compared so a forged length can't overflow
+    let room = (data.len() - WRAPPED_START).checked_sub(trailer_len);
+    if room.is_none_or(|room| room < length) {
+        return Err(PayloadError::Truncated);
+    }
+    let end = WRAPPED_START + length;
+    Ok(data[..end + trailer_len].split_at(end))
+}
+
+fn integrity_tag(key_material: &[u8], data: &[u8]) -> [u8; TAG_LEN] {
+    let key = blake3::derive_key(TAG_CONTEXT, key_material);
+    *blake3::keyed_hash(&key, data).as_bytes()
+}
+
+/// Wrap a payload in a keyed BLAKE3 tag covering its header and contents
+pub fn tag(inner: &[u8], key_material: &[u8]) -> Vec<u8> {
+    let mut payload = wrap(Format::Tagged, inner);
+    let tag = integrity_tag(key_material, &payload);
+    payload.extend_from_slice(&tag);
+    payload
+}
+
+/// Signature input: the chunk type first, so a signed payload can't be
+/// replayed under a different chunk type
+fn signed_message(chunk_type: &ChunkType, covered: &[u8]) -> Vec<u8> {
+    chunk_type.bytes().iter().chain(covered).copied().collect()
+}
+
+/// Wrap a payload in an Ed25519 signature bound to `chunk_type`
+pub fn sign(inner: &[u8], chunk_type: &ChunkType, key: &SigningKey) -> Vec<u8> {
+    let mut payload = wrap(Format::Signed, inner);
+    let signature = key.sign(&signed_message(chunk_type, &payload));
+    payload.extend_from_slice(&signature.to_bytes());
+    payload
+}
+
+/// Length of the compressed payload header
+const COMPRESSED_START: usize = MAGIC.len() + 1 + 1 + 8;
+
+/// Compress `message`, recording the codec so decode can pick it out;
+/// with [`Codec::None`] the message is returned as-is
+pub fn compress(message: &[u8], codec: Codec) -> io::Result<Vec<u8>> {
+    if codec == Codec::None {
+        return Ok(message.to_vec());
+    }
+    let mut payload = MAGIC.to_vec();
+    payload.push(Format::Compressed as u8);
+    payload.push(codec as u8);
+    payload.extend_from_slice(&(message.len() as u64).to_be_bytes());
+    payload.extend(codec.compress(message)?);
+    Ok(payload)
+}
+
+/// Inverse of [`compress`]; anything without a compressed header is
+/// returned unchanged. Data inflating past [`Limits::PAYLOAD`] fails,
+/// whatever length the header records
+pub(crate) fn decompress(data: Vec<u8>) -> Result<Vec<u8>, PayloadError> {
+    if !data.starts_with(&MAGIC) || data.get(MAGIC.len()) != Some(&(Format::Compressed as u8)) {
+        return Ok(data);
+    }
+    let header = data
+        .get(..COMPRESSED_START)
+        .ok_or(PayloadError::Truncated)?;
+    let codec = Codec::try_from(header[MAGIC.len() + 1]).map_err(PayloadError::UnknownCodec)?;
+    let length = u64::from_be_bytes(field(header, MAGIC.len() + 2)?);
+    let compressed = &data[COMPRESSED_START..];
+    let limit = length.min(Limits::PAYLOAD.output_for(compressed.len()));
+    let message = codec
+        .decompress(compressed, limit)
+        .map_err(|_| PayloadError::Decompress)?;
+    if message.len() as u64 != length {
+        return Err(PayloadError::Decompress);
+    }
+    Ok(message)
+}
+
+/// Encrypt `message` under `secret`, a password or a [`crypto::secret`]
+/// combining one with a keyfile; the header is authenticated as AAD. A
+/// suite other than the default is recorded in the payload, while the
+/// default keeps the Aes256Gcm format older releases read
+pub fn encrypt(
+    message: &[u8],
+    secret: &[u8],
+    params: &KdfParams,
+    suite: CryptoSuite,
+) -> Result<Vec<u8>, PayloadError> {
+    let salt: [u8; SALT_LEN] = crypto::random()?;
+    let nonce: [u8; NONCE_LEN] = crypto::random()?;
+    let key = suite.derive_key(secret, &salt, params)?;
+
+    let mut payload = MAGIC.to_vec();
+    match suite == CryptoSuite::default() {
+        true => payload.push(Format::Aes256Gcm as u8),
+        false => {
+            payload.push(Format::Suite as u8);
+            payload.extend_from_slice(&suite.to_bytes());
+        }
+    }
+    payload.extend_from_slice(&params.to_bytes());
+    payload.extend_from_slice(&salt);
+    payload.extend_from_slice(&nonce);
+    let ciphertext = suite.encrypt(&key, &nonce, message, &payload)?;
+    payload.extend(ciphertext);
+    Ok(payload)
+}
+
+/// Offset of the codewords in an error-corrected payload
+const ECC_START: usize = MAGIC.len() + 6;
+
+/// Add `parity` Reed-Solomon bytes per 255-byte block, so the payload
+/// survives that many erased or half as many flipped bytes in each block.
+/// The short frame header itself isn't protected
+pub fn protect(inner: &[u8], parity: u8) -> Result<Vec<u8>, PayloadError> {
+    let mut payload = MAGIC.to_vec();
+    payload.push(Format::Ecc as u8);
+    payload.push(parity);
+    payload.extend_from_slice(&(inner.len() as u32).to_be_bytes());
+    payload.extend_from_slice(&ecc::encode(inner, parity)?);
+    Ok(payload)
+}
+
+/// Undo [`protect`], repairing what damage the parity allows; returns the
+/// inner payload and the number of bytes corrected. Anything else is
+/// returned as it is
+pub fn correct(data: &[u8]) -> Result<(Vec<u8>, usize), PayloadError> {
+    if !data.starts_with(&MAGIC) || data.get(MAGIC.len()) != Some(&(Format::Ecc as u8)) {
+        return Ok((data.to_vec(), 0));
+    }
+    let header = data.get(..ECC_START).ok_or(PayloadError::Truncated)?;
+    let parity = header[MAGIC.len() + 1];
+    let length = u32::from_be_bytes(field(header, MAGIC.len() + 2)?) as usize;
+    Ok(ecc::decode(&data[ECC_START..], length, parity)?)
+}
+
+/// Most layers of wrapping a payload may have; more than any encoder
+/// produces, and few enough that forged nesting can't exhaust the stack
+pub const MAX_LAYERS: usize = 16;
+
+/// Recover the message from a payload stored in a `chunk_type` chunk
+pub fn decode(data: &[u8], chunk_type: &ChunkType, keys: &Keys) -> Result<Vec<u8>, PayloadError> {
+    decode_layer(data, chunk_type, keys, 0)
+}
+
+fn decode_layer(
+    data: &[u8],
+    chunk_type: &ChunkType,
+    keys: &Keys,
+    depth: usize,
+) -> Result<Vec<u8>, PayloadError> {
+    if !data.starts_with(&MAGIC) {
+        return Ok(data.to_vec());
+    }
+    if depth == MAX_LAYERS {
+        return Err(PayloadError::TooManyLayers);
+    }
+    let inner = |data: &[u8]| decode_layer(data, chunk_type, keys, depth + 1);
+    let format = data.get(MAGIC.len()).ok_or(PayloadError::Truncated)?;
+    match Format::try_from(*format)? {
+        Format::Header => open(data, inner),
+        Format::Aes256Gcm | Format::Suite => decompress(decrypt(
+            data,
+            keys.secret().as_deref(),
+            keys.kdf_limit.as_ref(),
+        )?),
+        Format::Tagged => inner(check_tag(data, keys)?),
+        Format::Signed => inner(check_signature(data, chunk_type, keys)?),
+        Format::Manifest | Format::Part | Format::Stream | Format::Segment => {
+            Err(PayloadError::Unassembled)
+        }
+        Format::Compressed => decompress(data.to_vec()),
+        Format::Deniable => decompress(decrypt_deniable(
+            data,
+            keys.secret().as_deref(),
+            keys.kdf_limit.as_ref(),
+        )?),
+        Format::Recipients => decompress(decrypt_for(data, keys.identity.as_ref())?),
+        Format::Ecc => inner(&correct(data)?.0),
+        Format::Age => decrypt_age(data, keys),
+    }
+}
+
+fn open(
+    data: &[u8],
+    inner: impl Fn(&[u8]) -> Result<Vec<u8>, PayloadError>,
+) -> Result<Vec<u8>, PayloadError> {
+    let header = Header::parse(data)?;
+    let body = inner(&data[Header::LEN..])?;
+    let deniable = header.flags & Header::DENIABLE != 0;
+    let limit = match deniable {
+        true => u32::MAX,
+        false => header.length,
+    };
+    let limit = (limit as u64).min(Limits::PAYLOAD.output_for(body.len()));
+    let message = header
+        .codec
+        .decompress(&body, limit)
+        .map_err(|_| PayloadError::Decompress)?;
+    if !deniable && message.len() != header.length as usize {
+        return Err(PayloadError::Decompress);
+    }
+    Ok(message)
+}
+
+fn check_tag<'a>(data: &'a [u8], keys: &Keys) -> Result<&'a [u8], PayloadError> {
+    let (covered, tag) = unwrap(data, TAG_LEN)?;
+    let key_material = keys.integrity_key.as_deref().unwrap_or_default();
+    if integrity_tag(key_material, covered) != tag {
+        return Err(PayloadError::IntegrityMismatch);
+    }
+    Ok(&covered[WRAPPED_START..])
+}
+
+fn check_signature<'a>(
+    data: &'a [u8],
+    chunk_type: &ChunkType,
+    keys: &Keys,
+) -> Result<&'a [u8], PayloadError> {
+    let (covered, signature) = unwrap(data, SIGNATURE_LEN)?;
+    if let Some(key) = &keys.verifying_key {
+        let signature =
+            Signature::from_slice(signature).map_err(|_| PayloadError::InvalidSignature)?;
+        key.verify(&signed_message(chunk_type, covered), &signature)
+            .map_err(|_| PayloadError::InvalidSignature)?;
+    }
+    Ok(&covered[WRAPPED_START..])
+}
+
+/// Length of the manifest chunk data
+const MANIFEST_LEN: usize = MAGIC.len() + 1 + 4 + 8 + 32;
+
+/// Offset of the data in a part chunk
+const PART_START: usize = MAGIC.len() + 1 + 8 + 4;
+
+/// Split a payload too big for one chunk into a manifest followed by parts
+/// of at most `max_len` bytes each (including their header); payloads that
+/// fit are returned whole
+pub fn split(payload: Vec<u8>, max_len: usize) -> Vec<Vec<u8>> {
+    if payload.len() <= max_len {
+        return vec![payload];
+    }
+    let hash = blake3::hash(&payload);
+    let pieces: Vec<&[u8]> = payload
+        .chunks(max_len.saturating_sub(PART_START).max(1))
+        .collect();
+
+    let mut manifest = MAGIC.to_vec();
+    manifest.push(Format::Manifest as u8);
+    manifest.extend_from_slice(&(pieces.len() as u32).to_be_bytes());
+    manifest.extend_from_slice(&(payload.len() as u64).to_be_bytes());
+    manifest.extend_from_slice(hash.as_bytes());
+
+    let mut chunks = vec![manifest];
+    for (index, piece) in pieces.iter().enumerate() {
+        let mut part = MAGIC.to_vec();
+        part.push(Format::Part as u8);
+        part.extend_from_slice(&hash.as_bytes()[..8]);
+        part.extend_from_slice(&(index as u32).to_be_bytes());
+        part.extend_from_slice(piece);
+        chunks.push(part);
+    }
+    chunks
+}
+
+/// Whether `data` is the manifest of a split payload
+pub fn is_manifest(data: &[u8]) -> bool {
+    data.len() == MANIFEST_LEN
+        && data.starts_with(&MAGIC)
+        && data[MAGIC.len()] == Format::Manifest as u8
+}
+
+/// Whether `part` belongs to the payload described by `manifest`
+pub fn is_part_of(manifest: &[u8], part: &[u8]) -> bool {
+    part.len() >= PART_START
+        && part.starts_with(&MAGIC)
+        && part[MAGIC.len()] == Format::Part as u8
+        && part[MAGIC.len() + 1..MAGIC.len() + 9] == manifest[MAGIC.len() + 13..MAGIC.len() + 21]
+}
+
+/// Reassemble the payload from the data of every chunk of its type, in file
+/// order; a payload that wasn't split is just the first chunk
+pub fn reassemble<'a>(mut chunks: impl Iterator<Item = &'a [u8]>) -> Result<Vec<u8>, PayloadError> {
+    let first = chunks.next().ok_or(PayloadError::Truncated)?;
+    if !is_manifest(first) {
+        return Ok(first.to_vec());
+    }
+    let expected = u32::from_be_bytes(field(first, MAGIC.len() + 1)?);
+    let total = u64::from_be_bytes(field(first, MAGIC.len() + 5)?);
+
+    let mut payload = Vec::new();
+    let mut found = 0;
+    for part in chunks.filter(|part| is_part_of(first, part)) {
+        let index = u32::from_be_bytes(field(part, PART_START - 4)?);
+        if index != found {
+            break;
+        }
+        payload.extend_from_slice(&part[PART_START..]);
+        found += 1;
+    }
+    if found != expected || payload.len() as u64 != total {
+        return Err(PayloadError::MissingParts { expected, found });
+    }
+    if blake3::hash(&payload).as_bytes()[..] != first[MAGIC.len() + 13..] {
+        return Err(PayloadError::ContentMismatch);
+    }
+    Ok(payload)
+}
+
+/// Length of the deniable payload header
+const DENIABLE_START: usize = MAGIC.len() + 1 + KdfParams::LEN + 4;
+
+/// Slot plaintexts are padded to a multiple of this, so the slot size says
+/// little about either message
+const SLOT_PADDING: usize = 64;
+
+/// Encrypt `message` into one of two slots, at random, and `decoy` (if any)
+/// into the other. Either secret opens only its own slot, and since an
+/// unused slot is random filler, nothing shows whether the second holds a
+/// message
+pub fn encrypt_deniable(
+    message: &[u8],
+    secret: &[u8],
+    decoy: Option<(&[u8], &[u8])>,
+    params: &KdfParams,
+) -> Result<Vec<u8>, PayloadError> {
+    let longest = message.len().max(decoy.map_or(0, |(m, _)| m.len()));
+    let padded = (longest + 4).div_ceil(SLOT_PADDING) * SLOT_PADDING;
+    let slot_len = SALT_LEN + NONCE_LEN + padded + crypto::TAG_LEN;
+
+    let mut payload = MAGIC.to_vec();
+    payload.push(Format::Deniable as u8);
+    payload.extend_from_slice(&params.to_bytes());
+    payload.extend_from_slice(&(slot_len as u32).to_be_bytes());
+
+    let seal = |message: &[u8], secret: &[u8]| -> Result<Vec<u8>, PayloadError> {
+        let salt: [u8; SALT_LEN] = crypto::random()?;
+        let nonce: [u8; NONCE_LEN] = crypto::random()?;
+        let key = crypto::derive_key(secret, &salt, params)?;
+        let mut plaintext = (message.len() as u32).to_be_bytes().to_vec();
+        plaintext.extend_from_slice(message);
+        plaintext.resize(padded, 0);
+        let mut slot = salt.to_vec();
+        slot.extend_from_slice(&nonce);
+        slot.extend(crypto::encrypt(&key, &nonce, &plaintext, &payload)?);
+        Ok(slot)
+    };
+    let mut slots = [
+        seal(message, secret)?,
+        match decoy {
+            Some((decoy, secret)) => seal(decoy, secret)?,
+            None => crypto::random_bytes(slot_len)?,
+        },
+    ];
+    if crypto::random::<1>()?[0] & 1 == 1 {
+        slots.swap(0, 1);
+    }
+    for slot in slots {
+        payload.extend(slot);
+    }
+    Ok(payload)
+}
+
+/// Open whichever slot `secret` fits; a wrong secret and an empty slot fail
+/// the same way
+fn decrypt_deniable(
+    data: &[u8],
+    secret: Option<&[u8]>,
+    limit: Option<&KdfParams>,
+) -> Result<Vec<u8>, PayloadError> {
+    let header = data.get(..DENIABLE_START).ok_or(PayloadError::Truncated)?;
+    let secret = secret.ok_or(PayloadError::PasswordRequired)?;
+    let params = check_params(
+        KdfParams::from_bytes(&field(header, MAGIC.len() + 1)?),
+        limit,
+    )?;
+    let slot_len = u32::from_be_bytes(field(header, DENIABLE_START - 4)?) as usize;
+    if slot_len < SALT_LEN + NONCE_LEN + crypto::TAG_LEN + 4
+        || data.len() != DENIABLE_START + 2 * slot_len
+    {
+        return Err(PayloadError::Truncated);
+    }
+    for slot in data[DENIABLE_START..].chunks_exact(slot_len) {
+        let (salt, rest) = slot.split_at(SALT_LEN);
+        let (nonce, ciphertext) = rest.split_at(NONCE_LEN);
+        let key = crypto::derive_key(secret, salt, &params)?;
+        if let Ok(plaintext) = crypto::decrypt(&key, &field(nonce, 0)?, ciphertext, header) {
+            let (length, message) = plaintext
+                .split_first_chunk::<4>()
+                .ok_or(PayloadError::Truncated)?;
+            let message = message
+                .get(..u32::from_be_bytes(*length) as usize)
+                .ok_or(PayloadError::Truncated)?;
+            return Ok(message.to_vec());
+        }
+    }
+    Err(CryptoError::Decrypt.into())
+}
+
+/// Encrypt `message` under a random key, and wrap that key to each of the
+/// recipients so any one of them can decrypt; there must be 1 to 255
+pub fn encrypt_to(message: &[u8], recipients: &[PublicKey]) -> Result<Vec<u8>, PayloadError> {
+    let count = u8::try_from(recipients.len())
+        .ok()
+        .filter(|&count| count > 0)
+        .ok_or(PayloadError::RecipientCount(recipients.len()))?;
+    let file_key: [u8; KEY_LEN] = crypto::random()?;
+    let ephemeral = recipients::generate()?;
+    let nonce: [u8; NONCE_LEN] = crypto::random()?;
+
+    let mut payload = MAGIC.to_vec();
+    payload.push(Format::Recipients as u8);
+    payload.extend_from_slice(PublicKey::from(&ephemeral).as_bytes());
+    payload.push(count);
+    for recipient in recipients {
+        payload.extend(recipients::wrap(&file_key, &ephemeral, recipient)?);
+    }
+    payload.extend_from_slice(&nonce);
+    let ciphertext = crypto::encrypt(&file_key, &nonce, message, &payload)?;
+    payload.extend(ciphertext);
+    Ok(payload)
+}
+
+fn decrypt_for(data: &[u8], identity: Option<&StaticSecret>) -> Result<Vec<u8>, PayloadError> {
+    let stanzas_start = MAGIC.len() + 1 + 32 + 1;
+    let count = *data.get(stanzas_start - 1).ok_or(PayloadError::Truncated)? as usize;
+    let header_len = stanzas_start + count * STANZA_LEN + NONCE_LEN;
+    if data.len() < header_len {
+        return Err(PayloadError::Truncated);
+    }
+    let identity = identity.ok_or(PayloadError::IdentityRequired)?;
+    let ephemeral: [u8; 32] = field(data, MAGIC.len() + 1)?;
+    let ephemeral = PublicKey::from(ephemeral);
+    let file_key = data[stanzas_start..header_len - NONCE_LEN]
+        .chunks_exact(STANZA_LEN)
+        .find_map(|stanza| recipients::unwrap(stanza, &ephemeral, identity))
+        .ok_or(PayloadError::NotARecipient)?;
+    let (header, ciphertext) = data.split_at(header_len);
+    let nonce = &field(header, header_len - NONCE_LEN)?;
+    Ok(crypto::decrypt(&file_key, nonce, ciphertext, header)?)
+}
+
+/// Encrypt to age recipients, `age1...` public keys
+#[cfg(feature = "age")]
+pub fn encrypt_age(message: &[u8], recipients: &[String]) -> Result<Vec<u8>, PayloadError> {
+    let mut payload = MAGIC.to_vec();
+    payload.push(Format::Age as u8);
+    payload.extend_from_slice(&age_format::encrypt(message, recipients)?);
+    Ok(payload)
+}
+
+#[cfg(feature = "age")]
+fn decrypt_age(data: &[u8], keys: &Keys) -> Result<Vec<u8>, PayloadError> {
+    let identities = keys
+        .age_identities
+        .as_deref()
+        .ok_or(PayloadError::IdentityRequired)?;
+    decompress(age_format::decrypt(&data[MAGIC.len() + 1..], identities)?)
+}
+
+#[cfg(not(feature = "age"))]
+fn decrypt_age(_data: &[u8], _keys: &Keys) -> Result<Vec<u8>, PayloadError> {
+    Err(PayloadError::AgeUnsupported)
+}
+
+/// `params` unless they cost more than `limit`
+fn check_params(params: KdfParams, limit: Option<&KdfParams>) -> Result<KdfParams, PayloadError> {
+    match limit {
+        Some(limit) if !params.within(limit) => Err(CryptoError::KdfTooCostly(params).into()),
+        _ => Ok(params),
+    }
+}
+
+/// Decrypt an Aes256Gcm or Suite payload, if its key derivation costs no
+/// more than `limit`
+pub(crate) fn decrypt(
+    data: &[u8],
+    secret: Option<&[u8]>,
+    limit: Option<&KdfParams>,
+) -> Result<Vec<u8>, PayloadError> {
+    let suite_len = match data.get(MAGIC.len()) {
+        Some(&format) if format == Format::Suite as u8 => CryptoSuite::LEN,
+        _ => 0,
+    };
+    let header_len = MAGIC.len() + 1 + suite_len + KdfParams::LEN + SALT_LEN + NONCE_LEN;
+    if data.len() < header_len {
+        return Err(PayloadError::Truncated);
+    }
+    let (header, ciphertext) = data.split_at(header_len);
+    let (suite, rest) = header[MAGIC.len() + 1..].split_at(suite_len);
+    let suite = match suite {
+        [] => CryptoSuite::default(),
+        ids => CryptoSuite::from_bytes(field(ids, 0)?)?,
+    };
+    let secret = secret.ok_or(PayloadError::PasswordRequired)?;
+    let (params, rest) = rest.split_at(KdfParams::LEN);
+    let params = check_params(KdfParams::from_bytes(&field(params, 0)?), limit)?;
+    let (salt, nonce) = rest.split_at(SALT_LEN);
+    let key = suite.derive_key(secret, salt, &params)?;
+    Ok(suite.decrypt(&key, &field(nonce, 0)?, ciphertext, header)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::{Cipher, Kdf};
+
+    fn rust() -> ChunkType {
+        "ruSt".parse().unwrap()
+    }
+
+    fn password(password: &str) -> Keys {
+        Keys {
+            password: Some(password.to_string()),
+            ..Keys::default()
+        }
+    }
+
+    #[test]
+    fn test_plain_passthrough() {
+        assert!(!is_encrypted(b"hello"));
+        assert_eq!(
+            decode(b"hello", &rust(), &Keys::default()).unwrap(),
+            b"hello"
+        );
+    }
+
+    #[test]
+    fn test_encrypt_round_trip() {
+        let payload = encrypt(
+            b"attack at dawn",
+            b"hunter2",
+            &KdfParams::default(),
+            CryptoSuite::default(),
+        )
+        .unwrap();
+        assert!(is_encrypted(&payload));
+        assert!(!payload.windows(6).any(|w| w == b"attack"));
+        assert_eq!(
+            decode(&payload, &rust(), &password("hunter2")).unwrap(),
+            b"attack at dawn"
+        );
+    }
+
+    #[test]
+    fn test_wrong_or_missing_password() {
+        let payload = encrypt(
+            b"attack at dawn",
+            b"hunter2",
+            &KdfParams::default(),
+            CryptoSuite::default(),
+        )
+        .unwrap();
+        assert_eq!(
+            decode(&payload, &rust(), &Keys::default()),
+            Err(PayloadError::PasswordRequired)
+        );
+        assert_eq!(
+            decode(&payload, &rust(), &password("hunter3")),
+            Err(PayloadError::Crypto(CryptoError::Decrypt))
+        );
+    }
+
+    #[test]
+    fn test_kdf_params_stored_in_header() {
+        let params = KdfParams {
+            memory_kib: 4096,
+            time_cost: 3,
+            parallelism: 1,
+        };
+        let payload = encrypt(
+            b"attack at dawn",
+            b"hunter2",
+            &params,
+            CryptoSuite::default(),
+        )
+        .unwrap();
+        let stored: [u8; KdfParams::LEN] = payload[5..5 + KdfParams::LEN].try_into().unwrap();
+        assert_eq!(KdfParams::from_bytes(&stored), params);
+        assert_eq!(
+            decode(&payload, &rust(), &password("hunter2")).unwrap(),
+            b"attack at dawn"
+        );
+    }
+
+    #[test]
+    fn test_kdf_limit_by_default() {
+        let params = KdfParams {
+            memory_kib: 64,
+            time_cost: KdfParams::DECODE_LIMIT.time_cost + 1,
+            parallelism: 1,
+        };
+        let payload = encrypt(
+            b"attack at dawn",
+            b"hunter2",
+            &params,
+            CryptoSuite::default(),
+        )
+        .unwrap();
+        assert!(matches!(
+            decode(&payload, &rust(), &password("hunter2")),
+            Err(PayloadError::Crypto(CryptoError::KdfTooCostly(p))) if p == params
+        ));
+        let unlimited = Keys {
+            kdf_limit: None,
+            ..password("hunter2")
+        };
+        assert_eq!(
+            decode(&payload, &rust(), &unlimited).unwrap(),
+            b"attack at dawn"
+        );
+    }
+
+    #[test]
+    fn test_suite_recorded() {
+        let suite = CryptoSuite {
+            kdf: Kdf::Scrypt,
+            cipher: Cipher::ChaCha20Poly1305,
+        };
+        let payload = encrypt(b"attack at dawn", b"hunter2", &cheap(), suite).unwrap();
+        assert_eq!(payload[MAGIC.len()], Format::Suite as u8);
+        assert!(is_encrypted(&payload));
+        assert!(nonce_id(&payload).is_some());
+        assert_eq!(
+            decode(&payload, &rust(), &password("hunter2")).unwrap(),
+            b"attack at dawn"
+        );
+
+        let default = encrypt(b"x", b"pw", &cheap(), CryptoSuite::default()).unwrap();
+        assert_eq!(default[MAGIC.len()], Format::Aes256Gcm as u8);
+
+        let mut unknown = payload.clone();
+        unknown[MAGIC.len() + 2] = 9;
+        assert_eq!(
+            decode(&unknown, &rust(), &password("hunter2")),
+            Err(PayloadError::Crypto(CryptoError::UnknownSuite([1, 9])))
+        );
+    }
+
+    #[test]
+    fn test_tampered_header() {
+        let mut payload = encrypt(
+            b"attack at dawn",
+            b"hunter2",
+            &KdfParams::default(),
+            CryptoSuite::default(),
+        )
+        .unwrap();
+        payload[MAGIC.len() + 1] ^= 1;
+        assert!(decode(&payload, &rust(), &password("hunter2")).is_err());
+    }
+
+    #[test]
+    fn test_unknown_format_and_truncation() {
+        let mut payload =
+            encrypt(b"x", b"pw", &KdfParams::default(), CryptoSuite::default()).unwrap();
+        payload[MAGIC.len()] = 0xff;
+        assert_eq!(
+            decode(&payload, &rust(), &password("pw")),
+            Err(PayloadError::UnknownFormat(0xff))
+        );
+        assert_eq!(
+            decode(&MAGIC, &rust(), &password("pw")),
+            Err(PayloadError::Truncated)
+        );
+    }
+
+    #[test]
+    fn test_tagged_round_trip() {
+        let payload = tag(b"plain message", b"");
+        assert!(!is_encrypted(&payload));
+        assert_eq!(
+            decode(&payload, &rust(), &Keys::default()).unwrap(),
+            b"plain message"
+        );
+
+        let keys = Keys {
+            integrity_key: Some(b"key".to_vec()),
+            ..Keys::default()
+        };
+        let payload = tag(b"plain message", b"key");
+        assert_eq!(decode(&payload, &rust(), &keys).unwrap(), b"plain message");
+        assert_eq!(
+            decode(&payload, &rust(), &Keys::default()),
+            Err(PayloadError::IntegrityMismatch)
+        );
+    }
+
+    #[test]
+    fn test_tagged_tampering() {
+        let mut payload = tag(b"plain message", b"");
+        payload[MAGIC.len() + 6] ^= 1;
+        assert_eq!(
+            decode(&payload, &rust(), &Keys::default()),
+            Err(PayloadError::IntegrityMismatch)
+        );
+    }
+
+    #[test]
+    fn test_tagged_truncation() {
+        let payload = tag(b"plain message", b"");
+        for len in [MAGIC.len() + 2, payload.len() - 1] {
+            assert_eq!(
+                decode(&payload[..len], &rust(), &Keys::default()),
+                Err(PayloadError::Truncated)
+            );
+        }
+    }
+
+    #[test]
+    fn test_tagged_encrypted() {
+        let encrypted = encrypt(
+            b"attack at dawn",
+            b"hunter2",
+            &KdfParams::default(),
+            CryptoSuite::default(),
+        )
+        .unwrap();
+        let payload = tag(&encrypted, b"");
+        assert!(is_encrypted(&payload));
+        assert_eq!(
+            decode(&payload, &rust(), &password("hunter2")).unwrap(),
+            b"attack at dawn"
+        );
+    }
+
+    #[test]
+    fn test_signed_round_trip() {
+        let key = SigningKey::from_bytes(&[7; 32]);
+        let payload = sign(b"from me", &rust(), &key);
+        assert!(is_signed(&payload));
+        let keys = Keys {
+            verifying_key: Some(key.verifying_key()),
+            ..Keys::default()
+        };
+        assert_eq!(decode(&payload, &rust(), &keys).unwrap(), b"from me");
+
+        let other = Keys {
+            verifying_key: Some(SigningKey::from_bytes(&[8; 32]).verifying_key()),
+            ..Keys::default()
+        };
+        assert_eq!(
+            decode(&payload, &rust(), &other),
+            Err(PayloadError::InvalidSignature)
+        );
+    }
+
+    #[test]
+    fn test_signature_bound_to_chunk_type() {
+        let key = SigningKey::from_bytes(&[7; 32]);
+        let payload = sign(b"from me", &rust(), &key);
+        let keys = Keys {
+            verifying_key: Some(key.verifying_key()),
+            ..Keys::default()
+        };
+        let other_type: ChunkType = "ruSH".parse().unwrap();
+        assert_eq!(
+            decode(&payload, &other_type, &keys),
+            Err(PayloadError::InvalidSignature)
+        );
+    }
+
+    #[test]
+    fn test_signed_encrypted_and_tagged() {
+        let key = SigningKey::from_bytes(&[7; 32]);
+        let encrypted = encrypt(
+            b"attack at dawn",
+            b"hunter2",
+            &KdfParams::default(),
+            CryptoSuite::default(),
+        )
+        .unwrap();
+        let payload = tag(&sign(&encrypted, &rust(), &key), b"");
+        assert!(is_signed(&payload) && is_encrypted(&payload));
+        let keys = Keys {
+            verifying_key: Some(key.verifying_key()),
+            ..password("hunter2")
+        };
+        assert_eq!(decode(&payload, &rust(), &keys).unwrap(), b"attack at dawn");
+    }
+
+    #[test]
+    fn test_split_small_payload() {
+        assert_eq!(split(b"small".to_vec(), 64), [b"small".to_vec()]);
+    }
+
+    #[test]
+    fn test_split_and_reassemble() {
+        let payload: Vec<u8> = (0..=255).cycle().take(1000).collect();
+        let chunks = split(payload.clone(), 100);
+        assert!(is_manifest(&chunks[0]));
+        assert!(chunks.iter().all(|c| c.len() <= 100));
+        assert_eq!(chunks.len(), 1 + 1000usize.div_ceil(100 - PART_START));
+
+        let unrelated = split(vec![1; 500], 100);
+        let mixed = chunks
+            .iter()
+            .chain(unrelated.iter().skip(1))
+            .map(|c| c.as_slice());
+        assert_eq!(reassemble(mixed).unwrap(), payload);
+        assert_eq!(
+            decode(&chunks[0], &rust(), &Keys::default()),
+            Err(PayloadError::Unassembled)
+        );
+    }
+
+    #[test]
+    fn test_reassemble_missing_part() {
+        let chunks = split(vec![7; 1000], 100);
+        let without_last = chunks[..chunks.len() - 1].iter().map(|c| c.as_slice());
+        assert!(matches!(
+            reassemble(without_last),
+            Err(PayloadError::MissingParts { .. })
+        ));
+    }
+
+    #[test]
+    fn test_reassemble_corrupt_part() {
+        let mut chunks = split(vec![7; 1000], 100);
+        chunks[2][PART_START] ^= 1;
+        assert_eq!(
+            reassemble(chunks.iter().map(|c| c.as_slice())),
+            Err(PayloadError::ContentMismatch)
+        );
+    }
+
+    #[test]
+    fn test_compressed_round_trip() {
+        let message = "all work and no play ".repeat(100);
+        for codec in [Codec::Zlib, Codec::Zstd] {
+            let payload = compress(message.as_bytes(), codec).unwrap();
+            assert!(payload.len() < message.len() / 10);
+            assert_eq!(payload[MAGIC.len() + 1], codec as u8);
+            assert_eq!(
+                decode(&payload, &rust(), &Keys::default()).unwrap(),
+                message.as_bytes()
+            );
+        }
+        assert_eq!(compress(b"hello", Codec::None).unwrap(), b"hello");
+    }
+
+    #[test]
+    fn test_compressed_then_encrypted() {
+        let message = "all work and no play ".repeat(100);
+        let compressed = compress(message.as_bytes(), Codec::Zstd).unwrap();
+        let payload = encrypt(
+            &compressed,
+            b"hunter2",
+            &KdfParams::default(),
+            CryptoSuite::default(),
+        )
+        .unwrap();
+        assert_eq!(
+            decode(&payload, &rust(), &password("hunter2")).unwrap(),
+            message.as_bytes()
+        );
+    }
+
+    #[test]
+    fn test_compressed_corrupt() {
+        let mut payload = compress(&[0; 1000], Codec::Zlib).unwrap();
+        payload[MAGIC.len() + 1] = 9;
+        assert_eq!(
+            decode(&payload, &rust(), &Keys::default()),
+            Err(PayloadError::UnknownCodec(9))
+        );
+
+        let mut payload = compress(&[0; 1000], Codec::Zlib).unwrap();
+        payload[COMPRESSED_START - 1] ^= 1;
+        assert_eq!(
+            decode(&payload, &rust(), &Keys::default()),
+            Err(PayloadError::Decompress)
+        );
+    }
+
+    fn cheap() -> KdfParams {
+        KdfParams {
+            memory_kib: 1024,
+            time_cost: 1,
+            ..KdfParams::default()
+        }
+    }
+
+    #[test]
+    fn test_deniable_round_trip() {
+        let payload = encrypt_deniable(
+            b"the real plans",
+            b"hunter2",
+            Some((b"grocery list", b"letmein")),
+            &cheap(),
+        )
+        .unwrap();
+        assert!(is_encrypted(&payload));
+        assert_eq!(
+            decode(&payload, &rust(), &password("hunter2")).unwrap(),
+            b"the real plans"
+        );
+        assert_eq!(
+            decode(&payload, &rust(), &password("letmein")).unwrap(),
+            b"grocery list"
+        );
+        assert_eq!(
+            decode(&payload, &rust(), &password("wrong")),
+            Err(PayloadError::Crypto(CryptoError::Decrypt))
+        );
+    }
+
+    #[test]
+    fn test_deniable_without_decoy() {
+        let with_decoy = encrypt_deniable(
+            b"secret",
+            b"hunter2",
+            Some((b"decoy", b"letmein")),
+            &cheap(),
+        )
+        .unwrap();
+        let without = encrypt_deniable(b"secret", b"hunter2", None, &cheap()).unwrap();
+        assert_eq!(with_decoy.len(), without.len());
+        assert_eq!(
+            decode(&without, &rust(), &password("hunter2")).unwrap(),
+            b"secret"
+        );
+        assert_eq!(
+            decode(&without, &rust(), &password("letmein")),
+            Err(PayloadError::Crypto(CryptoError::Decrypt))
+        );
+    }
+
+    #[test]
+    fn test_deniable_slots_padded() {
+        let short = encrypt_deniable(b"a", b"pw", None, &cheap()).unwrap();
+        let longer = encrypt_deniable(&[b'a'; 40], b"pw", None, &cheap()).unwrap();
+        assert_eq!(short.len(), longer.len());
+        assert_eq!(
+            decode(&short[..short.len() - 1], &rust(), &password("pw")),
+            Err(PayloadError::Truncated)
+        );
+    }
+
+    #[test]
+    fn test_deniable_header_hides_length() {
+        // The real message and the decoy swapped between the two secrets
+        let (message, decoy) = (&b"a"[..], &[b'b'; 40][..]);
+        let headers = [(message, decoy), (decoy, message)].map(|(real, other)| {
+            let header = Header {
+                version: VERSION,
+                flags: Header::ENCRYPTED | Header::DENIABLE,
+                codec: Codec::None,
+                length: real.len() as u32,
+            };
+            let body = encrypt_deniable(real, b"pw", Some((other, b"decoy")), &cheap()).unwrap();
+            let payload = seal(header, &body);
+            assert_eq!(decode(&payload, &rust(), &password("pw")).unwrap(), real);
+            payload[..Header::LEN].to_vec()
+        });
+        assert_eq!(headers[0], headers[1]);
+        assert_eq!(Header::parse(&headers[0]).unwrap().length, 0);
+    }
+
+    fn sealed(message: &[u8], codec: Codec) -> Vec<u8> {
+        let header = Header {
+            version: VERSION,
+            flags: 0,
+            codec,
+            length: message.len() as u32,
+        };
+        seal(header, &codec.compress(message).unwrap())
+    }
+
+    #[test]
+    fn test_header_round_trip() {
+        let message = "all work and no play ".repeat(100);
+        for codec in [Codec::None, Codec::Zlib, Codec::Zstd] {
+            let payload = sealed(message.as_bytes(), codec);
+            assert_eq!(version(&payload), Ok(VERSION));
+            assert_eq!(Header::parse(&payload).unwrap().codec, codec);
+            assert_eq!(
+                decode(&payload, &rust(), &Keys::default()).unwrap(),
+                message.as_bytes()
+            );
+        }
+    }
+
+    #[test]
+    fn test_header_layers() {
+        let message = b"attack at dawn";
+        let body = tag(
+            &encrypt(message, b"hunter2", &cheap(), CryptoSuite::default()).unwrap(),
+            b"key",
+        );
+        let header = Header {
+            version: VERSION,
+            flags: Header::ENCRYPTED | Header::TAGGED,
+            codec: Codec::None,
+            length: message.len() as u32,
+        };
+        let payload = seal(header, &body);
+        assert!(is_encrypted(&payload));
+        assert!(!is_signed(&payload));
+        let keys = Keys {
+            integrity_key: Some(b"key".to_vec()),
+            ..password("hunter2")
+        };
+        assert_eq!(decode(&payload, &rust(), &keys).unwrap(), message);
+    }
+
+    #[test]
+    fn test_older_versions() {
+        assert_eq!(version(b"hello"), Ok(0));
+        let legacy = compress(b"hello", Codec::Zlib).unwrap();
+        assert_eq!(version(&legacy), Ok(1));
+        assert_eq!(
+            decode(&legacy, &rust(), &Keys::default()).unwrap(),
+            b"hello"
+        );
+    }
+
+    #[test]
+    fn test_newer_version_refused() {
+        let mut payload = sealed(b"hello", Codec::None);
+        payload[MAGIC.len() + 1] = VERSION + 1;
+        payload[MAGIC.len() + 3] = 200;
+        assert_eq!(version(&payload), Ok(VERSION + 1));
+        assert_eq!(
+            decode(&payload, &rust(), &Keys::default()),
+            Err(PayloadError::UnsupportedVersion(VERSION + 1))
+        );
+    }
+
+    #[test]
+    fn test_decompression_bomb() {
+        let bomb = compress(&vec![0; 8 << 20], Codec::Zstd).unwrap();
+        assert!(bomb.len() < 8 << 10);
+        assert_eq!(
+            decode(&bomb, &rust(), &Keys::default()),
+            Err(PayloadError::Decompress)
+        );
+        let sealed = sealed(&vec![0; 8 << 20], Codec::Zstd);
+        assert_eq!(
+            decode(&sealed, &rust(), &Keys::default()),
+            Err(PayloadError::Decompress)
+        );
+    }
+
+    #[test]
+    fn test_header_length_checked() {
+        let mut payload = sealed(b"hello", Codec::Zstd);
+        payload[Header::LEN - 1] += 1;
+        assert_eq!(
+            decode(&payload, &rust(), &Keys::default()),
+            Err(PayloadError::Decompress)
+        );
+    }
+
+    #[test]
+    fn test_nesting_limited() {
+        let mut payload = b"hello".to_vec();
+        for _ in 0..MAX_LAYERS {
+            payload = tag(&payload, b"");
+        }
+        assert_eq!(
+            decode(&payload, &rust(), &Keys::default()).unwrap(),
+            b"hello"
+        );
+        assert_eq!(
+            decode(&tag(&payload, b""), &rust(), &Keys::default()),
+            Err(PayloadError::TooManyLayers)
+        );
+        let header = sealed(b"hello", Codec::None)[..Header::LEN].to_vec();
+        let forged = header.repeat(100_000);
+        assert_eq!(
+            decode(&forged, &rust(), &Keys::default()),
+            Err(PayloadError::TooManyLayers)
+        );
+    }
+
+    #[test]
+    fn test_recipient_count() {
+        assert_eq!(
+            encrypt_to(b"secret", &[]),
+            Err(PayloadError::RecipientCount(0))
+        );
+        let key = PublicKey::from(&StaticSecret::from([2; 32]));
+        assert_eq!(
+            encrypt_to(b"secret", &[key; 256]),
+            Err(PayloadError::RecipientCount(256))
+        );
+        let payload = encrypt_to(b"secret", &[key; 255]).unwrap();
+        assert_eq!(payload[MAGIC.len() + 1 + 32], 255);
+    }
+
+    #[test]
+    fn test_recipients_round_trip() {
+        let alice = StaticSecret::from([2; 32]);
+        let bob = StaticSecret::from([3; 32]);
+        let eve = StaticSecret::from([4; 32]);
+        let payload = encrypt_to(
+            b"team secret",
+            &[PublicKey::from(&alice), PublicKey::from(&bob)],
+        )
+        .unwrap();
+        assert!(is_for_recipients(&payload));
+        assert!(!is_encrypted(&payload));
+
+        for identity in [alice, bob] {
+            let keys = Keys {
+                identity: Some(identity),
+                ..Keys::default()
+            };
+            assert_eq!(decode(&payload, &rust(), &keys).unwrap(), b"team secret");
+        }
+        let keys = Keys {
+            identity: Some(eve),
+            ..Keys::default()
+        };
+        assert_eq!(
+            decode(&payload, &rust(), &keys),
+            Err(PayloadError::NotARecipient)
+        );
+        assert_eq!(
+            decode(&payload, &rust(), &Keys::default()),
+            Err(PayloadError::IdentityRequired)
+        );
+
+        // Version 1 payloads compress inside the encryption
+        let message = "team secret ".repeat(50);
+        let compressed = compress(message.as_bytes(), Codec::Zlib).unwrap();
+        let carol = StaticSecret::from([5; 32]);
+        let payload = encrypt_to(&compressed, &[PublicKey::from(&carol)]).unwrap();
+        let keys = Keys {
+            identity: Some(carol),
+            ..Keys::default()
+        };
+        assert_eq!(
+            decode(&payload, &rust(), &keys).unwrap(),
+            message.as_bytes()
+        );
+    }
+
+    #[test]
+    fn test_keyfile_secret() {
+        let keyfile = vec![5; 64];
+        let secret = crypto::secret(Some("hunter2"), Some(&keyfile));
+        let payload = encrypt(b"two factor", &secret, &cheap(), CryptoSuite::default()).unwrap();
+        let keys = Keys {
+            keyfile: Some(keyfile.clone()),
+            ..password("hunter2")
+        };
+        assert_eq!(decode(&payload, &rust(), &keys).unwrap(), b"two factor");
+        for keys in [
+            password("hunter2"),
+            Keys {
+                keyfile: Some(keyfile),
+                ..Keys::default()
+            },
+        ] {
+            assert_eq!(
+                decode(&payload, &rust(), &keys),
+                Err(PayloadError::Crypto(CryptoError::Decrypt))
+            );
+        }
+    }
+
+    #[test]
+    fn test_protect_and_correct() {
+        let message = "all work and no play ".repeat(30);
+        let inner = sealed(message.as_bytes(), Codec::None);
+        let mut payload = protect(&inner, 16).unwrap();
+        assert_eq!(correct(&payload), Ok((inner.clone(), 0)));
+
+        for i in [ECC_START, ECC_START + 100, payload.len() - 1] {
+            payload[i] ^= 0x55;
+        }
+        assert_eq!(correct(&payload), Ok((inner.clone(), 3)));
+        assert_eq!(
+            decode(&payload, &rust(), &Keys::default()).unwrap(),
+            message.as_bytes()
+        );
+
+        payload.truncate(payload.len() - 20);
+        assert_eq!(correct(&payload).unwrap().0, inner);
+        assert_eq!(correct(b"plain text"), Ok((b"plain text".to_vec(), 0)));
+    }
+
+    #[cfg(feature = "age")]
+    #[test]
+    fn test_age_round_trip() {
+        use age::secrecy::ExposeSecret;
+
+        let identity = age::x25519::Identity::generate();
+        let payload = encrypt_age(b"attack at dawn", &[identity.to_public().to_string()]).unwrap();
+        assert!(is_age(&payload));
+        assert_eq!(
+            decode(&payload, &rust(), &Keys::default()),
+            Err(PayloadError::IdentityRequired)
+        );
+        let keys = Keys {
+            age_identities: Some(identity.to_string().expose_secret().to_string()),
+            ..Keys::default()
+        };
+        assert_eq!(decode(&payload, &rust(), &keys).unwrap(), b"attack at dawn");
+    }
+
+    #[test]
+    fn test_nonce_id() {
+        let payload = encrypt(
+            b"attack at dawn",
+            b"hunter2",
+            &cheap(),
+            CryptoSuite::default(),
+        )
+        .unwrap();
+        let again = encrypt(
+            b"attack at dawn",
+            b"hunter2",
+            &cheap(),
+            CryptoSuite::default(),
+        )
+        .unwrap();
+        let id = nonce_id(&payload).unwrap();
+        assert_ne!(nonce_id(&again), Some(id));
+        assert_eq!(nonce_id(&tag(&payload, b"key")), Some(id));
+
+        // the ciphertext isn't part of it
+        let mut tampered = payload.clone();
+        *tampered.last_mut().unwrap() ^= 1;
+        assert_eq!(nonce_id(&tampered), Some(id));
+        assert_eq!(nonce_id(b"plain text"), None);
+        assert_eq!(nonce_id(&sealed(b"plain", Codec::None)), None);
+
+        let deniable = encrypt_deniable(b"real", b"pw", None, &cheap()).unwrap();
+        assert!(nonce_id(&deniable).is_some());
+    }
+}
@@ -0,0 +1,183 @@
+//! Opt-in counters for finding where an operation spends its time: bytes
+//! parsed, chunks seen, time in CRCs and compression, and named stages.
+//! Nothing is counted outside [`measure`], so the hooks cost one atomic
+//! load otherwise.
+//!
+//! Counters are process-wide, so they include work on rayon's threads, and
+//! measurements overlapping on different threads see each other's work
+
+use std::fmt::Display;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// How many [`measure`] calls are running
+static ACTIVE: AtomicUsize = AtomicUsize::new(0);
+static BYTES: AtomicU64 = AtomicU64::new(0);
+static CHUNKS: AtomicU64 = AtomicU64::new(0);
+static CRC: AtomicU64 = AtomicU64::new(0);
+static COMPRESS: AtomicU64 = AtomicU64::new(0);
+/// Time in each stage so far, summed by name
+static STAGES: Mutex<Vec<(&'static str, Duration)>> = Mutex::new(Vec::new());
+
+/// What an operation did, as [`measure`] returns it
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Stats {
+    /// Bytes of chunks parsed or validated
+    pub bytes_parsed: u64,
+    pub chunks: u64,
+    pub crc: Duration,
+    pub compress: Duration,
+    /// Time in each [`stage`], in the order they first finished, summed
+    /// over threads and repeats
+    pub stages: Vec<(&'static str, Duration)>,
+    /// Wall time of the whole operation
+    pub total: Duration,
+}
+
+impl Display for Stats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "parsed\t{} bytes in {} chunks",
+            self.bytes_parsed, self.chunks
+        )?;
+        let timings = [("crc", self.crc), ("compress", self.compress)];
+        for (name, time) in timings.iter().chain(&self.stages) {
+            writeln!(f, "{}\t{:.3} ms", name, time.as_secs_f64() * 1000.0)?;
+        }
+        write!(f, "total\t{:.3} ms", self.total.as_secs_f64() * 1000.0)
+    }
+}
+
+fn enabled() -> bool {
+    ACTIVE.load(Ordering::Relaxed) > 0
+}
+
+/// Run `operation`, returning what it did alongside its result
+pub fn measure<T>(operation: impl FnOnce() -> T) -> (T, Stats) {
+    let stages_before = {
+        let stages = STAGES.lock().unwrap();
+        ACTIVE.fetch_add(1, Ordering::Relaxed);
+        stages.clone()
+    };
+    let counters = [&BYTES, &CHUNKS, &CRC, &COMPRESS];
+    let before = counters.map(|c| c.load(Ordering::Relaxed));
+    let start = Instant::now();
+    let result = operation();
+    let total = start.elapsed();
+    let [bytes, chunks, crc, compress] = counters.map(|c| c.load(Ordering::Relaxed));
+
+    let mut stages = STAGES.lock().unwrap();
+    let ran = stages
+        .iter()
+        .map(|&(name, time)| {
+            let before = stages_before.iter().find(|(n, _)| *n == name);
+            (name, time, before.map(|(_, before)| *before))
+        })
+        .filter(|(_, time, before)| Some(*time) != *before)
+        .map(|(name, time, before)| (name, time - before.unwrap_or_default()))
+        .collect();
+    if ACTIVE.fetch_sub(1, Ordering::Relaxed) == 1 {
+        stages.clear();
+    }
+    let stats = Stats {
+        bytes_parsed: bytes - before[0],
+        chunks: chunks - before[1],
+        crc: Duration::from_nanos(crc - before[2]),
+        compress: Duration::from_nanos(compress - before[3]),
+        stages: ran,
+        total,
+    };
+    (result, stats)
+}
+
+/// A named stage of an operation, timed from here until it's dropped
+#[must_use = "the stage ends when this is dropped"]
+pub struct Stage(Option<(&'static str, Instant)>);
+
+/// Start timing a stage called `name`
+pub fn stage(name: &'static str) -> Stage {
+    Stage(enabled().then(|| (name, Instant::now())))
+}
+
+impl Drop for Stage {
+    fn drop(&mut self) {
+        if let Some((name, start)) = self.0 {
+            let time = start.elapsed();
+            let mut stages = STAGES.lock().unwrap();
+            match stages.iter_mut().find(|(n, _)| *n == name) {
+                Some((_, sum)) => *sum += time,
+                None => stages.push((name, time)),
+            }
+        }
+    }
+}
+
+pub(crate) fn parsed(bytes: usize, chunks: usize) {
+    if enabled() {
+        BYTES.fetch_add(bytes as u64, Ordering::Relaxed);
+        CHUNKS.fetch_add(chunks as u64, Ordering::Relaxed);
+    }
+}
+
+fn timed<T>(counter: &AtomicU64, work: impl FnOnce() -> T) -> T {
+    if !enabled() {
+        return work();
+    }
+    let start = Instant::now();
+    let result = work();
+    counter.fetch_add(start.elapsed().as_nanos() as u64, Ordering::Relaxed);
+    result
+}
+
+pub(crate) fn crc<T>(work: impl FnOnce() -> T) -> T {
+    timed(&CRC, work)
+}
+
+pub(crate) fn compress<T>(work: impl FnOnce() -> T) -> T {
+    timed(&COMPRESS, work)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compress::Codec;
+    use crate::pixels::tests::testing_png;
+    use crate::png::Png;
+
+    #[test]
+    fn test_measure() {
+        let bytes = testing_png(16, 16).as_bytes();
+        let (png, stats) = measure(|| {
+            let _parse = stage("parse");
+            let png = Png::try_from(bytes.as_slice()).unwrap();
+            let _compress = stage("compress");
+            Codec::Zlib.compress(&bytes).unwrap();
+            png
+        });
+        // Other tests parsing meanwhile count too
+        assert!(stats.chunks >= png.chunks().len() as u64);
+        assert!(stats.bytes_parsed >= bytes.len() as u64 - 8);
+        assert!(stats.crc > Duration::ZERO && stats.compress > Duration::ZERO);
+        let position = |stage| stats.stages.iter().position(|(name, _)| *name == stage);
+        assert!(position("compress").unwrap() < position("parse").unwrap());
+        assert!(stats.total >= stats.stages[position("parse").unwrap()].1);
+        assert!(stats.to_string().starts_with("parsed\t"));
+    }
+
+    #[test]
+    fn test_stages_sum() {
+        let (_, stats) = measure(|| {
+            for _ in 0..3 {
+                let _stage = stage("read");
+            }
+        });
+        let reads: Vec<_> = stats.stages.iter().filter(|(n, _)| *n == "read").collect();
+        assert_eq!(reads.len(), 1);
+
+        drop(stage("write"));
+        let (_, stats) = measure(|| ());
+        assert!(stats.stages.iter().all(|(name, _)| *name != "write"));
+    }
+}
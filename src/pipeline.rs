@@ -0,0 +1,191 @@
+//! Payload transforms as values, so an application can insert its own
+//! between the built-in ones without reimplementing the rest of embedding
+
+use crate::compress::Codec;
+use crate::crypto::{CryptoSuite, KdfParams};
+use crate::payload;
+use crate::Result;
+
+/// A reversible transform of a payload's bytes
+pub trait PayloadCodec {
+    fn encode(&self, data: &[u8]) -> Result<Vec<u8>>;
+
+    /// Undo [`encode`](PayloadCodec::encode)
+    fn decode(&self, data: &[u8]) -> Result<Vec<u8>>;
+}
+
+/// Codecs applied in turn, the first innermost: encoding runs them in
+/// order, decoding in reverse
+#[derive(Default)]
+pub struct Chain(Vec<Box<dyn PayloadCodec>>);
+
+impl Chain {
+    pub fn new() -> Chain {
+        Chain::default()
+    }
+
+    /// Add `codec` as the outermost layer so far
+    pub fn then(mut self, codec: impl PayloadCodec + 'static) -> Chain {
+        self.0.push(Box::new(codec));
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl PayloadCodec for Chain {
+    fn encode(&self, data: &[u8]) -> Result<Vec<u8>> {
+        self.0
+            .iter()
+            .try_fold(data.to_vec(), |data, codec| codec.encode(&data))
+    }
+
+    fn decode(&self, data: &[u8]) -> Result<Vec<u8>> {
+        self.0
+            .iter()
+            .rev()
+            .try_fold(data.to_vec(), |data, codec| codec.decode(&data))
+    }
+}
+
+/// [`payload::compress`], recording the codec so any codec decodes
+#[derive(Debug, Clone, Copy)]
+pub struct Compress(pub Codec);
+
+impl PayloadCodec for Compress {
+    fn encode(&self, data: &[u8]) -> Result<Vec<u8>> {
+        Ok(payload::compress(data, self.0)?)
+    }
+
+    fn decode(&self, data: &[u8]) -> Result<Vec<u8>> {
+        Ok(payload::decompress(data.to_vec())?)
+    }
+}
+
+/// [`payload::encrypt`] under a secret from [`crypto::secret`](crate::crypto::secret)
+#[derive(Debug, Clone)]
+pub struct Encrypt {
+    pub secret: Vec<u8>,
+    pub params: KdfParams,
+    pub suite: CryptoSuite,
+}
+
+impl Encrypt {
+    /// Under `password` with the default key derivation cost and suite
+    pub fn with_password(password: &str) -> Encrypt {
+        Encrypt {
+            secret: crate::crypto::secret(Some(password), None),
+            params: KdfParams::default(),
+            suite: CryptoSuite::default(),
+        }
+    }
+}
+
+impl PayloadCodec for Encrypt {
+    fn encode(&self, data: &[u8]) -> Result<Vec<u8>> {
+        Ok(payload::encrypt(
+            data,
+            &self.secret,
+            &self.params,
+            self.suite,
+        )?)
+    }
+
+    fn decode(&self, data: &[u8]) -> Result<Vec<u8>> {
+        // Whatever this codec writes, and as much as anything else may cost
+        let limit = match self.params.within(&KdfParams::DECODE_LIMIT) {
+            true => KdfParams::DECODE_LIMIT,
+            false => self.params,
+        };
+        Ok(payload::decrypt(data, Some(&self.secret), Some(&limit))?)
+    }
+}
+
+/// [`payload::protect`] with this many parity bytes per block; decoding
+/// repairs what it can
+#[derive(Debug, Clone, Copy)]
+pub struct Ecc(pub u8);
+
+impl PayloadCodec for Ecc {
+    fn encode(&self, data: &[u8]) -> Result<Vec<u8>> {
+        Ok(payload::protect(data, self.0)?)
+    }
+
+    fn decode(&self, data: &[u8]) -> Result<Vec<u8>> {
+        Ok(payload::correct(data)?.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::payload::PayloadError;
+
+    /// XOR with a repeating key, as an application might obfuscate
+    struct Xor(&'static [u8]);
+
+    impl PayloadCodec for Xor {
+        fn encode(&self, data: &[u8]) -> Result<Vec<u8>> {
+            Ok(data
+                .iter()
+                .zip(self.0.iter().cycle())
+                .map(|(byte, key)| byte ^ key)
+                .collect())
+        }
+
+        fn decode(&self, data: &[u8]) -> Result<Vec<u8>> {
+            self.encode(data)
+        }
+    }
+
+    fn cheap(password: &str) -> Encrypt {
+        Encrypt {
+            params: KdfParams {
+                memory_kib: 1024,
+                time_cost: 1,
+                ..KdfParams::default()
+            },
+            ..Encrypt::with_password(password)
+        }
+    }
+
+    #[test]
+    fn test_chain_round_trip() {
+        let message = "all work and no play ".repeat(20);
+        let chain = Chain::new()
+            .then(Compress(Codec::Zstd))
+            .then(Xor(b"key"))
+            .then(cheap("hunter2"))
+            .then(Ecc(16));
+        assert_eq!(chain.len(), 4);
+        let mut encoded = chain.encode(message.as_bytes()).unwrap();
+        assert!(payload::correct(&encoded).is_ok());
+        encoded[20] ^= 0xff;
+        assert_eq!(chain.decode(&encoded).unwrap(), message.as_bytes());
+
+        let wrong = Chain::new()
+            .then(Compress(Codec::Zstd))
+            .then(Xor(b"key"))
+            .then(cheap("hunter3"))
+            .then(Ecc(16));
+        let error = wrong.decode(&encoded).unwrap_err();
+        assert!(matches!(
+            error.downcast_ref(),
+            Some(PayloadError::Crypto(_))
+        ));
+    }
+
+    #[test]
+    fn test_empty_chain() {
+        let chain = Chain::new();
+        assert!(chain.is_empty());
+        assert_eq!(chain.encode(b"hello").unwrap(), b"hello");
+        assert_eq!(chain.decode(b"hello").unwrap(), b"hello");
+    }
+}
@@ -0,0 +1,913 @@
+#![deny(clippy::unwrap_used, clippy::expect_used)]
+
+use std::fmt::Display;
+use std::ops::Range;
+
+use rayon::prelude::*;
+
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::deflate::{self, Deflate, Limits};
+use crate::perf;
+use crate::png::Png;
+
+/// Image header fields
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Ihdr {
+    pub width: u32,
+    pub height: u32,
+    pub bit_depth: u8,
+    pub color_type: u8,
+    pub compression: u8,
+    pub filter: u8,
+    pub interlace: u8,
+}
+
+impl Ihdr {
+    pub const LEN: usize = 13;
+
+    pub fn parse(data: &[u8]) -> Result<Ihdr, PixelError> {
+        let Ok(
+            &[w0, w1, w2, w3, h0, h1, h2, h3, bit_depth, color_type, compression, filter, interlace],
+        ) = <&[u8; Self::LEN]>::try_from(data)
+        else {
+            return Err(PixelError::InvalidIhdr);
+        };
+        Ok(Ihdr {
+            width: u32::from_be_bytes([w0, w1, w2, w3]),
+            height: u32::from_be_bytes([h0, h1, h2, h3]),
+            bit_depth,
+            color_type,
+            compression,
+            filter,
+            interlace,
+        })
+    }
+
+    /// Check every field holds a value the spec allows, and the bit depth
+    /// is one the color type allows
+    pub fn check(&self) -> Result<(), PixelError> {
+        if self.width == 0 || self.height == 0 {
+            return Err(PixelError::ZeroDimension(self.width, self.height));
+        }
+        if self.width > i32::MAX as u32 || self.height > i32::MAX as u32 {
+            return Err(PixelError::TooLarge(self.width, self.height));
+        }
+        let depths = bit_depths(self.color_type).ok_or(PixelError::ColorType(self.color_type))?;
+        if !depths.contains(&self.bit_depth) {
+            return Err(PixelError::BitDepth(self.color_type, self.bit_depth));
+        }
+        match (self.compression, self.filter, self.interlace) {
+            (0, 0, 0 | 1) => Ok(()),
+            (0, 0, interlace) => Err(PixelError::InterlaceMethod(interlace)),
+            (0, filter, _) => Err(PixelError::FilterMethod(filter)),
+            (compression, _, _) => Err(PixelError::Compression(compression)),
+        }
+    }
+
+    /// Check the file has a PLTE chunk if and only if the color type
+    /// allows or needs one, given how long any is
+    pub fn check_palette(&self, plte: Option<usize>) -> Result<(), PixelError> {
+        match (self.color_type, plte) {
+            (3, None) => Err(PixelError::MissingPalette),
+            (0 | 4, Some(_)) => Err(PixelError::UnexpectedPalette(self.color_type)),
+            (_, Some(length)) if length == 0 || length % 3 != 0 => {
+                Err(PixelError::PaletteLength(length))
+            }
+            (3, Some(length)) if self.bit_depth <= 8 && length / 3 > 1 << self.bit_depth => {
+                Err(PixelError::PaletteTooLong(length / 3, self.bit_depth))
+            }
+            (_, Some(length)) if length / 3 > 256 => Err(PixelError::PaletteTooLong(length / 3, 8)),
+            _ => Ok(()),
+        }
+    }
+
+    /// Check an indexed image's tRNS chunk has no more alpha values than
+    /// PLTE has entries, given both lengths
+    pub fn check_transparency(
+        &self,
+        plte: Option<usize>,
+        trns: Option<usize>,
+    ) -> Result<(), PixelError> {
+        match (self.color_type, plte, trns) {
+            (3, Some(plte), Some(trns)) if trns > plte / 3 => {
+                Err(PixelError::TransparencyTooLong(trns, plte / 3))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    pub fn channels(&self) -> usize {
+        match self.color_type {
+            2 => 3,
+            4 => 2,
+            6 => 4,
+            _ => 1,
+        }
+    }
+
+    pub fn bits_per_pixel(&self) -> usize {
+        self.channels() * self.bit_depth as usize
+    }
+
+    /// Bytes in one unfiltered scanline
+    pub fn stride(&self) -> usize {
+        (self.width as usize * self.bits_per_pixel()).div_ceil(8)
+    }
+
+    /// Bytes the IDAT stream inflates to: every pass's scanlines, each
+    /// with its filter byte; none if that's more than memory holds
+    pub fn filtered_len(&self) -> Option<usize> {
+        let bits = self.bits_per_pixel();
+        self.passes().iter().try_fold(0usize, |sum, pass| {
+            (pass.stride(bits) + 1)
+                .checked_mul(pass.height)?
+                .checked_add(sum)
+        })
+    }
+
+    /// Distance back to the corresponding byte of the previous pixel, as
+    /// used by the Sub, Average and Paeth filters
+    fn filter_distance(&self) -> usize {
+        self.bits_per_pixel().div_ceil(8)
+    }
+
+    /// The reduced images the scanlines are stored as: the whole image, or
+    /// Adam7's seven passes, leaving out empty ones as the stream does
+    fn passes(&self) -> Vec<Pass> {
+        let (width, height) = (self.width as usize, self.height as usize);
+        if self.interlace == 0 {
+            return vec![Pass {
+                x: (0, 1),
+                y: (0, 1),
+                width,
+                height,
+            }];
+        }
+        ADAM7
+            .iter()
+            .map(|&(x, y)| Pass {
+                x,
+                y,
+                width: width.saturating_sub(x.0).div_ceil(x.1),
+                height: height.saturating_sub(y.0).div_ceil(y.1),
+            })
+            .filter(|pass| pass.width > 0 && pass.height > 0)
+            .collect()
+    }
+}
+
+/// Each Adam7 pass's first column and row, and the steps between them
+const ADAM7: [((usize, usize), (usize, usize)); 7] = [
+    ((0, 8), (0, 8)),
+    ((4, 8), (0, 8)),
+    ((0, 4), (4, 8)),
+    ((2, 4), (0, 4)),
+    ((0, 2), (2, 4)),
+    ((1, 2), (0, 2)),
+    ((0, 1), (1, 2)),
+];
+
+/// One reduced image: the pixels at `x.0 + i * x.1` in rows `y.0 + j * y.1`
+#[derive(Debug, Clone, Copy)]
+struct Pass {
+    x: (usize, usize),
+    y: (usize, usize),
+    width: usize,
+    height: usize,
+}
+
+impl Pass {
+    fn stride(&self, bits_per_pixel: usize) -> usize {
+        (self.width * bits_per_pixel).div_ceil(8)
+    }
+
+    /// Move pixels between the pass's rows, `stride` bytes each, and the
+    /// whole image's: out of the image with `gather`, else into it
+    fn scatter(&self, ihdr: &Ihdr, pass: &mut [u8], image: &mut [u8], gather: bool) {
+        let bits = ihdr.bits_per_pixel();
+        let (stride, image_stride) = (self.stride(bits), ihdr.stride());
+        for row in 0..self.height {
+            let pass_row = &mut pass[row * stride..(row + 1) * stride];
+            let y = self.y.0 + row * self.y.1;
+            let image_row = &mut image[y * image_stride..(y + 1) * image_stride];
+            for column in 0..self.width {
+                let x = self.x.0 + column * self.x.1;
+                match gather {
+                    true => copy_pixel(image_row, x, pass_row, column, bits),
+                    false => copy_pixel(pass_row, column, image_row, x, bits),
+                }
+            }
+        }
+    }
+}
+
+/// Copy pixel `from` of the row `src` to pixel `to` of `dst`
+fn copy_pixel(src: &[u8], from: usize, dst: &mut [u8], to: usize, bits: usize) {
+    if bits >= 8 {
+        let bytes = bits / 8;
+        dst[to * bytes..(to + 1) * bytes].copy_from_slice(&src[from * bytes..(from + 1) * bytes]);
+        return;
+    }
+    let mask = (1u8 << bits) - 1;
+    let (from, to) = (from * bits, to * bits);
+    let value = (src[from / 8] >> (8 - bits - from % 8)) & mask;
+    let shift = 8 - bits - to % 8;
+    dst[to / 8] = (dst[to / 8] & !(mask << shift)) | (value << shift);
+}
+
+/// The IDAT stream inflated, which must be `expected` bytes; sizes past
+/// [`Limits::IMAGE`] are refused before inflating anything
+fn inflate_idat(png: &Png, expected: usize) -> Result<Vec<u8>, PixelError> {
+    let limit = Limits::IMAGE.max_output;
+    if expected as u64 > limit {
+        return Err(PixelError::DecompressionLimitExceeded(limit));
+    }
+    let idat: Vec<u8> = png
+        .chunks_by_type("IDAT")
+        .flat_map(|c| c.data().iter().copied())
+        .collect();
+    // Inflating past the expected size is as corrupt as falling short
+    let filtered =
+        deflate::inflate(&idat, expected as u64).map_err(|_| PixelError::InvalidImageData)?;
+    match filtered.len() == expected {
+        true => Ok(filtered),
+        false => Err(PixelError::InvalidImageData),
+    }
+}
+
+/// Bit depths the spec allows for a color type, or `None` if it's unknown
+fn bit_depths(color_type: u8) -> Option<&'static [u8]> {
+    match color_type {
+        0 => Some(&[1, 2, 4, 8, 16]),
+        3 => Some(&[1, 2, 4, 8]),
+        2 | 4 | 6 => Some(&[8, 16]),
+        _ => None,
+    }
+}
+
+pub(crate) fn color_type_name(color_type: u8) -> &'static str {
+    match color_type {
+        0 => "grayscale",
+        2 => "truecolor",
+        3 => "indexed",
+        4 => "grayscale with alpha",
+        6 => "truecolor with alpha",
+        _ => "unknown",
+    }
+}
+
+/// Check `png`'s IHDR fields and that its PLTE chunk agrees with them,
+/// returning the header
+pub fn check_header(png: &Png) -> Result<Ihdr, PixelError> {
+    let ihdr = png.chunk_by_type("IHDR").ok_or(PixelError::MissingIhdr)?;
+    let ihdr = Ihdr::parse(ihdr.data())?;
+    ihdr.check()?;
+    let plte = png.chunk_by_type("PLTE").map(|c| c.data().len());
+    ihdr.check_palette(plte)?;
+    ihdr.check_transparency(plte, png.chunk_by_type("tRNS").map(|c| c.data().len()))?;
+    Ok(ihdr)
+}
+
+/// Check [`check_header`]'s rules and, for an indexed image, that every
+/// pixel's index is one PLTE has; this decodes the image
+pub fn check_indices(png: &Png) -> Result<(), PixelError> {
+    let ihdr = check_header(png)?;
+    match png.chunk_by_type("PLTE") {
+        Some(plte) if ihdr.color_type == 3 => {
+            Pixels::read(png)?.check_indices(plte.data().len() / 3)
+        }
+        _ => Ok(()),
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum PixelError {
+    MissingIhdr,
+    InvalidIhdr,
+    /// Width and height, one of which is zero
+    ZeroDimension(u32, u32),
+    /// Width and height, one of which is past 2^31 - 1
+    TooLarge(u32, u32),
+    ColorType(u8),
+    /// A color type and a bit depth it doesn't allow
+    BitDepth(u8, u8),
+    Compression(u8),
+    FilterMethod(u8),
+    InterlaceMethod(u8),
+    MissingPalette,
+    /// A PLTE chunk in a file of this grayscale color type
+    UnexpectedPalette(u8),
+    /// PLTE data of a length that isn't whole entries
+    PaletteLength(usize),
+    /// More palette entries than the bit depth can index
+    PaletteTooLong(usize, u8),
+    /// tRNS alpha values and PLTE entries, when there are more of the first
+    TransparencyTooLong(usize, usize),
+    /// The first pixel whose index is past the palette's `entries`, and
+    /// how many such pixels there are
+    IndexOutOfRange {
+        x: usize,
+        y: usize,
+        index: u8,
+        entries: usize,
+        count: usize,
+    },
+    /// The IDAT stream doesn't inflate to one filtered scanline per row
+    InvalidImageData,
+    /// An image whose scanlines would take more than this many bytes; see
+    /// [`Limits::IMAGE`]
+    DecompressionLimitExceeded(u64),
+    UnknownFilter(u8),
+}
+
+impl Display for PixelError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PixelError::MissingIhdr => write!(f, "file has no IHDR chunk"),
+            PixelError::InvalidIhdr => write!(f, "invalid IHDR chunk"),
+            PixelError::ZeroDimension(width, height) => write!(
+                f,
+                "image is {}x{}, but width and height must be at least 1",
+                width, height
+            ),
+            PixelError::TooLarge(width, height) => write!(
+                f,
+                "image is {}x{}, but width and height can be at most 2147483647",
+                width, height
+            ),
+            PixelError::ColorType(color_type) => write!(
+                f,
+                "unknown color type {}; the spec defines 0, 2, 3, 4 and 6",
+                color_type
+            ),
+            PixelError::BitDepth(color_type, bit_depth) => write!(
+                f,
+                "bit depth {} isn't allowed for color type {} ({}), which takes {:?}",
+                bit_depth,
+                color_type,
+                color_type_name(*color_type),
+                bit_depths(*color_type).unwrap_or_default()
+            ),
+            PixelError::Compression(method) => write!(
+                f,
+                "unknown compression method {}; only 0 (deflate) is defined",
+                method
+            ),
+            PixelError::FilterMethod(method) => write!(
+                f,
+                "unknown filter method {}; only 0 (adaptive) is defined",
+                method
+            ),
+            PixelError::InterlaceMethod(method) => write!(
+                f,
+                "unknown interlace method {}; use 0 (none) or 1 (Adam7)",
+                method
+            ),
+            PixelError::MissingPalette => {
+                write!(f, "color type 3 (indexed) needs a PLTE chunk before IDAT")
+            }
+            PixelError::UnexpectedPalette(color_type) => write!(
+                f,
+                "color type {} ({}) can't have a PLTE chunk; remove it",
+                color_type,
+                color_type_name(*color_type)
+            ),
+            PixelError::PaletteLength(length) => write!(
+                f,
+                "PLTE holds {} bytes, which isn't a whole number of 3-byte entries",
+                length
+            ),
+            PixelError::PaletteTooLong(entries, bit_depth) => write!(
+                f,
+                "PLTE has {} entries, more than bit depth {} can index",
+                entries, bit_depth
+            ),
+            PixelError::TransparencyTooLong(alpha, entries) => write!(
+                f,
+                "tRNS has {} alpha values but PLTE only {} entries; drop the extra values",
+                alpha, entries
+            ),
+            PixelError::IndexOutOfRange {
+                x,
+                y,
+                index,
+                entries,
+                count,
+            } => write!(
+                f,
+                "pixel ({}, {}) uses palette index {} but PLTE has {} entries; {} pixels \
+                 are out of range, which viewers render differently",
+                x, y, index, entries, count
+            ),
+            PixelError::InvalidImageData => write!(f, "image data is corrupt or truncated"),
+            PixelError::DecompressionLimitExceeded(limit) => write!(
+                f,
+                "image data would inflate to more than {} bytes; refusing it as a likely \
+                 decompression bomb",
+                limit
+            ),
+            PixelError::UnknownFilter(filter) => write!(f, "unknown scanline filter {}", filter),
+        }
+    }
+}
+
+impl std::error::Error for PixelError {}
+
+/// Unfiltered scanlines of an image, remembering each stored row's filter
+/// so rewriting keeps the encoder's choices. Adam7 images are
+/// deinterlaced on reading and interlaced again on writing
+#[derive(Debug)]
+pub struct Pixels {
+    pub ihdr: Ihdr,
+    /// One per row of every pass, in the order they're stored
+    filters: Vec<u8>,
+    /// `ihdr.stride()` bytes per row, without filter bytes
+    pub data: Vec<u8>,
+}
+
+impl Pixels {
+    pub fn read(png: &Png) -> Result<Pixels, PixelError> {
+        let ihdr = Ihdr::parse(
+            png.chunk_by_type("IHDR")
+                .ok_or(PixelError::MissingIhdr)?
+                .data(),
+        )?;
+        ihdr.check()?;
+        if ihdr.interlace != 0 {
+            return Self::read_interlaced(png, ihdr);
+        }
+        let stride = ihdr.stride();
+        let expected = ihdr.filtered_len().ok_or(PixelError::InvalidIhdr)?;
+
+        let filtered = inflate_idat(png, expected)?;
+
+        let lines: Vec<&[u8]> = filtered.chunks(stride + 1).collect();
+        let filters: Vec<u8> = lines.iter().map(|line| line[0]).collect();
+        if let Some(&unknown) = filters.iter().find(|&&f| f > 4) {
+            return Err(PixelError::UnknownFilter(unknown));
+        }
+        let mut data = vec![0u8; stride * ihdr.height as usize];
+        let distance = ihdr.filter_distance();
+        runs(&mut data, stride, &filters)
+            .into_par_iter()
+            .try_for_each(|(rows, run)| {
+                for (y, line) in lines[rows].iter().enumerate() {
+                    let (prev, row) = run.split_at_mut(y * stride);
+                    let prev = &prev[prev.len().saturating_sub(stride)..];
+                    let row = &mut row[..stride];
+                    row.copy_from_slice(&line[1..]);
+                    unfilter(line[0], row, prev, distance)?;
+                }
+                Ok(())
+            })?;
+        Ok(Pixels {
+            ihdr,
+            filters,
+            data,
+        })
+    }
+
+    /// The seven passes of an Adam7 image, each unfiltered in turn; they
+    /// are small enough next to the inflating not to be worth splitting up
+    fn read_interlaced(png: &Png, ihdr: Ihdr) -> Result<Pixels, PixelError> {
+        let passes = ihdr.passes();
+        let bits = ihdr.bits_per_pixel();
+        let expected = ihdr.filtered_len().ok_or(PixelError::InvalidIhdr)?;
+        let filtered = inflate_idat(png, expected)?;
+
+        let distance = ihdr.filter_distance();
+        let mut data = vec![0u8; ihdr.stride() * ihdr.height as usize];
+        let mut filters = Vec::new();
+        let mut lines = filtered.as_slice();
+        for pass in passes {
+            let stride = pass.stride(bits);
+            let mut rows = vec![0u8; stride * pass.height];
+            for y in 0..pass.height {
+                let (line, rest) = lines.split_at(stride + 1);
+                lines = rest;
+                let (prev, row) = rows.split_at_mut(y * stride);
+                let prev = &prev[prev.len().saturating_sub(stride)..];
+                let row = &mut row[..stride];
+                row.copy_from_slice(&line[1..]);
+                unfilter(line[0], row, prev, distance)?;
+                filters.push(line[0]);
+            }
+            pass.scatter(&ihdr, &mut rows, &mut data, false);
+        }
+        Ok(Pixels {
+            ihdr,
+            filters,
+            data,
+        })
+    }
+
+    /// Store the image without interlacing from the next write, rows
+    /// filtered with Paeth; the IHDR written says so too
+    pub fn deinterlace(&mut self) {
+        self.ihdr.interlace = 0;
+        self.filters = vec![4; self.ihdr.height as usize];
+    }
+
+    /// Check every index of an indexed image is below `entries`
+    pub fn check_indices(&self, entries: usize) -> Result<(), PixelError> {
+        let bits = self.ihdr.bit_depth as usize;
+        let mut first = None;
+        let mut count = 0;
+        for (y, row) in self.data.chunks(self.ihdr.stride()).enumerate() {
+            for x in 0..self.ihdr.width as usize {
+                let mut index = [0];
+                copy_pixel(row, x, &mut index, 0, bits);
+                let index = index[0] >> (8 - bits.min(8));
+                if index as usize >= entries {
+                    first.get_or_insert((x, y, index));
+                    count += 1;
+                }
+            }
+        }
+        match first {
+            Some((x, y, index)) => Err(PixelError::IndexOutOfRange {
+                x,
+                y,
+                index,
+                entries,
+                count,
+            }),
+            None => Ok(()),
+        }
+    }
+
+    /// Refilter, recompress and replace the file's IDAT chunks, dropping
+    /// and returning the chunks [`Png::drop_unsafe_to_copy`] does
+    pub fn write(&self, png: &mut Png) -> Vec<Chunk> {
+        self.write_with(png, deflate::backend(), deflate::DEFAULT_LEVEL)
+    }
+
+    /// [`write`](Pixels::write), compressing with `backend` at `level`
+    pub fn write_with(&self, png: &mut Png, backend: &dyn Deflate, level: u32) -> Vec<Chunk> {
+        let ihdr = png.chunk_by_type("IHDR").map(|ihdr| ihdr.data().to_vec());
+        if let Some(mut ihdr) = ihdr.filter(|ihdr| ihdr.get(12) != Some(&self.ihdr.interlace)) {
+            if let Some(interlace) = ihdr.get_mut(12) {
+                *interlace = self.ihdr.interlace;
+                png.replace_chunks("IHDR", vec![Chunk::new(ChunkType::new(*b"IHDR"), ihdr)]);
+            }
+        }
+        let filtered = match self.ihdr.interlace {
+            0 => self.filter_rows(),
+            _ => self.filter_passes(),
+        };
+        let idat = Chunk::new(
+            ChunkType::new(*b"IDAT"),
+            perf::compress(|| backend.compress(&filtered, level)),
+        );
+        png.replace_chunks("IDAT", vec![idat]);
+        png.drop_unsafe_to_copy()
+    }
+
+    fn filter_rows(&self) -> Vec<u8> {
+        let stride = self.ihdr.stride();
+        let distance = self.ihdr.filter_distance();
+        // Filtering looks only at unfiltered rows, so every row can be done
+        // at once
+        let mut filtered = vec![0u8; (stride + 1) * self.filters.len()];
+        filtered
+            .par_chunks_mut(stride + 1)
+            .enumerate()
+            .for_each(|(y, line)| {
+                let row = &self.data[y * stride..(y + 1) * stride];
+                let prev = match y {
+                    0 => &[][..],
+                    _ => &self.data[(y - 1) * stride..y * stride],
+                };
+                line[0] = self.filters[y];
+                line[1..].copy_from_slice(&filter(self.filters[y], row, prev, distance));
+            });
+        filtered
+    }
+
+    fn filter_passes(&self) -> Vec<u8> {
+        let bits = self.ihdr.bits_per_pixel();
+        let distance = self.ihdr.filter_distance();
+        let mut image = self.data.clone();
+        let mut filters = self.filters.iter();
+        let mut filtered = Vec::new();
+        for pass in self.ihdr.passes() {
+            let stride = pass.stride(bits);
+            let mut rows = vec![0u8; stride * pass.height];
+            pass.scatter(&self.ihdr, &mut rows, &mut image, true);
+            for y in 0..pass.height {
+                let row = &rows[y * stride..(y + 1) * stride];
+                let prev = match y {
+                    0 => &[][..],
+                    _ => &rows[(y - 1) * stride..y * stride],
+                };
+                let filter_type = filters.next().copied().unwrap_or(0);
+                filtered.push(filter_type);
+                filtered.extend(filter(filter_type, row, prev, distance));
+            }
+        }
+        filtered
+    }
+}
+
+/// Split `data` into runs of rows that can be unfiltered independently,
+/// each with its row numbers. A run starts at every row filtered with None
+/// or Sub, which don't look at the row above
+fn runs<'a>(
+    data: &'a mut [u8],
+    stride: usize,
+    filters: &[u8],
+) -> Vec<(Range<usize>, &'a mut [u8])> {
+    let mut starts: Vec<usize> = (1..filters.len()).filter(|&y| filters[y] <= 1).collect();
+    starts.push(filters.len());
+    let mut runs = Vec::with_capacity(starts.len());
+    let (mut first, mut rest) = (0, data);
+    for start in starts {
+        let (run, after) = rest.split_at_mut((start - first) * stride);
+        runs.push((first..start, run));
+        (first, rest) = (start, after);
+    }
+    runs
+}
+
+fn paeth(a: u8, b: u8, c: u8) -> u8 {
+    let p = a as i16 + b as i16 - c as i16;
+    let (pa, pb, pc) = (
+        (p - a as i16).abs(),
+        (p - b as i16).abs(),
+        (p - c as i16).abs(),
+    );
+    if pa <= pb && pa <= pc {
+        a
+    } else if pb <= pc {
+        b
+    } else {
+        c
+    }
+}
+
+/// Predictor for byte `i` of a row given the already-reconstructed bytes;
+/// `prev` is empty for the first row
+fn predict(filter: u8, row: &[u8], prev: &[u8], i: usize, distance: usize) -> u8 {
+    let a = if i >= distance { row[i - distance] } else { 0 };
+    let b = prev.get(i).copied().unwrap_or(0);
+    let c = if i >= distance {
+        prev.get(i - distance).copied().unwrap_or(0)
+    } else {
+        0
+    };
+    match filter {
+        1 => a,
+        2 => b,
+        3 => ((a as u16 + b as u16) / 2) as u8,
+        4 => paeth(a, b, c),
+        _ => 0,
+    }
+}
+
+fn unfilter(filter: u8, row: &mut [u8], prev: &[u8], distance: usize) -> Result<(), PixelError> {
+    if filter > 4 {
+        return Err(PixelError::UnknownFilter(filter));
+    }
+    for i in 0..row.len() {
+        let predicted = predict(filter, row, prev, i, distance);
+        row[i] = row[i].wrapping_add(predicted);
+    }
+    Ok(())
+}
+
+fn filter(filter: u8, row: &[u8], prev: &[u8], distance: usize) -> Vec<u8> {
+    (0..row.len())
+        .map(|i| row[i].wrapping_sub(predict(filter, row, prev, i, distance)))
+        .collect()
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    /// An 8-bit RGB image using every filter type, one per row
+    pub(crate) fn testing_png(width: u32, height: u32) -> Png {
+        let chunk = |t: &str, d: Vec<u8>| Chunk::new(ChunkType::from_str(t).unwrap(), d);
+        let mut ihdr = width.to_be_bytes().to_vec();
+        ihdr.extend_from_slice(&height.to_be_bytes());
+        ihdr.extend_from_slice(&[8, 2, 0, 0, 0]);
+        let mut png = Png::from_chunks(vec![
+            chunk("IHDR", ihdr),
+            chunk("IDAT", Vec::new()),
+            chunk("IEND", Vec::new()),
+        ]);
+        let stride = width as usize * 3;
+        let data = (0..stride * height as usize)
+            .map(|i| (i * 7 % 251) as u8)
+            .collect();
+        let pixels = Pixels {
+            ihdr: Ihdr::parse(png.chunks()[0].data()).unwrap(),
+            filters: (0..height).map(|y| (y % 5) as u8).collect(),
+            data,
+        };
+        pixels.write(&mut png);
+        png
+    }
+
+    /// [`testing_png`]'s pixels, stored interlaced
+    pub(crate) fn interlaced_png(width: u32, height: u32) -> Png {
+        let mut png = testing_png(width, height);
+        let mut pixels = Pixels::read(&png).unwrap();
+        pixels.ihdr.interlace = 1;
+        let rows = pixels.ihdr.passes().iter().map(|pass| pass.height).sum();
+        pixels.filters = (0..rows).map(|y| (y % 5) as u8).collect();
+        pixels.write(&mut png);
+        png
+    }
+
+    #[test]
+    fn test_ihdr() {
+        let ihdr = Ihdr::parse(&[0, 0, 0, 5, 0, 0, 0, 3, 16, 6, 0, 0, 0]).unwrap();
+        assert_eq!(ihdr.channels(), 4);
+        assert_eq!(ihdr.stride(), 40);
+        assert_eq!(Ihdr::parse(&[0; 12]), Err(PixelError::InvalidIhdr));
+    }
+
+    #[test]
+    fn test_check_header() {
+        let mut png = testing_png(4, 4);
+        assert_eq!(check_header(&png).unwrap().color_type, 2);
+        let header = |fields: [u8; 5]| {
+            let mut data = vec![0, 0, 0, 4, 0, 0, 0, 4];
+            data.extend_from_slice(&fields);
+            Ihdr::parse(&data).unwrap().check()
+        };
+        assert_eq!(header([16, 6, 0, 0, 1]), Ok(()));
+        assert_eq!(header([4, 2, 0, 0, 0]), Err(PixelError::BitDepth(2, 4)));
+        assert_eq!(header([8, 5, 0, 0, 0]), Err(PixelError::ColorType(5)));
+        assert_eq!(header([8, 2, 1, 0, 0]), Err(PixelError::Compression(1)));
+        assert_eq!(header([8, 2, 0, 0, 2]), Err(PixelError::InterlaceMethod(2)));
+        assert_eq!(
+            PixelError::BitDepth(2, 4).to_string(),
+            "bit depth 4 isn't allowed for color type 2 (truecolor), which takes [8, 16]"
+        );
+        let zero = Ihdr::parse(&[0, 0, 0, 0, 0, 0, 0, 4, 8, 2, 0, 0, 0]).unwrap();
+        assert_eq!(zero.check(), Err(PixelError::ZeroDimension(0, 4)));
+
+        let mut ihdr = png.chunks()[0].data().to_vec();
+        ihdr[8..10].copy_from_slice(&[2, 3]);
+        png.replace_chunks("IHDR", vec![Chunk::new("IHDR".parse().unwrap(), ihdr)]);
+        assert_eq!(check_header(&png), Err(PixelError::MissingPalette));
+        png.insert_chunk(1, Chunk::new("PLTE".parse().unwrap(), vec![0; 15]));
+        assert_eq!(check_header(&png), Err(PixelError::PaletteTooLong(5, 2)));
+        let gray = Ihdr::parse(&[0, 0, 0, 4, 0, 0, 0, 4, 8, 0, 0, 0, 0]).unwrap();
+        assert_eq!(
+            gray.check_palette(Some(3)),
+            Err(PixelError::UnexpectedPalette(0))
+        );
+    }
+
+    #[test]
+    fn test_check_indices() {
+        let mut data = vec![0, 0, 0, 4, 0, 0, 0, 2];
+        data.extend_from_slice(&[2, 3, 0, 0, 0]);
+        let ihdr = Ihdr::parse(&data).unwrap();
+        let chunk = |t: &str, d: Vec<u8>| Chunk::new(ChunkType::from_str(t).unwrap(), d);
+        let mut png = Png::from_chunks(vec![
+            chunk("IHDR", data),
+            chunk("PLTE", vec![0; 9]),
+            chunk("IEND", Vec::new()),
+        ]);
+        let mut pixels = Pixels {
+            ihdr,
+            filters: vec![0; 2],
+            data: vec![0b00011000, 0b10010010],
+        };
+        pixels.write(&mut png);
+        assert_eq!(check_indices(&png), Ok(()));
+        pixels.data = vec![0b00011011, 0b11010010];
+        pixels.write(&mut png);
+        assert_eq!(
+            check_indices(&png),
+            Err(PixelError::IndexOutOfRange {
+                x: 3,
+                y: 0,
+                index: 3,
+                entries: 3,
+                count: 2
+            })
+        );
+
+        png.insert_chunk(2, chunk("tRNS", vec![255; 4]));
+        assert_eq!(
+            check_header(&png),
+            Err(PixelError::TransparencyTooLong(4, 3))
+        );
+    }
+
+    #[test]
+    fn test_filter_round_trip() {
+        let prev: Vec<u8> = (0..12).map(|i| i * 20).collect();
+        let row: Vec<u8> = (0..12).map(|i| 255 - i * 3).collect();
+        for filter_type in 0..5 {
+            let mut restored = filter(filter_type, &row, &prev, 3);
+            unfilter(filter_type, &mut restored, &prev, 3).unwrap();
+            assert_eq!(restored, row);
+        }
+    }
+
+    #[test]
+    fn test_read_write_round_trip() {
+        let png = testing_png(6, 7);
+        let pixels = Pixels::read(&png).unwrap();
+        assert_eq!(pixels.data.len(), 6 * 7 * 3);
+        assert_eq!(pixels.data[10], 70);
+        assert_eq!(pixels.filters, [0, 1, 2, 3, 4, 0, 1]);
+    }
+
+    #[test]
+    fn test_unfilters_in_runs() {
+        let png = testing_png(40, 203);
+        let pixels = Pixels::read(&png).unwrap();
+        let expected: Vec<u8> = (0..40 * 3 * 203).map(|i| (i * 7 % 251) as u8).collect();
+        assert_eq!(pixels.data, expected);
+
+        let mut data = vec![0; 4 * 2];
+        let filters = [2, 0, 3, 1];
+        let runs: Vec<(Range<usize>, usize)> = runs(&mut data, 2, &filters)
+            .into_iter()
+            .map(|(rows, run)| (rows, run.len()))
+            .collect();
+        assert_eq!(runs, [(0..1, 2), (1..3, 4), (3..4, 2)]);
+    }
+
+    #[test]
+    fn test_interlaced() {
+        // A 2x2 image's pixels are in passes 1, 6 and 7
+        let mut png = testing_png(2, 2);
+        let mut pixels = Pixels::read(&png).unwrap();
+        pixels.ihdr.interlace = 1;
+        pixels.filters = vec![0; 3];
+        pixels.data = (1..=12).collect();
+        pixels.write(&mut png);
+        assert_eq!(png.chunks()[0].data()[12], 1);
+        let idat = deflate::backend()
+            .decompress(png.chunk_by_type("IDAT").unwrap().data(), 15)
+            .unwrap();
+        let expected = [0, 1, 2, 3, 0, 4, 5, 6, 0, 7, 8, 9, 10, 11, 12];
+        assert_eq!(idat, expected);
+        assert_eq!(Pixels::read(&png).unwrap().data, pixels.data);
+
+        let mut png = interlaced_png(13, 11);
+        let mut pixels = Pixels::read(&png).unwrap();
+        assert_eq!(
+            pixels.data,
+            Pixels::read(&testing_png(13, 11)).unwrap().data
+        );
+        pixels.deinterlace();
+        pixels.write(&mut png);
+        assert_eq!(png.chunks()[0].data()[12], 0);
+        assert_eq!(Pixels::read(&png).unwrap().data, pixels.data);
+    }
+
+    #[test]
+    fn test_interlaced_sub_byte() {
+        let mut data = vec![0, 0, 0, 4, 0, 0, 0, 3];
+        data.extend_from_slice(&[2, 0, 0, 0, 1]);
+        let ihdr = Ihdr::parse(&data).unwrap();
+        let mut png = Png::from_chunks(vec![
+            Chunk::new(ChunkType::from_str("IHDR").unwrap(), data),
+            Chunk::new(ChunkType::from_str("IEND").unwrap(), Vec::new()),
+        ]);
+        let rows = ihdr.passes().iter().map(|pass| pass.height).sum();
+        let pixels = Pixels {
+            ihdr,
+            filters: vec![1; rows],
+            data: vec![0b00011011, 0b11100100, 0b01010101],
+        };
+        pixels.write(&mut png);
+        assert_eq!(Pixels::read(&png).unwrap().data, pixels.data);
+    }
+
+    #[test]
+    fn test_decompression_limit() {
+        // A few bytes of IDAT can't make the reader allocate 24 GiB
+        let mut png = testing_png(4, 4);
+        let mut ihdr = png.chunks()[0].data().to_vec();
+        ihdr[..8].copy_from_slice(&[0, 1, 0, 0, 0, 2, 0, 0]);
+        png.replace_chunks("IHDR", vec![Chunk::new("IHDR".parse().unwrap(), ihdr)]);
+        assert_eq!(
+            Pixels::read(&png).unwrap_err(),
+            PixelError::DecompressionLimitExceeded(Limits::IMAGE.max_output)
+        );
+    }
+
+    #[test]
+    fn test_truncated_image_data() {
+        let mut png = testing_png(6, 7);
+        let idat = png.remove_first_chunk("IDAT").unwrap();
+        let short = idat.data()[..idat.data().len() / 2].to_vec();
+        png.append_chunk(Chunk::new(ChunkType::from_str("IDAT").unwrap(), short));
+        assert_eq!(
+            Pixels::read(&png).unwrap_err(),
+            PixelError::InvalidImageData
+        );
+    }
+}
@@ -0,0 +1,1708 @@
+#![deny(clippy::unwrap_used, clippy::expect_used)]
+
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::fmt::Display;
+
+use smallvec::SmallVec;
+
+use crate::chunk::Chunk;
+use crate::chunk_type::{ChunkError, ChunkType};
+use crate::crc;
+use crate::order::{self, Misplaced};
+
+/// Chunks held inline before spilling to the heap: enough for the header,
+/// the usual ancillary chunks, a few text chunks, one IDAT and IEND
+const INLINE_CHUNKS: usize = 16;
+
+type Chunks = SmallVec<[Chunk; INLINE_CHUNKS]>;
+
+/// A parsed file. Until it's edited it serializes back to the very bytes
+/// it was parsed from, chunk order, IDAT splits and data after IEND
+/// included; only a chunk kept despite a CRC mismatch is written with the
+/// CRC its data gives
+#[derive(Debug, Clone)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(into = "crate::model::PngModel", try_from = "crate::model::PngModel")
+)]
+pub struct Png {
+    chunks: Chunks,
+    /// Bytes after the IEND chunk
+    trailing: Vec<u8>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum PngError {
+    /// Too short to hold the signature, though what there is matches it
+    InvalidHeader,
+    /// The first byte differing from the signature, and where it is
+    Signature {
+        offset: usize,
+        found: u8,
+    },
+    Truncated(usize),
+    Chunk(usize, ChunkError),
+    ChunkNotFound(String),
+    ProtectedChunk(String),
+    Animated,
+    /// Data after IEND, which strict parsing rejects
+    TrailingData {
+        offset: usize,
+        length: usize,
+    },
+    /// A text chunk at this offset with a keyword breaking the spec's rules
+    InvalidKeyword(usize),
+    /// A second chunk of a type the spec allows once
+    Duplicate(String),
+    Crc(CrcMismatch),
+    Misplaced(Misplaced),
+    /// The chunk at this offset takes the file past [`ParseOptions::budget`]
+    OverBudget(usize, Resource),
+}
+
+/// A chunk whose stored CRC isn't the one its type and data give
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CrcMismatch {
+    /// Position in the file's chunk list
+    pub index: usize,
+    /// File offset of the chunk's length field
+    pub offset: usize,
+    pub chunk_type: ChunkType,
+    pub length: u32,
+    pub stored: u32,
+    pub computed: u32,
+}
+
+impl CrcMismatch {
+    /// File offset of the stored CRC
+    pub fn crc_offset(&self) -> usize {
+        self.offset + 8 + self.length as usize
+    }
+
+    /// Write the computed CRC over the stored one in `file`, the bytes the
+    /// mismatch was found in
+    pub fn repair(&self, file: &mut [u8]) {
+        let at = self.crc_offset();
+        file[at..at + 4].copy_from_slice(&self.computed.to_be_bytes());
+    }
+}
+
+impl Display for CrcMismatch {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "crc mismatch in chunk {} ({}) at offset {} ({:#x}): stored {:#010x}, computed {:#010x}",
+            self.index, self.chunk_type, self.offset, self.offset, self.stored, self.computed
+        )
+    }
+}
+
+impl Display for PngError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            PngError::InvalidHeader => write!(f, "too short for a png signature"),
+            PngError::Signature { offset, found } => write!(
+                f,
+                "invalid png signature: byte {} is {:#04x}, not {:#04x}",
+                offset,
+                found,
+                Png::STANDARD_HEADER[*offset]
+            ),
+            PngError::Truncated(offset) => write!(f, "truncated chunk at offset {}", offset),
+            PngError::Chunk(offset, err) => write!(f, "chunk at offset {}: {}", offset, err),
+            PngError::ChunkNotFound(chunk_type) => write!(f, "chunk {} not found", chunk_type),
+            PngError::ProtectedChunk(chunk_type) => {
+                write!(f, "{} is a critical or public chunk type", chunk_type)
+            }
+            PngError::Animated => write!(f, "animated png frames can't be reordered"),
+            PngError::TrailingData { offset, length } => {
+                write!(f, "{} bytes after IEND at offset {}", length, offset)
+            }
+            PngError::InvalidKeyword(offset) => {
+                write!(f, "text chunk at offset {} has an invalid keyword", offset)
+            }
+            PngError::Duplicate(chunk_type) => {
+                write!(f, "a png can only have one {} chunk", chunk_type)
+            }
+            PngError::Crc(mismatch) => write!(f, "{}", mismatch),
+            PngError::Misplaced(misplaced) => write!(f, "{}", misplaced),
+            PngError::OverBudget(offset, resource) => write!(
+                f,
+                "chunk at offset {} takes the file past its budget of {}",
+                offset, resource
+            ),
+        }
+    }
+}
+
+/// What [`Png::add_chunk`] does with a chunk of a type the file can only
+/// have one of, and already has
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OnDuplicate {
+    /// Fail with [`PngError::Duplicate`]
+    #[default]
+    Refuse,
+    /// Put the new chunk where the old one was
+    Replace,
+}
+
+/// Something parsing accepts that can be a sign of corruption or of data
+/// hidden in the file, or that [`Mode::Lenient`] read past
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Warning {
+    TrailingData {
+        offset: usize,
+        length: usize,
+    },
+    /// The file ends inside the chunk at `offset`; its `length` bytes
+    /// were dropped
+    Truncated {
+        offset: usize,
+        length: usize,
+    },
+    /// A chunk whose type isn't four ASCII letters, skipped
+    InvalidChunkType {
+        offset: usize,
+        bytes: [u8; 4],
+    },
+    /// Damaged bytes skipped to reach the next intact chunk
+    Skipped {
+        offset: usize,
+        length: usize,
+    },
+    Crc(CrcMismatch),
+    /// No chunk of a type every file has
+    Missing(ChunkType),
+    /// A second chunk of a type the spec allows once
+    Duplicate {
+        index: usize,
+        offset: usize,
+        chunk_type: ChunkType,
+    },
+    Misplaced(Misplaced),
+    /// A text chunk with a keyword breaking the spec's rules
+    InvalidKeyword {
+        index: usize,
+        offset: usize,
+    },
+}
+
+impl Display for Warning {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Warning::TrailingData { offset, length } => {
+                write!(f, "{} bytes after IEND at offset {}", length, offset)
+            }
+            Warning::Truncated { offset, length } => write!(
+                f,
+                "truncated chunk at offset {}: dropped its {} bytes",
+                offset, length
+            ),
+            Warning::InvalidChunkType { offset, bytes } => write!(
+                f,
+                "skipped a chunk of invalid type {:?} at offset {}",
+                bytes.escape_ascii().to_string(),
+                offset
+            ),
+            Warning::Skipped { offset, length } => write!(
+                f,
+                "skipped {} damaged bytes at offset {}, up to the next intact chunk",
+                length, offset
+            ),
+            Warning::Crc(mismatch) => write!(f, "{}", mismatch),
+            Warning::Missing(chunk_type) => write!(f, "no {} chunk", chunk_type),
+            Warning::Duplicate {
+                offset, chunk_type, ..
+            } => write!(
+                f,
+                "a second {} chunk at offset {}, which the file can only have one of",
+                chunk_type, offset
+            ),
+            Warning::Misplaced(misplaced) => write!(f, "{}", misplaced),
+            Warning::InvalidKeyword { offset, .. } => {
+                write!(f, "text chunk at offset {} has an invalid keyword", offset)
+            }
+        }
+    }
+}
+
+impl From<Warning> for PngError {
+    /// The error [`Mode::Strict`] fails with where lenient parsing warns
+    fn from(warning: Warning) -> Self {
+        match warning {
+            Warning::TrailingData { offset, length } => PngError::TrailingData { offset, length },
+            Warning::Truncated { offset, .. } => PngError::Truncated(offset),
+            Warning::InvalidChunkType { offset, .. } => {
+                PngError::Chunk(offset, ChunkError::InvalidChunk)
+            }
+            Warning::Skipped { offset, .. } => PngError::Truncated(offset),
+            Warning::Crc(mismatch) => PngError::Crc(mismatch),
+            Warning::Missing(chunk_type) => PngError::ChunkNotFound(chunk_type.to_string()),
+            Warning::Duplicate { chunk_type, .. } => PngError::Duplicate(chunk_type.to_string()),
+            Warning::Misplaced(misplaced) => PngError::Misplaced(misplaced),
+            Warning::InvalidKeyword { offset, .. } => PngError::InvalidKeyword(offset),
+        }
+    }
+}
+
+/// How [`Png::parse_with`] treats a file breaking the spec
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Mode {
+    /// Fail on the first violation
+    #[default]
+    Strict,
+    /// Read what can be read, returning each violation as a [`Warning`]:
+    /// real files are often slightly broken, and their metadata still
+    /// wanted. A chunk whose length can't be right is skipped up to the
+    /// next intact chunk, as is one failing its CRC unless a chunk could
+    /// start where it ends
+    Lenient,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ParseOptions {
+    pub mode: Mode,
+    pub budget: Budget,
+}
+
+/// What one parse may cost in all. Each chunk can pass every limit of its
+/// own and a file of many still exhaust memory, so untrusted files get a
+/// total for the lot; lenient parsing fails on it as strict does
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Budget {
+    /// Bytes of chunk data, by the lengths the chunks declare, with those
+    /// hashed looking for an intact chunk after a damaged one
+    pub data: u64,
+    pub chunks: usize,
+    /// Bytes the file's compressed data may inflate to: the image data
+    /// IHDR declares, and the most each compressed text chunk is allowed
+    /// to reach. Only counted with the std feature, which inflates
+    pub inflated: u64,
+}
+
+impl Budget {
+    /// No limits, for files that are trusted
+    pub const UNLIMITED: Budget = Budget {
+        data: u64::MAX,
+        chunks: usize::MAX,
+        inflated: u64::MAX,
+    };
+
+    /// The first of `spent` past this budget
+    fn exceeded_by(&self, spent: &Budget) -> Option<Resource> {
+        if spent.data > self.data {
+            Some(Resource::Data(self.data))
+        } else if spent.chunks > self.chunks {
+            Some(Resource::Chunks(self.chunks))
+        } else if spent.inflated > self.inflated {
+            Some(Resource::Inflated(self.inflated))
+        } else {
+            None
+        }
+    }
+}
+
+impl Default for Budget {
+    /// Room for any image the pixel layer decodes, a million chunks and
+    /// 4 GiB of data besides
+    fn default() -> Self {
+        Budget {
+            data: 4 << 30,
+            chunks: 1 << 20,
+            inflated: 8 << 30,
+        }
+    }
+}
+
+/// A part of a [`Budget`] a file ran out of, with its limit
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Resource {
+    Data(u64),
+    Chunks(usize),
+    Inflated(u64),
+}
+
+impl Display for Resource {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Resource::Data(limit) => write!(f, "{} bytes of chunk data", limit),
+            Resource::Chunks(limit) => write!(f, "{} chunks", limit),
+            Resource::Inflated(limit) => write!(f, "{} bytes of inflated data", limit),
+        }
+    }
+}
+
+/// The most a chunk's data may inflate to once decoded
+#[cfg(feature = "std")]
+fn inflated_len(chunk_type: &ChunkType, data: &[u8]) -> u64 {
+    use crate::deflate::Limits;
+
+    let compressed = match &chunk_type.bytes() {
+        b"IHDR" => {
+            let ihdr = crate::pixels::Ihdr::parse(data).ok();
+            let len = ihdr.and_then(|ihdr| ihdr.filtered_len());
+            return len.map_or(0, |len| len as u64);
+        }
+        b"zTXt" => true,
+        // The compression flag follows the keyword
+        b"iTXt" => data
+            .iter()
+            .position(|&b| b == 0)
+            .and_then(|end| data.get(end + 1))
+            .is_some_and(|&flag| flag != 0),
+        _ => false,
+    };
+    match compressed {
+        true => Limits::METADATA.output_for(data.len()),
+        false => 0,
+    }
+}
+
+#[cfg(not(feature = "std"))]
+fn inflated_len(_: &ChunkType, _: &[u8]) -> u64 {
+    0
+}
+
+/// Violations found so far, or the first of them in strict mode
+struct Violations {
+    mode: Mode,
+    warnings: Vec<Warning>,
+}
+
+impl Violations {
+    fn record(&mut self, warning: Warning) -> Result<(), PngError> {
+        match self.mode {
+            Mode::Strict => Err(warning.into()),
+            Mode::Lenient => {
+                self.warnings.push(warning);
+                Ok(())
+            }
+        }
+    }
+
+    /// Where the next intact chunk after the damaged one at `offset`
+    /// starts, within [`RESYNC_WINDOW`] bytes, recording the bytes skipped
+    /// to reach it; none in strict mode or if there isn't one. The data
+    /// each candidate's CRC covers is charged to `spent`
+    fn skip(
+        &mut self,
+        value: &[u8],
+        offset: usize,
+        budget: &Budget,
+        spent: &mut Budget,
+    ) -> Result<Option<usize>, PngError> {
+        if self.mode == Mode::Strict {
+            return Ok(None);
+        }
+        let end = value.len().min(offset.saturating_add(RESYNC_WINDOW));
+        for at in offset + 1..end {
+            let Some(length) = chunk_length_at(value, at) else {
+                continue;
+            };
+            spent.data = spent.data.saturating_add(length as u64);
+            if let Some(resource) = budget.exceeded_by(spent) {
+                return Err(PngError::OverBudget(at, resource));
+            }
+            let rest = &value[at..];
+            if Chunk::read_crc(rest, length) == Some(crc::checksum(&rest[4..8 + length])) {
+                self.record(Warning::Skipped {
+                    offset,
+                    length: at - offset,
+                })?;
+                return Ok(Some(at));
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// How far past a damaged chunk lenient parsing looks for an intact one
+const RESYNC_WINDOW: usize = 1 << 20;
+
+/// The data length of a chunk that could start at `at`, one with a valid
+/// type and a length the file has room for
+fn chunk_length_at(value: &[u8], at: usize) -> Option<usize> {
+    let rest = value.get(at..).unwrap_or_default();
+    let (length, bytes) = Chunk::read_head(rest)?;
+    let length = length as usize;
+    let fits = rest.len() >= Chunk::OVERHEAD && rest.len() - Chunk::OVERHEAD >= length;
+    (fits && ChunkType::try_from(bytes).is_ok()).then_some(length)
+}
+
+/// Whether a chunk could start at `at`, as [`chunk_length_at`] has it
+fn chunk_at(value: &[u8], at: usize) -> bool {
+    chunk_length_at(value, at).is_some()
+}
+
+impl core::error::Error for PngError {}
+
+impl TryFrom<&[u8]> for Png {
+    type Error = PngError;
+
+    fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
+        Png::parse(value, false).map(|(png, _)| png)
+    }
+}
+
+impl Png {
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            level = "debug",
+            skip(value),
+            fields(len = value.len()),
+            err(level = "debug")
+        )
+    )]
+    fn parse(value: &[u8], ignore_crc: bool) -> Result<(Png, Vec<CrcMismatch>), PngError> {
+        Png::check_signature(value)?;
+
+        let mut chunks = Chunks::new();
+        let mut mismatches = Vec::new();
+        let mut offset = Png::STANDARD_HEADER.len();
+        while offset < value.len() {
+            let rest = &value[offset..];
+            let Some((length, chunk_type)) = Chunk::read_head(rest) else {
+                return Err(PngError::Truncated(offset));
+            };
+            let length = length as usize;
+            // Compared this way round so a forged length can't overflow on
+            // 32-bit targets
+            if rest.len() < Chunk::OVERHEAD || rest.len() - Chunk::OVERHEAD < length {
+                return Err(PngError::Truncated(offset));
+            }
+            let end = length + Chunk::OVERHEAD;
+            let chunk = match Chunk::try_from(&rest[..end]) {
+                Err(ChunkError::CrcMismatch { expected, actual }) => {
+                    let chunk_type =
+                        ChunkType::try_from(chunk_type).map_err(|e| PngError::Chunk(offset, e))?;
+                    let mismatch = CrcMismatch {
+                        index: chunks.len(),
+                        offset,
+                        chunk_type,
+                        length: length as u32,
+                        stored: expected,
+                        computed: actual,
+                    };
+                    if !ignore_crc {
+                        return Err(PngError::Crc(mismatch));
+                    }
+                    #[cfg(feature = "tracing")]
+                    tracing::warn!(offset, "keeping a chunk that fails its crc check");
+                    mismatches.push(mismatch);
+                    Chunk::new(chunk_type, rest[8..end - 4].to_vec())
+                }
+                chunk => chunk.map_err(|e| PngError::Chunk(offset, e))?,
+            };
+            let is_iend = chunk.chunk_type().as_str() == "IEND";
+            chunks.push(chunk);
+            offset += end;
+            if is_iend {
+                break;
+            }
+        }
+
+        #[cfg(feature = "std")]
+        crate::perf::parsed(offset - Png::STANDARD_HEADER.len(), chunks.len());
+        let png = Png {
+            chunks,
+            trailing: value[offset..].to_vec(),
+        };
+        #[cfg(feature = "tracing")]
+        tracing::debug!(
+            chunks = png.chunks.len(),
+            trailing = png.trailing.len(),
+            "parsed"
+        );
+        #[cfg(feature = "tracing")]
+        if !png.trailing.is_empty() {
+            tracing::warn!(offset, length = png.trailing.len(), "data after IEND");
+        }
+        Ok((png, mismatches))
+    }
+}
+
+impl Display for Png {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        for chunk in &self.chunks {
+            writeln!(f, "{}", chunk)?;
+        }
+        Ok(())
+    }
+}
+
+impl Png {
+    pub const STANDARD_HEADER: [u8; 8] = [137, 80, 78, 71, 13, 10, 26, 10];
+
+    /// Check that `bytes` start with the signature, naming the first byte
+    /// that differs
+    pub fn check_signature(bytes: &[u8]) -> Result<(), PngError> {
+        let at = bytes
+            .iter()
+            .zip(&Png::STANDARD_HEADER)
+            .position(|(a, b)| a != b);
+        match at {
+            Some(offset) => Err(PngError::Signature {
+                offset,
+                found: bytes[offset],
+            }),
+            None if bytes.len() < Png::STANDARD_HEADER.len() => Err(PngError::InvalidHeader),
+            None => Ok(()),
+        }
+    }
+
+    /// Parse a file that may follow up to `max_preamble` bytes of
+    /// something else, such as HTTP headers or a byte order mark, returning
+    /// it with what came before it. Offsets in errors count from the
+    /// signature, and a file with no signature in reach fails as
+    /// [`Png::try_from`] does
+    pub fn parse_after_preamble(
+        value: &[u8],
+        max_preamble: usize,
+    ) -> Result<(Png, &[u8]), PngError> {
+        let reach = &value[..value.len().min(max_preamble.saturating_add(8))];
+        let at = reach
+            .windows(Png::STANDARD_HEADER.len())
+            .position(|window| window == Png::STANDARD_HEADER);
+        let Some(at) = at else {
+            // Only a file too short for a signature starts with one here
+            let error = Png::check_signature(value).err();
+            return Err(error.unwrap_or(PngError::InvalidHeader));
+        };
+        let (preamble, png) = value.split_at(at);
+        Ok((Png::try_from(png)?, preamble))
+    }
+
+    /// Parse a file whose chunks may be damaged, accepting them whatever
+    /// their CRC; also returns the chunks that didn't match
+    pub fn parse_ignoring_crc(value: &[u8]) -> Result<(Png, Vec<CrcMismatch>), PngError> {
+        Png::parse(value, true)
+    }
+    /// Parse as [`Png::try_from`] does, but fail on data after IEND rather
+    /// than keep it as trailing data
+    pub fn parse_strict(value: &[u8]) -> Result<Png, PngError> {
+        let png = Png::try_from(value)?;
+        match png.warnings().into_iter().next() {
+            Some(warning) => Err(warning.into()),
+            None => Ok(png),
+        }
+    }
+
+    /// Parse checking what the spec asks of a file's structure: chunk
+    /// types and CRCs, IHDR and IEND present, unique and placed chunks,
+    /// text keywords with the std feature, nothing after IEND. The
+    /// warnings are empty in strict mode, which fails instead; a file
+    /// without the signature, or over the options' budget, fails in either
+    pub fn parse_with(
+        value: &[u8],
+        options: &ParseOptions,
+    ) -> Result<(Png, Vec<Warning>), PngError> {
+        Png::parse_with_offsets(value, options).map(|(png, _, warnings)| (png, warnings))
+    }
+
+    /// [`Png::parse_with`], also returning each chunk's offset in `value`,
+    /// which skipped bytes make differ from [`Png::chunk_offsets`]
+    pub fn parse_with_offsets(
+        value: &[u8],
+        options: &ParseOptions,
+    ) -> Result<(Png, Vec<usize>, Vec<Warning>), PngError> {
+        Png::check_signature(value)?;
+        let mut violations = Violations {
+            mode: options.mode,
+            warnings: Vec::new(),
+        };
+        let mut spent = Budget {
+            data: 0,
+            chunks: 0,
+            inflated: 0,
+        };
+        // Whether a chunk could follow one ending at `end`
+        let boundary = |end| end == value.len() || chunk_at(value, end);
+        let mut chunks = Chunks::new();
+        let mut offsets = Vec::new();
+        let mut offset = Png::STANDARD_HEADER.len();
+        while offset < value.len() {
+            let rest = &value[offset..];
+            let head = Chunk::read_head(rest).filter(|&(length, _)| {
+                rest.len() >= Chunk::OVERHEAD && rest.len() - Chunk::OVERHEAD >= length as usize
+            });
+            let Some((length, bytes)) = head else {
+                // A damaged length, unless the file really ends here
+                if let Some(next) = violations.skip(value, offset, &options.budget, &mut spent)? {
+                    offset = next;
+                    continue;
+                }
+                violations.record(Warning::Truncated {
+                    offset,
+                    length: rest.len(),
+                })?;
+                offset = value.len();
+                break;
+            };
+            let length = length as usize;
+            let end = length + Chunk::OVERHEAD;
+            let Ok(chunk_type) = ChunkType::try_from(bytes) else {
+                violations.record(Warning::InvalidChunkType { offset, bytes })?;
+                offset = match boundary(offset + end) {
+                    true => offset + end,
+                    false => violations
+                        .skip(value, offset, &options.budget, &mut spent)?
+                        .unwrap_or(offset + end),
+                };
+                continue;
+            };
+            // Charged before the data is copied, whether it's kept or not
+            spent.data = spent.data.saturating_add(length as u64);
+            spent.chunks += 1;
+            let inflated = inflated_len(&chunk_type, &rest[8..end - 4]);
+            spent.inflated = spent.inflated.saturating_add(inflated);
+            if let Some(resource) = options.budget.exceeded_by(&spent) {
+                return Err(PngError::OverBudget(offset, resource));
+            }
+            let chunk = match Chunk::try_from(&rest[..end]) {
+                Err(ChunkError::CrcMismatch { expected, actual }) => {
+                    violations.record(Warning::Crc(CrcMismatch {
+                        index: chunks.len(),
+                        offset,
+                        chunk_type,
+                        length: length as u32,
+                        stored: expected,
+                        computed: actual,
+                    }))?;
+                    // Only the data is damaged if the next chunk is where
+                    // the length says
+                    if !boundary(offset + end) {
+                        if let Some(next) =
+                            violations.skip(value, offset, &options.budget, &mut spent)?
+                        {
+                            offset = next;
+                            continue;
+                        }
+                    }
+                    Chunk::new(chunk_type, rest[8..end - 4].to_vec())
+                }
+                chunk => chunk.map_err(|e| PngError::Chunk(offset, e))?,
+            };
+            let is_iend = chunk.chunk_type().as_str() == "IEND";
+            chunks.push(chunk);
+            offsets.push(offset);
+            offset += end;
+            if is_iend {
+                break;
+            }
+        }
+        if offset < value.len() {
+            violations.record(Warning::TrailingData {
+                offset,
+                length: value.len() - offset,
+            })?;
+        }
+
+        let types: Vec<(usize, ChunkType)> = offsets
+            .iter()
+            .copied()
+            .zip(chunks.iter().map(|c| *c.chunk_type()))
+            .collect();
+        for required in [ChunkType::new(*b"IHDR"), ChunkType::new(*b"IEND")] {
+            if !types.iter().any(|(_, t)| *t == required) {
+                violations.record(Warning::Missing(required))?;
+            }
+        }
+        // Only the few standard types are unique, so the ones seen stay few
+        let mut unique = Vec::new();
+        for (index, &(offset, chunk_type)) in types.iter().enumerate() {
+            if !crate::order::is_unique(chunk_type.as_str()) {
+                continue;
+            }
+            if unique.contains(&chunk_type) {
+                violations.record(Warning::Duplicate {
+                    index,
+                    offset,
+                    chunk_type,
+                })?;
+            } else {
+                unique.push(chunk_type);
+            }
+        }
+        for misplaced in crate::order::misplaced(&types) {
+            violations.record(Warning::Misplaced(misplaced))?;
+        }
+        #[cfg(feature = "std")]
+        for (index, (&offset, chunk)) in offsets.iter().zip(&chunks).enumerate() {
+            let is_text = matches!(chunk.chunk_type().as_str(), "tEXt" | "zTXt" | "iTXt");
+            if is_text && crate::text::check_keyword_in(chunk.data()).is_err() {
+                violations.record(Warning::InvalidKeyword { index, offset })?;
+            }
+        }
+
+        let png = Png {
+            chunks,
+            trailing: value[offset..].to_vec(),
+        };
+        Ok((png, offsets, violations.warnings))
+    }
+    pub fn from_chunks(chunks: Vec<Chunk>) -> Png {
+        Png {
+            chunks: Chunks::from_vec(chunks),
+            trailing: Vec::new(),
+        }
+    }
+    /// Append a chunk, keeping IEND last if the file has one
+    pub fn append_chunk(&mut self, chunk: Chunk) {
+        match self.chunks.last() {
+            Some(last) if last.chunk_type().as_str() == "IEND" => {
+                let at = self.chunks.len() - 1;
+                self.chunks.insert(at, chunk);
+            }
+            _ => self.chunks.push(chunk),
+        }
+    }
+    /// Append a chunk as [`append_chunk`](Png::append_chunk) does, unless
+    /// its type is one the file can only have one of and already has; see
+    /// [`order::is_unique`](crate::order::is_unique). Returns the chunk
+    /// replaced, if any
+    pub fn add_chunk(
+        &mut self,
+        chunk: Chunk,
+        on_duplicate: OnDuplicate,
+    ) -> Result<Option<Chunk>, PngError> {
+        let chunk_type = chunk.chunk_type().as_str();
+        let existing = match crate::order::is_unique(chunk_type) {
+            true => self
+                .chunks
+                .iter()
+                .position(|c| c.chunk_type().as_str() == chunk_type),
+            false => None,
+        };
+        match (existing, on_duplicate) {
+            (None, _) => {
+                self.append_chunk(chunk);
+                Ok(None)
+            }
+            (Some(_), OnDuplicate::Refuse) => Err(PngError::Duplicate(chunk_type.to_string())),
+            (Some(index), OnDuplicate::Replace) => {
+                Ok(Some(core::mem::replace(&mut self.chunks[index], chunk)))
+            }
+        }
+    }
+    /// Insert a chunk at `index` in the chunk list, or append it with
+    /// [`append_chunk`](Png::append_chunk) if that's past the end
+    pub fn insert_chunk(&mut self, index: usize, chunk: Chunk) {
+        match index < self.chunks.len() {
+            true => self.chunks.insert(index, chunk),
+            false => self.append_chunk(chunk),
+        }
+    }
+    pub fn remove_first_chunk(&mut self, chunk_type: &str) -> Result<Chunk, PngError> {
+        let index = self
+            .chunks
+            .iter()
+            .position(|c| c.chunk_type().as_str() == chunk_type)
+            .ok_or_else(|| PngError::ChunkNotFound(chunk_type.to_string()))?;
+        Ok(self.chunks.remove(index))
+    }
+    /// Change the type of every `old` chunk to `new`, returning how many
+    pub fn rename_chunks(&mut self, old: &str, new: ChunkType) -> Result<usize, PngError> {
+        let mut renamed = 0;
+        self.chunks = core::mem::take(&mut self.chunks)
+            .into_iter()
+            .map(|c| {
+                if c.chunk_type().as_str() == old {
+                    renamed += 1;
+                    c.with_type(new)
+                } else {
+                    c
+                }
+            })
+            .collect();
+        match renamed {
+            0 => Err(PngError::ChunkNotFound(old.to_string())),
+            n => Ok(n),
+        }
+    }
+    /// Put the chunks in canonical order; see [`order::rank`]
+    #[cfg(feature = "std")]
+    pub fn reorder(&mut self) -> Result<(), PngError> {
+        if self.chunk_by_type("acTL").is_some() {
+            return Err(PngError::Animated);
+        }
+        order::canonicalize(&mut self.chunks);
+        Ok(())
+    }
+    /// Replace every chunk of `chunk_type` with `chunks`, placed where the
+    /// first of them was
+    pub fn replace_chunks(&mut self, chunk_type: &str, chunks: Vec<Chunk>) {
+        match self
+            .chunks
+            .iter()
+            .position(|c| c.chunk_type().as_str() == chunk_type)
+        {
+            Some(at) => {
+                self.chunks
+                    .retain(|c| c.chunk_type().as_str() != chunk_type);
+                self.chunks.insert_many(at, chunks);
+            }
+            None => chunks.into_iter().for_each(|c| self.append_chunk(c)),
+        }
+    }
+    /// Keep only the chunks `keep` returns true for
+    pub fn retain_chunks(&mut self, mut keep: impl FnMut(&Chunk) -> bool) {
+        self.chunks.retain(|c| keep(c));
+    }
+    /// Remove and return the unknown ancillary chunks not safe to copy,
+    /// which the spec says an editor mustn't carry over once it has
+    /// changed a critical chunk, as they may describe what it held
+    pub fn drop_unsafe_to_copy(&mut self) -> Vec<Chunk> {
+        let unsafe_to_copy = |c: &Chunk| {
+            let chunk_type = c.chunk_type();
+            !chunk_type.is_critical()
+                && !chunk_type.is_safe_to_copy()
+                && !order::is_standard(chunk_type.as_str())
+        };
+        let dropped = self
+            .chunks
+            .iter()
+            .filter(|c| unsafe_to_copy(c))
+            .cloned()
+            .collect();
+        self.chunks.retain(|c| !unsafe_to_copy(c));
+        dropped
+    }
+    pub fn header(&self) -> &[u8; 8] {
+        &Self::STANDARD_HEADER
+    }
+    pub fn chunks(&self) -> &[Chunk] {
+        &self.chunks
+    }
+    pub fn chunk_by_type(&self, chunk_type: &str) -> Option<&Chunk> {
+        self.chunks
+            .iter()
+            .find(|c| c.chunk_type().as_str() == chunk_type)
+    }
+    pub fn chunks_by_type<'a>(&'a self, chunk_type: &'a str) -> impl Iterator<Item = &'a Chunk> {
+        self.chunks
+            .iter()
+            .filter(move |c| c.chunk_type().as_str() == chunk_type)
+    }
+    /// File offset of each chunk's length field
+    pub fn chunk_offsets(&self) -> Vec<usize> {
+        self.chunks
+            .iter()
+            .scan(Self::STANDARD_HEADER.len(), |offset, chunk| {
+                let start = *offset;
+                *offset += chunk.length() as usize + Chunk::OVERHEAD;
+                Some(start)
+            })
+            .collect()
+    }
+    /// Chunks breaking the spec's placement rules; see
+    /// [`order::misplaced`](crate::order::misplaced)
+    pub fn misplaced(&self) -> Vec<crate::order::Misplaced> {
+        let chunks: Vec<(usize, ChunkType)> = self
+            .chunk_offsets()
+            .into_iter()
+            .zip(self.chunks.iter().map(|c| *c.chunk_type()))
+            .collect();
+        crate::order::misplaced(&chunks)
+    }
+    /// Data after IEND, which decoders ignore
+    pub fn trailing(&self) -> &[u8] {
+        &self.trailing
+    }
+    pub fn set_trailing(&mut self, trailing: Vec<u8>) {
+        self.trailing = trailing;
+    }
+    /// Remove the data after IEND, returning it
+    pub fn take_trailing(&mut self) -> Vec<u8> {
+        core::mem::take(&mut self.trailing)
+    }
+    /// What parsing the file accepted but might not have: data after IEND
+    pub fn warnings(&self) -> Vec<Warning> {
+        match self.trailing.len() {
+            0 => Vec::new(),
+            length => alloc::vec![Warning::TrailingData {
+                offset: self.trailing_offset(),
+                length,
+            }],
+        }
+    }
+    /// File offset of the data after IEND
+    pub fn trailing_offset(&self) -> usize {
+        Self::STANDARD_HEADER.len()
+            + self
+                .chunks
+                .iter()
+                .map(|c| c.length() as usize + Chunk::OVERHEAD)
+                .sum::<usize>()
+    }
+    /// Bytes the file takes serialized, trailing data included
+    pub fn encoded_len(&self) -> usize {
+        self.trailing_offset() + self.trailing.len()
+    }
+    /// Append the file as [`Png::as_bytes`] would serialize it to `out`,
+    /// so one buffer can be written over and over
+    pub fn write_into(&self, out: &mut Vec<u8>) {
+        out.reserve_exact(self.encoded_len());
+        out.extend_from_slice(self.header());
+        for chunk in &self.chunks {
+            out.extend_from_slice(&chunk.length().to_be_bytes());
+            out.extend_from_slice(&chunk.chunk_type().bytes());
+            out.extend_from_slice(chunk.data());
+            out.extend_from_slice(&chunk.crc().to_be_bytes());
+        }
+        out.extend_from_slice(&self.trailing);
+    }
+    /// Write the file to `writer` with vectored writes straight from the
+    /// chunks' data. Small chunks are gathered with the fields around them
+    /// into blocks, as the kernel takes longer over a slice per few bytes
+    /// than copying them does
+    #[cfg(feature = "std")]
+    pub fn write_to(&self, mut writer: impl std::io::Write) -> std::io::Result<()> {
+        let mut gather = Gather::default();
+        gather.copy(self.header());
+        for chunk in &self.chunks {
+            gather.copy(&chunk.head());
+            gather.add(chunk.data());
+            gather.copy(&chunk.crc().to_be_bytes());
+            if gather.is_full() {
+                gather.write(&mut writer)?;
+            }
+        }
+        gather.add(&self.trailing);
+        gather.write(&mut writer)?;
+        writer.flush()
+    }
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip_all, fields(chunks = self.chunks.len()))
+    )]
+    pub fn as_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        self.write_into(&mut bytes);
+        bytes
+    }
+}
+
+/// A chunk as it's framed in the file it was read from
+pub(crate) struct Frame<'a> {
+    pub(crate) chunk_type: ChunkType,
+    pub(crate) data: &'a [u8],
+    pub(crate) crc: u32,
+}
+
+/// The chunks of a file after its signature, each one's CRC checked, up
+/// to and including IEND; [`Frames::end`] is then where any trailing
+/// bytes start. Iteration stops after the first error
+pub(crate) struct Frames<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+    index: usize,
+    done: bool,
+}
+
+impl<'a> Frames<'a> {
+    pub(crate) fn new(bytes: &'a [u8]) -> Result<Frames<'a>, PngError> {
+        Png::check_signature(bytes)?;
+        Ok(Frames {
+            bytes,
+            offset: Png::STANDARD_HEADER.len(),
+            index: 0,
+            done: false,
+        })
+    }
+
+    /// The offset just past the last chunk read
+    pub(crate) fn end(&self) -> usize {
+        self.offset
+    }
+
+    fn frame(&mut self) -> Result<Frame<'a>, PngError> {
+        let offset = self.offset;
+        let rest = &self.bytes[offset..];
+        let Some((length, chunk_type)) = Chunk::read_head(rest) else {
+            return Err(PngError::Truncated(offset));
+        };
+        let length = length as usize;
+        if rest.len() < Chunk::OVERHEAD || rest.len() - Chunk::OVERHEAD < length {
+            return Err(PngError::Truncated(offset));
+        }
+        let chunk_type = ChunkType::try_from(chunk_type).map_err(|e| PngError::Chunk(offset, e))?;
+        // the type and data are contiguous, so the CRC needs no copy
+        let actual = crc::checksum(&rest[4..8 + length]);
+        let expected = Chunk::read_crc(rest, length).ok_or(PngError::Truncated(offset))?;
+        if actual != expected {
+            return Err(PngError::Crc(CrcMismatch {
+                index: self.index,
+                offset,
+                chunk_type,
+                length: length as u32,
+                stored: expected,
+                computed: actual,
+            }));
+        }
+        self.offset += length + Chunk::OVERHEAD;
+        self.index += 1;
+        self.done = &chunk_type.bytes() == b"IEND";
+        Ok(Frame {
+            chunk_type,
+            data: &rest[8..8 + length],
+            crc: expected,
+        })
+    }
+}
+
+impl<'a> Iterator for Frames<'a> {
+    type Item = Result<Frame<'a>, PngError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done || self.offset >= self.bytes.len() {
+            return None;
+        }
+        let frame = self.frame();
+        self.done |= frame.is_err();
+        Some(frame)
+    }
+}
+
+/// Chunk data shorter than this is copied in among the fields rather than
+/// written from where it is
+#[cfg(feature = "std")]
+const GATHER_BELOW: usize = 4 << 10;
+
+/// Bytes gathered before they're written
+#[cfg(feature = "std")]
+const GATHER: usize = 64 << 10;
+
+#[cfg(feature = "std")]
+enum Piece<'a> {
+    Gathered(core::ops::Range<usize>),
+    Data(&'a [u8]),
+}
+
+/// Pieces of a file waiting to be written: runs of bytes copied into one
+/// buffer, between data written from where it is
+#[cfg(feature = "std")]
+#[derive(Default)]
+pub(crate) struct Gather<'a> {
+    bytes: Vec<u8>,
+    pieces: Vec<Piece<'a>>,
+    /// Where the bytes not yet in a piece start
+    start: usize,
+}
+
+#[cfg(feature = "std")]
+impl<'a> Gather<'a> {
+    pub(crate) fn copy(&mut self, bytes: &[u8]) {
+        self.bytes.extend_from_slice(bytes);
+    }
+
+    /// Copy `data` if it's small, else queue it to be written in place
+    pub(crate) fn add(&mut self, data: &'a [u8]) {
+        if data.len() < GATHER_BELOW {
+            return self.copy(data);
+        }
+        self.close();
+        self.pieces.push(Piece::Data(data));
+    }
+
+    fn close(&mut self) {
+        if self.start < self.bytes.len() {
+            self.pieces
+                .push(Piece::Gathered(self.start..self.bytes.len()));
+            self.start = self.bytes.len();
+        }
+    }
+
+    pub(crate) fn is_full(&self) -> bool {
+        self.bytes.len() >= GATHER || self.pieces.len() >= crate::chunk::MAX_SLICES
+    }
+
+    pub(crate) fn write(&mut self, writer: &mut impl std::io::Write) -> std::io::Result<()> {
+        self.close();
+        let mut slices: Vec<std::io::IoSlice> = self
+            .pieces
+            .iter()
+            .map(|piece| match piece {
+                Piece::Gathered(range) => std::io::IoSlice::new(&self.bytes[range.clone()]),
+                Piece::Data(data) => std::io::IoSlice::new(data),
+            })
+            .collect();
+        crate::chunk::write_all_vectored(writer, &mut slices)?;
+        self.bytes.clear();
+        self.pieces.clear();
+        self.start = 0;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    fn chunk_from_strings(chunk_type: &str, data: &str) -> Chunk {
+        let chunk_type = ChunkType::from_str(chunk_type).unwrap();
+        Chunk::new(chunk_type, data.as_bytes().to_vec())
+    }
+
+    fn testing_png() -> Png {
+        Png::from_chunks(vec![
+            chunk_from_strings("FrSt", "I am the first chunk"),
+            chunk_from_strings("miDl", "I am another chunk"),
+            chunk_from_strings("IEND", ""),
+        ])
+    }
+
+    #[test]
+    fn test_from_chunks() {
+        let png = testing_png();
+        assert_eq!(png.chunks().len(), 3);
+    }
+
+    #[test]
+    fn test_valid_from_bytes() {
+        let bytes = testing_png().as_bytes();
+        let png = Png::try_from(bytes.as_ref()).unwrap();
+        assert_eq!(png.chunks().len(), 3);
+        assert_eq!(png.as_bytes(), bytes);
+    }
+
+    #[test]
+    fn test_invalid_header() {
+        let mut bytes = testing_png().as_bytes();
+        bytes[3] = 13;
+        let error = Png::try_from(bytes.as_ref()).unwrap_err();
+        assert_eq!(
+            error,
+            PngError::Signature {
+                offset: 3,
+                found: 13
+            }
+        );
+        assert_eq!(
+            error.to_string(),
+            "invalid png signature: byte 3 is 0x0d, not 0x47"
+        );
+        assert_eq!(
+            Png::try_from(&Png::STANDARD_HEADER[..5]).unwrap_err(),
+            PngError::InvalidHeader
+        );
+    }
+
+    #[test]
+    fn test_preamble() {
+        let bytes = testing_png().as_bytes();
+        let mut junk = b"\xef\xbb\xbfHTTP/1.1 200 OK\r\n\r\n".to_vec();
+        let preamble = junk.clone();
+        junk.extend_from_slice(&bytes);
+        let (png, skipped) = Png::parse_after_preamble(&junk, 64).unwrap();
+        assert_eq!(png.as_bytes(), bytes);
+        assert_eq!(skipped, preamble);
+
+        let (_, skipped) = Png::parse_after_preamble(&bytes, 0).unwrap();
+        assert!(skipped.is_empty());
+        assert_eq!(
+            Png::parse_after_preamble(&junk, 4).unwrap_err(),
+            PngError::Signature {
+                offset: 0,
+                found: 0xef
+            }
+        );
+    }
+
+    #[test]
+    fn test_invalid_chunk() {
+        let mut bytes = testing_png().as_bytes();
+        let last = bytes.len() - 1;
+        bytes[last] ^= 0xff;
+        assert!(matches!(
+            Png::try_from(bytes.as_ref()),
+            Err(PngError::Crc(CrcMismatch { index: 2, .. }))
+        ));
+    }
+
+    #[test]
+    fn test_parse_ignoring_crc() {
+        let mut bytes = testing_png().as_bytes();
+        bytes[20] ^= 0xff;
+        let (png, mismatches) = Png::parse_ignoring_crc(&bytes).unwrap();
+        let mismatch = mismatches[0];
+        assert_eq!(mismatches.len(), 1);
+        assert_eq!((mismatch.index, mismatch.offset), (0, 8));
+        assert_eq!(mismatch.chunk_type, *png.chunks()[0].chunk_type());
+        assert_eq!(mismatch.computed, png.chunks()[0].crc());
+        assert_eq!(png.chunks().len(), 3);
+        assert_ne!(png.chunks()[0].data(), b"I am the first chunk");
+        assert!(mismatch
+            .to_string()
+            .starts_with("crc mismatch in chunk 0 (FrSt) at offset 8 (0x8): stored 0x"));
+
+        let mut repaired = bytes.clone();
+        mismatch.repair(&mut repaired);
+        assert_eq!(
+            Png::try_from(repaired.as_ref()).unwrap().as_bytes(),
+            repaired
+        );
+    }
+
+    #[test]
+    fn test_parse_with() {
+        let chunk = |t: &str, d: &[u8]| Chunk::new(ChunkType::from_str(t).unwrap(), d.to_vec());
+        let mut png = Png::from_chunks(vec![
+            chunk("IHDR", &[0; 13]),
+            chunk("IDAT", b"data"),
+            chunk("tIME", &[0; 7]),
+            chunk("IEND", b""),
+        ]);
+        let bytes = png.as_bytes();
+        let (parsed, warnings) = Png::parse_with(&bytes, &ParseOptions::default()).unwrap();
+        assert_eq!((parsed.as_bytes(), warnings), (bytes, Vec::new()));
+
+        png.insert_chunk(3, chunk("tIME", &[0; 7]));
+        let bytes = png.as_bytes();
+        let offsets = png.chunk_offsets();
+        let mut broken = bytes[..bytes.len() - 4].to_vec();
+        broken[offsets[1] + 8] ^= 1;
+        assert!(matches!(
+            Png::parse_with(&broken, &ParseOptions::default()),
+            Err(PngError::Crc(CrcMismatch { index: 1, .. }))
+        ));
+        let lenient = ParseOptions {
+            mode: Mode::Lenient,
+            ..ParseOptions::default()
+        };
+        let (parsed, warnings) = Png::parse_with(&broken, &lenient).unwrap();
+        assert_eq!(parsed.chunks().len(), 4);
+        assert_eq!(parsed.chunks()[1].data(), b"eata");
+        assert!(matches!(
+            warnings[0],
+            Warning::Crc(CrcMismatch { index: 1, .. })
+        ));
+        let time = ChunkType::from_str("tIME").unwrap();
+        assert_eq!(
+            warnings[1..],
+            [
+                Warning::Truncated {
+                    offset: offsets[4],
+                    length: 8
+                },
+                Warning::Missing(ChunkType::from_str("IEND").unwrap()),
+                Warning::Duplicate {
+                    index: 3,
+                    offset: offsets[3],
+                    chunk_type: time
+                },
+            ]
+        );
+        assert_eq!(
+            PngError::from(warnings[3]),
+            PngError::Duplicate("tIME".to_string())
+        );
+    }
+
+    #[test]
+    fn test_resync() {
+        let chunk = |t: &str, d: &[u8]| Chunk::new(ChunkType::from_str(t).unwrap(), d.to_vec());
+        let png = Png::from_chunks(vec![
+            chunk("IHDR", &[0; 13]),
+            chunk("tEXt", b"Author\0me"),
+            chunk("IDAT", b"data"),
+            chunk("IEND", b""),
+        ]);
+        let bytes = png.as_bytes();
+        let at = png.chunk_offsets()[1];
+        let lenient = ParseOptions {
+            mode: Mode::Lenient,
+            ..ParseOptions::default()
+        };
+        let types = |png: &Png| -> Vec<String> {
+            png.chunks()
+                .iter()
+                .map(|c| c.chunk_type().to_string())
+                .collect()
+        };
+        let skipped = Warning::Skipped {
+            offset: at,
+            length: 21,
+        };
+
+        // A length past the end of the file
+        let mut broken = bytes.clone();
+        broken[at..at + 4].copy_from_slice(&1000u32.to_be_bytes());
+        let (parsed, warnings) = Png::parse_with(&broken, &lenient).unwrap();
+        assert_eq!(types(&parsed), ["IHDR", "IDAT", "IEND"]);
+        assert_eq!(warnings, [skipped]);
+        assert_eq!(
+            Png::parse_with(&broken, &ParseOptions::default()).unwrap_err(),
+            PngError::Truncated(at)
+        );
+
+        // A length too short, so the CRC fails and no chunk follows
+        broken[at..at + 4].copy_from_slice(&6u32.to_be_bytes());
+        let (parsed, warnings) = Png::parse_with(&broken, &lenient).unwrap();
+        assert_eq!(types(&parsed), ["IHDR", "IDAT", "IEND"]);
+        assert!(matches!(
+            warnings[0],
+            Warning::Crc(CrcMismatch { index: 1, .. })
+        ));
+        assert_eq!(warnings[1..], [skipped]);
+        assert_eq!(
+            skipped.to_string(),
+            "skipped 21 damaged bytes at offset 33, up to the next intact chunk"
+        );
+
+        // With nothing intact after it, the damaged chunk is kept
+        broken.truncate(at + 21);
+        let (parsed, warnings) = Png::parse_with(&broken, &lenient).unwrap();
+        assert_eq!(types(&parsed), ["IHDR", "tEXt"]);
+        assert_eq!(parsed.chunks()[1].data(), b"Author");
+        assert!(matches!(warnings[0], Warning::Crc(_)));
+    }
+
+    /// A `side` pixels square RGBA image with eight tiny zTXt chunks and
+    /// an uncompressed iTXt
+    fn text_heavy(side: u32) -> Png {
+        let chunk = |t: &str, d: &[u8]| Chunk::new(ChunkType::from_str(t).unwrap(), d.to_vec());
+        let mut ihdr = side.to_be_bytes().repeat(2);
+        ihdr.extend_from_slice(&[8, 6, 0, 0, 0]);
+        let mut chunks = vec![chunk("IHDR", &ihdr)];
+        chunks.extend((0..8).map(|_| chunk("zTXt", b"Comment\0\0\x78\x9c")));
+        chunks.push(chunk("iTXt", b"Title\0\0\0\0\0uncompressed"));
+        chunks.push(chunk("IEND", b""));
+        Png::from_chunks(chunks)
+    }
+
+    fn parse_within(bytes: &[u8], budget: Budget) -> Result<usize, PngError> {
+        let options = ParseOptions {
+            budget,
+            ..ParseOptions::default()
+        };
+        Png::parse_with(bytes, &options).map(|(png, _)| png.chunks().len())
+    }
+
+    #[test]
+    fn test_budget() {
+        let png = text_heavy(1);
+        let (bytes, offsets) = (png.as_bytes(), png.chunk_offsets());
+        assert_eq!(parse_within(&bytes, Budget::default()), Ok(11));
+        let budget = Budget {
+            chunks: 10,
+            ..Budget::UNLIMITED
+        };
+        assert_eq!(
+            parse_within(&bytes, budget),
+            Err(PngError::OverBudget(offsets[10], Resource::Chunks(10)))
+        );
+        let budget = Budget {
+            data: 13 + 11 * 4,
+            ..Budget::UNLIMITED
+        };
+        let error = parse_within(&bytes, budget).unwrap_err();
+        assert_eq!(error, PngError::OverBudget(offsets[5], Resource::Data(57)));
+        assert_eq!(
+            error.to_string(),
+            format!(
+                "chunk at offset {} takes the file past its budget of 57 bytes of chunk data",
+                offsets[5]
+            )
+        );
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_inflated_budget() {
+        // 40 GB of declared pixels
+        let bytes = text_heavy(100_000).as_bytes();
+        assert_eq!(
+            parse_within(&bytes, Budget::default()),
+            Err(PngError::OverBudget(8, Resource::Inflated(8 << 30)))
+        );
+        assert_eq!(parse_within(&bytes, Budget::UNLIMITED), Ok(11));
+
+        // Each tiny zTXt may reach a MiB, and the iTXt isn't compressed
+        let png = text_heavy(1);
+        let (bytes, offsets) = (png.as_bytes(), png.chunk_offsets());
+        let budget = Budget {
+            inflated: 5 << 20,
+            ..Budget::UNLIMITED
+        };
+        assert_eq!(
+            parse_within(&bytes, budget),
+            Err(PngError::OverBudget(
+                offsets[5],
+                Resource::Inflated(5 << 20)
+            ))
+        );
+        let budget = Budget {
+            inflated: (8 << 20) + 5,
+            ..Budget::UNLIMITED
+        };
+        assert_eq!(parse_within(&bytes, budget), Ok(11));
+    }
+
+    #[test]
+    fn test_resync_budget() {
+        // Damaged chunks that each look whole until their CRC is checked,
+        // a byte apart so none ends where the next begins
+        let mut bytes = Png::from_chunks(vec![chunk_from_strings("IHDR", "")]).as_bytes();
+        for _ in 0..4 {
+            bytes.extend_from_slice(&1000u32.to_be_bytes());
+            bytes.extend_from_slice(b"abcd");
+            bytes.extend_from_slice(&[0; 1004]);
+            bytes.push(0xff);
+        }
+        let iend = bytes.len();
+        bytes.extend_from_slice(
+            &Chunk::new(ChunkType::from_str("IEND").unwrap(), vec![]).as_bytes(),
+        );
+        let lenient = |budget| ParseOptions {
+            mode: Mode::Lenient,
+            budget,
+        };
+
+        let (png, offsets, _) =
+            Png::parse_with_offsets(&bytes, &lenient(Budget::UNLIMITED)).unwrap();
+        assert_eq!(offsets.last(), Some(&iend));
+        assert_eq!(png.chunks().len(), 2);
+        let budget = Budget {
+            data: 2500,
+            ..Budget::UNLIMITED
+        };
+        assert!(matches!(
+            Png::parse_with_offsets(&bytes, &lenient(budget)),
+            Err(PngError::OverBudget(_, Resource::Data(2500)))
+        ));
+    }
+
+    #[test]
+    fn test_truncated() {
+        let bytes = testing_png().as_bytes();
+        let bytes = &bytes[..bytes.len() - 3];
+        assert!(matches!(Png::try_from(bytes), Err(PngError::Truncated(_))));
+    }
+
+    #[test]
+    fn test_append_chunk_before_iend() {
+        let mut png = testing_png();
+        png.append_chunk(chunk_from_strings("TeSt", "Message"));
+        let types: Vec<String> = png
+            .chunks()
+            .iter()
+            .map(|c| c.chunk_type().to_string())
+            .collect();
+        assert_eq!(types, ["FrSt", "miDl", "TeSt", "IEND"]);
+    }
+
+    #[test]
+    fn test_add_chunk() {
+        let mut png = testing_png();
+        let time = |second| chunk_from_strings("tIME", second);
+        assert_eq!(png.add_chunk(time("1"), OnDuplicate::Refuse), Ok(None));
+        assert_eq!(
+            png.add_chunk(time("2"), OnDuplicate::Refuse),
+            Err(PngError::Duplicate("tIME".to_string()))
+        );
+        assert_eq!(
+            png.add_chunk(time("3"), OnDuplicate::Replace),
+            Ok(Some(time("1")))
+        );
+        png.add_chunk(chunk_from_strings("tEXt", "a\0b"), OnDuplicate::Refuse)
+            .unwrap();
+        png.add_chunk(chunk_from_strings("tEXt", "c\0d"), OnDuplicate::Refuse)
+            .unwrap();
+        let types: Vec<String> = png
+            .chunks()
+            .iter()
+            .map(|c| c.chunk_type().to_string())
+            .collect();
+        assert_eq!(types, ["FrSt", "miDl", "tIME", "tEXt", "tEXt", "IEND"]);
+        assert_eq!(png.chunks()[2].data(), b"3");
+    }
+
+    #[test]
+    fn test_remove_first_chunk() {
+        let mut png = testing_png();
+        let removed = png.remove_first_chunk("miDl").unwrap();
+        assert_eq!(removed.data_as_string().unwrap(), "I am another chunk");
+        assert!(png.chunk_by_type("miDl").is_none());
+        assert!(png.remove_first_chunk("miDl").is_err());
+    }
+
+    #[test]
+    fn test_empty_chunks() {
+        let empty = |t: &str| Chunk::new(ChunkType::from_str(t).unwrap(), Vec::new());
+        let png = Png::from_chunks(vec![
+            empty("IHDR"),
+            empty("sRGB"),
+            empty("IDAT"),
+            empty("IEND"),
+        ]);
+        let bytes = png.as_bytes();
+        assert_eq!(bytes.len(), 8 + 4 * Chunk::OVERHEAD);
+        let parsed = Png::try_from(bytes.as_slice()).unwrap();
+        assert_eq!(parsed.chunks().len(), 4);
+        assert!(parsed.chunks().iter().all(|c| c.length() == 0));
+        assert_eq!(parsed.as_bytes(), bytes);
+        assert_eq!(parsed.chunk_offsets(), [8, 20, 32, 44]);
+    }
+
+    #[test]
+    fn test_chunk_offsets() {
+        let png = testing_png();
+        assert_eq!(png.chunk_offsets(), [8, 40, 70]);
+    }
+
+    #[test]
+    fn test_rename_chunks() {
+        let mut png = testing_png();
+        png.append_chunk(chunk_from_strings("miDl", "Another one"));
+        let renamed = png
+            .rename_chunks("miDl", ChunkType::from_str("ruSt").unwrap())
+            .unwrap();
+        assert_eq!(renamed, 2);
+        assert!(png.chunk_by_type("miDl").is_none());
+        assert_eq!(
+            png.chunk_by_type("ruSt").unwrap().data_as_string().unwrap(),
+            "I am another chunk"
+        );
+        let bytes = png.as_bytes();
+        assert!(Png::try_from(bytes.as_ref()).is_ok());
+    }
+
+    #[test]
+    fn test_rename_missing_chunk() {
+        let mut png = testing_png();
+        let result = png.rename_chunks("haHa", ChunkType::from_str("ruSt").unwrap());
+        assert_eq!(result, Err(PngError::ChunkNotFound("haHa".to_string())));
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn test_reorder() {
+        let mut png = Png::from_chunks(vec![
+            chunk_from_strings("IHDR", ""),
+            chunk_from_strings("IDAT", ""),
+            chunk_from_strings("tEXt", "Title\0x"),
+            chunk_from_strings("IEND", ""),
+        ]);
+        png.reorder().unwrap();
+        let types: Vec<String> = png
+            .chunks()
+            .iter()
+            .map(|c| c.chunk_type().to_string())
+            .collect();
+        assert_eq!(types, ["IHDR", "tEXt", "IDAT", "IEND"]);
+
+        png.append_chunk(chunk_from_strings("acTL", ""));
+        assert_eq!(png.reorder(), Err(PngError::Animated));
+    }
+
+    #[test]
+    fn test_replace_chunks() {
+        let mut png = testing_png();
+        png.append_chunk(chunk_from_strings("miDl", "Another one"));
+        png.replace_chunks("miDl", vec![chunk_from_strings("miDl", "Merged")]);
+        let data: Vec<String> = png
+            .chunks()
+            .iter()
+            .map(|c| c.data_as_string().unwrap())
+            .collect();
+        assert_eq!(data, ["I am the first chunk", "Merged", ""]);
+    }
+
+    #[test]
+    fn test_chunks_inline_until_many() {
+        let mut png = Png::try_from(testing_png().as_bytes().as_slice()).unwrap();
+        assert!(!png.chunks.spilled());
+        for _ in 0..INLINE_CHUNKS {
+            png.insert_chunk(0, chunk_from_strings("miDl", ""));
+        }
+        assert!(png.chunks.spilled());
+        assert_eq!(png.chunks().len(), INLINE_CHUNKS + 3);
+    }
+
+    #[test]
+    fn test_write_into() {
+        let mut png = testing_png();
+        png.set_trailing(b"after".to_vec());
+        let bytes = png.as_bytes();
+        assert_eq!(bytes.len(), png.encoded_len());
+        assert_eq!(bytes.capacity(), png.encoded_len());
+
+        let mut out = b"x".to_vec();
+        png.write_into(&mut out);
+        assert_eq!(out[0], b'x');
+        assert_eq!(out[1..], bytes);
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn test_write_to() {
+        /// Takes at most three bytes a call, to exercise partial writes
+        struct Trickle(Vec<u8>);
+        impl std::io::Write for Trickle {
+            fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+                let n = buf.len().min(3);
+                self.0.extend_from_slice(&buf[..n]);
+                Ok(n)
+            }
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+        let mut png = testing_png();
+        let large = Chunk::new(ChunkType::from_str("laRg").unwrap(), vec![7; GATHER]);
+        png.insert_chunk(1, large);
+        let mut out = Vec::new();
+        png.write_to(&mut out).unwrap();
+        assert_eq!(out, png.as_bytes());
+        let mut trickle = Trickle(Vec::new());
+        png.write_to(&mut trickle).unwrap();
+        assert_eq!(trickle.0, out);
+    }
+
+    #[test]
+    fn test_trailing_data() {
+        let mut bytes = testing_png().as_bytes();
+        let end = bytes.len();
+        bytes.extend_from_slice(b"not a chunk");
+        let png = Png::try_from(bytes.as_ref()).unwrap();
+        assert_eq!(png.chunks().len(), 3);
+        assert_eq!(png.trailing(), b"not a chunk");
+        assert_eq!(png.trailing_offset(), end);
+        assert_eq!(png.as_bytes(), bytes);
+        let warning = Warning::TrailingData {
+            offset: end,
+            length: 11,
+        };
+        assert_eq!(png.warnings(), [warning]);
+        assert_eq!(
+            warning.to_string(),
+            format!("11 bytes after IEND at offset {}", end)
+        );
+        assert_eq!(
+            Png::parse_strict(&bytes).unwrap_err(),
+            PngError::TrailingData {
+                offset: end,
+                length: 11
+            }
+        );
+
+        let mut png = png;
+        assert_eq!(png.take_trailing(), b"not a chunk");
+        assert!(png.warnings().is_empty());
+        assert_eq!(
+            Png::parse_strict(&png.as_bytes()).unwrap().as_bytes(),
+            &bytes[..end]
+        );
+    }
+
+    #[test]
+    fn test_chunk_by_type() {
+        let png = testing_png();
+        let chunk = png.chunk_by_type("FrSt").unwrap();
+        assert_eq!(chunk.data_as_string().unwrap(), "I am the first chunk");
+    }
+}
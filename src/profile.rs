@@ -0,0 +1,198 @@
+//! ImageMagick's raw profiles: binary metadata such as EXIF and IPTC kept
+//! as hex in a text chunk with the keyword `Raw profile type <name>`, from
+//! before PNG had an eXIf chunk
+
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::png::Png;
+use crate::text::{self, TextChunk};
+
+/// What comes before the TIFF data of an EXIF profile, as in JPEG's APP1
+pub const EXIF_HEADER: &[u8; 6] = b"Exif\0\0";
+
+/// Hex digits per line, as ImageMagick writes them
+const LINE_LEN: usize = 72;
+
+/// Keyword of an ImageMagick raw profile chunk, which carries a binary
+/// profile as hex text
+pub fn keyword(name: &str) -> String {
+    format!("Raw profile type {}", name)
+}
+
+/// Chunk text for `data`: the profile name, its length and the hex lines
+pub fn encode(name: &str, data: &[u8]) -> String {
+    let hex: String = data.iter().map(|b| format!("{:02x}", b)).collect();
+    let mut text = format!("\n{}\n{:8}\n", name, data.len());
+    for line in hex.as_bytes().chunks(LINE_LEN) {
+        text.push_str(std::str::from_utf8(line).unwrap());
+        text.push('\n');
+    }
+    text
+}
+
+/// Profile name and data from chunk text, or `None` if it's malformed or
+/// shorter than its recorded length
+pub fn decode(text: &str) -> Option<(String, Vec<u8>)> {
+    let mut lines = text.trim_start_matches('\n').splitn(3, '\n');
+    let name = lines.next()?.trim().to_string();
+    let length: usize = lines.next()?.trim().parse().ok()?;
+    let digits: Vec<u8> = lines
+        .next()?
+        .bytes()
+        .filter(|b| !b.is_ascii_whitespace())
+        .collect();
+    if digits.len() < length * 2 {
+        return None;
+    }
+    let data = digits[..length * 2]
+        .chunks(2)
+        .map(|pair| u8::from_str_radix(std::str::from_utf8(pair).ok()?, 16).ok())
+        .collect::<Option<Vec<u8>>>()?;
+    Some((name, data))
+}
+
+fn find(png: &Png, name: &str) -> Option<usize> {
+    let keyword = keyword(name);
+    png.chunks()
+        .iter()
+        .position(|c| text::has_keyword(c, &keyword))
+}
+
+/// The data of the `name` profile, or `None` if there's no well-formed one
+pub fn read(png: &Png, name: &str) -> Option<Vec<u8>> {
+    let text = TextChunk::parse(&png.chunks()[find(png, name)?])?.ok()?;
+    decode(&text.text).map(|(_, data)| data)
+}
+
+/// Store `data` as the `name` profile, in a zTXt chunk as ImageMagick
+/// writes them, in place of any there already
+pub fn write(png: &mut Png, name: &str, data: &[u8]) {
+    let at = find(png, name);
+    remove(png, name);
+    let text = TextChunk {
+        keyword: keyword(name),
+        text: encode(name, data),
+    };
+    png.insert_chunk(at.unwrap_or(usize::MAX), text.to_ztxt());
+}
+
+/// Drop the `name` profile, returning whether there was one
+pub fn remove(png: &mut Png, name: &str) -> bool {
+    png.remove_text(&keyword(name)) > 0
+}
+
+/// Whether `data` starts with a TIFF header, as EXIF does
+fn is_tiff(data: &[u8]) -> bool {
+    data.starts_with(b"II*\0") || data.starts_with(b"MM\0*")
+}
+
+/// The TIFF data of an `exif` or `APP1` profile, without [`EXIF_HEADER`]
+fn profile_exif(png: &Png) -> Option<Vec<u8>> {
+    ["exif", "APP1"].iter().find_map(|name| {
+        let data = read(png, name)?;
+        let tiff = data.strip_prefix(EXIF_HEADER).unwrap_or(&data);
+        is_tiff(tiff).then(|| tiff.to_vec())
+    })
+}
+
+/// The file's EXIF as TIFF data, from its eXIf chunk or else from a raw
+/// profile
+pub fn exif(png: &Png) -> Option<Vec<u8>> {
+    match png.chunk_by_type("eXIf") {
+        Some(chunk) => Some(chunk.data().to_vec()),
+        None => profile_exif(png),
+    }
+}
+
+/// Move EXIF from a raw profile into an eXIf chunk before the image data,
+/// replacing any eXIf there was. Returns whether there was a profile to
+/// convert
+pub fn exif_to_chunk(png: &mut Png) -> bool {
+    let Some(tiff) = profile_exif(png) else {
+        return false;
+    };
+    remove(png, "exif");
+    remove(png, "APP1");
+    let chunk = Chunk::new(ChunkType::new(*b"eXIf"), tiff);
+    png.retain_chunks(|c| c.chunk_type().as_str() != "eXIf");
+    let idat = png
+        .chunks()
+        .iter()
+        .position(|c| c.chunk_type().as_str() == "IDAT");
+    png.insert_chunk(idat.unwrap_or(usize::MAX), chunk);
+    true
+}
+
+/// Move the eXIf chunk into an `exif` raw profile, for readers that only
+/// know ImageMagick's convention. Returns whether there was one
+pub fn exif_to_profile(png: &mut Png) -> bool {
+    let Ok(chunk) = png.remove_first_chunk("eXIf") else {
+        return false;
+    };
+    png.retain_chunks(|c| c.chunk_type().as_str() != "eXIf");
+    let data = [&EXIF_HEADER[..], chunk.data()].concat();
+    write(png, "exif", &data);
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pixels::tests::testing_png;
+
+    #[test]
+    fn test_round_trip() {
+        let data: Vec<u8> = (0..=255).collect();
+        let text = encode("iptc", &data);
+        assert!(text.starts_with("\niptc\n     256\n"));
+        assert!(text.lines().skip(3).all(|line| line.len() <= LINE_LEN));
+        assert_eq!(decode(&text), Some(("iptc".to_string(), data)));
+    }
+
+    fn types(png: &Png) -> Vec<String> {
+        png.chunks()
+            .iter()
+            .map(|c| c.chunk_type().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_exif_conversion() {
+        let tiff = b"MM\0*\0\0\0\x08\0\0".to_vec();
+        let mut png = testing_png(4, 4);
+        assert_eq!(exif(&png), None);
+        png.append_chunk(Chunk::new(
+            ChunkType::try_from(*b"tEXt").unwrap(),
+            b"a\0b".to_vec(),
+        ));
+        write(&mut png, "exif", &[&EXIF_HEADER[..], &tiff].concat());
+        write(&mut png, "iptc", b"\x1c\x02\x00\x00\x02\x00\x04");
+        assert_eq!(exif(&png), Some(tiff.clone()));
+        assert_eq!(read(&png, "iptc").unwrap(), b"\x1c\x02\x00\x00\x02\x00\x04");
+
+        assert!(exif_to_chunk(&mut png));
+        assert_eq!(
+            types(&png),
+            ["IHDR", "eXIf", "IDAT", "tEXt", "zTXt", "IEND"]
+        );
+        assert_eq!(png.chunk_by_type("eXIf").unwrap().data(), tiff);
+        assert!(!exif_to_chunk(&mut png));
+
+        assert!(exif_to_profile(&mut png));
+        assert_eq!(
+            types(&png),
+            ["IHDR", "IDAT", "tEXt", "zTXt", "zTXt", "IEND"]
+        );
+        assert_eq!(exif(&png), Some(tiff));
+        assert!(!exif_to_profile(&mut png));
+        assert!(remove(&mut png, "exif") && !remove(&mut png, "exif"));
+        assert_eq!(read(&png, "exif"), None);
+    }
+
+    #[test]
+    fn test_decode_malformed() {
+        assert_eq!(decode("\nexif\n       4\nabcd\n"), None);
+        assert_eq!(decode("\nexif\nfour\n00000000\n"), None);
+        assert_eq!(decode("\nexif\n       2\nzzzz\n"), None);
+    }
+}
@@ -0,0 +1,151 @@
+//! Python extension module, built with maturin. `Png` and `Chunk` wrap the
+//! library types, and failures are raised as `ValueError`s
+
+use std::str::FromStr;
+
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+use pyo3::types::PyBytes;
+
+use crate::chunk;
+use crate::chunk_type::ChunkType;
+use crate::message;
+use crate::png;
+
+fn value_error(e: impl ToString) -> PyErr {
+    PyValueError::new_err(e.to_string())
+}
+
+fn chunk_type(name: &str) -> PyResult<ChunkType> {
+    ChunkType::from_str(name).map_err(value_error)
+}
+
+#[pyclass(name = "Chunk", module = "ping", frozen, skip_from_py_object)]
+#[derive(Clone)]
+pub struct Chunk(chunk::Chunk);
+
+#[pymethods]
+impl Chunk {
+    #[new]
+    fn new(chunk_type: &str, data: Vec<u8>) -> PyResult<Chunk> {
+        Ok(Chunk(chunk::Chunk::new(
+            self::chunk_type(chunk_type)?,
+            data,
+        )))
+    }
+
+    #[getter]
+    fn chunk_type(&self) -> String {
+        self.0.chunk_type().to_string()
+    }
+
+    #[getter]
+    fn data<'py>(&self, py: Python<'py>) -> Bound<'py, PyBytes> {
+        PyBytes::new(py, self.0.data())
+    }
+
+    #[getter]
+    fn length(&self) -> u32 {
+        self.0.length()
+    }
+
+    #[getter]
+    fn crc(&self) -> u32 {
+        self.0.crc()
+    }
+
+    fn __repr__(&self) -> String {
+        format!(
+            "Chunk({:?}, length={}, crc={:#010x})",
+            self.0.chunk_type().to_string(),
+            self.0.length(),
+            self.0.crc()
+        )
+    }
+}
+
+#[pyclass(name = "Png", module = "ping")]
+pub struct Png(png::Png);
+
+#[pymethods]
+impl Png {
+    #[new]
+    fn new(bytes: &[u8]) -> PyResult<Png> {
+        Ok(Png(png::Png::try_from(bytes).map_err(value_error)?))
+    }
+
+    #[staticmethod]
+    fn open(path: std::path::PathBuf) -> PyResult<Png> {
+        Png::new(&std::fs::read(path)?)
+    }
+
+    fn chunks(&self) -> Vec<Chunk> {
+        self.0.chunks().iter().cloned().map(Chunk).collect()
+    }
+
+    fn to_bytes<'py>(&self, py: Python<'py>) -> Bound<'py, PyBytes> {
+        PyBytes::new(py, &self.0.as_bytes())
+    }
+
+    fn save(&self, path: std::path::PathBuf) -> PyResult<()> {
+        Ok(std::fs::write(path, self.0.as_bytes())?)
+    }
+
+    /// Add `message` in a new chunk, encrypted under `password` if given
+    #[pyo3(signature = (chunk_type, message, password=None))]
+    fn encode(&mut self, chunk_type: &str, message: &[u8], password: Option<&str>) -> PyResult<()> {
+        message::embed(
+            &mut self.0,
+            self::chunk_type(chunk_type)?,
+            message,
+            password,
+        )
+        .map_err(value_error)
+    }
+
+    /// Read the message in the chunks of `chunk_type`
+    #[pyo3(signature = (chunk_type, password=None))]
+    fn decode<'py>(
+        &self,
+        py: Python<'py>,
+        chunk_type: &str,
+        password: Option<&str>,
+    ) -> PyResult<Bound<'py, PyBytes>> {
+        let message = message::extract(&self.0, &self::chunk_type(chunk_type)?, password)
+            .map_err(value_error)?;
+        Ok(PyBytes::new(py, &message))
+    }
+
+    /// Drop ancillary chunks that don't affect rendering and any trailing
+    /// data, returning how many chunks went
+    fn strip(&mut self) -> usize {
+        message::strip(&mut self.0)
+    }
+
+    /// Remove and return the first chunk of `chunk_type`
+    fn remove(&mut self, chunk_type: &str) -> PyResult<Chunk> {
+        Ok(Chunk(
+            self.0.remove_first_chunk(chunk_type).map_err(value_error)?,
+        ))
+    }
+
+    /// Add `chunk` before IEND
+    fn append(&mut self, chunk: &Chunk) {
+        self.0.append_chunk(chunk.0.clone());
+    }
+
+    fn __len__(&self) -> usize {
+        self.0.chunks().len()
+    }
+
+    fn __str__(&self) -> String {
+        self.0.to_string()
+    }
+}
+
+#[pymodule]
+fn ping(module: &Bound<'_, PyModule>) -> PyResult<()> {
+    module.add_class::<Png>()?;
+    module.add_class::<Chunk>()?;
+    Ok(())
+}
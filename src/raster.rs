@@ -0,0 +1,141 @@
+//! Conversion to and from the image crate's `DynamicImage`, for editing
+//! pixels there and chunks here without a temporary file
+
+use std::fmt::Display;
+
+use image::codecs::png::{CompressionType, FilterType, PngEncoder};
+use image::{DynamicImage, ImageError, ImageFormat};
+
+use crate::chunk::Chunk;
+use crate::png::{Png, PngError};
+
+/// Ancillary chunks that describe the pixel format or animation, which
+/// can't carry over once the image crate has re-encoded the pixels
+const PIXEL_BOUND: [&str; 7] = ["tRNS", "sBIT", "bKGD", "hIST", "acTL", "fcTL", "fdAT"];
+
+#[derive(Debug)]
+pub enum RasterError {
+    Image(ImageError),
+    Png(PngError),
+}
+
+impl Display for RasterError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RasterError::Image(e) => write!(f, "{}", e),
+            RasterError::Png(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for RasterError {}
+
+impl From<ImageError> for RasterError {
+    fn from(e: ImageError) -> Self {
+        RasterError::Image(e)
+    }
+}
+
+impl From<PngError> for RasterError {
+    fn from(e: PngError) -> Self {
+        RasterError::Png(e)
+    }
+}
+
+/// How [`Png::from_dynamic_image`] encodes an image
+#[derive(Debug, Clone, Default)]
+pub struct ImageOptions {
+    pub compression: CompressionType,
+    pub filter: FilterType,
+    /// Chunks to add to the encoded file, in canonical order; see
+    /// [`metadata`]. Critical chunks and those tied to the old pixel
+    /// format or animation are skipped
+    pub metadata: Vec<Chunk>,
+}
+
+/// The ancillary chunks of `png` worth keeping when its pixels are
+/// replaced: text, EXIF, colour space and the like
+pub fn metadata(png: &Png) -> Vec<Chunk> {
+    png.chunks()
+        .iter()
+        .filter(|c| carries_over(c))
+        .cloned()
+        .collect()
+}
+
+fn carries_over(chunk: &Chunk) -> bool {
+    !chunk.chunk_type().is_critical() && !PIXEL_BOUND.contains(&chunk.chunk_type().as_str())
+}
+
+impl Png {
+    /// Decode the pixels
+    pub fn to_dynamic_image(&self) -> Result<DynamicImage, RasterError> {
+        Ok(image::load_from_memory_with_format(
+            &self.as_bytes(),
+            ImageFormat::Png,
+        )?)
+    }
+
+    /// Encode `image` as a new file, adding the chunks in
+    /// `options.metadata`
+    pub fn from_dynamic_image(
+        image: &DynamicImage,
+        options: ImageOptions,
+    ) -> Result<Png, RasterError> {
+        let mut bytes = Vec::new();
+        image.write_with_encoder(PngEncoder::new_with_quality(
+            &mut bytes,
+            options.compression,
+            options.filter,
+        ))?;
+        let mut png = Png::try_from(bytes.as_slice())?;
+        let metadata = options.metadata.into_iter().filter(carries_over);
+        metadata.for_each(|c| png.append_chunk(c));
+        png.reorder()?;
+        Ok(png)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk_type::ChunkType;
+    use crate::pixels::tests::testing_png;
+    use std::str::FromStr;
+
+    fn chunk(chunk_type: &str, data: &[u8]) -> Chunk {
+        Chunk::new(ChunkType::from_str(chunk_type).unwrap(), data.to_vec())
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let mut png = testing_png(6, 7);
+        png.append_chunk(chunk("tEXt", b"Comment\0kept"));
+        png.append_chunk(chunk("gAMA", &[0, 0, 177, 143]));
+        png.append_chunk(chunk("tRNS", &[0, 0, 0, 0, 0, 0]));
+
+        let image = png.to_dynamic_image().unwrap();
+        assert_eq!((image.width(), image.height()), (6, 7));
+        assert_eq!(image.as_rgb8().unwrap().get_pixel(3, 0).0, [63, 70, 77]);
+
+        let options = ImageOptions {
+            metadata: metadata(&png),
+            ..ImageOptions::default()
+        };
+        let encoded = Png::from_dynamic_image(&image, options).unwrap();
+        let types: Vec<String> = encoded
+            .chunks()
+            .iter()
+            .map(|c| c.chunk_type().to_string())
+            .filter(|t| t != "IDAT")
+            .collect();
+        assert_eq!(types, ["IHDR", "gAMA", "tEXt", "IEND"]);
+        assert_eq!(encoded.to_dynamic_image().unwrap(), image);
+    }
+
+    #[test]
+    fn test_invalid_image() {
+        let png = Png::from_chunks(vec![chunk("IHDR", &[0; 13]), chunk("IEND", b"")]);
+        assert!(matches!(png.to_dynamic_image(), Err(RasterError::Image(_))));
+    }
+}
@@ -0,0 +1,87 @@
+use std::path::Path;
+
+use x25519_dalek::{PublicKey, StaticSecret};
+
+use crate::crypto::{self, CryptoError, KEY_LEN, NONCE_LEN};
+use crate::sign;
+
+/// Key derivation context for the per-recipient key wrapping keys
+const WRAP_CONTEXT: &str = "ping 2023 recipient file key wrap";
+
+/// An encrypted file key: AES-GCM tag included
+pub const STANZA_LEN: usize = KEY_LEN + crypto::TAG_LEN;
+
+/// Nonce for wrapping file keys; every wrapping key is used exactly once,
+/// being derived from a fresh ephemeral key
+const WRAP_NONCE: [u8; NONCE_LEN] = [0; NONCE_LEN];
+
+/// A fresh secret key from the system random number generator
+pub fn generate() -> Result<StaticSecret, CryptoError> {
+    Ok(StaticSecret::from(crypto::random::<32>()?))
+}
+
+/// Read a raw 32-byte or hex-encoded X25519 public key
+pub fn read_public_key(path: &Path) -> crate::Result<PublicKey> {
+    Ok(PublicKey::from(sign::key_bytes(&std::fs::read(path)?)?))
+}
+
+/// Read a raw 32-byte or hex-encoded X25519 secret key
+pub fn read_secret_key(path: &Path) -> crate::Result<StaticSecret> {
+    Ok(StaticSecret::from(sign::key_bytes(&std::fs::read(path)?)?))
+}
+
+fn wrap_key(shared: &[u8; 32], ephemeral: &PublicKey, recipient: &PublicKey) -> [u8; KEY_LEN] {
+    let material: Vec<u8> = [&shared[..], ephemeral.as_bytes(), recipient.as_bytes()].concat();
+    blake3::derive_key(WRAP_CONTEXT, &material)
+}
+
+/// Encrypt `file_key` to `recipient` from the sender's ephemeral key
+pub fn wrap(
+    file_key: &[u8; KEY_LEN],
+    ephemeral: &StaticSecret,
+    recipient: &PublicKey,
+) -> Result<Vec<u8>, CryptoError> {
+    let shared = ephemeral.diffie_hellman(recipient);
+    let key = wrap_key(shared.as_bytes(), &PublicKey::from(ephemeral), recipient);
+    crypto::encrypt(&key, &WRAP_NONCE, file_key, &[])
+}
+
+/// Recover the file key from a stanza addressed to `identity`, or `None`
+/// if the stanza is for someone else
+pub fn unwrap(
+    stanza: &[u8],
+    ephemeral: &PublicKey,
+    identity: &StaticSecret,
+) -> Option<[u8; KEY_LEN]> {
+    let shared = identity.diffie_hellman(ephemeral);
+    let key = wrap_key(shared.as_bytes(), ephemeral, &PublicKey::from(identity));
+    crypto::decrypt(&key, &WRAP_NONCE, stanza, &[])
+        .ok()?
+        .try_into()
+        .ok()
+}
+
+/// Lowercase hex, the format written by `keygen` and read back by the key
+/// readers
+pub fn to_hex(bytes: &[u8; 32]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wrap_unwrap() {
+        let ephemeral = StaticSecret::from([1; 32]);
+        let alice = StaticSecret::from([2; 32]);
+        let bob = StaticSecret::from([3; 32]);
+        let file_key = [9; KEY_LEN];
+
+        let stanza = wrap(&file_key, &ephemeral, &PublicKey::from(&alice)).unwrap();
+        assert_eq!(stanza.len(), STANZA_LEN);
+        let ephemeral = PublicKey::from(&ephemeral);
+        assert_eq!(unwrap(&stanza, &ephemeral, &alice), Some(file_key));
+        assert_eq!(unwrap(&stanza, &ephemeral, &bob), None);
+    }
+}
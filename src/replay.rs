@@ -0,0 +1,193 @@
+use std::fmt::Display;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+use crate::checksum::to_hex;
+
+/// Whether a logged payload was written or read here
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Encoded,
+    Decoded,
+}
+
+impl Direction {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Direction::Encoded => "encoded",
+            Direction::Decoded => "decoded",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Entry {
+    direction: Direction,
+    /// [`payload::nonce_id`](crate::payload::nonce_id) of the payload
+    nonce: String,
+    /// BLAKE3 hash of the whole payload
+    content: String,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ReplayError {
+    /// A different payload was sealed with the same key and nonce, which
+    /// breaks AES-GCM's guarantees
+    NonceReuse,
+    /// This exact payload was decoded before
+    Replayed,
+    InvalidLog(usize),
+}
+
+impl Display for ReplayError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ReplayError::NonceReuse => {
+                write!(
+                    f,
+                    "another payload was encrypted with the same key and nonce"
+                )
+            }
+            ReplayError::Replayed => write!(f, "payload replayed: it was decoded before"),
+            ReplayError::InvalidLog(line) => write!(f, "invalid nonce log line {}", line),
+        }
+    }
+}
+
+impl std::error::Error for ReplayError {}
+
+/// Key and nonce combinations seen by encode and decode, one
+/// `<direction> <nonce id> <content hash>` line each
+#[derive(Debug, Default)]
+pub struct NonceLog {
+    path: PathBuf,
+    entries: Vec<Entry>,
+}
+
+impl NonceLog {
+    /// Read the log at `path`; a missing file is an empty log
+    pub fn open(path: &Path) -> crate::Result<NonceLog> {
+        let text = match fs::read_to_string(path) {
+            Err(e) if e.kind() == ErrorKind::NotFound => String::new(),
+            text => text?,
+        };
+        Ok(NonceLog {
+            path: path.to_path_buf(),
+            entries: parse(&text)?,
+        })
+    }
+
+    /// Log a payload, refusing one that reuses a logged key and nonce for
+    /// different content and, when decoding, one decoded before
+    pub fn record(
+        &mut self,
+        direction: Direction,
+        nonce: &[u8; 32],
+        payload: &[u8],
+    ) -> Result<(), ReplayError> {
+        let entry = Entry {
+            direction,
+            nonce: to_hex(nonce),
+            content: blake3::hash(payload).to_hex().to_string(),
+        };
+        for seen in self.entries.iter().filter(|e| e.nonce == entry.nonce) {
+            if seen.content != entry.content {
+                return Err(ReplayError::NonceReuse);
+            }
+            if seen.direction == Direction::Decoded && direction == Direction::Decoded {
+                return Err(ReplayError::Replayed);
+            }
+        }
+        if !self.entries.contains(&entry) {
+            self.entries.push(entry);
+        }
+        Ok(())
+    }
+
+    pub fn save(&self) -> std::io::Result<()> {
+        fs::write(&self.path, self.to_string())
+    }
+}
+
+impl Display for NonceLog {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for entry in &self.entries {
+            writeln!(
+                f,
+                "{} {} {}",
+                entry.direction.as_str(),
+                entry.nonce,
+                entry.content
+            )?;
+        }
+        Ok(())
+    }
+}
+
+fn parse(text: &str) -> Result<Vec<Entry>, ReplayError> {
+    text.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(index, line)| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let direction = match fields.first() {
+                Some(&"encoded") => Direction::Encoded,
+                Some(&"decoded") => Direction::Decoded,
+                _ => return Err(ReplayError::InvalidLog(index + 1)),
+            };
+            match fields[1..] {
+                [nonce, content] => Ok(Entry {
+                    direction,
+                    nonce: nonce.to_string(),
+                    content: content.to_string(),
+                }),
+                _ => Err(ReplayError::InvalidLog(index + 1)),
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_replay_detected() {
+        let mut log = NonceLog::default();
+        log.record(Direction::Encoded, &[1; 32], b"payload")
+            .unwrap();
+        log.record(Direction::Encoded, &[1; 32], b"payload")
+            .unwrap();
+        log.record(Direction::Decoded, &[1; 32], b"payload")
+            .unwrap();
+        assert_eq!(
+            log.record(Direction::Decoded, &[1; 32], b"payload"),
+            Err(ReplayError::Replayed)
+        );
+    }
+
+    #[test]
+    fn test_nonce_reuse_refused() {
+        let mut log = NonceLog::default();
+        log.record(Direction::Encoded, &[1; 32], b"first").unwrap();
+        assert_eq!(
+            log.record(Direction::Encoded, &[1; 32], b"second"),
+            Err(ReplayError::NonceReuse)
+        );
+        assert_eq!(
+            log.record(Direction::Decoded, &[1; 32], b"second"),
+            Err(ReplayError::NonceReuse)
+        );
+        log.record(Direction::Encoded, &[2; 32], b"second").unwrap();
+    }
+
+    #[test]
+    fn test_log_round_trip() {
+        let mut log = NonceLog::default();
+        log.record(Direction::Encoded, &[1; 32], b"first").unwrap();
+        log.record(Direction::Decoded, &[2; 32], b"second").unwrap();
+        assert_eq!(parse(&log.to_string()), Ok(log.entries));
+        assert_eq!(parse("encoded abc\n"), Err(ReplayError::InvalidLog(1)));
+    }
+}
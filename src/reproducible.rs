@@ -0,0 +1,115 @@
+//! Output that depends only on the input, so a build embedding metadata
+//! writes the same bytes every time: chunks in canonical order and tIME
+//! pinned to a given moment or dropped. The time is meant to come from
+//! `SOURCE_DATE_EPOCH`, as <https://reproducible-builds.org> specifies
+//!
+//! Compression is already at fixed levels, but the codec's output still
+//! differs between deflate backends and with zstd between one thread and
+//! several, so reproducing a file takes the same build and thread count
+
+use alloc::vec::Vec;
+
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::png::{Png, PngError};
+
+/// The environment variable reproducible builds set to the time their
+/// outputs should record, in seconds since the Unix epoch
+pub const SOURCE_DATE_EPOCH: &str = "SOURCE_DATE_EPOCH";
+
+/// A tIME chunk for `epoch` seconds after 1970-01-01 00:00:00 UTC
+pub fn time_chunk(epoch: u64) -> Chunk {
+    let (days, seconds) = (epoch / 86_400, epoch % 86_400);
+    // Days to a civil date, after Howard Hinnant's days_from_civil inverse,
+    // counting years from a March 1 so leap days fall last
+    let days = days + 719_468;
+    let era = days / 146_097;
+    let day_of_era = days % 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    };
+    let year = year_of_era + era * 400 + u64::from(month <= 2);
+
+    let mut data = Vec::with_capacity(7);
+    data.extend_from_slice(&(year.min(u16::MAX as u64) as u16).to_be_bytes());
+    data.extend([month, day, seconds / 3600, seconds / 60 % 60, seconds % 60].map(|v| v as u8));
+    Chunk::new(ChunkType::new(*b"tIME"), data)
+}
+
+/// Put `png`'s chunks in canonical order and replace any tIME by one for
+/// `epoch`, or with no epoch remove it. Files without tIME don't gain one
+pub fn normalize(png: &mut Png, epoch: Option<u64>) -> Result<(), PngError> {
+    match epoch {
+        Some(epoch) if png.chunk_by_type("tIME").is_some() => {
+            png.replace_chunks("tIME", alloc::vec![time_chunk(epoch)])
+        }
+        _ => png.retain_chunks(|c| c.chunk_type().as_str() != "tIME"),
+    }
+    png.reorder()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::string::String;
+    use core::str::FromStr;
+
+    fn chunk(chunk_type: &str, data: &[u8]) -> Chunk {
+        Chunk::new(ChunkType::from_str(chunk_type).unwrap(), data.to_vec())
+    }
+
+    #[test]
+    fn test_time_chunk() {
+        assert_eq!(time_chunk(0).data(), [7, 178, 1, 1, 0, 0, 0]);
+        // 2000-02-29 23:59:59, a leap day in a century year
+        assert_eq!(time_chunk(951_868_799).data(), [7, 208, 2, 29, 23, 59, 59]);
+        // 2026-10-14 12:30:05
+        assert_eq!(
+            time_chunk(1_791_981_005).data(),
+            [7, 234, 10, 14, 12, 30, 5]
+        );
+    }
+
+    #[test]
+    fn test_normalize() {
+        let file = || {
+            Png::from_chunks(alloc::vec![
+                chunk("IHDR", &[0; 13]),
+                chunk("IDAT", b""),
+                chunk("tIME", &[7, 233, 1, 2, 3, 4, 5]),
+                chunk("tEXt", b"Author\0me"),
+                chunk("tIME", &[7, 234, 6, 7, 8, 9, 10]),
+                chunk("IEND", b""),
+            ])
+        };
+        let types = |png: &Png| -> Vec<String> {
+            png.chunks()
+                .iter()
+                .map(|c| c.chunk_type().as_str().into())
+                .collect()
+        };
+
+        let mut png = file();
+        normalize(&mut png, Some(0)).unwrap();
+        assert_eq!(types(&png), ["IHDR", "tIME", "tEXt", "IDAT", "IEND"]);
+        assert_eq!(
+            png.chunk_by_type("tIME").unwrap().data(),
+            time_chunk(0).data()
+        );
+        let mut again = file();
+        normalize(&mut again, Some(0)).unwrap();
+        assert_eq!(again.as_bytes(), png.as_bytes());
+
+        normalize(&mut png, None).unwrap();
+        assert_eq!(types(&png), ["IHDR", "tEXt", "IDAT", "IEND"]);
+        normalize(&mut png, Some(0)).unwrap();
+        assert!(png.chunk_by_type("tIME").is_none());
+    }
+}
@@ -0,0 +1,264 @@
+//! Edits described as a JSON array of operations, applied in order to a
+//! parsed file so any number of them cost one parse and one write:
+//!
+//! ```json
+//! [
+//!     {"op": "remove", "chunk_type": "tIME", "all": true},
+//!     {"op": "set-text", "keyword": "Author", "text": "Ferris"},
+//!     {"op": "encode", "chunk_type": "ruSt", "message": "hi", "encrypt": true}
+//! ]
+//! ```
+//!
+//! Chunk types and keywords are checked when the script is parsed, so a
+//! typo fails before any file is touched
+
+use std::fmt::Display;
+use std::str::FromStr;
+
+use serde::de::{self, Deserializer};
+use serde::Deserialize;
+
+use crate::chunk_type::ChunkType;
+use crate::message;
+use crate::png::Png;
+use crate::text::{self, TextChunk};
+
+#[derive(Debug)]
+pub enum ScriptError {
+    Json(serde_json::Error),
+    /// An `encrypt` operation with no password to encrypt under
+    NoPassword,
+    /// The operation at this index failed
+    Op(usize, String),
+}
+
+impl Display for ScriptError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ScriptError::Json(e) => write!(f, "invalid script: {}", e),
+            ScriptError::NoPassword => write!(f, "the script encrypts but has no password"),
+            ScriptError::Op(index, reason) => write!(f, "operation {}: {}", index, reason),
+        }
+    }
+}
+
+impl std::error::Error for ScriptError {}
+
+impl From<serde_json::Error> for ScriptError {
+    fn from(e: serde_json::Error) -> Self {
+        ScriptError::Json(e)
+    }
+}
+
+fn chunk_type<'de, D: Deserializer<'de>>(deserializer: D) -> Result<ChunkType, D::Error> {
+    let name = String::deserialize(deserializer)?;
+    ChunkType::from_str(&name).map_err(|e| de::Error::custom(format!("{:?}: {}", name, e)))
+}
+
+/// A text keyword, as [`text::check_keyword`] takes
+fn keyword<'de, D: Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+    let keyword = String::deserialize(deserializer)?;
+    match text::check_keyword(&keyword) {
+        Ok(()) => Ok(keyword),
+        Err(_) => Err(de::Error::custom(format!(
+            "{:?} isn't a text keyword",
+            keyword
+        ))),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(tag = "op", rename_all = "kebab-case", deny_unknown_fields)]
+pub enum Op {
+    /// Add a message as [`message::embed`] does, encrypted under the
+    /// script's password if `encrypt` is set
+    Encode {
+        #[serde(deserialize_with = "chunk_type")]
+        chunk_type: ChunkType,
+        message: String,
+        #[serde(default)]
+        encrypt: bool,
+    },
+    /// Remove the first chunk of a type, or with `all` every one, failing
+    /// if there's none
+    Remove {
+        #[serde(deserialize_with = "chunk_type")]
+        chunk_type: ChunkType,
+        #[serde(default)]
+        all: bool,
+    },
+    /// Change the type of every chunk of a type
+    Rename {
+        #[serde(deserialize_with = "chunk_type")]
+        from: ChunkType,
+        #[serde(deserialize_with = "chunk_type")]
+        to: ChunkType,
+    },
+    /// Replace the text chunks with `keyword` by one holding `text`, where
+    /// the first of them was or else before IEND
+    SetText {
+        #[serde(deserialize_with = "keyword")]
+        keyword: String,
+        text: String,
+    },
+    /// Remove the text chunks with `keyword`
+    RemoveText {
+        #[serde(deserialize_with = "keyword")]
+        keyword: String,
+    },
+    /// Drop the chunks [`message::strip`] does
+    Strip,
+    /// Put the chunks in canonical order
+    Reorder,
+}
+
+impl Op {
+    /// Apply to `png`
+    fn apply(&self, png: &mut Png, password: Option<&str>) -> crate::Result<()> {
+        match self {
+            Op::Encode {
+                chunk_type,
+                message,
+                encrypt,
+            } => {
+                let password = match encrypt {
+                    true => Some(password.ok_or(ScriptError::NoPassword)?),
+                    false => None,
+                };
+                message::embed(png, *chunk_type, message.as_bytes(), password)?;
+            }
+            Op::Remove { chunk_type, all } => {
+                let name = chunk_type.to_string();
+                png.remove_first_chunk(&name)?;
+                if *all {
+                    png.retain_chunks(|c| c.chunk_type().as_str() != name);
+                }
+            }
+            Op::Rename { from, to } => {
+                png.rename_chunks(&from.to_string(), *to)?;
+            }
+            Op::SetText { keyword, text } => png.set_text(&TextChunk {
+                keyword: keyword.clone(),
+                text: text.clone(),
+            })?,
+            Op::RemoveText { keyword } => {
+                png.remove_text(keyword);
+            }
+            Op::Strip => {
+                message::strip(png);
+            }
+            Op::Reorder => png.reorder()?,
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Script(pub Vec<Op>);
+
+impl Script {
+    pub fn from_json(json: &str) -> Result<Script, ScriptError> {
+        Ok(Script(serde_json::from_str(json)?))
+    }
+
+    /// Whether an operation encrypts, and so needs a password to run
+    pub fn needs_password(&self) -> bool {
+        self.0
+            .iter()
+            .any(|op| matches!(op, Op::Encode { encrypt: true, .. }))
+    }
+
+    /// Apply every operation in turn, stopping at the first that fails,
+    /// and return how many chunks went, net of any added; none if the
+    /// file gained chunks overall
+    pub fn apply(&self, png: &mut Png, password: Option<&str>) -> Result<usize, ScriptError> {
+        if self.needs_password() && password.is_none() {
+            return Err(ScriptError::NoPassword);
+        }
+        let before = png.chunks().len();
+        for (index, op) in self.0.iter().enumerate() {
+            op.apply(png, password)
+                .map_err(|e| ScriptError::Op(index, e.to_string()))?;
+        }
+        Ok(before.saturating_sub(png.chunks().len()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::Chunk;
+    use crate::pixels::tests::testing_png;
+
+    fn text(png: &Png) -> Vec<(String, String)> {
+        png.chunks()
+            .iter()
+            .filter_map(|c| TextChunk::parse(c)?.ok())
+            .map(|t| (t.keyword, t.text))
+            .collect()
+    }
+
+    #[test]
+    fn test_apply() {
+        let mut png = testing_png(4, 4);
+        for data in [&b"Author\0me"[..], b"Comment\0one", b"Author\0you"] {
+            png.append_chunk(Chunk::new("tEXt".parse().unwrap(), data.to_vec()));
+        }
+        png.append_chunk(Chunk::new("tIME".parse().unwrap(), vec![0; 7]));
+        let script = Script::from_json(
+            r#"[
+                {"op": "set-text", "keyword": "Author", "text": "Ferris 🦀"},
+                {"op": "remove", "chunk_type": "tIME"},
+                {"op": "encode", "chunk_type": "ruSt", "message": "hi"},
+                {"op": "rename", "from": "ruSt", "to": "raIn"},
+                {"op": "remove-text", "keyword": "Comment"}
+            ]"#,
+        )
+        .unwrap();
+        assert!(!script.needs_password());
+        // Seven chunks down to five: two tEXt became one iTXt and tIME and
+        // a tEXt went, while the message added one
+        assert_eq!(script.apply(&mut png, None).unwrap(), 2);
+        assert_eq!(
+            text(&png),
+            [("Author".to_string(), "Ferris 🦀".to_string())]
+        );
+        let types: Vec<String> = png
+            .chunks()
+            .iter()
+            .map(|c| c.chunk_type().to_string())
+            .collect();
+        assert_eq!(types, ["IHDR", "IDAT", "iTXt", "raIn", "IEND"]);
+        let message = message::extract(&png, &"raIn".parse().unwrap(), None).unwrap();
+        assert_eq!(message, b"hi");
+    }
+
+    #[test]
+    fn test_errors() {
+        let invalid = [
+            r#"[{"op": "remove", "chunk_type": "ru5t"}]"#,
+            r#"[{"op": "set-text", "keyword": "", "text": "x"}]"#,
+            r#"[{"op": "shred"}]"#,
+            r#"[{"op": "encode", "chunk_type": "ruSt"}]"#,
+        ];
+        for json in invalid {
+            assert!(matches!(Script::from_json(json), Err(ScriptError::Json(_))));
+        }
+
+        let script =
+            Script::from_json(r#"[{"op": "strip"}, {"op": "remove", "chunk_type": "tIME"}]"#)
+                .unwrap();
+        let error = script.apply(&mut testing_png(4, 4), None).unwrap_err();
+        assert_eq!(error.to_string(), "operation 1: chunk tIME not found");
+
+        let script = Script::from_json(
+            r#"[{"op": "encode", "chunk_type": "ruSt", "message": "hi", "encrypt": true}]"#,
+        )
+        .unwrap();
+        assert!(script.needs_password());
+        assert!(matches!(
+            script.apply(&mut testing_png(4, 4), None),
+            Err(ScriptError::NoPassword)
+        ));
+    }
+}
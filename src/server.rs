@@ -0,0 +1,456 @@
+//! A small HTTP API for running ping as a service. Each endpoint takes a
+//! PNG as the POST body:
+//!
+//! - `/chunks` answers with the chunk list as JSON
+//! - `/scrub` answers with the file as [`message::strip`] leaves it
+//! - `/decode?chunk_type=ruSt` answers with the message in those chunks,
+//!   decrypted under the `X-Ping-Password` header if there is one
+//!
+//! Failures come back as a status and a plain text reason. Bodies, key
+//! derivation and decompression are all bounded, and a connection that
+//! goes quiet is dropped, so no request can cost the server more than
+//! [`Options`] allows. Each connection carries one request
+
+use std::fmt::Display;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{Shutdown, TcpListener, TcpStream, ToSocketAddrs};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use crate::chunk_type::ChunkType;
+use crate::crypto::KdfParams;
+use crate::message;
+use crate::payload::Keys;
+use crate::png::Png;
+
+/// Header carrying the password for `/decode`, kept out of the URL so it
+/// stays out of access logs
+pub const PASSWORD_HEADER: &str = "X-Ping-Password";
+
+#[derive(Debug)]
+pub enum ServeError {
+    Bind(String),
+}
+
+impl Display for ServeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ServeError::Bind(e) => write!(f, "can't listen: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for ServeError {}
+
+#[derive(Debug, Clone)]
+pub struct Options {
+    /// Largest request body accepted, in bytes
+    pub max_size: usize,
+    /// Requests handled at once
+    pub threads: usize,
+    /// Most an encrypted payload's key derivation may cost; by default
+    /// what `ping encode` uses without --kdf-memory or --kdf-time
+    pub max_kdf: KdfParams,
+    /// How long a read or write on a connection may wait before the
+    /// connection is dropped
+    pub timeout: Duration,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            max_size: 64 << 20,
+            threads: thread::available_parallelism().map_or(4, |n| n.get()),
+            max_kdf: KdfParams::default(),
+            timeout: Duration::from_secs(30),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Response {
+    pub status: u16,
+    pub content_type: &'static str,
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    fn ok(content_type: &'static str, body: Vec<u8>) -> Response {
+        Response {
+            status: 200,
+            content_type,
+            body,
+        }
+    }
+
+    fn error(status: u16, reason: impl Display) -> Response {
+        Response {
+            status,
+            content_type: "text/plain; charset=utf-8",
+            body: format!("{}\n", reason).into_bytes(),
+        }
+    }
+}
+
+/// The value of `key` in a URL's query string
+fn query<'a>(url: &'a str, key: &str) -> Option<&'a str> {
+    let (_, query) = url.split_once('?')?;
+    query
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .find(|(k, _)| *k == key)
+        .map(|(_, value)| value)
+}
+
+/// The chunk list as a JSON array. Chunk types are ASCII letters, so
+/// nothing needs escaping
+fn chunks_json(png: &Png) -> String {
+    let chunks: Vec<String> = png
+        .chunks()
+        .iter()
+        .zip(png.chunk_offsets())
+        .map(|(chunk, offset)| {
+            format!(
+                r#"{{"type":"{}","offset":{},"length":{},"crc":{}}}"#,
+                chunk.chunk_type(),
+                offset,
+                chunk.length(),
+                chunk.crc()
+            )
+        })
+        .collect();
+    format!("[{}]", chunks.join(","))
+}
+
+/// Answer one request, given its method, URL, password header and body
+pub fn handle(
+    method: &str,
+    url: &str,
+    password: Option<&str>,
+    body: &[u8],
+    options: &Options,
+) -> Response {
+    let path = url.split('?').next().unwrap_or_default();
+    if !["/chunks", "/scrub", "/decode"].contains(&path) {
+        return Response::error(404, format!("no endpoint {}", path));
+    }
+    if method != "POST" {
+        return Response::error(405, "POST a PNG");
+    }
+    if body.len() > options.max_size {
+        return Response::error(413, format!("larger than {} bytes", options.max_size));
+    }
+    let mut png = match Png::try_from(body) {
+        Ok(png) => png,
+        Err(e) => return Response::error(400, e),
+    };
+    match path {
+        "/chunks" => Response::ok("application/json", chunks_json(&png).into_bytes()),
+        "/scrub" => {
+            message::strip(&mut png);
+            Response::ok("image/png", png.as_bytes())
+        }
+        _ => {
+            let Some(chunk_type) = query(url, "chunk_type") else {
+                return Response::error(400, "missing chunk_type");
+            };
+            let chunk_type = match ChunkType::from_str(chunk_type) {
+                Ok(chunk_type) => chunk_type,
+                Err(e) => return Response::error(400, e),
+            };
+            let keys = Keys {
+                password: password.map(str::to_string),
+                kdf_limit: Some(options.max_kdf),
+                ..Keys::default()
+            };
+            match message::extract_keyed(&png, &chunk_type, &keys) {
+                Ok(message) => Response::ok("application/octet-stream", message),
+                Err(e) => Response::error(422, e),
+            }
+        }
+    }
+}
+
+/// Longest request line and headers accepted, in bytes
+const MAX_HEAD: usize = 16 << 10;
+
+/// Most headers a request may have
+const MAX_HEADERS: usize = 32;
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        400 => "Bad Request",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        408 => "Request Timeout",
+        411 => "Length Required",
+        413 => "Content Too Large",
+        422 => "Unprocessable Content",
+        431 => "Request Header Fields Too Large",
+        _ => "",
+    }
+}
+
+/// Read the request on `reader` and work out the response
+fn answer(reader: &mut BufReader<TcpStream>, options: &Options) -> io::Result<Response> {
+    let mut head = Vec::new();
+    while !head.ends_with(b"\r\n\r\n") {
+        let limit = (MAX_HEAD - head.len()) as u64;
+        if reader.by_ref().take(limit).read_until(b'\n', &mut head)? == 0 {
+            return match head.len() {
+                MAX_HEAD => Ok(Response::error(
+                    431,
+                    format!("headers longer than {} bytes", MAX_HEAD),
+                )),
+                _ => Err(io::ErrorKind::UnexpectedEof.into()),
+            };
+        }
+    }
+    let mut headers = [httparse::EMPTY_HEADER; MAX_HEADERS];
+    let mut request = httparse::Request::new(&mut headers);
+    match request.parse(&head) {
+        Ok(status) if status.is_complete() => {}
+        Ok(_) => return Ok(Response::error(400, "incomplete request")),
+        Err(e) => return Ok(Response::error(400, e)),
+    }
+    let header = |name: &str| {
+        let mut headers = request.headers.iter();
+        let header = headers.find(|h| h.name.eq_ignore_ascii_case(name))?;
+        std::str::from_utf8(header.value).ok()
+    };
+    if header("Transfer-Encoding").is_some() {
+        return Ok(Response::error(411, "send the body with a Content-Length"));
+    }
+    let length = match header("Content-Length").map(|v| v.trim().parse::<usize>()) {
+        None => 0,
+        Some(Ok(length)) => length,
+        Some(Err(_)) => return Ok(Response::error(400, "invalid Content-Length")),
+    };
+    if length > options.max_size {
+        return Ok(Response::error(
+            413,
+            format!("larger than {} bytes", options.max_size),
+        ));
+    }
+    if header("Expect").is_some_and(|v| v.eq_ignore_ascii_case("100-continue")) {
+        reader
+            .get_mut()
+            .write_all(b"HTTP/1.1 100 Continue\r\n\r\n")?;
+    }
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body)?;
+    let method = request.method.unwrap_or_default();
+    let url = request.path.unwrap_or_default();
+    Ok(handle(method, url, header(PASSWORD_HEADER), &body, options))
+}
+
+/// Answer the request on `stream`, then close it; a client sending or
+/// reading too slowly gets a 408 or is dropped
+fn respond(stream: TcpStream, options: &Options) -> io::Result<()> {
+    stream.set_read_timeout(Some(options.timeout))?;
+    stream.set_write_timeout(Some(options.timeout))?;
+    let mut reader = BufReader::new(stream);
+    let response = match answer(&mut reader, options) {
+        Err(e)
+            if matches!(
+                e.kind(),
+                io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock
+            ) =>
+        {
+            Response::error(408, "timed out")
+        }
+        response => response?,
+    };
+    let stream = reader.get_mut();
+    write!(
+        stream,
+        "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        response.status,
+        reason(response.status),
+        response.content_type,
+        response.body.len()
+    )?;
+    stream.write_all(&response.body)?;
+    // Closing with an unread upload would reset the connection, which can
+    // discard the response before the client reads it
+    stream.shutdown(Shutdown::Write)?;
+    io::copy(&mut reader.take(options.max_size as u64), &mut io::sink())?;
+    Ok(())
+}
+
+/// Serve requests on `addr` until the process is stopped
+pub fn serve(addr: impl ToSocketAddrs, options: &Options) -> Result<(), ServeError> {
+    let listener = TcpListener::bind(addr).map_err(|e| ServeError::Bind(e.to_string()))?;
+    run(Arc::new(listener), options);
+    Ok(())
+}
+
+fn run(listener: Arc<TcpListener>, options: &Options) {
+    let workers: Vec<_> = (0..options.threads.max(1))
+        .map(|_| {
+            let listener = Arc::clone(&listener);
+            let options = options.clone();
+            thread::spawn(move || {
+                for stream in listener.incoming().flatten() {
+                    // The client hanging up early is its own business
+                    let _ = respond(stream, &options);
+                }
+            })
+        })
+        .collect();
+    for worker in workers {
+        let _ = worker.join();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::Chunk;
+    use crate::pixels::tests::testing_png;
+    use std::io::Write;
+    use std::net::TcpStream;
+
+    #[test]
+    fn test_endpoints() {
+        let mut png = testing_png(4, 4);
+        message::embed(&mut png, "ruSt".parse().unwrap(), b"hello", None).unwrap();
+        let text = Chunk::new("tEXt".parse().unwrap(), b"Author\0me".to_vec());
+        png.append_chunk(text);
+        let bytes = png.as_bytes();
+        let options = Options::default();
+
+        let response = handle("POST", "/chunks", None, &bytes, &options);
+        assert_eq!(response.content_type, "application/json");
+        let json = String::from_utf8(response.body).unwrap();
+        assert!(json.starts_with(r#"[{"type":"IHDR","offset":8,"length":13,"crc":"#));
+        assert_eq!(json.matches("\"type\"").count(), 5);
+
+        let scrubbed = handle("POST", "/scrub", None, &bytes, &options).body;
+        assert_eq!(scrubbed, testing_png(4, 4).as_bytes());
+
+        let response = handle("POST", "/decode?chunk_type=ruSt", None, &bytes, &options);
+        assert_eq!(
+            response,
+            Response::ok("application/octet-stream", b"hello".to_vec())
+        );
+        assert_eq!(
+            handle("POST", "/decode?chunk_type=raIn", None, &bytes, &options).status,
+            422
+        );
+        assert_eq!(
+            handle("POST", "/decode", None, &bytes, &options).status,
+            400
+        );
+        assert_eq!(
+            handle("POST", "/chunks", None, b"not a png", &options).status,
+            400
+        );
+        assert_eq!(handle("GET", "/chunks", None, &bytes, &options).status, 405);
+        assert_eq!(handle("POST", "/", None, &bytes, &options).status, 404);
+    }
+
+    #[test]
+    fn test_kdf_limit() {
+        let options = Options {
+            max_kdf: KdfParams {
+                memory_kib: 1024,
+                time_cost: 2,
+                parallelism: 1,
+            },
+            ..Options::default()
+        };
+        let post = |time_cost| {
+            let params = KdfParams {
+                time_cost,
+                ..options.max_kdf
+            };
+            let secret = crate::crypto::secret(Some("pw"), None);
+            let data =
+                crate::payload::encrypt(b"hello", &secret, &params, Default::default()).unwrap();
+            let mut png = testing_png(4, 4);
+            png.append_chunk(Chunk::new("ruSt".parse().unwrap(), data));
+            handle(
+                "POST",
+                "/decode?chunk_type=ruSt",
+                Some("pw"),
+                &png.as_bytes(),
+                &options,
+            )
+        };
+        assert_eq!(post(2).body, b"hello");
+        let response = post(3);
+        assert_eq!(response.status, 422);
+        assert!(response
+            .body
+            .ends_with(b"1024 KiB, 3 passes and 1 lanes, more than allowed\n"));
+
+        let small = Options {
+            max_size: 8,
+            ..options.clone()
+        };
+        let bytes = testing_png(4, 4).as_bytes();
+        assert_eq!(handle("POST", "/chunks", None, &bytes, &small).status, 413);
+    }
+
+    #[test]
+    fn test_serve() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let options = Options {
+            max_size: 1024,
+            threads: 1,
+            ..Options::default()
+        };
+        thread::spawn(move || run(Arc::new(listener), &options));
+
+        let post = |body: &[u8]| {
+            let mut stream = TcpStream::connect(addr).unwrap();
+            let head = format!(
+                "POST /scrub HTTP/1.1\r\nHost: localhost\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                body.len()
+            );
+            stream.write_all(head.as_bytes()).unwrap();
+            stream.write_all(body).unwrap();
+            let mut response = Vec::new();
+            stream.read_to_end(&mut response).unwrap();
+            response
+        };
+        let bytes = testing_png(4, 4).as_bytes();
+        let response = post(&bytes);
+        assert!(response.starts_with(b"HTTP/1.1 200"));
+        assert!(response.ends_with(&bytes));
+        assert!(post(&[0; 1025]).starts_with(b"HTTP/1.1 413"));
+    }
+
+    #[test]
+    fn test_timeout() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let options = Options {
+            threads: 1,
+            timeout: Duration::from_millis(100),
+            ..Options::default()
+        };
+        thread::spawn(move || run(Arc::new(listener), &options));
+
+        // A client that stops partway through its headers
+        let mut stalled = TcpStream::connect(addr).unwrap();
+        stalled.write_all(b"POST /chunks HTTP/1.1\r\n").unwrap();
+        let mut response = Vec::new();
+        stalled.read_to_end(&mut response).unwrap();
+        assert!(response.starts_with(b"HTTP/1.1 408"));
+
+        // The one worker is free again for the next client
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream
+            .write_all(b"GET /chunks HTTP/1.1\r\nConnection: close\r\n\r\n")
+            .unwrap();
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response).unwrap();
+        assert!(response.starts_with(b"HTTP/1.1 405"));
+    }
+}
@@ -0,0 +1,320 @@
+//! Parsing into one shared, reference-counted buffer: each chunk's data is
+//! a [`Bytes`] slice of the input rather than a copy of it, so listing a
+//! file or reading its metadata costs one small allocation per chunk
+//! however large the image data is.
+//!
+//! Edits are copy on write: a chunk added or changed owns its data, while
+//! every other chunk stays a slice of the input, so changing one text
+//! chunk in a huge file copies nothing else. The input can be any owner
+//! [`Bytes::from_owner`] takes, such as a memory map
+
+#![deny(clippy::unwrap_used, clippy::expect_used)]
+
+use alloc::vec::Vec;
+
+use bytes::Bytes;
+
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::crc;
+#[cfg(feature = "std")]
+use crate::png::Gather;
+use crate::png::{Frames, Png, PngError};
+
+/// A chunk whose data is a slice of the parsed buffer
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SharedChunk {
+    chunk_type: ChunkType,
+    data: Bytes,
+    crc: u32,
+}
+
+impl SharedChunk {
+    /// A chunk of `data`, computing its CRC
+    pub fn new(chunk_type: ChunkType, data: Bytes) -> SharedChunk {
+        let mut hasher = crc::CrcHasher::new();
+        hasher.update(&chunk_type.bytes());
+        hasher.update(&data);
+        SharedChunk {
+            chunk_type,
+            data,
+            crc: hasher.finalize(),
+        }
+    }
+    pub fn chunk_type(&self) -> &ChunkType {
+        &self.chunk_type
+    }
+    pub fn data(&self) -> &Bytes {
+        &self.data
+    }
+    pub fn length(&self) -> u32 {
+        self.data.len() as u32
+    }
+    pub fn crc(&self) -> u32 {
+        self.crc
+    }
+    /// A [`Chunk`] with its own copy of the data
+    pub fn to_chunk(&self) -> Chunk {
+        Chunk::new(self.chunk_type, self.data.to_vec())
+    }
+    /// The length and type fields that come before the data
+    fn head(&self) -> [u8; 8] {
+        let mut head = [0; 8];
+        head[..4].copy_from_slice(&self.length().to_be_bytes());
+        head[4..].copy_from_slice(&self.chunk_type.bytes());
+        head
+    }
+}
+
+/// Takes the chunk's data without copying it
+impl From<Chunk> for SharedChunk {
+    fn from(chunk: Chunk) -> Self {
+        let (chunk_type, data, crc) = chunk.into_parts();
+        SharedChunk {
+            chunk_type,
+            data: Bytes::from(data),
+            crc,
+        }
+    }
+}
+
+/// A parsed file whose chunks share the buffer it was parsed from
+#[derive(Debug, Clone)]
+pub struct SharedPng {
+    chunks: Vec<SharedChunk>,
+    /// Bytes after the IEND chunk
+    trailing: Bytes,
+}
+
+impl TryFrom<Bytes> for SharedPng {
+    type Error = PngError;
+
+    fn try_from(value: Bytes) -> Result<Self, Self::Error> {
+        SharedPng::parse(value)
+    }
+}
+
+impl SharedPng {
+    /// Parse `value` as [`Png::try_from`] does, checking every CRC
+    pub fn parse(value: Bytes) -> Result<SharedPng, PngError> {
+        let mut frames = Frames::new(&value)?;
+        let mut chunks = Vec::new();
+        for frame in &mut frames {
+            let frame = frame?;
+            chunks.push(SharedChunk {
+                chunk_type: frame.chunk_type,
+                data: value.slice_ref(frame.data),
+                crc: frame.crc,
+            });
+        }
+        Ok(SharedPng {
+            chunks,
+            trailing: value.slice(frames.end()..),
+        })
+    }
+    pub fn chunks(&self) -> &[SharedChunk] {
+        &self.chunks
+    }
+    pub fn chunk_by_type(&self, chunk_type: &str) -> Option<&SharedChunk> {
+        self.chunks
+            .iter()
+            .find(|c| c.chunk_type.bytes()[..] == *chunk_type.as_bytes())
+    }
+    pub fn chunks_by_type<'a>(
+        &'a self,
+        chunk_type: &'a str,
+    ) -> impl Iterator<Item = &'a SharedChunk> {
+        self.chunks
+            .iter()
+            .filter(move |c| c.chunk_type.bytes()[..] == *chunk_type.as_bytes())
+    }
+    /// Data after IEND, which decoders ignore
+    pub fn trailing(&self) -> &Bytes {
+        &self.trailing
+    }
+    /// Insert a chunk before IEND, or at the end if there's no IEND
+    pub fn append_chunk(&mut self, chunk: impl Into<SharedChunk>) {
+        let at = match self.chunks.last() {
+            Some(last) if last.chunk_type.as_str() == "IEND" => self.chunks.len() - 1,
+            _ => self.chunks.len(),
+        };
+        self.chunks.insert(at, chunk.into());
+    }
+    /// Insert a chunk at `index`, or append it with
+    /// [`append_chunk`](SharedPng::append_chunk) if that's past the end
+    pub fn insert_chunk(&mut self, index: usize, chunk: impl Into<SharedChunk>) {
+        match index < self.chunks.len() {
+            true => self.chunks.insert(index, chunk.into()),
+            false => self.append_chunk(chunk),
+        }
+    }
+    /// Put `chunk` in place of the `index`th chunk, returning the one
+    /// replaced
+    pub fn replace_chunk(
+        &mut self,
+        index: usize,
+        chunk: impl Into<SharedChunk>,
+    ) -> Option<SharedChunk> {
+        let slot = self.chunks.get_mut(index)?;
+        Some(core::mem::replace(slot, chunk.into()))
+    }
+    pub fn remove_first_chunk(&mut self, chunk_type: &str) -> Result<SharedChunk, PngError> {
+        let index = self
+            .chunks
+            .iter()
+            .position(|c| c.chunk_type.as_str() == chunk_type)
+            .ok_or_else(|| PngError::ChunkNotFound(chunk_type.into()))?;
+        Ok(self.chunks.remove(index))
+    }
+    pub fn retain_chunks(&mut self, keep: impl FnMut(&SharedChunk) -> bool) {
+        self.chunks.retain(keep);
+    }
+    pub fn set_trailing(&mut self, trailing: Bytes) {
+        self.trailing = trailing;
+    }
+    /// Bytes the file takes serialized
+    pub fn encoded_len(&self) -> usize {
+        let chunks: usize = self
+            .chunks
+            .iter()
+            .map(|c| c.data.len() + Chunk::OVERHEAD)
+            .sum();
+        Png::STANDARD_HEADER.len() + chunks + self.trailing.len()
+    }
+    /// Serialize the file as [`Png::as_bytes`] does
+    pub fn as_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(self.encoded_len());
+        bytes.extend_from_slice(&Png::STANDARD_HEADER);
+        for chunk in &self.chunks {
+            bytes.extend_from_slice(&chunk.head());
+            bytes.extend_from_slice(&chunk.data);
+            bytes.extend_from_slice(&chunk.crc.to_be_bytes());
+        }
+        bytes.extend_from_slice(&self.trailing);
+        bytes
+    }
+    /// Write the file as [`Png::write_to`] does, straight from the shared
+    /// buffer, so saving an edit never holds a second copy of the file
+    #[cfg(feature = "std")]
+    pub fn write_to(&self, mut writer: impl std::io::Write) -> std::io::Result<()> {
+        let mut gather = Gather::default();
+        gather.copy(&Png::STANDARD_HEADER);
+        for chunk in &self.chunks {
+            gather.copy(&chunk.head());
+            gather.add(&chunk.data);
+            gather.copy(&chunk.crc.to_be_bytes());
+            if gather.is_full() {
+                gather.write(&mut writer)?;
+            }
+        }
+        gather.add(&self.trailing);
+        gather.write(&mut writer)?;
+        writer.flush()
+    }
+    /// A [`Png`] owning a copy of every chunk, for the edits only it has
+    pub fn to_png(&self) -> Png {
+        let mut png = Png::from_chunks(self.chunks.iter().map(SharedChunk::to_chunk).collect());
+        png.set_trailing(self.trailing.to_vec());
+        png
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::png::CrcMismatch;
+    use std::str::FromStr;
+
+    fn testing_bytes() -> Vec<u8> {
+        let mut png = Png::from_chunks(
+            [
+                ("IHDR", &[0; 13][..]),
+                ("tEXt", b"Author\0me"),
+                ("IDAT", &[9; 4096]),
+                ("IEND", b""),
+            ]
+            .into_iter()
+            .map(|(t, d)| Chunk::new(ChunkType::from_str(t).unwrap(), d.to_vec()))
+            .collect(),
+        );
+        png.set_trailing(b"after".to_vec());
+        png.as_bytes()
+    }
+
+    #[test]
+    fn test_parse_shares_the_buffer() {
+        let bytes = Bytes::from(testing_bytes());
+        let png = SharedPng::parse(bytes.clone()).unwrap();
+        assert_eq!(png.chunks().len(), 4);
+        let buffer = bytes.as_ptr_range();
+        for chunk in png.chunks().iter().filter(|c| c.length() > 0) {
+            assert!(buffer.contains(&chunk.data().as_ptr()));
+        }
+        let idat = png.chunk_by_type("IDAT").unwrap();
+        assert_eq!(idat.data().as_ref(), &[9; 4096]);
+        assert_eq!(png.trailing().as_ref(), b"after");
+        assert_eq!(png.to_png().as_bytes(), bytes);
+    }
+
+    #[test]
+    fn test_copy_on_write() {
+        let bytes = Bytes::from(testing_bytes());
+        let mut shared = SharedPng::parse(bytes.clone()).unwrap();
+        let mut png = shared.to_png();
+        let text = |data: &[u8]| Chunk::new(ChunkType::from_str("tEXt").unwrap(), data.to_vec());
+
+        png.remove_first_chunk("tEXt").unwrap();
+        png.insert_chunk(1, text(b"Author\0you"));
+        png.append_chunk(text(b"Comment\0hi"));
+        png.set_trailing(Vec::new());
+        shared.replace_chunk(1, text(b"Author\0you")).unwrap();
+        shared.append_chunk(text(b"Comment\0hi"));
+        shared.set_trailing(Bytes::new());
+        assert!(shared.replace_chunk(10, text(b"")).is_none());
+
+        let buffer = bytes.as_ptr_range();
+        let types = shared.chunks().iter().map(|c| c.chunk_type().to_string());
+        let inside = shared
+            .chunks()
+            .iter()
+            .map(|c| buffer.contains(&c.data().as_ptr()));
+        let chunks: Vec<(String, bool)> = types.zip(inside).collect();
+        assert_eq!(chunks[2], ("IDAT".to_string(), true));
+        assert!(!chunks[1].1 && !chunks[3].1);
+        assert_eq!(shared.as_bytes(), png.as_bytes());
+        assert_eq!(shared.encoded_len(), png.encoded_len());
+
+        #[cfg(feature = "std")]
+        {
+            let mut written = Vec::new();
+            shared.write_to(&mut written).unwrap();
+            assert_eq!(written, png.as_bytes());
+        }
+        shared.retain_chunks(|c| c.chunk_type().as_str() != "tEXt");
+        assert!(matches!(
+            shared.remove_first_chunk("tEXt"),
+            Err(PngError::ChunkNotFound(_))
+        ));
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let mut bytes = testing_bytes();
+        assert!(matches!(
+            SharedPng::parse(Bytes::from(bytes[..40].to_vec())),
+            Err(PngError::Truncated(_))
+        ));
+        bytes[40] ^= 1;
+        assert!(matches!(
+            SharedPng::parse(Bytes::from(bytes)),
+            Err(PngError::Crc(CrcMismatch { offset: 33, .. }))
+        ));
+        assert_eq!(
+            SharedPng::parse(Bytes::from_static(b"not a png")).unwrap_err(),
+            PngError::Signature {
+                offset: 0,
+                found: b'n'
+            }
+        );
+    }
+}
@@ -0,0 +1,118 @@
+use std::fs::File;
+use std::io::{Seek, SeekFrom, Write};
+use std::ops::Range;
+use std::path::Path;
+
+use crate::chunk::Chunk;
+use crate::crypto;
+use crate::png::Png;
+use crate::Result;
+
+/// What removed data is overwritten with
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum Fill {
+    Zero,
+    Random,
+}
+
+/// File regions holding the chunks of `before` that are missing from
+/// `after`, which must keep the remaining chunks in order
+pub fn removed_regions(before: &Png, after: &Png) -> Vec<Range<usize>> {
+    let mut kept = after.chunks().iter().peekable();
+    before
+        .chunks()
+        .iter()
+        .zip(before.chunk_offsets())
+        .filter(|(chunk, _)| kept.next_if_eq(chunk).is_none())
+        .map(|(chunk, offset)| offset..offset + chunk.length() as usize + Chunk::OVERHEAD)
+        .collect()
+}
+
+/// Overwrite `regions` of the file in place and flush them to disk. Neither
+/// journaling nor copy-on-write filesystems nor SSD wear levelling promise
+/// the old blocks are the ones overwritten, so this only defeats a casual
+/// look at the disk
+pub fn overwrite(path: &Path, regions: &[Range<usize>], fill: Fill) -> Result<()> {
+    let mut file = File::options().write(true).open(path)?;
+    for region in regions {
+        let bytes = match fill {
+            Fill::Zero => vec![0; region.len()],
+            Fill::Random => crypto::random_bytes(region.len())?,
+        };
+        file.seek(SeekFrom::Start(region.start as u64))?;
+        file.write_all(&bytes)?;
+    }
+    file.sync_all()?;
+    Ok(())
+}
+
+/// Replace the file's contents in place rather than through a temporary
+/// copy, which would leave the old contents behind in the original's blocks
+pub fn rewrite(path: &Path, bytes: &[u8]) -> Result<()> {
+    let mut file = File::options().write(true).open(path)?;
+    file.write_all(bytes)?;
+    file.set_len(bytes.len() as u64)?;
+    file.sync_all()?;
+    Ok(())
+}
+
+/// Remove the chunks missing from `after` from the file `before` was read
+/// from, overwriting them before the file is rewritten
+pub fn shred(path: &Path, before: &Png, after: &Png, fill: Fill) -> Result<()> {
+    overwrite(path, &removed_regions(before, after), fill)?;
+    rewrite(path, &after.as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk_type::ChunkType;
+    use std::fs;
+    use std::str::FromStr;
+
+    fn chunk(chunk_type: &str, data: &[u8]) -> Chunk {
+        Chunk::new(ChunkType::from_str(chunk_type).unwrap(), data.to_vec())
+    }
+
+    fn testing_png() -> Png {
+        Png::from_chunks(vec![
+            chunk("IHDR", &[0; 13]),
+            chunk("ruSt", b"secret one"),
+            chunk("tEXt", b"Comment\0hello"),
+            chunk("ruSt", b"secret two"),
+            chunk("IEND", b""),
+        ])
+    }
+
+    #[test]
+    fn test_removed_regions() {
+        let before = testing_png();
+        let mut after = before.clone();
+        after.retain_chunks(|c| c.chunk_type().as_str() != "ruSt");
+        let offsets = before.chunk_offsets();
+        assert_eq!(
+            removed_regions(&before, &after),
+            [offsets[1]..offsets[2], offsets[3]..offsets[4]]
+        );
+    }
+
+    #[test]
+    fn test_shred() {
+        let path = std::env::temp_dir().join(format!("ping-shred-{}", std::process::id()));
+        let before = testing_png();
+        fs::write(&path, before.as_bytes()).unwrap();
+        let mut after = before.clone();
+        after.retain_chunks(|c| c.data() != b"secret two");
+
+        let regions = removed_regions(&before, &after);
+        overwrite(&path, &regions, Fill::Zero).unwrap();
+        let bytes = fs::read(&path).unwrap();
+        assert!(bytes[regions[0].clone()].iter().all(|b| *b == 0));
+        assert!(bytes.windows(10).any(|w| w == b"secret one"));
+
+        shred(&path, &before, &after, Fill::Random).unwrap();
+        assert_eq!(fs::read(&path).unwrap(), after.as_bytes());
+        fs::remove_file(&path).unwrap();
+    }
+}
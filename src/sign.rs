@@ -0,0 +1,243 @@
+use std::fmt::Display;
+use std::path::Path;
+
+use ed25519_dalek::pkcs8::{DecodePrivateKey, DecodePublicKey};
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+
+use crate::checksum::Algorithm;
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::png::Png;
+
+/// Private, unsafe-to-copy chunk holding the Ed25519 signature
+pub const SIGNATURE_CHUNK: &str = "sgNT";
+
+const SIGNATURE_LEN: usize = 64;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum SignError {
+    InvalidKey,
+    MissingSignature,
+    InvalidSignature,
+}
+
+impl Display for SignError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SignError::InvalidKey => write!(f, "invalid ed25519 key"),
+            SignError::MissingSignature => write!(f, "file has no {} chunk", SIGNATURE_CHUNK),
+            SignError::InvalidSignature => write!(f, "signature does not match"),
+        }
+    }
+}
+
+impl std::error::Error for SignError {}
+
+/// Bytes covered by the signature: every critical chunk, in file order.
+/// Ancillary chunks are left out, including those that change how the
+/// image renders (tRNS, gAMA, cHRM, sRGB, iCCP), so editing them keeps
+/// the signature valid
+fn signed_bytes(png: &Png) -> Vec<u8> {
+    png.chunks()
+        .iter()
+        .filter(|c| c.chunk_type().is_critical())
+        .flat_map(|c| c.as_bytes())
+        .collect()
+}
+
+/// What a signature made with `digest` signs: the algorithm's id and the
+/// digest of the critical chunks
+fn digest_message(png: &Png, digest: Algorithm) -> Vec<u8> {
+    let mut message = vec![digest.id()];
+    message.extend(digest.digest(&signed_bytes(png)));
+    message
+}
+
+/// Sign the critical chunks, replacing any earlier signature chunk.
+/// Ancillary chunks aren't signed, even those that change how the image
+/// renders
+pub fn sign(png: &mut Png, key: &SigningKey) {
+    sign_with(png, key, None)
+}
+
+/// Like [`sign`], but with `digest` the signature covers the critical
+/// chunks' digest under that algorithm, recorded ahead of the signature,
+/// which is quicker for large images than Ed25519 hashing them itself
+pub fn sign_with(png: &mut Png, key: &SigningKey, digest: Option<Algorithm>) {
+    while png.remove_first_chunk(SIGNATURE_CHUNK).is_ok() {}
+    let data = match digest {
+        Some(digest) => {
+            let message = digest_message(png, digest);
+            let mut data = vec![digest.id()];
+            data.extend_from_slice(&key.sign(&message).to_bytes());
+            data
+        }
+        None => key.sign(&signed_bytes(png)).to_bytes().to_vec(),
+    };
+    png.append_chunk(Chunk::new(ChunkType::new(*b"sgNT"), data));
+}
+
+/// Check a signature made by [`sign`] or [`sign_with`]
+pub fn verify(png: &Png, key: &VerifyingKey) -> Result<(), SignError> {
+    let chunk = png
+        .chunk_by_type(SIGNATURE_CHUNK)
+        .ok_or(SignError::MissingSignature)?;
+    let (message, signature) = match chunk.data() {
+        [id, signature @ ..] if signature.len() == SIGNATURE_LEN => {
+            let digest = Algorithm::from_id(*id).ok_or(SignError::InvalidSignature)?;
+            (digest_message(png, digest), signature)
+        }
+        signature => (signed_bytes(png), signature),
+    };
+    let signature = Signature::from_slice(signature).map_err(|_| SignError::InvalidSignature)?;
+    key.verify(&message, &signature)
+        .map_err(|_| SignError::InvalidSignature)
+}
+
+/// Read a PKCS#8 PEM, raw 32-byte or hex-encoded private key
+pub fn read_signing_key(path: &Path) -> crate::Result<SigningKey> {
+    let bytes = std::fs::read(path)?;
+    if let Ok(pem) = std::str::from_utf8(&bytes) {
+        if pem.trim_start().starts_with("-----BEGIN") {
+            return SigningKey::from_pkcs8_pem(pem).map_err(|_| SignError::InvalidKey.into());
+        }
+    }
+    Ok(SigningKey::from_bytes(&key_bytes(&bytes)?))
+}
+
+/// Read a SPKI PEM, raw 32-byte or hex-encoded public key
+pub fn read_verifying_key(path: &Path) -> crate::Result<VerifyingKey> {
+    let bytes = std::fs::read(path)?;
+    if let Ok(pem) = std::str::from_utf8(&bytes) {
+        if pem.trim_start().starts_with("-----BEGIN") {
+            return VerifyingKey::from_public_key_pem(pem)
+                .map_err(|_| SignError::InvalidKey.into());
+        }
+    }
+    Ok(VerifyingKey::from_bytes(&key_bytes(&bytes)?).map_err(|_| SignError::InvalidKey)?)
+}
+
+/// A raw 32-byte or hex-encoded key
+pub fn key_bytes(bytes: &[u8]) -> Result<[u8; 32], SignError> {
+    if let Ok(raw) = <[u8; 32]>::try_from(bytes) {
+        return Ok(raw);
+    }
+    let hex = std::str::from_utf8(bytes)
+        .map_err(|_| SignError::InvalidKey)?
+        .trim();
+    if hex.len() != 64 || !hex.is_ascii() {
+        return Err(SignError::InvalidKey);
+    }
+    let mut key = [0u8; 32];
+    for (i, byte) in key.iter_mut().enumerate() {
+        *byte =
+            u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).map_err(|_| SignError::InvalidKey)?;
+    }
+    Ok(key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    fn chunk(chunk_type: &str, data: &[u8]) -> Chunk {
+        Chunk::new(ChunkType::from_str(chunk_type).unwrap(), data.to_vec())
+    }
+
+    fn testing_png() -> Png {
+        Png::from_chunks(vec![
+            chunk("IHDR", &[0; 13]),
+            chunk("tEXt", b"Comment\0hello"),
+            chunk("IDAT", b"pixels"),
+            chunk("IEND", b""),
+        ])
+    }
+
+    fn testing_key() -> SigningKey {
+        SigningKey::from_bytes(&[7; 32])
+    }
+
+    #[test]
+    fn test_sign_and_verify() {
+        let mut png = testing_png();
+        sign(&mut png, &testing_key());
+        assert_eq!(png.chunks()[3].chunk_type().to_string(), SIGNATURE_CHUNK);
+        assert_eq!(verify(&png, &testing_key().verifying_key()), Ok(()));
+    }
+
+    #[test]
+    fn test_resign_replaces_signature() {
+        let mut png = testing_png();
+        sign(&mut png, &testing_key());
+        sign(&mut png, &testing_key());
+        let count = png
+            .chunks()
+            .iter()
+            .filter(|c| c.chunk_type().as_str() == SIGNATURE_CHUNK)
+            .count();
+        assert_eq!(count, 1);
+    }
+
+    #[test]
+    fn test_ancillary_edits_keep_signature() {
+        let mut png = testing_png();
+        sign(&mut png, &testing_key());
+        png.remove_first_chunk("tEXt").unwrap();
+        assert_eq!(verify(&png, &testing_key().verifying_key()), Ok(()));
+    }
+
+    #[test]
+    fn test_image_edits_break_signature() {
+        let mut png = testing_png();
+        sign(&mut png, &testing_key());
+        png.remove_first_chunk("IDAT").unwrap();
+        png.append_chunk(chunk("IDAT", b"tampered"));
+        assert_eq!(
+            verify(&png, &testing_key().verifying_key()),
+            Err(SignError::InvalidSignature)
+        );
+    }
+
+    #[test]
+    fn test_wrong_key() {
+        let mut png = testing_png();
+        sign(&mut png, &testing_key());
+        let other = SigningKey::from_bytes(&[8; 32]).verifying_key();
+        assert_eq!(verify(&png, &other), Err(SignError::InvalidSignature));
+    }
+
+    #[test]
+    fn test_digest_signatures() {
+        for digest in [Algorithm::Sha256, Algorithm::Blake3] {
+            let mut png = testing_png();
+            sign_with(&mut png, &testing_key(), Some(digest));
+            let signature = png.chunk_by_type(SIGNATURE_CHUNK).unwrap();
+            assert_eq!(signature.data()[0], digest.id());
+            assert_eq!(verify(&png, &testing_key().verifying_key()), Ok(()));
+
+            png.remove_first_chunk("IDAT").unwrap();
+            png.append_chunk(chunk("IDAT", b"tampered"));
+            assert_eq!(
+                verify(&png, &testing_key().verifying_key()),
+                Err(SignError::InvalidSignature)
+            );
+        }
+    }
+
+    #[test]
+    fn test_missing_signature() {
+        let png = testing_png();
+        assert_eq!(
+            verify(&png, &testing_key().verifying_key()),
+            Err(SignError::MissingSignature)
+        );
+    }
+
+    #[test]
+    fn test_key_bytes() {
+        assert_eq!(key_bytes(&[1; 32]), Ok([1; 32]));
+        assert_eq!(key_bytes("ab".repeat(32).as_bytes()), Ok([0xab; 32]));
+        assert_eq!(key_bytes(b"short"), Err(SignError::InvalidKey));
+    }
+}
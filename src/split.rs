@@ -0,0 +1,111 @@
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::png::Png;
+use crate::Result;
+
+/// Lists the chunk files in order, one `<type>\t<file>` line each
+pub const MANIFEST: &str = "manifest.txt";
+
+/// Write each chunk's data to `dir/NNN-TYPE.bin` and list them in the manifest
+pub fn split(png: &Png, dir: &Path) -> Result<()> {
+    fs::create_dir_all(dir)?;
+    let mut manifest = String::new();
+    for (index, chunk) in png.chunks().iter().enumerate() {
+        let name = format!("{:03}-{}.bin", index, chunk.chunk_type());
+        fs::write(dir.join(&name), chunk.data())?;
+        manifest.push_str(&format!("{}\t{}\n", chunk.chunk_type(), name));
+    }
+    fs::write(dir.join(MANIFEST), manifest)?;
+    Ok(())
+}
+
+/// Rebuild a png from a directory written by [`split`], recomputing CRCs
+/// so chunk files can be edited in place. Chunk files must be in `dir`
+/// itself, so a manifest can't pull in files from elsewhere
+pub fn join(dir: &Path) -> Result<Png> {
+    let manifest = fs::read_to_string(dir.join(MANIFEST))?;
+    let mut chunks = Vec::new();
+    for line in manifest.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (chunk_type, name) = line
+            .split_once('\t')
+            .ok_or_else(|| format!("invalid manifest line: {}", line))?;
+        let chunk_type = ChunkType::from_str(chunk_type)?;
+        if Path::new(name).file_name() != Some(name.as_ref()) {
+            return Err(format!("chunk file {:?} isn't in the directory", name).into());
+        }
+        chunks.push(Chunk::new(chunk_type, fs::read(dir.join(name))?));
+    }
+    Ok(Png::from_chunks(chunks))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("ping-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn chunk(chunk_type: &str, data: &[u8]) -> Chunk {
+        Chunk::new(ChunkType::from_str(chunk_type).unwrap(), data.to_vec())
+    }
+
+    fn testing_png() -> Png {
+        Png::from_chunks(vec![
+            chunk("IHDR", &[0; 13]),
+            chunk("tEXt", b"Comment\0hello"),
+            chunk("IEND", b""),
+        ])
+    }
+
+    #[test]
+    fn test_split_join_round_trip() {
+        let dir = temp_dir("round-trip");
+        split(&testing_png(), &dir).unwrap();
+        assert!(dir.join("001-tEXt.bin").exists());
+        assert_eq!(join(&dir).unwrap().as_bytes(), testing_png().as_bytes());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_join_edited_chunk() {
+        let dir = temp_dir("edited");
+        split(&testing_png(), &dir).unwrap();
+        fs::write(dir.join("001-tEXt.bin"), b"Comment\0edited").unwrap();
+        let png = join(&dir).unwrap();
+        assert_eq!(png.chunks()[1].data(), b"Comment\0edited");
+        assert!(Png::try_from(png.as_bytes().as_ref()).is_ok());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_join_invalid_manifest() {
+        let dir = temp_dir("invalid");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join(MANIFEST), "IHDR 000-IHDR.bin\n").unwrap();
+        assert!(join(&dir).is_err());
+        fs::write(dir.join("000-IHDR.bin"), [0; 13]).unwrap();
+        for name in [
+            "../000-IHDR.bin",
+            "/etc/passwd",
+            "./000-IHDR.bin",
+            "sub/000-IHDR.bin",
+        ] {
+            fs::write(dir.join(MANIFEST), format!("IHDR\t{}\n", name)).unwrap();
+            let error = join(&dir).unwrap_err().to_string();
+            assert!(error.ends_with("isn't in the directory"), "{}", name);
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
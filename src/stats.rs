@@ -0,0 +1,158 @@
+use std::collections::BTreeMap;
+
+use crate::deflate;
+use crate::png::Png;
+use crate::stego;
+
+/// Percentiles of chunk lengths reported by [`file_stats`]
+pub const PERCENTILES: [usize; 4] = [50, 90, 99, 100];
+
+/// Measurements of one chunk's data
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChunkStats {
+    pub chunk_type: String,
+    pub offset: usize,
+    pub length: usize,
+    /// Shannon entropy in bits per byte
+    pub entropy: f64,
+    /// zlib-compressed length over length, above 1 when compression doesn't
+    /// pay; `None` for empty chunks
+    pub ratio: Option<f64>,
+}
+
+/// Per-chunk measurements of a file, with percentiles of their lengths
+#[derive(Debug, Clone, PartialEq)]
+pub struct FileStats {
+    pub chunks: Vec<ChunkStats>,
+    /// Lengths at each of [`PERCENTILES`], by the nearest-rank method
+    pub percentiles: Vec<usize>,
+}
+
+pub fn file_stats(png: &Png) -> FileStats {
+    let chunks: Vec<ChunkStats> = png
+        .chunks()
+        .iter()
+        .zip(png.chunk_offsets())
+        .map(|(chunk, offset)| {
+            let data = chunk.data();
+            ChunkStats {
+                chunk_type: chunk.chunk_type().to_string(),
+                offset,
+                length: data.len(),
+                entropy: stego::entropy(data),
+                ratio: (!data.is_empty()).then(|| {
+                    let compressed = deflate::backend().compress(data, deflate::BEST_LEVEL);
+                    compressed.len() as f64 / data.len() as f64
+                }),
+            }
+        })
+        .collect();
+    let mut lengths: Vec<usize> = chunks.iter().map(|c| c.length).collect();
+    lengths.sort_unstable();
+    FileStats {
+        percentiles: PERCENTILES
+            .iter()
+            .map(|p| percentile(&lengths, *p))
+            .collect(),
+        chunks,
+    }
+}
+
+/// Nearest-rank percentile of sorted values, 0 if there are none
+fn percentile(sorted: &[usize], percent: usize) -> usize {
+    match sorted.len() {
+        0 => 0,
+        len => sorted[(len * percent).div_ceil(100).max(1) - 1],
+    }
+}
+
+/// How often a chunk type occurs across files
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TypeCount {
+    pub chunks: usize,
+    /// Files with at least one chunk of the type
+    pub files: usize,
+    pub bytes: usize,
+}
+
+/// Chunk type histogram over a batch of files, by type
+#[derive(Debug, Default)]
+pub struct Histogram {
+    pub types: BTreeMap<String, TypeCount>,
+    pub files: usize,
+}
+
+impl Histogram {
+    pub fn add(&mut self, stats: &FileStats) {
+        self.files += 1;
+        let mut seen = Vec::new();
+        for chunk in &stats.chunks {
+            let count = self.types.entry(chunk.chunk_type.clone()).or_default();
+            count.chunks += 1;
+            count.bytes += chunk.length;
+            if !seen.contains(&&chunk.chunk_type) {
+                count.files += 1;
+                seen.push(&chunk.chunk_type);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::Chunk;
+    use crate::chunk_type::ChunkType;
+    use std::str::FromStr;
+
+    fn chunk(chunk_type: &str, data: &[u8]) -> Chunk {
+        Chunk::new(ChunkType::from_str(chunk_type).unwrap(), data.to_vec())
+    }
+
+    #[test]
+    fn test_file_stats() {
+        let png = Png::from_chunks(vec![
+            chunk("IHDR", &[0; 13]),
+            chunk("tEXt", b"Comment\0abab"),
+            chunk("IDAT", &[7; 1000]),
+            chunk("IEND", b""),
+        ]);
+        let stats = file_stats(&png);
+        assert_eq!(stats.chunks[0].entropy, 0.0);
+        assert_eq!(stats.chunks[2].offset, 8 + 25 + 24);
+        assert!(stats.chunks[2].ratio.unwrap() < 0.1);
+        assert_eq!(stats.chunks[3].ratio, None);
+        assert_eq!(stats.percentiles, [12, 1000, 1000, 1000]);
+    }
+
+    #[test]
+    fn test_percentile() {
+        let values: Vec<usize> = (1..=10).collect();
+        assert_eq!(percentile(&values, 50), 5);
+        assert_eq!(percentile(&values, 99), 10);
+        assert_eq!(percentile(&values, 0), 1);
+        assert_eq!(percentile(&[], 50), 0);
+    }
+
+    #[test]
+    fn test_histogram() {
+        let png = Png::from_chunks(vec![
+            chunk("IHDR", &[0; 13]),
+            chunk("IDAT", &[1; 10]),
+            chunk("IDAT", &[1; 20]),
+            chunk("IEND", b""),
+        ]);
+        let mut histogram = Histogram::default();
+        histogram.add(&file_stats(&png));
+        histogram.add(&file_stats(&png));
+        assert_eq!(histogram.files, 2);
+        assert_eq!(
+            histogram.types["IDAT"],
+            TypeCount {
+                chunks: 4,
+                files: 2,
+                bytes: 60
+            }
+        );
+    }
+}
@@ -0,0 +1,465 @@
+#![deny(clippy::unwrap_used, clippy::expect_used)]
+
+use std::fmt::Display;
+
+use crate::chunk::Chunk;
+use crate::grep;
+use crate::pixels::{PixelError, Pixels};
+use crate::png::Png;
+use crate::profile;
+use crate::text::TextChunk;
+#[cfg(feature = "crypto")]
+use crate::{chunk_type::ChunkType, order, payload};
+
+/// Where encode puts the payload
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum Method {
+    /// A chunk of its own, visible to `print`
+    Chunk,
+    /// The least-significant bit of every pixel sample
+    Lsb,
+    /// A zTXt chunk shaped like the raw IPTC profiles ImageMagick writes
+    Profile,
+    /// Appended after IEND, where decoders stop reading
+    Trailer,
+}
+
+/// Raw profile name disguised payloads are stored under
+const PROFILE_NAME: &str = "iptc";
+
+/// Bytes of the big-endian payload length written ahead of the payload
+const LENGTH_LEN: usize = 4;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum StegoError {
+    Pixels(PixelError),
+    /// Palette and sub-byte images can't hide data in their low bits
+    /// without visibly changing colours
+    Unsupported {
+        color_type: u8,
+        bit_depth: u8,
+    },
+    TooLarge {
+        capacity: usize,
+        needed: usize,
+    },
+    /// The recorded length doesn't fit the image, so nothing is embedded
+    NoPayload,
+    /// The file already has a real profile under the disguise keyword
+    ProfileExists,
+    TrailerExists,
+}
+
+impl Display for StegoError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StegoError::Pixels(e) => write!(f, "{}", e),
+            StegoError::Unsupported {
+                color_type,
+                bit_depth,
+            } => write!(
+                f,
+                "color type {} at bit depth {} can't carry lsb data",
+                color_type, bit_depth
+            ),
+            StegoError::TooLarge { capacity, needed } => write!(
+                f,
+                "payload needs {} bytes but the image only holds {}",
+                needed, capacity
+            ),
+            StegoError::NoPayload => write!(f, "image has no hidden payload"),
+            StegoError::TrailerExists => write!(f, "file already has data after IEND"),
+            StegoError::ProfileExists => {
+                write!(
+                    f,
+                    "file already has a {} chunk",
+                    profile::keyword(PROFILE_NAME)
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for StegoError {}
+
+impl From<PixelError> for StegoError {
+    fn from(value: PixelError) -> Self {
+        StegoError::Pixels(value)
+    }
+}
+
+/// Indices of the bytes whose low bit is a sample's low bit: every byte at
+/// depth 8, the second byte of each big-endian sample at depth 16
+pub fn carriers(pixels: &Pixels) -> Result<impl Iterator<Item = usize>, StegoError> {
+    let ihdr = pixels.ihdr;
+    let step = match (ihdr.color_type, ihdr.bit_depth) {
+        (0 | 2 | 4 | 6, 8) => 1,
+        (0 | 2 | 4 | 6, 16) => 2,
+        (color_type, bit_depth) => {
+            return Err(StegoError::Unsupported {
+                color_type,
+                bit_depth,
+            })
+        }
+    };
+    Ok((step - 1..pixels.data.len()).step_by(step))
+}
+
+fn capacity_of(pixels: &Pixels) -> Result<usize, StegoError> {
+    Ok((carriers(pixels)?.count() / 8).saturating_sub(LENGTH_LEN))
+}
+
+/// Largest payload, in bytes, that [`embed`] can hide in this image
+pub fn capacity(png: &Png) -> Result<usize, StegoError> {
+    capacity_of(&Pixels::read(png)?)
+}
+
+/// Hide `payload` in the pixel samples' low bits, rewriting the IDAT chunks
+/// and so dropping the chunks [`Pixels::write`] returns
+pub fn embed(png: &mut Png, payload: &[u8]) -> Result<Vec<Chunk>, StegoError> {
+    let mut pixels = Pixels::read(png)?;
+    let capacity = capacity_of(&pixels)?;
+    if payload.len() > capacity {
+        return Err(StegoError::TooLarge {
+            capacity,
+            needed: payload.len(),
+        });
+    }
+    let length = (payload.len() as u32).to_be_bytes();
+    let bits = length
+        .iter()
+        .chain(payload)
+        .flat_map(|byte| (0..8).rev().map(move |i| (byte >> i) & 1));
+    for (index, bit) in carriers(&pixels)?.zip(bits) {
+        pixels.data[index] = (pixels.data[index] & !1) | bit;
+    }
+    Ok(pixels.write(png))
+}
+
+/// Recover a payload hidden by [`embed`]
+pub fn extract(png: &Png) -> Result<Vec<u8>, StegoError> {
+    let pixels = Pixels::read(png)?;
+    let capacity = capacity_of(&pixels)?;
+    let bytes: Vec<u8> = carriers(&pixels)?
+        .map(|index| pixels.data[index] & 1)
+        .collect::<Vec<u8>>()
+        .chunks_exact(8)
+        .map(|bits| bits.iter().fold(0, |byte, bit| (byte << 1) | bit))
+        .collect();
+    let Some((length, rest)) = bytes.split_first_chunk::<LENGTH_LEN>() else {
+        return Err(StegoError::NoPayload);
+    };
+    let length = u32::from_be_bytes(*length) as usize;
+    match rest.get(..length) {
+        Some(payload) if length <= capacity => Ok(payload.to_vec()),
+        _ => Err(StegoError::NoPayload),
+    }
+}
+
+fn find_profile(png: &Png) -> Option<TextChunk> {
+    png.chunks()
+        .iter()
+        .filter_map(|c| TextChunk::parse(c)?.ok())
+        .find(|text| text.keyword == profile::keyword(PROFILE_NAME))
+}
+
+/// Store `payload` as a raw profile text chunk, so it reads as ordinary
+/// ImageMagick metadata rather than a custom chunk type
+pub fn embed_profile(png: &mut Png, payload: &[u8]) -> Result<(), StegoError> {
+    if find_profile(png).is_some() {
+        return Err(StegoError::ProfileExists);
+    }
+    let text = TextChunk {
+        keyword: profile::keyword(PROFILE_NAME),
+        text: profile::encode(PROFILE_NAME, payload),
+    };
+    png.append_chunk(text.to_ztxt());
+    Ok(())
+}
+
+/// Recover a payload stored by [`embed_profile`]
+pub fn extract_profile(png: &Png) -> Result<Vec<u8>, StegoError> {
+    let text = find_profile(png).ok_or(StegoError::NoPayload)?;
+    match profile::decode(&text.text) {
+        Some((_, payload)) => Ok(payload),
+        None => Err(StegoError::NoPayload),
+    }
+}
+
+/// Append `payload` after IEND
+pub fn embed_trailer(png: &mut Png, payload: &[u8]) -> Result<(), StegoError> {
+    if !png.trailing().is_empty() {
+        return Err(StegoError::TrailerExists);
+    }
+    png.set_trailing(payload.to_vec());
+    Ok(())
+}
+
+pub fn extract_trailer(png: &Png) -> Result<Vec<u8>, StegoError> {
+    match png.trailing() {
+        [] => Err(StegoError::NoPayload),
+        trailing => Ok(trailing.to_vec()),
+    }
+}
+
+/// Data found after IEND
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Trailer {
+    pub offset: usize,
+    pub length: usize,
+    /// The first bytes, printable characters only
+    pub preview: String,
+}
+
+/// Bytes of trailing data shown in a [`Trailer`] preview
+const PREVIEW_LEN: usize = 32;
+
+/// Look for data after IEND, whoever put it there
+pub fn scan(png: &Png) -> Option<Trailer> {
+    let trailing = png.trailing();
+    if trailing.is_empty() {
+        return None;
+    }
+    Some(Trailer {
+        offset: png.trailing_offset(),
+        length: trailing.len(),
+        preview: grep::printable(&trailing[..trailing.len().min(PREVIEW_LEN)]),
+    })
+}
+
+#[cfg(feature = "crypto")]
+/// What to [`estimate`] for
+#[derive(Debug, Clone, Copy)]
+pub struct EstimateOptions {
+    pub payload_len: usize,
+    /// Used by the chunk method
+    pub chunk_type: ChunkType,
+    pub max_chunk_size: usize,
+}
+
+#[cfg(feature = "crypto")]
+/// How well a method suits a payload, measured by embedding stand-in data
+#[derive(Debug, Clone, PartialEq)]
+pub struct Estimate {
+    /// Largest payload the method holds, `None` if only the format limits it
+    pub capacity: Option<usize>,
+    /// Change in file size, `None` if the payload doesn't fit
+    pub growth: Option<i64>,
+    /// Chunks of a type the PNG specification doesn't define
+    pub unknown_chunks: usize,
+    /// Change in the whole file's byte entropy, in bits per byte
+    pub entropy_delta: f64,
+    /// Rough odds, from 0 to 1, that a casual look at the file finds the
+    /// payload
+    pub detectability: f64,
+}
+
+/// Shannon entropy in bits per byte
+pub fn entropy(bytes: &[u8]) -> f64 {
+    let mut counts = [0usize; 256];
+    for byte in bytes {
+        counts[*byte as usize] += 1;
+    }
+    counts
+        .iter()
+        .filter(|&&n| n > 0)
+        .map(|&n| n as f64 / bytes.len() as f64)
+        .fold(0.0, |entropy, p| entropy - p * p.log2())
+}
+
+#[cfg(feature = "crypto")]
+fn unknown_chunks(png: &Png) -> usize {
+    png.chunks()
+        .iter()
+        .filter(|c| !order::is_standard(c.chunk_type().as_str()))
+        .count()
+}
+
+#[cfg(feature = "crypto")]
+/// Embed a random-looking stand-in for an encrypted payload into a copy of
+/// `png` and report what changed, so a method can be picked before the real
+/// payload is written
+pub fn estimate(
+    png: &Png,
+    method: Method,
+    options: &EstimateOptions,
+) -> Result<Estimate, StegoError> {
+    let mut stand_in = vec![0; options.payload_len];
+    blake3::Hasher::new().finalize_xof().fill(&mut stand_in);
+
+    let capacity = match method {
+        Method::Lsb => Some(capacity(png)?),
+        _ => None,
+    };
+    let fits = capacity.is_none_or(|c| options.payload_len <= c);
+    let mut after = png.clone();
+    if fits {
+        match method {
+            Method::Chunk => {
+                for part in payload::split(stand_in, options.max_chunk_size) {
+                    after.append_chunk(Chunk::new(options.chunk_type, part));
+                }
+            }
+            Method::Lsb => {
+                embed(&mut after, &stand_in)?;
+            }
+            Method::Profile => embed_profile(&mut after, &stand_in)?,
+            Method::Trailer => embed_trailer(&mut after, &stand_in)?,
+        }
+    }
+    let (before, after_bytes) = (png.as_bytes(), after.as_bytes());
+    let entropy_delta = entropy(&after_bytes) - entropy(&before);
+    let unknown = unknown_chunks(&after);
+
+    // chunk lists show custom chunks at once and data after IEND is the
+    // first thing forensic tools check; raw profiles are common metadata;
+    // lsb statistics weaken with the fraction of the capacity used. A rise
+    // in entropy only nudges the score, since on small files any payload
+    // shifts it a lot
+    let base = match method {
+        _ if unknown > unknown_chunks(png) => 0.9,
+        Method::Trailer => 0.7,
+        Method::Profile => 0.3,
+        Method::Lsb => 0.6 * options.payload_len as f64 / capacity.unwrap_or(1).max(1) as f64,
+        Method::Chunk => 0.0,
+    };
+    Ok(Estimate {
+        capacity,
+        growth: fits.then(|| after_bytes.len() as i64 - before.len() as i64),
+        unknown_chunks: unknown,
+        entropy_delta,
+        detectability: (base + 0.1 * entropy_delta.max(0.0)).min(1.0),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pixels::tests::{interlaced_png, testing_png};
+
+    #[test]
+    fn test_capacity() {
+        // 16 * 16 RGB samples, one bit each, less the length prefix
+        let png = testing_png(16, 16);
+        assert_eq!(capacity(&png), Ok(16 * 16 * 3 / 8 - LENGTH_LEN));
+    }
+
+    #[test]
+    fn test_embed_and_extract() {
+        let mut png = testing_png(16, 16);
+        // Only the unknown chunk that isn't safe to copy goes
+        for chunk_type in ["ruSt", "ruST", "gAMA"] {
+            png.insert_chunk(1, Chunk::new(chunk_type.parse().unwrap(), vec![0; 4]));
+        }
+        let before = Pixels::read(&png).unwrap().data;
+        let dropped = embed(&mut png, b"attack at dawn").unwrap();
+        assert_eq!(dropped.len(), 1);
+        assert_eq!(dropped[0].chunk_type().as_str(), "ruST");
+        assert_eq!(png.chunks().len(), 5);
+        assert_eq!(extract(&png).unwrap(), b"attack at dawn");
+
+        let after = Pixels::read(&png).unwrap().data;
+        assert!(before.iter().zip(&after).all(|(a, b)| a >> 1 == b >> 1));
+    }
+
+    #[test]
+    fn test_interlaced() {
+        let mut png = interlaced_png(16, 16);
+        assert_eq!(capacity(&png), capacity(&testing_png(16, 16)));
+        embed(&mut png, b"attack at dawn").unwrap();
+        assert_eq!(png.chunks()[0].data()[12], 1);
+        assert_eq!(extract(&png).unwrap(), b"attack at dawn");
+    }
+
+    #[test]
+    fn test_payload_too_large() {
+        let mut png = testing_png(4, 4);
+        assert_eq!(
+            embed(&mut png, &[0; 100]),
+            Err(StegoError::TooLarge {
+                capacity: 2,
+                needed: 100
+            })
+        );
+    }
+
+    #[test]
+    fn test_no_payload() {
+        // the untouched test image's low bits alternate, reading as a
+        // length far beyond its capacity
+        let mut png = testing_png(16, 16);
+        assert_eq!(extract(&png), Err(StegoError::NoPayload));
+
+        embed(&mut png, &[]).unwrap();
+        assert_eq!(extract(&png).unwrap(), b"");
+    }
+
+    #[test]
+    fn test_profile_round_trip() {
+        let mut png = testing_png(4, 4);
+        embed_profile(&mut png, b"\x89PAY binary payload").unwrap();
+        assert_eq!(png.chunks()[2].chunk_type().to_string(), "zTXt");
+        assert_eq!(extract_profile(&png).unwrap(), b"\x89PAY binary payload");
+        assert_eq!(
+            embed_profile(&mut png, b"again"),
+            Err(StegoError::ProfileExists)
+        );
+        assert_eq!(
+            extract_profile(&testing_png(4, 4)),
+            Err(StegoError::NoPayload)
+        );
+    }
+
+    #[cfg(feature = "crypto")]
+    fn options(payload_len: usize) -> EstimateOptions {
+        EstimateOptions {
+            payload_len,
+            chunk_type: "ruSt".parse().unwrap(),
+            max_chunk_size: 1 << 20,
+        }
+    }
+
+    #[cfg(feature = "crypto")]
+    #[test]
+    fn test_estimate() {
+        let png = testing_png(16, 16);
+        let chunk = estimate(&png, Method::Chunk, &options(50)).unwrap();
+        assert_eq!(chunk.capacity, None);
+        assert_eq!(chunk.growth, Some(50 + 12));
+        assert_eq!(chunk.unknown_chunks, 1);
+        assert!(chunk.detectability >= 0.9);
+
+        let trailer = estimate(&png, Method::Trailer, &options(50)).unwrap();
+        assert_eq!(trailer.growth, Some(50));
+        assert_eq!(trailer.unknown_chunks, 0);
+
+        let lsb = estimate(&png, Method::Lsb, &options(50)).unwrap();
+        assert_eq!(lsb.capacity, Some(92));
+        assert!(lsb.growth.is_some());
+        assert!(lsb.detectability < chunk.detectability);
+        assert_eq!(
+            estimate(&png, Method::Lsb, &options(500)).unwrap().growth,
+            None
+        );
+    }
+
+    #[test]
+    fn test_trailer_round_trip() {
+        let mut png = testing_png(4, 4);
+        assert_eq!(scan(&png), None);
+        let offset = png.as_bytes().len();
+        embed_trailer(&mut png, b"after the end\x00").unwrap();
+
+        let png = Png::try_from(png.as_bytes().as_slice()).unwrap();
+        assert_eq!(extract_trailer(&png).unwrap(), b"after the end\x00");
+        assert_eq!(
+            scan(&png),
+            Some(Trailer {
+                offset,
+                length: 14,
+                preview: "after the end.".to_string(),
+            })
+        );
+    }
+}
@@ -0,0 +1,389 @@
+use std::fmt::Display;
+use std::io::{self, Read, Write};
+
+use crate::compress::Codec;
+use crate::crypto::{self, KdfParams, KEY_LEN, NONCE_LEN, SALT_LEN, TAG_LEN};
+use crate::payload::{Format, MAGIC};
+
+/// Random stream id linking the segments to their header
+const ID_LEN: usize = 8;
+
+/// Each segment's nonce is this prefix, the u32 segment index and a byte
+/// marking the last segment, so segments can't be reordered, dropped or
+/// cut short unnoticed
+const NONCE_PREFIX_LEN: usize = NONCE_LEN - 5;
+
+/// Stream header: magic, format, codec, encrypted flag, kdf params, salt,
+/// nonce prefix, stream id
+const HEADER_LEN: usize = MAGIC.len() + 3 + KdfParams::LEN + SALT_LEN + NONCE_PREFIX_LEN + ID_LEN;
+
+/// Segment: magic, format, stream id, u32 index, last flag, then the data
+const SEGMENT_START: usize = MAGIC.len() + 1 + ID_LEN + 4 + 1;
+
+/// Unencrypted streams end with a BLAKE3 hash of the compressed data
+const HASH_LEN: usize = 32;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum StreamError {
+    Truncated,
+    PasswordRequired,
+    UnknownCodec(u8),
+    /// A segment is missing or out of place
+    OutOfOrder {
+        expected: u32,
+        found: u32,
+    },
+    /// The stream ended without its last segment
+    Unterminated,
+    Decrypt,
+    HashMismatch,
+}
+
+impl Display for StreamError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StreamError::Truncated => write!(f, "streamed payload segment is truncated"),
+            StreamError::PasswordRequired => {
+                write!(f, "payload is encrypted, a password is required")
+            }
+            StreamError::UnknownCodec(id) => write!(f, "unknown compression codec {}", id),
+            StreamError::OutOfOrder { expected, found } => {
+                write!(f, "expected payload segment {}, found {}", expected, found)
+            }
+            StreamError::Unterminated => write!(f, "streamed payload is missing its end"),
+            StreamError::Decrypt => write!(f, "decryption failed: wrong password or corrupt data"),
+            StreamError::HashMismatch => write!(f, "streamed payload does not match its hash"),
+        }
+    }
+}
+
+impl std::error::Error for StreamError {}
+
+/// Whether `data` starts a streamed payload
+pub fn is_stream(data: &[u8]) -> bool {
+    data.len() == HEADER_LEN
+        && data.starts_with(&MAGIC)
+        && data[MAGIC.len()] == Format::Stream as u8
+}
+
+/// Whether a stream header needs a password
+pub fn is_encrypted(header: &[u8]) -> bool {
+    is_stream(header) && header[MAGIC.len() + 2] == 1
+}
+
+fn segment_nonce(prefix: &[u8], index: u32, last: bool) -> [u8; NONCE_LEN] {
+    let mut nonce = [0u8; NONCE_LEN];
+    nonce[..NONCE_PREFIX_LEN].copy_from_slice(prefix);
+    nonce[NONCE_PREFIX_LEN..NONCE_LEN - 1].copy_from_slice(&index.to_be_bytes());
+    nonce[NONCE_LEN - 1] = last as u8;
+    nonce
+}
+
+/// Frames what's written to it as segments of at most `segment_len` bytes,
+/// encrypting them if it has a key, and hands each to `emit`
+struct SegmentWriter<'a, F: FnMut(Vec<u8>) -> io::Result<()>> {
+    header: &'a [u8],
+    key: Option<[u8; KEY_LEN]>,
+    index: u32,
+    segment_len: usize,
+    buffer: Vec<u8>,
+    hasher: blake3::Hasher,
+    emit: F,
+}
+
+impl<F: FnMut(Vec<u8>) -> io::Result<()>> SegmentWriter<'_, F> {
+    fn seal(&mut self, len: usize, last: bool) -> io::Result<()> {
+        let data: Vec<u8> = self.buffer.drain(..len).collect();
+        let mut segment = MAGIC.to_vec();
+        segment.push(Format::Segment as u8);
+        segment.extend_from_slice(&self.header[HEADER_LEN - ID_LEN..]);
+        segment.extend_from_slice(&self.index.to_be_bytes());
+        segment.push(last as u8);
+        match &self.key {
+            Some(key) => {
+                let prefix =
+                    &self.header[HEADER_LEN - ID_LEN - NONCE_PREFIX_LEN..][..NONCE_PREFIX_LEN];
+                let nonce = segment_nonce(prefix, self.index, last);
+                segment.extend(
+                    crypto::encrypt(key, &nonce, &data, self.header).map_err(io::Error::other)?,
+                );
+            }
+            None => {
+                self.hasher.update(&data);
+                segment.extend_from_slice(&data);
+                if last {
+                    segment.extend_from_slice(self.hasher.finalize().as_bytes());
+                }
+            }
+        }
+        self.index += 1;
+        (self.emit)(segment)
+    }
+
+    fn finish(mut self) -> io::Result<()> {
+        self.seal(self.buffer.len(), true)
+    }
+}
+
+impl<F: FnMut(Vec<u8>) -> io::Result<()>> Write for SegmentWriter<'_, F> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buffer.extend_from_slice(buf);
+        // the last segment is only known at finish, so one is always held back
+        while self.buffer.len() > self.segment_len {
+            self.seal(self.segment_len, false)?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Compress, encrypt and split everything read from `input` into chunk data
+/// of at most `max_chunk_len` bytes, passing each to `emit` as soon as it's
+/// ready: first a stream header, then the segments. Memory use is bounded
+/// by the chunk size, whatever the size of the input
+pub fn encode(
+    input: &mut impl Read,
+    codec: Codec,
+    encryption: Option<(&[u8], &KdfParams)>,
+    max_chunk_len: usize,
+    mut emit: impl FnMut(Vec<u8>) -> io::Result<()>,
+) -> crate::Result<()> {
+    let mut header = MAGIC.to_vec();
+    header.extend_from_slice(&[
+        Format::Stream as u8,
+        codec as u8,
+        encryption.is_some() as u8,
+    ]);
+    let salt: [u8; SALT_LEN] = crypto::random()?;
+    let key = match encryption {
+        Some((secret, params)) => {
+            header.extend_from_slice(&params.to_bytes());
+            Some(crypto::derive_key(secret, &salt, params)?)
+        }
+        None => {
+            header.extend_from_slice(&[0; KdfParams::LEN]);
+            None
+        }
+    };
+    header.extend_from_slice(&salt);
+    header.extend_from_slice(&crypto::random::<NONCE_PREFIX_LEN>()?);
+    header.extend_from_slice(&crypto::random::<ID_LEN>()?);
+    emit(header.clone())?;
+
+    let overhead = SEGMENT_START + TAG_LEN.max(HASH_LEN);
+    let mut writer = SegmentWriter {
+        header: &header,
+        key,
+        index: 0,
+        segment_len: max_chunk_len.saturating_sub(overhead).max(1),
+        buffer: Vec::new(),
+        hasher: blake3::Hasher::new(),
+        emit,
+    };
+    codec.compress_stream(input, &mut writer)?;
+    writer.finish()?;
+    Ok(())
+}
+
+/// Decrypt and decompress the stream whose header and segments are the
+/// data of `chunks`, in file order, into `output`. Segments of other streams
+/// are skipped. The whole stream is checked before anything is written,
+/// so tampered or cut short data never reaches `output`
+pub fn decode<'a>(
+    mut chunks: impl Iterator<Item = &'a [u8]> + Clone,
+    secret: Option<&[u8]>,
+    kdf_limit: Option<&KdfParams>,
+    output: impl Write,
+) -> crate::Result<()> {
+    let header = chunks
+        .next()
+        .filter(|h| is_stream(h))
+        .ok_or(StreamError::Truncated)?;
+    let codec = Codec::try_from(header[MAGIC.len() + 1]).map_err(StreamError::UnknownCodec)?;
+    let rest = &header[MAGIC.len() + 3..];
+    let (params, rest) = rest.split_at(KdfParams::LEN);
+    let (salt, rest) = rest.split_at(SALT_LEN);
+    let (prefix, id) = rest.split_at(NONCE_PREFIX_LEN);
+    let key = match is_encrypted(header) {
+        true => {
+            let secret = secret.ok_or(StreamError::PasswordRequired)?;
+            let params = KdfParams::from_bytes(params.try_into().unwrap());
+            if kdf_limit.is_some_and(|limit| !params.within(limit)) {
+                return Err(crypto::CryptoError::KdfTooCostly(params).into());
+            }
+            Some(crypto::derive_key(secret, salt, &params)?)
+        }
+        false => None,
+    };
+
+    let segments = chunks.filter(|c| {
+        c.len() >= SEGMENT_START
+            && c.starts_with(&MAGIC)
+            && c[MAGIC.len()] == Format::Segment as u8
+            && &c[MAGIC.len() + 1..][..ID_LEN] == id
+    });
+    let stream = Segments {
+        header,
+        key: key.as_ref(),
+        prefix,
+    };
+    stream.each(segments.clone(), |_| Ok(()))?;
+    let mut output = codec.decompressor(output)?;
+    stream.each(segments, |data| output.write_all(data))?;
+    Ok(output.flush()?)
+}
+
+/// What reading a stream's segments takes from its header
+struct Segments<'a> {
+    header: &'a [u8],
+    key: Option<&'a [u8; KEY_LEN]>,
+    prefix: &'a [u8],
+}
+
+impl Segments<'_> {
+    /// Pass each segment's data, decrypted, to `each` in order, failing at
+    /// the first out of place or not authentic, and if none is last
+    fn each<'a>(
+        &self,
+        segments: impl Iterator<Item = &'a [u8]>,
+        mut each: impl FnMut(&[u8]) -> io::Result<()>,
+    ) -> crate::Result<()> {
+        let mut hasher = blake3::Hasher::new();
+        for (expected, segment) in (0..).zip(segments) {
+            let index = u32::from_be_bytes(
+                segment[SEGMENT_START - 5..SEGMENT_START - 1]
+                    .try_into()
+                    .unwrap(),
+            );
+            if index != expected {
+                return Err(StreamError::OutOfOrder {
+                    expected,
+                    found: index,
+                }
+                .into());
+            }
+            let last = segment[SEGMENT_START - 1] == 1;
+            let body = &segment[SEGMENT_START..];
+            match self.key {
+                Some(key) => {
+                    let nonce = segment_nonce(self.prefix, index, last);
+                    let data = crypto::decrypt(key, &nonce, body, self.header)
+                        .map_err(|_| StreamError::Decrypt)?;
+                    each(&data)?;
+                }
+                None => {
+                    let data = match last {
+                        true => body
+                            .get(..body.len().wrapping_sub(HASH_LEN))
+                            .ok_or(StreamError::Truncated)?,
+                        false => body,
+                    };
+                    hasher.update(data);
+                    each(data)?;
+                    if last && hasher.finalize().as_bytes()[..] != body[data.len()..] {
+                        return Err(StreamError::HashMismatch.into());
+                    }
+                }
+            }
+            if last {
+                return Ok(());
+            }
+        }
+        Err(StreamError::Unterminated.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cheap() -> KdfParams {
+        KdfParams {
+            memory_kib: 1024,
+            time_cost: 1,
+            ..KdfParams::default()
+        }
+    }
+
+    fn encoded(
+        data: &[u8],
+        codec: Codec,
+        secret: Option<&[u8]>,
+        max_chunk_len: usize,
+    ) -> Vec<Vec<u8>> {
+        let mut chunks = Vec::new();
+        let params = cheap();
+        encode(
+            &mut &data[..],
+            codec,
+            secret.map(|s| (s, &params)),
+            max_chunk_len,
+            |chunk| {
+                assert!(chunk.len() <= max_chunk_len.max(HEADER_LEN));
+                chunks.push(chunk);
+                Ok(())
+            },
+        )
+        .unwrap();
+        chunks
+    }
+
+    fn decoded(chunks: &[Vec<u8>], secret: Option<&[u8]>) -> crate::Result<Vec<u8>> {
+        let mut out = Vec::new();
+        decode(chunks.iter().map(|c| c.as_slice()), secret, None, &mut out)?;
+        Ok(out)
+    }
+
+    fn testing_data() -> Vec<u8> {
+        (0..10_000u32).flat_map(|i| (i * 7).to_be_bytes()).collect()
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let data = testing_data();
+        for codec in [Codec::None, Codec::Zstd] {
+            for secret in [None, Some(&b"hunter2"[..])] {
+                let chunks = encoded(&data, codec, secret, 4096);
+                assert!(is_stream(&chunks[0]));
+                assert_eq!(is_encrypted(&chunks[0]), secret.is_some());
+                assert_eq!(decoded(&chunks, secret).unwrap(), data);
+            }
+        }
+        let chunks = encoded(b"", Codec::None, None, 4096);
+        assert_eq!(chunks.len(), 2);
+        assert_eq!(decoded(&chunks, None).unwrap(), b"");
+    }
+
+    #[test]
+    fn test_segments_checked() {
+        let data = testing_data();
+        let chunks = encoded(&data, Codec::None, Some(b"hunter2"), 4096);
+        assert!(chunks.len() > 5);
+
+        let mut dropped = chunks.clone();
+        dropped.remove(2);
+        assert!(decoded(&dropped, Some(b"hunter2")).is_err());
+
+        let cut = &chunks[..chunks.len() - 1];
+        let error = decoded(cut, Some(b"hunter2")).unwrap_err();
+        assert_eq!(error.to_string(), StreamError::Unterminated.to_string());
+
+        let error = decoded(&chunks, Some(b"wrong")).unwrap_err();
+        assert_eq!(error.to_string(), StreamError::Decrypt.to_string());
+
+        let mut plain = encoded(&data, Codec::None, None, 4096);
+        plain[3][SEGMENT_START] ^= 1;
+        let error = decoded(&plain, None).unwrap_err();
+        assert_eq!(error.to_string(), StreamError::HashMismatch.to_string());
+
+        // Nothing is written before the stream checks out
+        for (chunks, secret) in [(&plain[..], None), (cut, Some(&b"hunter2"[..]))] {
+            let mut out = Vec::new();
+            assert!(decode(chunks.iter().map(|c| c.as_slice()), secret, None, &mut out).is_err());
+            assert!(out.is_empty());
+        }
+    }
+}
@@ -0,0 +1,572 @@
+//! Validating, stripping and encoding files of any size in bounded memory.
+//! Chunks pass from reader to writer a block at a time, their CRCs checked
+//! on the way, so nothing is held but the block and, when encoding, a
+//! chunk of payload. [`Options::max_memory`] caps both, however large the
+//! file or any one chunk in it. [`append`] skips even the CRC checks,
+//! copying everything before IEND untouched
+
+#![deny(clippy::unwrap_used, clippy::expect_used)]
+
+use std::collections::HashSet;
+use std::fmt::Display;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::crc::CrcHasher;
+use crate::message;
+use crate::order::{self, Misplaced, Placement};
+use crate::perf;
+use crate::pixels::{Ihdr, PixelError};
+use crate::png::{CrcMismatch, Png, PngError};
+use crate::text;
+
+/// Bytes of chunk data read and written at a time
+const BLOCK: usize = Chunk::READ_BLOCK;
+
+/// The least `max_memory` a run can work in
+pub const MIN_MEMORY: usize = 4 * BLOCK;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Options {
+    /// Bytes a run may allocate at once, beyond what the key derivation
+    /// takes when encoding with encryption
+    pub max_memory: usize,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            max_memory: 64 << 20,
+        }
+    }
+}
+
+impl Options {
+    fn check(&self) -> Result<(), StreamingError> {
+        match self.max_memory < MIN_MEMORY {
+            true => Err(StreamingError::TooLittleMemory(self.max_memory)),
+            false => Ok(()),
+        }
+    }
+
+    /// Payload bytes per chunk when encoding. Sealing a segment holds it
+    /// a few times over, so this leaves room for that and the codec
+    #[cfg(feature = "crypto")]
+    fn payload_chunk_len(&self) -> usize {
+        (self.max_memory / 8).min(i32::MAX as usize)
+    }
+}
+
+#[derive(Debug)]
+pub enum StreamingError {
+    Io(io::Error),
+    Png(PngError),
+    MissingIhdr,
+    MissingIend,
+    /// A second chunk, at this offset, of a type the spec allows once
+    Duplicate(usize, ChunkType),
+    Misplaced(Misplaced),
+    Header(PixelError),
+    TooLittleMemory(usize),
+}
+
+impl Display for StreamingError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StreamingError::Io(e) => write!(f, "{}", e),
+            StreamingError::Png(e) => write!(f, "{}", e),
+            StreamingError::MissingIhdr => write!(f, "the first chunk isn't IHDR"),
+            StreamingError::MissingIend => write!(f, "the last chunk isn't IEND"),
+            StreamingError::Duplicate(offset, chunk_type) => {
+                write!(f, "a second {} chunk at offset {}", chunk_type, offset)
+            }
+            StreamingError::Misplaced(misplaced) => write!(f, "{}", misplaced),
+            StreamingError::Header(e) => write!(f, "{}", e),
+            StreamingError::TooLittleMemory(max) => write!(
+                f,
+                "{} bytes of memory is too little, at least {} are needed",
+                max, MIN_MEMORY
+            ),
+        }
+    }
+}
+
+impl std::error::Error for StreamingError {}
+
+impl From<io::Error> for StreamingError {
+    fn from(e: io::Error) -> Self {
+        StreamingError::Io(e)
+    }
+}
+
+impl From<PixelError> for StreamingError {
+    fn from(e: PixelError) -> Self {
+        StreamingError::Header(e)
+    }
+}
+
+impl From<PngError> for StreamingError {
+    fn from(e: PngError) -> Self {
+        StreamingError::Png(e)
+    }
+}
+
+/// Fill as much of `buffer` as the reader has left, returning how much
+fn read_full(reader: &mut impl Read, buffer: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buffer.len() {
+        match reader.read(&mut buffer[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(filled)
+}
+
+/// A file read chunk by chunk, each chunk's data a block at a time
+struct Walker<R> {
+    reader: R,
+    offset: usize,
+    /// Position of the next chunk in the file's chunk list
+    index: usize,
+    block: Vec<u8>,
+}
+
+impl<R: Read> Walker<R> {
+    fn new(mut reader: R, options: &Options) -> Result<Walker<R>, StreamingError> {
+        options.check()?;
+        let mut header = [0; 8];
+        let read = read_full(&mut reader, &mut header)?;
+        Png::check_signature(&header[..read])?;
+        Ok(Walker {
+            reader,
+            offset: header.len(),
+            index: 0,
+            block: vec![0; BLOCK],
+        })
+    }
+
+    /// The next chunk's length and type, or `None` at the end of the file
+    fn next(&mut self) -> Result<Option<(u32, ChunkType)>, StreamingError> {
+        let mut head = [0; 8];
+        match read_full(&mut self.reader, &mut head)? {
+            0 => return Ok(None),
+            8 => {}
+            _ => return Err(PngError::Truncated(self.offset).into()),
+        }
+        let (length, chunk_type) = Chunk::split_head(head);
+        let chunk_type =
+            ChunkType::try_from(chunk_type).map_err(|e| PngError::Chunk(self.offset, e))?;
+        Ok(Some((length, chunk_type)))
+    }
+
+    /// Read the data and CRC of the chunk [`next`](Walker::next) returned,
+    /// checking the CRC and copying the whole chunk to `out` if given.
+    /// What's copied of a chunk that fails is already written
+    fn pass(
+        &mut self,
+        length: u32,
+        chunk_type: ChunkType,
+        mut out: Option<&mut dyn Write>,
+    ) -> Result<(), StreamingError> {
+        if let Some(out) = out.as_mut() {
+            out.write_all(&length.to_be_bytes())?;
+            out.write_all(&chunk_type.bytes())?;
+        }
+        let mut hasher = CrcHasher::new();
+        hasher.update(&chunk_type.bytes());
+        let mut left = length as usize;
+        while left > 0 {
+            let block = &mut self.block[..left.min(BLOCK)];
+            if read_full(&mut self.reader, block)? < block.len() {
+                return Err(PngError::Truncated(self.offset).into());
+            }
+            hasher.update(block);
+            if let Some(out) = out.as_mut() {
+                out.write_all(block)?;
+            }
+            left -= block.len();
+        }
+        let mut crc = [0; 4];
+        if read_full(&mut self.reader, &mut crc)? < crc.len() {
+            return Err(PngError::Truncated(self.offset).into());
+        }
+        let (expected, actual) = (u32::from_be_bytes(crc), hasher.finalize());
+        if expected != actual {
+            return Err(PngError::Crc(CrcMismatch {
+                index: self.index,
+                offset: self.offset,
+                chunk_type,
+                length,
+                stored: expected,
+                computed: actual,
+            })
+            .into());
+        }
+        if let Some(out) = out {
+            out.write_all(&crc)?;
+        }
+        self.offset += length as usize + Chunk::OVERHEAD;
+        self.index += 1;
+        perf::parsed(length as usize + Chunk::OVERHEAD, 1);
+        Ok(())
+    }
+
+    /// Copy whatever follows to `out`
+    #[cfg(feature = "crypto")]
+    fn copy_rest(&mut self, out: &mut dyn Write) -> io::Result<()> {
+        loop {
+            match read_full(&mut self.reader, &mut self.block)? {
+                0 => return Ok(()),
+                n => out.write_all(&self.block[..n])?,
+            }
+        }
+    }
+}
+
+/// The first bytes written to it, up to a limit, keeping none of the rest
+struct Prefix {
+    bytes: Vec<u8>,
+    limit: usize,
+}
+
+impl Write for Prefix {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let room = self.limit - self.bytes.len();
+        self.bytes.extend_from_slice(&buf[..buf.len().min(room)]);
+        Ok(buf.len())
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Check `reader` as [`validate`](crate::validate::validate) checks a
+/// file in memory with every check on but palette indices, which would
+/// mean inflating the image, returning how many chunks it has
+pub fn validate(reader: impl Read, options: &Options) -> Result<usize, StreamingError> {
+    let mut walker = Walker::new(reader, options)?;
+    let (mut chunks, mut last) = (0, None);
+    let mut seen = HashSet::new();
+    // Placement in constant memory, however many chunks the file has
+    let (mut placement, mut misplaced) = (Placement::default(), None);
+    let (mut ihdr, mut plte, mut trns) = (None, None, None);
+    while let Some((length, chunk_type)) = walker.next()? {
+        let placed = placement.push(walker.offset, chunk_type);
+        misplaced = misplaced.or(placed);
+        if chunks == 0 && chunk_type.as_str() != "IHDR" {
+            return Err(StreamingError::MissingIhdr);
+        }
+        if order::is_unique(chunk_type.as_str()) && !seen.insert(chunk_type) {
+            return Err(StreamingError::Duplicate(walker.offset, chunk_type));
+        }
+        if chunks == 0 {
+            let mut head = Prefix {
+                bytes: Vec::new(),
+                limit: 8 + Ihdr::LEN,
+            };
+            walker.pass(length, chunk_type, Some(&mut head))?;
+            let parsed = match length as usize {
+                Ihdr::LEN => Ihdr::parse(&head.bytes[8..]),
+                _ => Err(PixelError::InvalidIhdr),
+            };
+            ihdr = Some(parsed.and_then(|ihdr| ihdr.check().map(|_| ihdr))?);
+        } else if matches!(chunk_type.as_str(), "tEXt" | "zTXt" | "iTXt") {
+            // The length and type, then as much data as a keyword can take
+            let mut head = Prefix {
+                bytes: Vec::new(),
+                limit: 8 + 80,
+            };
+            let offset = walker.offset;
+            walker.pass(length, chunk_type, Some(&mut head))?;
+            if text::check_keyword_in(&head.bytes[8..]).is_err() {
+                return Err(PngError::InvalidKeyword(offset).into());
+            }
+        } else {
+            walker.pass(length, chunk_type, None)?;
+        }
+        match chunk_type.as_str() {
+            "PLTE" => plte = Some(length as usize),
+            "tRNS" => trns = Some(length as usize),
+            _ => {}
+        }
+        chunks += 1;
+        last = Some(chunk_type);
+        if chunk_type.as_str() == "IEND" {
+            break;
+        }
+    }
+    match last.map(|t| t.as_str() == "IEND") {
+        Some(true) => {
+            if let Some(ihdr) = ihdr {
+                ihdr.check_palette(plte)?;
+                ihdr.check_transparency(plte, trns)?;
+            }
+            match misplaced {
+                Some(misplaced) => Err(StreamingError::Misplaced(misplaced)),
+                None => Ok(chunks),
+            }
+        }
+        Some(false) => Err(StreamingError::MissingIend),
+        None => Err(StreamingError::MissingIhdr),
+    }
+}
+
+/// Copy `reader` to `writer` without the chunks and trailing data
+/// [`message::strip`] drops, returning how many chunks went
+pub fn strip(
+    reader: impl Read,
+    mut writer: impl Write,
+    options: &Options,
+) -> Result<usize, StreamingError> {
+    let mut walker = Walker::new(reader, options)?;
+    writer.write_all(&Png::STANDARD_HEADER)?;
+    let mut removed = 0;
+    while let Some((length, chunk_type)) = walker.next()? {
+        match message::keeps(&chunk_type) {
+            true => walker.pass(length, chunk_type, Some(&mut writer))?,
+            false => {
+                walker.pass(length, chunk_type, None)?;
+                removed += 1;
+            }
+        }
+        if chunk_type.as_str() == "IEND" {
+            break;
+        }
+    }
+    writer.flush()?;
+    Ok(removed)
+}
+
+/// Where IEND starts in `reader`, found by seeking from one chunk header
+/// to the next without reading any chunk's data
+pub fn iend_offset(reader: &mut (impl Read + Seek)) -> Result<u64, StreamingError> {
+    let mut header = [0; 8];
+    reader.seek(SeekFrom::Start(0))?;
+    let read = read_full(reader, &mut header)?;
+    Png::check_signature(&header[..read])?;
+    let mut offset = header.len() as u64;
+    loop {
+        let mut head = [0; 8];
+        match read_full(reader, &mut head)? {
+            0 => return Err(StreamingError::MissingIend),
+            8 => {}
+            _ => return Err(PngError::Truncated(offset as usize).into()),
+        }
+        let (length, chunk_type) = Chunk::split_head(head);
+        if &chunk_type == b"IEND" {
+            return Ok(offset);
+        }
+        offset += length as u64 + Chunk::OVERHEAD as u64;
+        reader.seek(SeekFrom::Start(offset))?;
+    }
+}
+
+/// Copy `reader` to `writer` with `chunks` added before IEND, as
+/// [`Png::append_chunk`] would. Nothing but the chunk headers is read
+/// until the copy, and no CRC but the new chunks' is worked out
+pub fn append(
+    mut reader: impl Read + Seek,
+    mut writer: impl Write,
+    chunks: &[Chunk],
+) -> Result<(), StreamingError> {
+    let iend = iend_offset(&mut reader)?;
+    reader.seek(SeekFrom::Start(0))?;
+    io::copy(&mut (&mut reader).take(iend), &mut writer)?;
+    for chunk in chunks {
+        chunk.write_to(&mut writer)?;
+    }
+    io::copy(&mut reader, &mut writer)?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// Copy `reader` to `writer` with everything read from `message` added
+/// before IEND as a streamed payload in `chunk_type` chunks, which `ping
+/// decode` reads; see [`stream::encode`](crate::stream::encode)
+#[cfg(feature = "crypto")]
+pub fn encode(
+    reader: impl Read,
+    mut writer: impl Write,
+    chunk_type: ChunkType,
+    message: &mut impl Read,
+    codec: crate::compress::Codec,
+    encryption: Option<(&[u8], &crate::crypto::KdfParams)>,
+    options: &Options,
+) -> crate::Result<()> {
+    let mut walker = Walker::new(reader, options)?;
+    writer.write_all(&Png::STANDARD_HEADER)?;
+    let mut embed = |writer: &mut dyn Write| {
+        crate::stream::encode(
+            message,
+            codec,
+            encryption,
+            options.payload_chunk_len(),
+            |data| Chunk::new(chunk_type, data).write_to(&mut *writer),
+        )
+    };
+    let mut embedded = false;
+    while let Some((length, found)) = walker.next()? {
+        if found.as_str() == "IEND" {
+            embed(&mut writer)?;
+            embedded = true;
+            walker.pass(length, found, Some(&mut writer))?;
+            walker.copy_rest(&mut writer)?;
+            break;
+        }
+        walker.pass(length, found, Some(&mut writer))?;
+    }
+    if !embedded {
+        embed(&mut writer)?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pixels::tests::testing_png;
+
+    fn testing_file() -> Png {
+        let mut png = testing_png(4, 4);
+        let text = Chunk::new("tEXt".parse().unwrap(), b"Comment\0x".to_vec());
+        png.insert_chunk(1, text);
+        png.append_chunk(Chunk::new("ruSt".parse().unwrap(), vec![7; 3 * BLOCK]));
+        png.set_trailing(b"after".to_vec());
+        png
+    }
+
+    #[test]
+    fn test_matches_in_memory() {
+        let mut png = testing_file();
+        let bytes = png.as_bytes();
+        let options = Options::default();
+        assert_eq!(validate(bytes.as_slice(), &options).unwrap(), 5);
+
+        let mut stripped = Vec::new();
+        assert_eq!(strip(bytes.as_slice(), &mut stripped, &options).unwrap(), 2);
+        message::strip(&mut png);
+        assert_eq!(stripped, png.as_bytes());
+    }
+
+    #[test]
+    fn test_errors() {
+        let bytes = testing_file().as_bytes();
+        let options = Options::default();
+        let mut damaged = bytes.clone();
+        let at = bytes.len() - 40;
+        damaged[at] ^= 1;
+        assert!(matches!(
+            validate(damaged.as_slice(), &options),
+            Err(StreamingError::Png(PngError::Crc(CrcMismatch { .. })))
+        ));
+        assert!(matches!(
+            validate(&bytes[..bytes.len() - 100], &options),
+            Err(StreamingError::Png(PngError::Truncated(_)))
+        ));
+        let mut headless = testing_file();
+        headless.remove_first_chunk("IHDR").unwrap();
+        assert!(matches!(
+            validate(headless.as_bytes().as_slice(), &options),
+            Err(StreamingError::MissingIhdr)
+        ));
+        let mut spaced = testing_file();
+        let text = Chunk::new("tEXt".parse().unwrap(), b"Two  spaces\0x".to_vec());
+        spaced.insert_chunk(2, text);
+        let offset = spaced.chunk_offsets()[2];
+        assert!(matches!(
+            validate(spaced.as_bytes().as_slice(), &options),
+            Err(StreamingError::Png(PngError::InvalidKeyword(at))) if at == offset
+        ));
+        let mut twice = testing_file();
+        let ihdr = twice.chunks()[0].clone();
+        twice.insert_chunk(1, ihdr);
+        assert!(matches!(
+            validate(twice.as_bytes().as_slice(), &options),
+            Err(StreamingError::Duplicate(33, _))
+        ));
+        let mut late = testing_file();
+        late.append_chunk(Chunk::new("gAMA".parse().unwrap(), vec![0; 4]));
+        assert!(matches!(
+            validate(late.as_bytes().as_slice(), &options),
+            Err(StreamingError::Misplaced(misplaced)) if misplaced == late.misplaced()[0]
+        ));
+        let mut empty = testing_png(0, 4);
+        empty.append_chunk(Chunk::new("ruSt".parse().unwrap(), Vec::new()));
+        assert!(matches!(
+            validate(empty.as_bytes().as_slice(), &options),
+            Err(StreamingError::Header(PixelError::ZeroDimension(0, 4)))
+        ));
+        let tiny = Options { max_memory: 1024 };
+        assert!(matches!(
+            validate(bytes.as_slice(), &tiny),
+            Err(StreamingError::TooLittleMemory(1024))
+        ));
+    }
+
+    #[test]
+    fn test_append() {
+        let mut png = testing_file();
+        let mut bytes = png.as_bytes();
+        let chunks = [Chunk::new("raIn".parse().unwrap(), b"drops".to_vec())];
+        let mut appended = Vec::new();
+        append(io::Cursor::new(&bytes), &mut appended, &chunks).unwrap();
+        png.append_chunk(chunks[0].clone());
+        assert_eq!(appended, png.as_bytes());
+
+        // chunks before IEND are copied as they are, CRC or not
+        let at = bytes.len() - 40;
+        bytes[at] ^= 1;
+        appended.clear();
+        append(io::Cursor::new(&bytes), &mut appended, &chunks).unwrap();
+        assert_eq!(appended[at], bytes[at]);
+
+        let mut endless = testing_png(4, 4);
+        endless.remove_first_chunk("IEND").unwrap();
+        assert!(matches!(
+            append(io::Cursor::new(endless.as_bytes()), io::sink(), &chunks),
+            Err(StreamingError::MissingIend)
+        ));
+    }
+
+    #[test]
+    #[cfg(feature = "crypto")]
+    fn test_encode() {
+        let png = testing_file();
+        let message: Vec<u8> = (0..200_000u32).map(|i| (i % 251) as u8).collect();
+        let mut encoded = Vec::new();
+        let options = Options {
+            max_memory: MIN_MEMORY,
+        };
+        let chunk_type: ChunkType = "raIn".parse().unwrap();
+        encode(
+            png.as_bytes().as_slice(),
+            &mut encoded,
+            chunk_type,
+            &mut message.as_slice(),
+            crate::compress::Codec::None,
+            None,
+            &options,
+        )
+        .unwrap();
+
+        let encoded = Png::try_from(encoded.as_slice()).unwrap();
+        assert_eq!(encoded.trailing(), b"after");
+        assert_eq!(
+            encoded.chunks().last().unwrap().chunk_type().as_str(),
+            "IEND"
+        );
+        let chunks: Vec<&[u8]> = encoded.chunks_by_type("raIn").map(|c| c.data()).collect();
+        assert!(chunks.len() > 1);
+        assert!(chunks
+            .iter()
+            .all(|c| c.len() <= options.payload_chunk_len()));
+        let mut decoded = Vec::new();
+        crate::stream::decode(chunks.into_iter(), None, None, &mut decoded).unwrap();
+        assert_eq!(decoded, message);
+    }
+}
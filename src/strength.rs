@@ -0,0 +1,39 @@
+use zxcvbn::{zxcvbn, Score};
+
+/// Lowest zxcvbn score accepted without `--allow-weak-password`: more than
+/// 10^10 guesses
+const MIN_SCORE: Score = Score::Three;
+
+/// Why a password is too weak to protect data that can be attacked offline,
+/// with zxcvbn's advice, or `None` if it's strong enough
+pub fn weakness(password: &str) -> Option<String> {
+    let entropy = zxcvbn(password, &[]);
+    if entropy.score() >= MIN_SCORE {
+        return None;
+    }
+    let mut reason = format!(
+        "password is weak: an offline attack could guess it in {}",
+        entropy.crack_times().offline_slow_hashing_1e4_per_second()
+    );
+    if let Some(feedback) = entropy.feedback() {
+        if let Some(warning) = feedback.warning() {
+            reason.push_str(&format!("\n  {}", warning));
+        }
+        for suggestion in feedback.suggestions() {
+            reason.push_str(&format!("\n  {}", suggestion));
+        }
+    }
+    Some(reason)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_weakness() {
+        assert!(weakness("password1").is_some());
+        assert!(weakness("").is_some());
+        assert_eq!(weakness("sleet ozone marble quorum vindaloo"), None);
+    }
+}
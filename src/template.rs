@@ -0,0 +1,277 @@
+use std::fmt::Display;
+use std::str::FromStr;
+
+use crate::chunk::Chunk;
+use crate::lazy::ChunkEntry;
+use crate::text::TextChunk;
+
+/// A per-chunk output line such as `{type}\t{length}\t{crc:x}`
+///
+/// Numeric fields take an optional width and `x`/`X` spec, e.g. `{crc:08x}`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Template(Vec<Part>);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Part {
+    Literal(String),
+    Field(Field, Spec),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Field {
+    Index,
+    Type,
+    Length,
+    Crc,
+    Offset,
+    Critical,
+    Keyword,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+struct Spec {
+    width: usize,
+    zero: bool,
+    hex: Option<bool>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum TemplateError {
+    UnknownField(String),
+    InvalidSpec(String),
+    Unclosed,
+    UnmatchedBrace,
+}
+
+impl Display for TemplateError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TemplateError::UnknownField(name) => write!(f, "unknown format field {{{}}}", name),
+            TemplateError::InvalidSpec(spec) => write!(f, "invalid format spec :{}", spec),
+            TemplateError::Unclosed => write!(f, "unclosed {{ in format"),
+            TemplateError::UnmatchedBrace => write!(f, "unmatched }} in format"),
+        }
+    }
+}
+
+impl std::error::Error for TemplateError {}
+
+impl FromStr for Field {
+    type Err = TemplateError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "index" => Ok(Field::Index),
+            "type" => Ok(Field::Type),
+            "length" => Ok(Field::Length),
+            "crc" => Ok(Field::Crc),
+            "offset" => Ok(Field::Offset),
+            "critical" => Ok(Field::Critical),
+            "keyword" => Ok(Field::Keyword),
+            _ => Err(TemplateError::UnknownField(s.to_string())),
+        }
+    }
+}
+
+impl Field {
+    fn is_numeric(&self) -> bool {
+        matches!(
+            self,
+            Field::Index | Field::Length | Field::Crc | Field::Offset
+        )
+    }
+}
+
+impl Spec {
+    fn parse(spec: &str, field: Field) -> Result<Spec, TemplateError> {
+        let invalid = || TemplateError::InvalidSpec(spec.to_string());
+        let (digits, hex) = match spec.strip_suffix('x') {
+            Some(rest) => (rest, Some(false)),
+            None => match spec.strip_suffix('X') {
+                Some(rest) => (rest, Some(true)),
+                None => (spec, None),
+            },
+        };
+        if hex.is_some() && !field.is_numeric() {
+            return Err(invalid());
+        }
+        let width = if digits.is_empty() {
+            0
+        } else {
+            digits.parse().map_err(|_| invalid())?
+        };
+        Ok(Spec {
+            width,
+            zero: digits.starts_with('0'),
+            hex,
+        })
+    }
+
+    fn number(&self, n: u64) -> String {
+        match (self.hex, self.zero) {
+            (Some(false), true) => format!("{:0w$x}", n, w = self.width),
+            (Some(false), false) => format!("{:w$x}", n, w = self.width),
+            (Some(true), true) => format!("{:0w$X}", n, w = self.width),
+            (Some(true), false) => format!("{:w$X}", n, w = self.width),
+            (None, true) => format!("{:0w$}", n, w = self.width),
+            (None, false) => format!("{:w$}", n, w = self.width),
+        }
+    }
+
+    fn text(&self, s: &str) -> String {
+        format!("{:w$}", s, w = self.width)
+    }
+}
+
+impl FromStr for Template {
+    type Err = TemplateError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = Vec::new();
+        let mut literal = String::new();
+        let mut chars = s.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    literal.push('{');
+                }
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    literal.push('}');
+                }
+                '}' => return Err(TemplateError::UnmatchedBrace),
+                '\\' => match chars.next() {
+                    Some('t') => literal.push('\t'),
+                    Some('n') => literal.push('\n'),
+                    Some(other) => literal.push(other),
+                    None => literal.push('\\'),
+                },
+                '{' => {
+                    let mut inner = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) => inner.push(c),
+                            None => return Err(TemplateError::Unclosed),
+                        }
+                    }
+                    let (name, spec) = inner.split_once(':').unwrap_or((&inner, ""));
+                    let field = Field::from_str(name)?;
+                    if !literal.is_empty() {
+                        parts.push(Part::Literal(std::mem::take(&mut literal)));
+                    }
+                    parts.push(Part::Field(field, Spec::parse(spec, field)?));
+                }
+                c => literal.push(c),
+            }
+        }
+        if !literal.is_empty() {
+            parts.push(Part::Literal(literal));
+        }
+        Ok(Template(parts))
+    }
+}
+
+impl Template {
+    /// Render one line for `chunk`, the `index`th chunk at file `offset`
+    pub fn render(&self, index: usize, offset: usize, chunk: &Chunk) -> String {
+        let entry = ChunkEntry {
+            chunk_type: *chunk.chunk_type(),
+            length: chunk.length(),
+            crc: chunk.crc(),
+            offset: offset as u64,
+        };
+        self.render_entry(index, &entry, || match TextChunk::parse(chunk) {
+            Some(Ok(text)) => text.keyword,
+            _ => String::new(),
+        })
+    }
+
+    /// Render one line from a chunk's header alone, as a file read with
+    /// [`LazyPng`](crate::lazy::LazyPng) has; `keyword` is only called if
+    /// the template shows it
+    pub fn render_entry(
+        &self,
+        index: usize,
+        entry: &ChunkEntry,
+        mut keyword: impl FnMut() -> String,
+    ) -> String {
+        self.0
+            .iter()
+            .map(|part| match part {
+                Part::Literal(s) => s.clone(),
+                Part::Field(field, spec) => match field {
+                    Field::Index => spec.number(index as u64),
+                    Field::Type => spec.text(entry.chunk_type.as_str()),
+                    Field::Length => spec.number(entry.length as u64),
+                    Field::Crc => spec.number(entry.crc as u64),
+                    Field::Offset => spec.number(entry.offset),
+                    Field::Critical => spec.text(&entry.chunk_type.is_critical().to_string()),
+                    Field::Keyword => spec.text(&keyword()),
+                },
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk_type::ChunkType;
+
+    fn chunk(chunk_type: &str, data: &[u8]) -> Chunk {
+        Chunk::new(ChunkType::from_str(chunk_type).unwrap(), data.to_vec())
+    }
+
+    #[test]
+    fn test_render_fields() {
+        let template = Template::from_str(r"{type}\t{length}\t{crc:x}").unwrap();
+        assert_eq!(
+            template.render(0, 8, &chunk("IEND", b"")),
+            "IEND\t0\tae426082"
+        );
+    }
+
+    #[test]
+    fn test_render_offset_and_critical() {
+        let template = Template::from_str("{index}@{offset:08X} {critical}").unwrap();
+        assert_eq!(
+            template.render(2, 255, &chunk("ruSt", b"")),
+            "2@000000FF false"
+        );
+    }
+
+    #[test]
+    fn test_render_keyword() {
+        let template = Template::from_str("[{keyword}]").unwrap();
+        assert_eq!(
+            template.render(0, 0, &chunk("tEXt", b"Author\0me")),
+            "[Author]"
+        );
+        assert_eq!(template.render(0, 0, &chunk("IDAT", b"")), "[]");
+    }
+
+    #[test]
+    fn test_escaped_braces() {
+        let template = Template::from_str("{{{type}}}").unwrap();
+        assert_eq!(template.render(0, 0, &chunk("IEND", b"")), "{IEND}");
+    }
+
+    #[test]
+    fn test_invalid_templates() {
+        assert_eq!(
+            Template::from_str("{size}"),
+            Err(TemplateError::UnknownField("size".to_string()))
+        );
+        assert_eq!(
+            Template::from_str("{type:x}"),
+            Err(TemplateError::InvalidSpec("x".to_string()))
+        );
+        assert_eq!(Template::from_str("{type"), Err(TemplateError::Unclosed));
+        assert_eq!(
+            Template::from_str("type}"),
+            Err(TemplateError::UnmatchedBrace)
+        );
+    }
+}
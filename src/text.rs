@@ -0,0 +1,527 @@
+#![deny(clippy::unwrap_used, clippy::expect_used)]
+
+use std::fmt::Display;
+
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::deflate::{self, InflateError, Limits};
+use crate::png::Png;
+
+/// Keyword and decoded text of a tEXt, zTXt or iTXt chunk
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TextChunk {
+    pub keyword: String,
+    pub text: String,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum TextError {
+    MissingSeparator,
+    UnsupportedCompression(u8),
+    InvalidCompressedData,
+    InvalidUtf8,
+    /// Not 1 to 79 printable Latin-1 characters, or with leading, trailing
+    /// or repeated spaces
+    InvalidKeyword,
+    /// A control character other than a newline, at this byte of the text
+    ControlCharacter(usize),
+    /// An iTXt language tag that isn't ASCII letters, digits and hyphens
+    InvalidLanguage,
+    /// Compressed text inflating past this many bytes; see
+    /// [`Limits::METADATA`]
+    DecompressionLimitExceeded(u64),
+}
+
+impl Display for TextError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TextError::MissingSeparator => write!(f, "missing null separator"),
+            TextError::UnsupportedCompression(method) => {
+                write!(f, "unsupported compression method {}", method)
+            }
+            TextError::InvalidCompressedData => write!(f, "invalid compressed text"),
+            TextError::InvalidUtf8 => write!(f, "text is not valid utf-8"),
+            TextError::InvalidKeyword => write!(
+                f,
+                "keyword isn't 1 to 79 printable latin-1 characters without extra spaces"
+            ),
+            TextError::ControlCharacter(at) => write!(f, "control character at byte {}", at),
+            TextError::InvalidLanguage => write!(f, "language tag isn't ascii"),
+            TextError::DecompressionLimitExceeded(limit) => write!(
+                f,
+                "compressed text inflates to more than {} bytes; refusing it as a likely \
+                 decompression bomb",
+                limit
+            ),
+        }
+    }
+}
+
+impl std::error::Error for TextError {}
+
+impl TextChunk {
+    /// Decode a text chunk, or `None` if the chunk isn't one
+    pub fn parse(chunk: &Chunk) -> Option<Result<TextChunk, TextError>> {
+        Self::decode(chunk, None)
+    }
+
+    /// Decode as [`TextChunk::parse`] does, also returning where the chunk
+    /// breaks the encoding rules for its kind; iTXt text that isn't UTF-8
+    /// is one of them, decoded lossily rather than failing
+    pub fn parse_checked(chunk: &Chunk) -> Option<Result<(TextChunk, Vec<TextError>), TextError>> {
+        let mut problems = Vec::new();
+        let text = Self::decode(chunk, Some(&mut problems))?;
+        Some(text.map(|text| (text, problems)))
+    }
+
+    /// Decode, failing on the first break of the encoding rules
+    pub fn parse_strict(chunk: &Chunk) -> Option<Result<TextChunk, TextError>> {
+        Some(Self::parse_checked(chunk)?.and_then(|(text, problems)| {
+            match problems.into_iter().next() {
+                Some(problem) => Err(problem),
+                None => Ok(text),
+            }
+        }))
+    }
+
+    /// With `problems` given, rule breaks go there rather than failing
+    fn decode(
+        chunk: &Chunk,
+        problems: Option<&mut Vec<TextError>>,
+    ) -> Option<Result<TextChunk, TextError>> {
+        match chunk.chunk_type().as_str() {
+            "tEXt" => Some(Self::parse_text(chunk.data(), problems)),
+            "zTXt" => Some(Self::parse_ztxt(chunk.data(), problems)),
+            "iTXt" => Some(Self::parse_itxt(chunk.data(), problems)),
+            _ => None,
+        }
+    }
+
+    /// What [`TextChunk::to_chunk`] has to change to store this: keyword
+    /// characters it replaces and NULs in the text, which no kind can hold
+    pub fn problems(&self) -> Vec<TextError> {
+        let mut problems: Vec<TextError> = check_keyword(&self.keyword).err().into_iter().collect();
+        if let Some(at) = self.text.find('\0') {
+            problems.push(TextError::ControlCharacter(at));
+        }
+        problems
+    }
+
+    /// Encode as [`TextChunk::to_chunk`] does, but fail rather than change
+    /// anything
+    pub fn try_to_chunk(&self) -> Result<Chunk, TextError> {
+        match self.problems().into_iter().next() {
+            Some(problem) => Err(problem),
+            None => Ok(self.to_chunk()),
+        }
+    }
+
+    /// Encode as a tEXt chunk, or as an uncompressed iTXt one if the text
+    /// isn't all printable Latin-1 and newlines; keyword characters outside
+    /// Latin-1 become '?' and NULs in the text become spaces
+    pub fn to_chunk(&self) -> Chunk {
+        let mut data = to_latin1(&self.keyword);
+        data.push(0);
+        let text = self.text.replace('\0', " ");
+        if text
+            .chars()
+            .all(|c| u8::try_from(c).is_ok_and(is_latin1_text))
+        {
+            data.extend(to_latin1(&text));
+            return Chunk::new(ChunkType::new(*b"tEXt"), data);
+        }
+        // Not compressed, no language tag, no translated keyword
+        data.extend_from_slice(&[0, 0, 0, 0]);
+        data.extend_from_slice(text.as_bytes());
+        Chunk::new(ChunkType::new(*b"iTXt"), data)
+    }
+
+    /// Encode as a zTXt chunk; characters outside Latin-1 become '?'
+    pub fn to_ztxt(&self) -> Chunk {
+        let mut data = to_latin1(&self.keyword);
+        data.extend_from_slice(&[0, 0]);
+        data.extend(deflate::backend().compress(&to_latin1(&self.text), deflate::BEST_LEVEL));
+        Chunk::new(ChunkType::new(*b"zTXt"), data)
+    }
+
+    fn parse_text(
+        data: &[u8],
+        problems: Option<&mut Vec<TextError>>,
+    ) -> Result<TextChunk, TextError> {
+        let (keyword, text) = split_nul(data)?;
+        if let Some(problems) = problems {
+            check_latin1(keyword, text, problems);
+        }
+        Ok(TextChunk {
+            keyword: latin1(keyword),
+            text: latin1(text),
+        })
+    }
+
+    fn parse_ztxt(
+        data: &[u8],
+        problems: Option<&mut Vec<TextError>>,
+    ) -> Result<TextChunk, TextError> {
+        let (keyword, rest) = split_nul(data)?;
+        let (&method, compressed) = rest.split_first().ok_or(TextError::MissingSeparator)?;
+        if method != 0 {
+            return Err(TextError::UnsupportedCompression(method));
+        }
+        let text = inflate(compressed)?;
+        if let Some(problems) = problems {
+            check_latin1(keyword, &text, problems);
+        }
+        Ok(TextChunk {
+            keyword: latin1(keyword),
+            text: latin1(&text),
+        })
+    }
+
+    fn parse_itxt(
+        data: &[u8],
+        problems: Option<&mut Vec<TextError>>,
+    ) -> Result<TextChunk, TextError> {
+        let (keyword, rest) = split_nul(data)?;
+        let [flag, method, rest @ ..] = rest else {
+            return Err(TextError::MissingSeparator);
+        };
+        let (language, rest) = split_nul(rest)?;
+        let (translated, text) = split_nul(rest)?;
+        let text = match (flag, method) {
+            (0, _) => text.to_vec(),
+            (_, 0) => inflate(text)?,
+            (_, method) => return Err(TextError::UnsupportedCompression(*method)),
+        };
+        let text = match problems {
+            None => String::from_utf8(text).map_err(|_| TextError::InvalidUtf8)?,
+            Some(problems) => {
+                if check_keyword(&latin1(keyword)).is_err() {
+                    problems.push(TextError::InvalidKeyword);
+                }
+                let tag = |b: &u8| b.is_ascii_alphanumeric() || *b == b'-';
+                if !language.iter().all(tag) {
+                    problems.push(TextError::InvalidLanguage);
+                }
+                if std::str::from_utf8(translated).is_err() {
+                    problems.push(TextError::InvalidUtf8);
+                }
+                match String::from_utf8(text) {
+                    Ok(text) => text,
+                    Err(e) => {
+                        problems.push(TextError::InvalidUtf8);
+                        String::from_utf8_lossy(e.as_bytes()).into_owned()
+                    }
+                }
+            }
+        };
+        Ok(TextChunk {
+            keyword: latin1(keyword),
+            text,
+        })
+    }
+}
+
+/// Check a keyword is 1 to 79 printable Latin-1 characters with no
+/// leading, trailing or repeated spaces, as every text chunk's must be
+pub fn check_keyword(keyword: &str) -> Result<(), TextError> {
+    let printable = keyword
+        .chars()
+        .all(|c| u8::try_from(c).is_ok_and(|b| matches!(b, 32..=126 | 161..=255)));
+    let spaced = keyword.starts_with(' ') || keyword.ends_with(' ') || keyword.contains("  ");
+    match (1..=79).contains(&keyword.chars().count()) && printable && !spaced {
+        true => Ok(()),
+        false => Err(TextError::InvalidKeyword),
+    }
+}
+
+/// Check the keyword at the start of a text chunk's `data`
+pub(crate) fn check_keyword_in(data: &[u8]) -> Result<(), TextError> {
+    match data.iter().take(80).position(|b| *b == 0) {
+        Some(end) => check_keyword(&latin1(&data[..end])),
+        None => Err(TextError::InvalidKeyword),
+    }
+}
+
+/// Whether a byte can be in tEXt or zTXt text: printable Latin-1 or a
+/// newline
+fn is_latin1_text(b: u8) -> bool {
+    matches!(b, b'\n' | 32..=126 | 160..=255)
+}
+
+fn check_latin1(keyword: &[u8], text: &[u8], problems: &mut Vec<TextError>) {
+    if check_keyword(&latin1(keyword)).is_err() {
+        problems.push(TextError::InvalidKeyword);
+    }
+    if let Some(at) = text.iter().position(|&b| !is_latin1_text(b)) {
+        problems.push(TextError::ControlCharacter(at));
+    }
+}
+
+/// Whether `chunk` is a readable text chunk with `keyword`
+pub fn has_keyword(chunk: &Chunk, keyword: &str) -> bool {
+    matches!(TextChunk::parse(chunk), Some(Ok(text)) if text.keyword == keyword)
+}
+
+impl Png {
+    /// Store `text` with [`TextChunk::to_chunk`] in place of the text
+    /// chunks with its keyword, where the first of them was or else before
+    /// IEND. A keyword breaking the spec's rules is refused
+    pub fn set_text(&mut self, text: &TextChunk) -> Result<(), TextError> {
+        check_keyword(&text.keyword)?;
+        let at = self
+            .chunks()
+            .iter()
+            .position(|c| has_keyword(c, &text.keyword));
+        self.remove_text(&text.keyword);
+        self.insert_chunk(at.unwrap_or(usize::MAX), text.to_chunk());
+        Ok(())
+    }
+
+    /// Drop the text chunks with `keyword`, returning how many
+    pub fn remove_text(&mut self, keyword: &str) -> usize {
+        let before = self.chunks().len();
+        self.retain_chunks(|c| !has_keyword(c, keyword));
+        before - self.chunks().len()
+    }
+}
+
+fn split_nul(data: &[u8]) -> Result<(&[u8], &[u8]), TextError> {
+    let at = data
+        .iter()
+        .position(|b| *b == 0)
+        .ok_or(TextError::MissingSeparator)?;
+    Ok((&data[..at], &data[at + 1..]))
+}
+
+fn latin1(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| *b as char).collect()
+}
+
+fn to_latin1(text: &str) -> Vec<u8> {
+    text.chars()
+        .map(|c| u8::try_from(c).unwrap_or(b'?'))
+        .collect()
+}
+
+fn inflate(data: &[u8]) -> Result<Vec<u8>, TextError> {
+    deflate::inflate(data, Limits::METADATA.output_for(data.len())).map_err(|e| match e {
+        InflateError::Corrupt => TextError::InvalidCompressedData,
+        InflateError::DecompressionLimitExceeded(limit) => {
+            TextError::DecompressionLimitExceeded(limit)
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::write::ZlibEncoder;
+    use flate2::Compression;
+    use std::io::Write;
+    use std::str::FromStr;
+
+    fn chunk(chunk_type: &str, data: &[u8]) -> Chunk {
+        Chunk::new(ChunkType::from_str(chunk_type).unwrap(), data.to_vec())
+    }
+
+    fn deflate(data: &[u8]) -> Vec<u8> {
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    #[test]
+    fn test_parse_text() {
+        let text = TextChunk::parse(&chunk("tEXt", b"Author\0Caf\xe9"))
+            .unwrap()
+            .unwrap();
+        assert_eq!(text.keyword, "Author");
+        assert_eq!(text.text, "Café");
+    }
+
+    #[test]
+    fn test_parse_ztxt() {
+        let mut data = b"Comment\0\0".to_vec();
+        data.extend(deflate(b"compressed words"));
+        let text = TextChunk::parse(&chunk("zTXt", &data)).unwrap().unwrap();
+        assert_eq!(text.text, "compressed words");
+    }
+
+    #[test]
+    fn test_parse_itxt() {
+        let plain = TextChunk::parse(&chunk("iTXt", "Title\0\0\0en\0Titre\0日本".as_bytes()))
+            .unwrap()
+            .unwrap();
+        assert_eq!(plain.text, "日本");
+
+        let mut data = b"Title\0\x01\0\0\0".to_vec();
+        data.extend(deflate("ünïcode".as_bytes()));
+        let compressed = TextChunk::parse(&chunk("iTXt", &data)).unwrap().unwrap();
+        assert_eq!(compressed.text, "ünïcode");
+    }
+
+    #[test]
+    fn test_parse_non_text() {
+        assert!(TextChunk::parse(&chunk("IEND", b"")).is_none());
+    }
+
+    #[test]
+    fn test_parse_missing_separator() {
+        let err = TextChunk::parse(&chunk("tEXt", b"no separator"))
+            .unwrap()
+            .unwrap_err();
+        assert_eq!(err, TextError::MissingSeparator);
+    }
+
+    #[test]
+    fn test_parse_empty_and_truncated() {
+        let empty = [
+            ("tEXt", &b""[..], Err(TextError::MissingSeparator)),
+            ("tEXt", b"\0", Ok(("", ""))),
+            ("tEXt", b"Comment\0", Ok(("Comment", ""))),
+            ("zTXt", b"Comment\0", Err(TextError::MissingSeparator)),
+            ("iTXt", b"Comment\0\0\0\0\0", Ok(("Comment", ""))),
+        ];
+        for (chunk_type, data, expected) in empty {
+            let parsed = TextChunk::parse(&chunk(chunk_type, data)).unwrap();
+            let expected = expected.map(|(keyword, text)| TextChunk {
+                keyword: keyword.to_string(),
+                text: text.to_string(),
+            });
+            assert_eq!(parsed, expected);
+        }
+        assert_eq!(check_keyword_in(b""), Err(TextError::InvalidKeyword));
+
+        // Every prefix of every kind is an error or a chunk, never a panic
+        let ztxt = [&b"Comment\0\0"[..], &deflate(b"text")].concat();
+        let itxt = [&b"Comment\0\x01\0en\0Kommentar\0"[..], &deflate(b"text")].concat();
+        for (chunk_type, data) in [
+            ("tEXt", &b"Comment\0text"[..]),
+            ("zTXt", &ztxt),
+            ("iTXt", &itxt),
+        ] {
+            for end in 0..data.len() {
+                let chunk = chunk(chunk_type, &data[..end]);
+                let _ = TextChunk::parse(&chunk).unwrap();
+                let _ = TextChunk::parse_checked(&chunk).unwrap();
+                let _ = check_keyword_in(chunk.data());
+            }
+        }
+    }
+
+    #[test]
+    fn test_to_chunk() {
+        for (text, chunk_type) in [("Café", "tEXt"), ("Café ☕", "iTXt")] {
+            let text = TextChunk {
+                keyword: "Comment".to_string(),
+                text: text.to_string(),
+            };
+            let chunk = text.to_chunk();
+            assert_eq!(chunk.chunk_type().to_string(), chunk_type);
+            assert_eq!(TextChunk::parse(&chunk).unwrap().unwrap(), text);
+        }
+    }
+
+    #[test]
+    fn test_set_text() {
+        let mut png = crate::pixels::tests::testing_png(4, 4);
+        png.append_chunk(chunk("tEXt", b"Author\0me"));
+        png.append_chunk(chunk("tEXt", b"Comment\0x"));
+        png.append_chunk(chunk("tEXt", b"Author\0you"));
+        png.set_text(&TextChunk {
+            keyword: "Author".to_string(),
+            text: "Ferris".to_string(),
+        })
+        .unwrap();
+        let spaced = TextChunk {
+            keyword: "Author ".to_string(),
+            text: "Ferris".to_string(),
+        };
+        assert_eq!(png.set_text(&spaced), Err(TextError::InvalidKeyword));
+        let texts: Vec<TextChunk> = png
+            .chunks()
+            .iter()
+            .filter_map(|c| TextChunk::parse(c)?.ok())
+            .collect();
+        assert_eq!(texts[0].text, "Ferris");
+        assert_eq!(texts[1].keyword, "Comment");
+        assert_eq!(texts.len(), 2);
+        assert_eq!(png.remove_text("Author"), 1);
+        assert_eq!(png.remove_text("Author"), 0);
+    }
+
+    #[test]
+    fn test_to_ztxt() {
+        let text = TextChunk {
+            keyword: "Comment".to_string(),
+            text: "Café ☕".to_string(),
+        };
+        let chunk = text.to_ztxt();
+        assert_eq!(chunk.chunk_type().to_string(), "zTXt");
+        let parsed = TextChunk::parse(&chunk).unwrap().unwrap();
+        assert_eq!(parsed.text, "Café ?");
+    }
+
+    #[test]
+    fn test_decompression_bomb() {
+        let data = [&b"Comment\0\0"[..], &deflate(&vec![b'a'; 4 << 20])].concat();
+        assert_eq!(
+            TextChunk::parse(&chunk("zTXt", &data)).unwrap(),
+            Err(TextError::DecompressionLimitExceeded(Limits::MIN_OUTPUT))
+        );
+    }
+
+    #[test]
+    fn test_parse_checked() {
+        let (text, problems) = TextChunk::parse_checked(&chunk("tEXt", b" Author\0tab\there"))
+            .unwrap()
+            .unwrap();
+        assert_eq!(text.text, "tab\there");
+        assert_eq!(
+            problems,
+            [TextError::InvalidKeyword, TextError::ControlCharacter(3)]
+        );
+        let strict = TextChunk::parse_strict(&chunk("tEXt", b"Author\0two\nlines"));
+        assert!(strict.unwrap().is_ok());
+
+        let data = b"Title\0\0\0en us\0\0bad \xff";
+        assert_eq!(
+            TextChunk::parse(&chunk("iTXt", data)).unwrap(),
+            Err(TextError::InvalidUtf8)
+        );
+        let (text, problems) = TextChunk::parse_checked(&chunk("iTXt", data))
+            .unwrap()
+            .unwrap();
+        assert_eq!(text.text, "bad \u{fffd}");
+        assert_eq!(
+            problems,
+            [TextError::InvalidLanguage, TextError::InvalidUtf8]
+        );
+        assert_eq!(
+            TextChunk::parse_strict(&chunk("iTXt", data)).unwrap(),
+            Err(TextError::InvalidLanguage)
+        );
+    }
+
+    #[test]
+    fn test_try_to_chunk() {
+        let text = |keyword: &str, text: &str| TextChunk {
+            keyword: keyword.to_string(),
+            text: text.to_string(),
+        };
+        let tab = text("Comment", "a\tb").try_to_chunk().unwrap();
+        assert_eq!(tab.chunk_type().to_string(), "iTXt");
+        let newline = text("Comment", "a\nb").try_to_chunk().unwrap();
+        assert_eq!(newline.chunk_type().to_string(), "tEXt");
+        for (keyword, value) in [("Kommentär ☕", "x"), ("", "x"), ("a  b", "x")] {
+            let text = text(keyword, value);
+            assert_eq!(text.try_to_chunk(), Err(TextError::InvalidKeyword));
+        }
+        let nul = text("Comment", "a\0b");
+        assert_eq!(nul.try_to_chunk(), Err(TextError::ControlCharacter(1)));
+        assert_eq!(
+            TextChunk::parse(&nul.to_chunk()).unwrap().unwrap().text,
+            "a b"
+        );
+    }
+}
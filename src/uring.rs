@@ -0,0 +1,217 @@
+//! Reading many files at once through io_uring, for batch runs over
+//! thousands of small images where a read syscall per file is the cost.
+//! Files are still opened with std; the reads of up to [`DEPTH`] of them
+//! go to the kernel in one submission
+
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::os::fd::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+
+use io_uring::{opcode, types, IoUring};
+
+/// Reads in flight at once
+pub const DEPTH: usize = 64;
+
+pub struct Reader {
+    /// None once a failed submission left reads queued that mustn't run
+    /// and no new ring could replace it
+    ring: Option<IoUring>,
+}
+
+impl Reader {
+    /// A reader with its own ring; fails where the kernel is too old or
+    /// io_uring is disallowed, as it often is in containers
+    pub fn new() -> io::Result<Reader> {
+        Ok(Reader {
+            ring: Some(IoUring::new(DEPTH as u32)?),
+        })
+    }
+
+    /// Read each of `paths` into the buffer at the same index, which is
+    /// cleared first, returning how each read went
+    pub fn read_all(&mut self, paths: &[PathBuf], buffers: &mut [Vec<u8>]) -> Vec<io::Result<()>> {
+        assert_eq!(paths.len(), buffers.len());
+        let mut results = Vec::with_capacity(paths.len());
+        for (paths, buffers) in paths.chunks(DEPTH).zip(buffers.chunks_mut(DEPTH)) {
+            results.extend(self.read_window(paths, buffers));
+        }
+        results
+    }
+
+    fn read_window(&mut self, paths: &[PathBuf], buffers: &mut [Vec<u8>]) -> Vec<io::Result<()>> {
+        let Some(ring) = &mut self.ring else {
+            let lost = || Err(io::Error::other("no io_uring after a failed submission"));
+            return paths.iter().map(|_| lost()).collect();
+        };
+        let mut files = Vec::with_capacity(paths.len());
+        let mut results = Vec::with_capacity(paths.len());
+        for (index, (path, buffer)) in paths.iter().zip(buffers.iter_mut()).enumerate() {
+            buffer.clear();
+            let file = match open(path, buffer) {
+                Ok(opened) => opened,
+                Err(e) => {
+                    files.push(None);
+                    results.push(Err(e));
+                    continue;
+                }
+            };
+            let read = opcode::Read::new(
+                types::Fd(file.0.as_raw_fd()),
+                buffer.as_mut_ptr(),
+                buffer.capacity().min(u32::MAX as usize) as u32,
+            )
+            .offset(0)
+            .build()
+            .user_data(index as u64);
+            // Safety: the file and buffer outlive the read, which completes
+            // below before either is touched again
+            if unsafe { ring.submission().push(&read) }.is_err() {
+                files.push(None);
+                results.push(Err(io::Error::other(
+                    "the io_uring submission queue is full",
+                )));
+                continue;
+            }
+            files.push(Some(file));
+            results.push(Ok(()));
+        }
+
+        let mut pending = files.iter().flatten().count();
+        let mut submitted = false;
+        let mut failed = vec![false; files.len()];
+        let mut draining = false;
+        while pending > 0 {
+            if draining {
+                thread::sleep(Duration::from_millis(1));
+            } else {
+                match ring.submit_and_wait(pending) {
+                    Ok(_) => submitted = true,
+                    Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                    // Reads in flight write into the buffers whatever
+                    // happens, so rather than return before they're done,
+                    // fail them and poll for their completions
+                    Err(e) if submitted => {
+                        for (index, result) in results.iter_mut().enumerate() {
+                            if files[index].is_some() && result.is_ok() {
+                                *result = Err(io::Error::new(e.kind(), e.to_string()));
+                                failed[index] = true;
+                            }
+                        }
+                        draining = true;
+                    }
+                    // Nothing went to the kernel, but the reads are still
+                    // queued, pointing at files and buffers about to go;
+                    // a fresh ring keeps them from the next submission
+                    Err(e) => {
+                        for result in results.iter_mut().filter(|r| r.is_ok()) {
+                            *result = Err(io::Error::new(e.kind(), e.to_string()));
+                        }
+                        self.ring = IoUring::new(DEPTH as u32).ok();
+                        return results;
+                    }
+                }
+            }
+            for completion in ring.completion() {
+                pending -= 1;
+                let index = completion.user_data() as usize;
+                let (Some((file, size)), buffer) = (&mut files[index], &mut buffers[index]) else {
+                    continue;
+                };
+                if failed[index] {
+                    continue;
+                }
+                results[index] = match completion.result() {
+                    read if read < 0 => Err(io::Error::from_raw_os_error(-read)),
+                    read => {
+                        // Safety: the kernel wrote this many bytes into the
+                        // buffer's capacity
+                        unsafe { buffer.set_len(read as usize) };
+                        finish(file, *size, buffer)
+                    }
+                };
+            }
+        }
+        results
+    }
+}
+
+/// Open `path` and take its size, with room in `buffer` for all of it
+fn open(path: &Path, buffer: &mut Vec<u8>) -> io::Result<(File, u64)> {
+    let file = File::open(path)?;
+    let size = file.metadata()?.len();
+    buffer.reserve(size as usize);
+    Ok((file, size))
+}
+
+/// Read whatever the ring didn't. Reads can stop short of the end, past
+/// 0x7ffff000 bytes or on network filesystems, and the file may have
+/// grown since `size` was taken, so only a read of exactly `size` bytes
+/// that left room in the buffer reached the end
+fn finish(file: &mut File, size: u64, buffer: &mut Vec<u8>) -> io::Result<()> {
+    if buffer.len() as u64 == size && buffer.len() < buffer.capacity() {
+        return Ok(());
+    }
+    file.seek(SeekFrom::Start(buffer.len() as u64))?;
+    file.read_to_end(buffer)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_read_all() {
+        let Ok(mut reader) = Reader::new() else {
+            // Nothing to test where rings aren't allowed
+            return;
+        };
+        let dir = std::env::temp_dir().join(format!("ping-uring-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let contents: Vec<Vec<u8>> = (0..DEPTH + 3).map(|i| vec![i as u8; i * 1000]).collect();
+        let mut paths: Vec<PathBuf> = contents
+            .iter()
+            .enumerate()
+            .map(|(i, data)| {
+                let path = dir.join(format!("{}.png", i));
+                fs::write(&path, data).unwrap();
+                path
+            })
+            .collect();
+        paths.push(dir.join("missing.png"));
+        let mut buffers = vec![b"stale".to_vec(); paths.len()];
+
+        let results = reader.read_all(&paths, &mut buffers);
+        assert!(results[..DEPTH + 3].iter().all(|r| r.is_ok()));
+        assert_eq!(buffers[..DEPTH + 3], contents[..]);
+        assert_eq!(
+            results[DEPTH + 3].as_ref().unwrap_err().kind(),
+            io::ErrorKind::NotFound
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_finish_short_reads() {
+        let path = std::env::temp_dir().join(format!("ping-uring-short-{}", std::process::id()));
+        let data: Vec<u8> = (0..100).collect();
+        fs::write(&path, &data).unwrap();
+        let mut file = File::open(&path).unwrap();
+
+        // A read that stopped partway, with the rest of the buffer free
+        let mut buffer = Vec::with_capacity(200);
+        buffer.extend_from_slice(&data[..30]);
+        finish(&mut file, 100, &mut buffer).unwrap();
+        assert_eq!(buffer, data);
+
+        // A file that grew past the size taken when it was opened
+        let mut buffer = data[..60].to_vec();
+        finish(&mut file, 60, &mut buffer).unwrap();
+        assert_eq!(buffer, data);
+        fs::remove_file(path).unwrap();
+    }
+}
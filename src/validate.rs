@@ -0,0 +1,691 @@
+//! Full validation of a file without building a [`Png`]: the signature,
+//! every chunk's framing and CRC, and IHDR first and IEND last. The
+//! framing is walked in order, which only reads length fields; the CRCs,
+//! where the time goes, can then be checked across rayon's pool
+
+#![deny(clippy::unwrap_used, clippy::expect_used)]
+
+use std::collections::HashSet;
+use std::fmt::Display;
+
+use rayon::prelude::*;
+
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::crc;
+use crate::order::{self, Misplaced, Rule};
+use crate::perf;
+use crate::pixels::{self, Ihdr, PixelError};
+use crate::png::{CrcMismatch, Mode, ParseOptions, Png, PngError, Warning};
+use crate::text;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ValidateOptions {
+    /// Check CRCs on rayon's thread pool rather than one after another
+    pub parallel: bool,
+    /// Check text chunk keywords follow the spec too, though decoders
+    /// accept any
+    pub keywords: bool,
+    /// Check there's at most one chunk of each type the spec allows once;
+    /// see [`order::is_unique`]
+    pub unique: bool,
+    /// Check chunks are where the spec places them; see
+    /// [`order::misplaced`]
+    pub placement: bool,
+    /// Check the IHDR fields, and PLTE and tRNS against them; see
+    /// [`Ihdr::check`]
+    pub header: bool,
+    /// Check an indexed image's pixels only use entries PLTE has, which
+    /// means decoding it; see [`pixels::check_indices`]
+    pub indices: bool,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ValidateError {
+    Png(PngError),
+    MissingIhdr,
+    MissingIend,
+    /// A second chunk, at this offset, of a type the spec allows once
+    Duplicate(usize, ChunkType),
+    Misplaced(Misplaced),
+    Header(PixelError),
+}
+
+impl Display for ValidateError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ValidateError::Png(e) => write!(f, "{}", e),
+            ValidateError::MissingIhdr => write!(f, "the first chunk isn't IHDR"),
+            ValidateError::MissingIend => write!(f, "the last chunk isn't IEND"),
+            ValidateError::Duplicate(offset, chunk_type) => {
+                write!(f, "a second {} chunk at offset {}", chunk_type, offset)
+            }
+            ValidateError::Misplaced(misplaced) => write!(f, "{}", misplaced),
+            ValidateError::Header(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for ValidateError {}
+
+impl From<PngError> for ValidateError {
+    fn from(e: PngError) -> Self {
+        ValidateError::Png(e)
+    }
+}
+
+/// Where a chunk is in the file, and its type
+#[derive(Debug, Clone, Copy)]
+struct Span {
+    offset: usize,
+    length: usize,
+    chunk_type: ChunkType,
+}
+
+impl Span {
+    fn data<'a>(&self, bytes: &'a [u8]) -> &'a [u8] {
+        &bytes[self.offset + 8..self.offset + 8 + self.length]
+    }
+}
+
+/// The chunks in `bytes`, up to and including IEND
+fn spans(bytes: &[u8]) -> Result<Vec<Span>, PngError> {
+    Png::check_signature(bytes)?;
+    let mut spans = Vec::new();
+    let mut offset = Png::STANDARD_HEADER.len();
+    while offset < bytes.len() {
+        let rest = &bytes[offset..];
+        let Some((length, chunk_type)) = Chunk::read_head(rest) else {
+            return Err(PngError::Truncated(offset));
+        };
+        let length = length as usize;
+        if rest.len() < Chunk::OVERHEAD || rest.len() - Chunk::OVERHEAD < length {
+            return Err(PngError::Truncated(offset));
+        }
+        let chunk_type = ChunkType::try_from(chunk_type).map_err(|e| PngError::Chunk(offset, e))?;
+        spans.push(Span {
+            offset,
+            length,
+            chunk_type,
+        });
+        offset += length + Chunk::OVERHEAD;
+        if &chunk_type.bytes() == b"IEND" {
+            break;
+        }
+    }
+    Ok(spans)
+}
+
+/// The CRC error of the `index`th chunk, if it has one
+fn check_crc(bytes: &[u8], index: usize, span: &Span) -> Option<PngError> {
+    let chunk = &bytes[span.offset..];
+    let actual = crc::checksum(&chunk[4..8 + span.length]);
+    let expected = Chunk::read_crc(chunk, span.length)?;
+    (actual != expected).then_some(PngError::Crc(CrcMismatch {
+        index,
+        offset: span.offset,
+        chunk_type: span.chunk_type,
+        length: span.length as u32,
+        stored: expected,
+        computed: actual,
+    }))
+}
+
+/// Validate `bytes`, returning how many chunks there are. With several
+/// broken chunks the error is the first one's, parallel or not
+pub fn validate(bytes: &[u8], options: &ValidateOptions) -> Result<usize, ValidateError> {
+    let spans = spans(bytes)?;
+    if let Some(last) = spans.last() {
+        perf::parsed(
+            last.offset + last.length + Chunk::OVERHEAD - Png::STANDARD_HEADER.len(),
+            spans.len(),
+        );
+    }
+    let error = match options.parallel {
+        true => spans
+            .par_iter()
+            .enumerate()
+            .filter_map(|(index, span)| check_crc(bytes, index, span))
+            .find_first(|_| true),
+        false => spans
+            .iter()
+            .enumerate()
+            .find_map(|(index, span)| check_crc(bytes, index, span)),
+    };
+    if let Some(error) = error {
+        return Err(error.into());
+    }
+    let is = |span: Option<&Span>, chunk_type: &str| {
+        span.is_some_and(|span| span.chunk_type.as_str() == chunk_type)
+    };
+    if !is(spans.first(), "IHDR") {
+        return Err(ValidateError::MissingIhdr);
+    }
+    if !is(spans.last(), "IEND") {
+        return Err(ValidateError::MissingIend);
+    }
+    if options.header {
+        let ihdr = Ihdr::parse(spans[0].data(bytes));
+        let length_of = |chunk_type: &str| {
+            let span = spans.iter().find(|span| is(Some(span), chunk_type));
+            span.map(|span| span.length)
+        };
+        let plte = length_of("PLTE");
+        ihdr.and_then(|ihdr| {
+            ihdr.check()?;
+            ihdr.check_palette(plte)?;
+            ihdr.check_transparency(plte, length_of("tRNS"))
+        })
+        .map_err(ValidateError::Header)?;
+    }
+    if options.indices {
+        let png = Png::try_from(bytes)?;
+        pixels::check_indices(&png).map_err(ValidateError::Header)?;
+    }
+    if options.keywords {
+        let bad_keyword = spans.iter().find(|span| {
+            let is_text = matches!(span.chunk_type.as_str(), "tEXt" | "zTXt" | "iTXt");
+            is_text && text::check_keyword_in(span.data(bytes)).is_err()
+        });
+        if let Some(span) = bad_keyword {
+            return Err(PngError::InvalidKeyword(span.offset).into());
+        }
+    }
+    if options.unique {
+        let mut seen = HashSet::new();
+        for span in &spans {
+            let chunk_type = span.chunk_type;
+            if order::is_unique(chunk_type.as_str()) && !seen.insert(chunk_type) {
+                return Err(ValidateError::Duplicate(span.offset, chunk_type));
+            }
+        }
+    }
+    if options.placement {
+        let chunks: Vec<(usize, ChunkType)> = spans
+            .iter()
+            .map(|span| (span.offset, span.chunk_type))
+            .collect();
+        if let Some(&misplaced) = order::misplaced(&chunks).first() {
+            return Err(ValidateError::Misplaced(misplaced));
+        }
+    }
+    Ok(spans.len())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize),
+    serde(rename_all = "lowercase")
+)]
+pub enum Severity {
+    /// About the file, not wrong with it
+    Info,
+    /// Accepted by decoders, but against the spec or a sign of hidden data
+    Warning,
+    Error,
+}
+
+impl Display for Severity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Severity::Info => write!(f, "info"),
+            Severity::Warning => write!(f, "warning"),
+            Severity::Error => write!(f, "error"),
+        }
+    }
+}
+
+/// One thing [`report`] found
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct Finding {
+    /// What was found, in kebab case, for scripts to match on
+    pub code: &'static str,
+    pub severity: Severity,
+    pub message: String,
+    /// Position in the file's chunk list of the chunk it's about
+    pub chunk: Option<usize>,
+    /// File offset of that chunk, or of whatever else it's about
+    pub offset: Option<usize>,
+}
+
+impl Finding {
+    fn new(code: &'static str, severity: Severity, message: impl Display) -> Finding {
+        Finding {
+            code,
+            severity,
+            message: message.to_string(),
+            chunk: None,
+            offset: None,
+        }
+    }
+
+    fn at(mut self, chunk: Option<usize>, offset: usize) -> Finding {
+        self.chunk = chunk;
+        self.offset = Some(offset);
+        self
+    }
+
+    fn from_warning(warning: &Warning) -> Finding {
+        let (code, severity, chunk, offset) = match *warning {
+            Warning::TrailingData { offset, .. } => {
+                ("trailing-data", Severity::Warning, None, Some(offset))
+            }
+            Warning::Truncated { offset, .. } => ("truncated", Severity::Error, None, Some(offset)),
+            Warning::InvalidChunkType { offset, .. } => {
+                ("chunk-type", Severity::Error, None, Some(offset))
+            }
+            Warning::Skipped { offset, .. } => ("skipped", Severity::Error, None, Some(offset)),
+            Warning::Crc(mismatch) => (
+                "crc-mismatch",
+                Severity::Error,
+                Some(mismatch.index),
+                Some(mismatch.offset),
+            ),
+            Warning::Missing(_) => ("missing-chunk", Severity::Error, None, None),
+            Warning::Duplicate { index, offset, .. } => (
+                "duplicate-chunk",
+                Severity::Error,
+                Some(index),
+                Some(offset),
+            ),
+            Warning::Misplaced(misplaced) => (
+                "misplaced-chunk",
+                Severity::Error,
+                Some(misplaced.index),
+                Some(misplaced.offset),
+            ),
+            Warning::InvalidKeyword { index, offset } => {
+                ("keyword", Severity::Warning, Some(index), Some(offset))
+            }
+        };
+        Finding {
+            chunk,
+            offset,
+            ..Finding::new(code, severity, warning)
+        }
+    }
+
+    fn to_json(&self) -> String {
+        let number = |n: Option<usize>| n.map_or("null".to_string(), |n| n.to_string());
+        format!(
+            r#"{{"code":"{}","severity":"{}","message":{},"chunk":{},"offset":{}}}"#,
+            self.code,
+            self.severity,
+            json_string(&self.message),
+            number(self.chunk),
+            number(self.offset)
+        )
+    }
+}
+
+impl Display for Finding {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {}", self.severity, self.code)?;
+        if let Some(chunk) = self.chunk {
+            write!(f, " (chunk {})", chunk)?;
+        }
+        write!(f, ": {}", self.message)
+    }
+}
+
+/// `text` as a JSON string
+fn json_string(text: &str) -> String {
+    let mut json = String::with_capacity(text.len() + 2);
+    json.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            '\r' => json.push_str("\\r"),
+            '\t' => json.push_str("\\t"),
+            c if c < ' ' => json.push_str(&format!("\\u{:04x}", c as u32)),
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+    json
+}
+
+/// Everything [`report`] found in a file, by severity
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct ValidationReport {
+    pub errors: Vec<Finding>,
+    pub warnings: Vec<Finding>,
+    pub info: Vec<Finding>,
+}
+
+impl ValidationReport {
+    fn push(&mut self, finding: Finding) {
+        match finding.severity {
+            Severity::Error => self.errors.push(finding),
+            Severity::Warning => self.warnings.push(finding),
+            Severity::Info => self.info.push(finding),
+        }
+    }
+
+    /// Whether nothing was wrong enough to be an error
+    pub fn is_valid(&self) -> bool {
+        self.errors.is_empty()
+    }
+
+    /// Every finding, errors first
+    pub fn findings(&self) -> impl Iterator<Item = &Finding> {
+        self.errors.iter().chain(&self.warnings).chain(&self.info)
+    }
+
+    /// The report as a JSON object of three arrays of findings, without
+    /// needing the serde feature
+    pub fn to_json(&self) -> String {
+        let list = |findings: &[Finding]| {
+            let findings: Vec<String> = findings.iter().map(Finding::to_json).collect();
+            format!("[{}]", findings.join(","))
+        };
+        format!(
+            r#"{{"errors":{},"warnings":{},"info":{}}}"#,
+            list(&self.errors),
+            list(&self.warnings),
+            list(&self.info)
+        )
+    }
+}
+
+/// Check everything [`validate`] does, and read past what's broken to
+/// find all of it rather than stop at the first; see [`Mode::Lenient`].
+/// What `options` leaves unchecked isn't reported, but IHDR and IEND
+/// missing or out of place always are. `parallel` is ignored
+pub fn report(bytes: &[u8], options: &ValidateOptions) -> ValidationReport {
+    let mut report = ValidationReport::default();
+    let lenient = ParseOptions {
+        mode: Mode::Lenient,
+        ..ParseOptions::default()
+    };
+    let (png, offsets, warnings) = match Png::parse_with_offsets(bytes, &lenient) {
+        Ok(parsed) => parsed,
+        Err(e) => {
+            let (code, offset) = match e {
+                PngError::OverBudget(offset, _) => ("budget", offset),
+                PngError::Signature { offset, .. } => ("signature", offset),
+                _ => ("signature", 0),
+            };
+            report.push(Finding::new(code, Severity::Error, e).at(None, offset));
+            return report;
+        }
+    };
+    for warning in &warnings {
+        let checked = match warning {
+            Warning::Duplicate { .. } => options.unique,
+            Warning::Misplaced(misplaced) => {
+                options.placement || matches!(misplaced.rule, Rule::IhdrFirst | Rule::IendLast)
+            }
+            Warning::InvalidKeyword { .. } => options.keywords,
+            _ => true,
+        };
+        if checked {
+            report.push(Finding::from_warning(warning));
+        }
+    }
+
+    let chunk_of = |chunk_type: &str| {
+        let index = png
+            .chunks()
+            .iter()
+            .position(|c| c.chunk_type().as_str() == chunk_type)?;
+        Some((index, *offsets.get(index)?))
+    };
+    // Decoding repeats the header checks, and can't go far without them
+    let mut decodable = false;
+    if let Some((index, offset)) = chunk_of("IHDR") {
+        match pixels::check_header(&png) {
+            Ok(ihdr) => {
+                decodable = true;
+                let interlaced = match ihdr.interlace {
+                    0 => "",
+                    _ => ", interlaced",
+                };
+                let message = format!(
+                    "{}x{}, {}-bit {}{}",
+                    ihdr.width,
+                    ihdr.height,
+                    ihdr.bit_depth,
+                    pixels::color_type_name(ihdr.color_type),
+                    interlaced
+                );
+                report.push(Finding::new("image", Severity::Info, message).at(Some(index), offset));
+            }
+            Err(e) if options.header => {
+                report.push(Finding::new("header", Severity::Error, e).at(Some(index), offset));
+            }
+            Err(_) => (),
+        }
+    }
+    let idat = chunk_of("IDAT");
+    if options.indices && decodable {
+        if let Err(e) = pixels::check_indices(&png) {
+            let code = match e {
+                PixelError::IndexOutOfRange { .. } => "palette-index",
+                _ => "image-data",
+            };
+            let finding = Finding::new(code, Severity::Error, e);
+            report.push(match idat {
+                Some((index, offset)) => finding.at(Some(index), offset),
+                None => finding,
+            });
+        }
+    }
+    for (index, (chunk, &offset)) in png.chunks().iter().zip(&offsets).enumerate() {
+        let chunk_type = chunk.chunk_type();
+        if !order::is_standard(chunk_type.as_str()) {
+            let message = format!(
+                "{} chunk of {} bytes the spec doesn't define",
+                chunk_type,
+                chunk.length()
+            );
+            report.push(
+                Finding::new("unknown-chunk", Severity::Info, message).at(Some(index), offset),
+            );
+        }
+    }
+    report.push(Finding::new(
+        "chunks",
+        Severity::Info,
+        format!("{} chunks", png.chunks().len()),
+    ));
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pixels::tests::testing_png;
+
+    fn many_chunks() -> Png {
+        let mut png = testing_png(4, 4);
+        for i in 0..64u8 {
+            png.insert_chunk(2, Chunk::new("IDAT".parse().unwrap(), vec![i; 1000]));
+        }
+        png
+    }
+
+    #[test]
+    fn test_valid() {
+        let bytes = many_chunks().as_bytes();
+        for parallel in [false, true] {
+            let options = ValidateOptions {
+                parallel,
+                keywords: true,
+                unique: true,
+                placement: true,
+                header: true,
+                indices: true,
+            };
+            assert_eq!(validate(&bytes, &options), Ok(67));
+        }
+    }
+
+    #[test]
+    fn test_first_error_wins() {
+        let png = many_chunks();
+        let offsets = png.chunk_offsets();
+        let mut bytes = png.as_bytes();
+        for index in [10, 40, 60] {
+            bytes[offsets[index] + 9] ^= 1;
+        }
+        for parallel in [false, true] {
+            let options = ValidateOptions {
+                parallel,
+                ..ValidateOptions::default()
+            };
+            let error = validate(&bytes, &options).unwrap_err();
+            assert!(matches!(
+                error,
+                ValidateError::Png(PngError::Crc(CrcMismatch { index: 10, offset, .. })) if offset == offsets[10]
+            ));
+        }
+
+        let mut png = many_chunks();
+        png.remove_first_chunk("IEND").unwrap();
+        let bytes = png.as_bytes();
+        assert_eq!(
+            validate(&bytes, &ValidateOptions::default()),
+            Err(ValidateError::MissingIend)
+        );
+        let bytes = Png::from_chunks(png.chunks()[1..].to_vec()).as_bytes();
+        assert_eq!(
+            validate(&bytes, &ValidateOptions::default()),
+            Err(ValidateError::MissingIhdr)
+        );
+    }
+
+    #[test]
+    fn test_report() {
+        let mut png = testing_png(4, 4);
+        let text = |data: &[u8]| Chunk::new("tEXt".parse().unwrap(), data.to_vec());
+        png.insert_chunk(1, text(b"Author\0me"));
+        png.insert_chunk(2, text(b" Author\0\"quoted\"\n"));
+        png.insert_chunk(3, Chunk::new("ruSt".parse().unwrap(), vec![1; 5]));
+        let offsets = png.chunk_offsets();
+        let mut bytes = png.as_bytes();
+        bytes[offsets[1] + 9] ^= 1;
+        let options = ValidateOptions {
+            keywords: true,
+            ..ValidateOptions::default()
+        };
+        let report = report(&bytes, &options);
+        assert!(!report.is_valid());
+        let codes =
+            |findings: &[Finding]| -> Vec<&str> { findings.iter().map(|f| f.code).collect() };
+        assert_eq!(codes(&report.errors), ["crc-mismatch"]);
+        assert_eq!(codes(&report.warnings), ["keyword"]);
+        assert_eq!(codes(&report.info), ["image", "unknown-chunk", "chunks"]);
+        assert_eq!(report.info[0].message, "4x4, 8-bit truecolor");
+        let crc = &report.errors[0];
+        assert_eq!((crc.chunk, crc.offset), (Some(1), Some(offsets[1])));
+        assert_eq!(report.warnings[0].severity, Severity::Warning);
+        assert_eq!(
+            (report.info[1].chunk, report.info[1].offset),
+            (Some(3), Some(offsets[3]))
+        );
+
+        let json = report.to_json();
+        assert!(json.starts_with(r#"{"errors":[{"code":"crc-mismatch","severity":"error","#));
+        assert!(json.contains(&format!(r#""chunk":2,"offset":{}}}],"info""#, offsets[2])));
+        #[cfg(feature = "serde")]
+        assert_eq!(serde_json::to_string(&report).unwrap(), json);
+
+        let report = super::report(&Png::STANDARD_HEADER, &ValidateOptions::default());
+        let all: Vec<Finding> = report.findings().cloned().collect();
+        assert_eq!(codes(&all), ["missing-chunk", "missing-chunk", "chunks"]);
+        let report = super::report(b"GIF89a", &ValidateOptions::default());
+        assert_eq!(codes(&report.errors), ["signature"]);
+        assert_eq!(report.errors[0].offset, Some(0));
+    }
+
+    #[test]
+    fn test_keywords() {
+        let mut png = testing_png(4, 4);
+        png.insert_chunk(
+            1,
+            Chunk::new("tEXt".parse().unwrap(), b"Author\0me".to_vec()),
+        );
+        png.insert_chunk(
+            2,
+            Chunk::new("zTXt".parse().unwrap(), b" Author\0\0".to_vec()),
+        );
+        let bytes = png.as_bytes();
+        let offset = png.chunk_offsets()[2];
+        assert_eq!(validate(&bytes, &ValidateOptions::default()), Ok(5));
+        let options = ValidateOptions {
+            keywords: true,
+            ..ValidateOptions::default()
+        };
+        assert_eq!(
+            validate(&bytes, &options),
+            Err(ValidateError::Png(PngError::InvalidKeyword(offset)))
+        );
+    }
+
+    #[test]
+    fn test_unique() {
+        let mut png = testing_png(4, 4);
+        let time = Chunk::new("tIME".parse().unwrap(), vec![0; 7]);
+        png.insert_chunk(1, time.clone());
+        png.insert_chunk(2, time);
+        let bytes = png.as_bytes();
+        assert_eq!(validate(&bytes, &ValidateOptions::default()), Ok(5));
+        let options = ValidateOptions {
+            unique: true,
+            ..ValidateOptions::default()
+        };
+        assert_eq!(
+            validate(&bytes, &options),
+            Err(ValidateError::Duplicate(
+                png.chunk_offsets()[2],
+                "tIME".parse().unwrap()
+            ))
+        );
+    }
+
+    #[test]
+    fn test_placement() {
+        let mut png = testing_png(4, 4);
+        png.append_chunk(Chunk::new("pHYs".parse().unwrap(), vec![0; 9]));
+        let bytes = png.as_bytes();
+        assert_eq!(validate(&bytes, &ValidateOptions::default()), Ok(4));
+        let options = ValidateOptions {
+            placement: true,
+            ..ValidateOptions::default()
+        };
+        let ValidateError::Misplaced(misplaced) = validate(&bytes, &options).unwrap_err() else {
+            panic!("not misplaced");
+        };
+        assert_eq!(misplaced, png.misplaced()[0]);
+        assert_eq!(
+            (misplaced.index, misplaced.offset),
+            (2, png.chunk_offsets()[2])
+        );
+
+        png.insert_chunk(1, Chunk::new("PLTE".parse().unwrap(), vec![0; 7]));
+        let options = ValidateOptions {
+            header: true,
+            ..ValidateOptions::default()
+        };
+        assert_eq!(
+            validate(&png.as_bytes(), &options),
+            Err(ValidateError::Header(PixelError::PaletteLength(7)))
+        );
+
+        // Transparency for a palette that isn't there doesn't count
+        let mut png = testing_png(4, 4);
+        png.insert_chunk(1, Chunk::new("tRNS".parse().unwrap(), vec![0; 6]));
+        let options = ValidateOptions {
+            header: true,
+            indices: true,
+            ..ValidateOptions::default()
+        };
+        assert_eq!(validate(&png.as_bytes(), &options), Ok(4));
+    }
+}
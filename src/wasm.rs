@@ -0,0 +1,106 @@
+//! wasm-bindgen exports for inspecting and editing PNGs in a web page. Files
+//! go in and come out as `Uint8Array`s; nothing touches the network or disk
+
+use std::str::FromStr;
+
+use wasm_bindgen::prelude::*;
+
+use crate::chunk_type::ChunkType;
+use crate::message;
+use crate::png::Png;
+
+fn read(bytes: &[u8]) -> Result<Png, JsError> {
+    Ok(Png::try_from(bytes)?)
+}
+
+/// One chunk of a parsed file
+#[wasm_bindgen(getter_with_clone)]
+pub struct ChunkInfo {
+    pub chunk_type: String,
+    pub offset: usize,
+    pub length: u32,
+    pub crc: u32,
+}
+
+/// Check a file parses, returning the same chunk listing `ping print` does
+#[wasm_bindgen]
+pub fn parse(bytes: &[u8]) -> Result<String, JsError> {
+    Ok(read(bytes)?.to_string())
+}
+
+#[wasm_bindgen]
+pub fn list_chunks(bytes: &[u8]) -> Result<Vec<ChunkInfo>, JsError> {
+    let png = read(bytes)?;
+    Ok(png
+        .chunks()
+        .iter()
+        .zip(png.chunk_offsets())
+        .map(|(chunk, offset)| ChunkInfo {
+            chunk_type: chunk.chunk_type().to_string(),
+            offset,
+            length: chunk.length(),
+            crc: chunk.crc(),
+        })
+        .collect())
+}
+
+/// Add `message` in a new chunk, encrypted under `password` if one is given
+/// with the default key derivation cost, and return the new file
+#[wasm_bindgen]
+pub fn encode_message(
+    bytes: &[u8],
+    chunk_type: &str,
+    message: &str,
+    password: Option<String>,
+) -> Result<Vec<u8>, JsError> {
+    let mut png = read(bytes)?;
+    let chunk_type = ChunkType::from_str(chunk_type)?;
+    message::embed(
+        &mut png,
+        chunk_type,
+        message.as_bytes(),
+        password.as_deref(),
+    )
+    .map_err(|e| JsError::new(&e.to_string()))?;
+    Ok(png.as_bytes())
+}
+
+/// Read the message in the chunks of `chunk_type`, as written by
+/// `encode_message` or by `ping encode` without keys beyond a password
+#[wasm_bindgen]
+pub fn decode_message(
+    bytes: &[u8],
+    chunk_type: &str,
+    password: Option<String>,
+) -> Result<String, JsError> {
+    let png = read(bytes)?;
+    let chunk_type = ChunkType::from_str(chunk_type)?;
+    let message = message::extract(&png, &chunk_type, password.as_deref())
+        .map_err(|e| JsError::new(&e.to_string()))?;
+    Ok(String::from_utf8(message)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pixels::tests::testing_png;
+
+    // JsError can only be built on wasm32, so only success paths run here
+    #[test]
+    fn test_round_trip() {
+        let bytes = testing_png(4, 4).as_bytes();
+        let encoded = encode_message(&bytes, "ruSt", "attack at dawn", None).unwrap();
+        let chunks = list_chunks(&encoded).unwrap();
+        assert_eq!(chunks.len(), 4);
+        assert_eq!(chunks[2].chunk_type, "ruSt");
+        assert_eq!(
+            chunks[2].offset,
+            chunks[1].offset + chunks[1].length as usize + 12
+        );
+        assert!(parse(&encoded).unwrap().contains("ruSt"));
+        assert_eq!(
+            decode_message(&encoded, "ruSt", None).unwrap(),
+            "attack at dawn"
+        );
+    }
+}
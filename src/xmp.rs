@@ -0,0 +1,550 @@
+//! XMP packets, stored in PNG as an uncompressed iTXt chunk with the
+//! keyword `XML:com.adobe.xmp`. [`Xmp`] holds the properties of a packet's
+//! RDF descriptions; writing it back produces a fresh packet, so markup
+//! outside those properties isn't kept
+
+use std::fmt::Display;
+
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::png::Png;
+use crate::text::{TextChunk, TextError};
+
+/// The iTXt keyword XMP is stored under
+pub const KEYWORD: &str = "XML:com.adobe.xmp";
+
+const RDF: &str = "http://www.w3.org/1999/02/22-rdf-syntax-ns#";
+const XML: &str = "http://www.w3.org/XML/1998/namespace";
+
+/// Deepest nesting of structs and arrays read
+const MAX_DEPTH: usize = 32;
+
+/// Prefixes for the common schemas, so they can be queried without the
+/// packet declaring them
+const STANDARD_PREFIXES: [(&str, &str); 8] = [
+    ("dc", "http://purl.org/dc/elements/1.1/"),
+    ("xmp", "http://ns.adobe.com/xap/1.0/"),
+    ("xmpRights", "http://ns.adobe.com/xap/1.0/rights/"),
+    ("xmpMM", "http://ns.adobe.com/xap/1.0/mm/"),
+    ("photoshop", "http://ns.adobe.com/photoshop/1.0/"),
+    ("tiff", "http://ns.adobe.com/tiff/1.0/"),
+    ("exif", "http://ns.adobe.com/exif/1.0/"),
+    (
+        "Iptc4xmpCore",
+        "http://iptc.org/std/Iptc4xmpCore/1.0/xmlns/",
+    ),
+];
+
+#[derive(Debug, PartialEq)]
+pub enum XmpError {
+    Xml(roxmltree::Error),
+    Text(TextError),
+    /// The packet has no rdf:RDF element
+    MissingRdf,
+    TooDeep,
+    /// A property name's prefix is neither declared by the packet nor a
+    /// standard one
+    UnknownPrefix(String),
+}
+
+impl Display for XmpError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            XmpError::Xml(e) => write!(f, "invalid XMP: {}", e),
+            XmpError::Text(e) => write!(f, "invalid XMP chunk: {}", e),
+            XmpError::MissingRdf => write!(f, "XMP packet has no rdf:RDF element"),
+            XmpError::TooDeep => {
+                write!(f, "XMP is nested more than {} levels deep", MAX_DEPTH)
+            }
+            XmpError::UnknownPrefix(prefix) => write!(f, "unknown XMP prefix {}", prefix),
+        }
+    }
+}
+
+impl std::error::Error for XmpError {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArrayKind {
+    /// Ordered, like dc:creator
+    Seq,
+    /// Unordered, like dc:subject
+    Bag,
+    /// Alternatives, usually one per language, like dc:title
+    Alt,
+}
+
+impl ArrayKind {
+    fn name(&self) -> &'static str {
+        match self {
+            ArrayKind::Seq => "Seq",
+            ArrayKind::Bag => "Bag",
+            ArrayKind::Alt => "Alt",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Item {
+    /// The item's xml:lang, as Alt items carry
+    pub lang: Option<String>,
+    pub value: Value,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Value {
+    Text(String),
+    Array(ArrayKind, Vec<Item>),
+    Struct(Vec<Property>),
+}
+
+impl Value {
+    /// The text of a simple value, or of an array's x-default or else
+    /// first item
+    pub fn as_text(&self) -> Option<&str> {
+        match self {
+            Value::Text(text) => Some(text),
+            Value::Array(_, items) => items
+                .iter()
+                .find(|item| item.lang.as_deref() == Some("x-default"))
+                .or(items.first())
+                .and_then(|item| item.value.as_text()),
+            Value::Struct(_) => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Property {
+    pub namespace: String,
+    pub name: String,
+    pub value: Value,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Xmp {
+    /// Prefixes the packet declared, for naming and writing properties
+    pub prefixes: Vec<(String, String)>,
+    pub properties: Vec<Property>,
+}
+
+impl Xmp {
+    pub fn parse(xml: &str) -> Result<Xmp, XmpError> {
+        let document = roxmltree::Document::parse(xml).map_err(XmpError::Xml)?;
+        let rdf = document
+            .descendants()
+            .find(|n| n.has_tag_name((RDF, "RDF")))
+            .ok_or(XmpError::MissingRdf)?;
+        let mut xmp = Xmp::default();
+        for node in document.descendants().filter(|n| n.is_element()) {
+            for namespace in node.namespaces() {
+                let Some(prefix) = namespace.name() else {
+                    continue;
+                };
+                if xmp.uri(prefix).is_none() && ![RDF, XML].contains(&namespace.uri()) {
+                    xmp.prefixes
+                        .push((prefix.to_string(), namespace.uri().to_string()));
+                }
+            }
+        }
+        for description in rdf
+            .children()
+            .filter(|n| n.has_tag_name((RDF, "Description")))
+        {
+            xmp.properties.extend(properties(description, 0)?);
+        }
+        Ok(xmp)
+    }
+
+    fn uri(&self, prefix: &str) -> Option<&str> {
+        self.prefixes
+            .iter()
+            .map(|(p, uri)| (p.as_str(), uri.as_str()))
+            .chain(STANDARD_PREFIXES)
+            .find(|(p, _)| *p == prefix)
+            .map(|(_, uri)| uri)
+    }
+
+    fn prefix<'a>(&'a self, uri: &str) -> Option<&'a str> {
+        self.prefixes
+            .iter()
+            .map(|(p, uri)| (p.as_str(), uri.as_str()))
+            .chain(STANDARD_PREFIXES)
+            .find(|(_, u)| *u == uri)
+            .map(|(p, _)| p)
+    }
+
+    /// `(namespace, name)` for a name like `dc:title`
+    fn resolve<'a>(&self, name: &'a str) -> Result<(String, &'a str), XmpError> {
+        let (prefix, local) = name
+            .split_once(':')
+            .ok_or_else(|| XmpError::UnknownPrefix(String::new()))?;
+        let uri = self
+            .uri(prefix)
+            .ok_or_else(|| XmpError::UnknownPrefix(prefix.to_string()))?;
+        Ok((uri.to_string(), local))
+    }
+
+    fn position(&self, name: &str) -> Option<usize> {
+        let (namespace, local) = self.resolve(name).ok()?;
+        self.properties
+            .iter()
+            .position(|p| p.namespace == namespace && p.name == local)
+    }
+
+    /// The value of a top-level property named like `dc:title`
+    pub fn get(&self, name: &str) -> Option<&Value> {
+        Some(&self.properties[self.position(name)?].value)
+    }
+
+    /// [`get`](Xmp::get) as text; see [`Value::as_text`]
+    pub fn text(&self, name: &str) -> Option<&str> {
+        self.get(name)?.as_text()
+    }
+
+    /// Set a top-level property named like `dc:title`, in place if it's
+    /// there already
+    pub fn set(&mut self, name: &str, value: Value) -> Result<(), XmpError> {
+        match self.position(name) {
+            Some(at) => self.properties[at].value = value,
+            None => {
+                let (namespace, local) = self.resolve(name)?;
+                self.properties.push(Property {
+                    namespace,
+                    name: local.to_string(),
+                    value,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    pub fn remove(&mut self, name: &str) -> Option<Value> {
+        Some(self.properties.remove(self.position(name)?).value)
+    }
+
+    /// Serialize as a complete packet, properties in one rdf:Description
+    pub fn to_xml(&self) -> String {
+        let mut namespaces = Vec::new();
+        collect_namespaces(&self.properties, &mut namespaces);
+        let prefixes: Vec<(String, &str)> = namespaces
+            .iter()
+            .enumerate()
+            .map(|(i, uri)| match self.prefix(uri) {
+                Some(prefix) => (prefix.to_string(), *uri),
+                None => (format!("ns{}", i + 1), *uri),
+            })
+            .collect();
+
+        let mut xml =
+            String::from("<?xpacket begin=\"\u{feff}\" id=\"W5M0MpCehiHzreSzNTczkc9d\"?>\n");
+        xml += "<x:xmpmeta xmlns:x=\"adobe:ns:meta/\">\n";
+        xml += &format!(" <rdf:RDF xmlns:rdf=\"{}\">\n", RDF);
+        xml += "  <rdf:Description rdf:about=\"\"";
+        for (prefix, uri) in &prefixes {
+            xml += &format!("\n    xmlns:{}=\"{}\"", prefix, escape(uri));
+        }
+        xml += ">\n";
+        for property in &self.properties {
+            write_property(&mut xml, property, &prefixes, 3);
+        }
+        xml += "  </rdf:Description>\n </rdf:RDF>\n</x:xmpmeta>\n<?xpacket end=\"w\"?>";
+        xml
+    }
+
+    /// The packet as the iTXt chunk it's stored in
+    pub fn to_chunk(&self) -> Chunk {
+        let mut data = KEYWORD.as_bytes().to_vec();
+        // Uncompressed, as the XMP specification asks, with no language
+        // tag or translated keyword
+        data.extend_from_slice(&[0, 0, 0, 0, 0]);
+        data.extend_from_slice(self.to_xml().as_bytes());
+        Chunk::new(ChunkType::new(*b"iTXt"), data)
+    }
+}
+
+/// The properties of an rdf:Description or a struct, from its attributes
+/// and child elements
+fn properties(node: roxmltree::Node, depth: usize) -> Result<Vec<Property>, XmpError> {
+    if depth > MAX_DEPTH {
+        return Err(XmpError::TooDeep);
+    }
+    let mut properties: Vec<Property> = node
+        .attributes()
+        .filter_map(|a| Some((a.namespace()?, a)))
+        .filter(|(namespace, _)| ![RDF, XML].contains(namespace))
+        .map(|(namespace, a)| Property {
+            namespace: namespace.to_string(),
+            name: a.name().to_string(),
+            value: Value::Text(a.value().to_string()),
+        })
+        .collect();
+    for child in node.children().filter(|n| n.is_element()) {
+        let Some(namespace) = child.tag_name().namespace() else {
+            continue;
+        };
+        properties.push(Property {
+            namespace: namespace.to_string(),
+            name: child.tag_name().name().to_string(),
+            value: value(child, depth + 1)?,
+        });
+    }
+    Ok(properties)
+}
+
+fn value(node: roxmltree::Node, depth: usize) -> Result<Value, XmpError> {
+    if depth > MAX_DEPTH {
+        return Err(XmpError::TooDeep);
+    }
+    if let Some(resource) = node.attribute((RDF, "resource")) {
+        return Ok(Value::Text(resource.to_string()));
+    }
+    if node.attribute((RDF, "parseType")) == Some("Resource") {
+        return Ok(Value::Struct(properties(node, depth)?));
+    }
+    let Some(child) = node.children().find(|n| n.is_element()) else {
+        let fields = properties(node, depth)?;
+        return Ok(match fields.is_empty() {
+            true => Value::Text(node.text().unwrap_or_default().to_string()),
+            // Struct fields written as attributes of the property
+            false => Value::Struct(fields),
+        });
+    };
+    let kind = match child.tag_name().name() {
+        _ if child.tag_name().namespace() != Some(RDF) => None,
+        "Seq" => Some(ArrayKind::Seq),
+        "Bag" => Some(ArrayKind::Bag),
+        "Alt" => Some(ArrayKind::Alt),
+        "Description" => return Ok(Value::Struct(properties(child, depth + 1)?)),
+        _ => None,
+    };
+    let Some(kind) = kind else {
+        return Ok(Value::Struct(properties(node, depth)?));
+    };
+    let items = child
+        .children()
+        .filter(|n| n.has_tag_name((RDF, "li")))
+        .map(|li| {
+            Ok(Item {
+                lang: li.attribute((XML, "lang")).map(str::to_string),
+                value: value(li, depth + 1)?,
+            })
+        })
+        .collect::<Result<_, XmpError>>()?;
+    Ok(Value::Array(kind, items))
+}
+
+fn collect_namespaces<'a>(properties: &'a [Property], namespaces: &mut Vec<&'a str>) {
+    for property in properties {
+        if !namespaces.contains(&property.namespace.as_str()) {
+            namespaces.push(&property.namespace);
+        }
+        let mut values = vec![&property.value];
+        while let Some(value) = values.pop() {
+            match value {
+                Value::Text(_) => {}
+                Value::Array(_, items) => values.extend(items.iter().map(|item| &item.value)),
+                Value::Struct(fields) => collect_namespaces(fields, namespaces),
+            }
+        }
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn write_property(
+    xml: &mut String,
+    property: &Property,
+    prefixes: &[(String, &str)],
+    indent: usize,
+) {
+    let prefix = prefixes
+        .iter()
+        .find(|(_, uri)| *uri == property.namespace)
+        .map_or("", |(prefix, _)| prefix.as_str());
+    let name = format!("{}:{}", prefix, property.name);
+    write_value(xml, &name, "", &property.value, prefixes, indent);
+}
+
+fn write_value(
+    xml: &mut String,
+    name: &str,
+    attributes: &str,
+    value: &Value,
+    prefixes: &[(String, &str)],
+    indent: usize,
+) {
+    let pad = " ".repeat(indent);
+    match value {
+        Value::Text(text) => {
+            *xml += &format!(
+                "{}<{}{}>{}</{}>\n",
+                pad,
+                name,
+                attributes,
+                escape(text),
+                name
+            );
+        }
+        Value::Array(kind, items) => {
+            *xml += &format!(
+                "{}<{}{}>\n{} <rdf:{}>\n",
+                pad,
+                name,
+                attributes,
+                pad,
+                kind.name()
+            );
+            for item in items {
+                let lang = match &item.lang {
+                    Some(lang) => format!(" xml:lang=\"{}\"", escape(lang)),
+                    None => String::new(),
+                };
+                write_value(xml, "rdf:li", &lang, &item.value, prefixes, indent + 2);
+            }
+            *xml += &format!("{} </rdf:{}>\n{}</{}>\n", pad, kind.name(), pad, name);
+        }
+        Value::Struct(fields) => {
+            *xml += &format!(
+                "{}<{}{} rdf:parseType=\"Resource\">\n",
+                pad, name, attributes
+            );
+            for field in fields {
+                write_property(xml, field, prefixes, indent + 1);
+            }
+            *xml += &format!("{}</{}>\n", pad, name);
+        }
+    }
+}
+
+fn is_xmp(chunk: &Chunk) -> bool {
+    chunk.chunk_type().as_str() == "iTXt"
+        && chunk
+            .data()
+            .strip_prefix(KEYWORD.as_bytes())
+            .is_some_and(|rest| rest.first() == Some(&0))
+}
+
+impl Png {
+    /// The file's XMP, or `None` if it has none
+    pub fn xmp(&self) -> Option<Result<Xmp, XmpError>> {
+        let chunk = self.chunks().iter().find(|c| is_xmp(c))?;
+        Some(match TextChunk::parse(chunk)? {
+            Ok(text) => Xmp::parse(&text.text),
+            Err(e) => Err(XmpError::Text(e)),
+        })
+    }
+
+    /// Store `xmp` in place of the file's XMP chunk, or before the image
+    /// data if it has none, so readers find it before they stop reading
+    pub fn set_xmp(&mut self, xmp: &Xmp) {
+        let at = self.chunks().iter().position(is_xmp).or_else(|| {
+            self.chunks()
+                .iter()
+                .position(|c| c.chunk_type().as_str() == "IDAT")
+        });
+        self.remove_xmp();
+        self.insert_chunk(at.unwrap_or(usize::MAX), xmp.to_chunk());
+    }
+
+    /// Drop the file's XMP, returning whether it had any
+    pub fn remove_xmp(&mut self) -> bool {
+        let before = self.chunks().len();
+        self.retain_chunks(|c| !is_xmp(c));
+        self.chunks().len() != before
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pixels::tests::testing_png;
+
+    const PACKET: &str = r#"<?xpacket begin="" id="W5M0MpCehiHzreSzNTczkc9d"?>
+<x:xmpmeta xmlns:x="adobe:ns:meta/">
+  <rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#">
+    <rdf:Description rdf:about=""
+        xmlns:xmp="http://ns.adobe.com/xap/1.0/"
+        xmlns:dc="http://purl.org/dc/elements/1.1/"
+        xmlns:Iptc4xmpCore="http://iptc.org/std/Iptc4xmpCore/1.0/xmlns/"
+        xmlns:my="http://example.com/ns/"
+        xmp:CreatorTool="ping &amp; friends">
+      <dc:title>
+        <rdf:Alt>
+          <rdf:li xml:lang="en">Cover</rdf:li>
+          <rdf:li xml:lang="x-default">Cover art</rdf:li>
+        </rdf:Alt>
+      </dc:title>
+      <dc:creator><rdf:Seq><rdf:li>Ferris</rdf:li><rdf:li>Corro</rdf:li></rdf:Seq></dc:creator>
+      <Iptc4xmpCore:CreatorContactInfo rdf:parseType="Resource">
+        <Iptc4xmpCore:CiEmailWork>ferris@example.com</Iptc4xmpCore:CiEmailWork>
+      </Iptc4xmpCore:CreatorContactInfo>
+      <my:Rating my:scale="5"/>
+    </rdf:Description>
+  </rdf:RDF>
+</x:xmpmeta>
+<?xpacket end="w"?>"#;
+
+    #[test]
+    fn test_parse() {
+        let xmp = Xmp::parse(PACKET).unwrap();
+        assert_eq!(xmp.text("xmp:CreatorTool"), Some("ping & friends"));
+        assert_eq!(xmp.text("dc:title"), Some("Cover art"));
+        let Some(Value::Array(ArrayKind::Seq, creators)) = xmp.get("dc:creator") else {
+            panic!("dc:creator isn't a Seq");
+        };
+        assert_eq!(creators[1].value, Value::Text("Corro".to_string()));
+        let Some(Value::Struct(contact)) = xmp.get("Iptc4xmpCore:CreatorContactInfo") else {
+            panic!("contact info isn't a struct");
+        };
+        assert_eq!(contact[0].name, "CiEmailWork");
+        assert!(matches!(xmp.get("my:Rating"), Some(Value::Struct(fields)) if fields.len() == 1));
+        assert_eq!(xmp.get("dc:subject"), None);
+        assert_eq!(xmp.get("nope:thing"), None);
+
+        assert_eq!(Xmp::parse("<x/>"), Err(XmpError::MissingRdf));
+        assert!(matches!(Xmp::parse("<x"), Err(XmpError::Xml(_))));
+        let deep = format!(
+            "<rdf:RDF xmlns:rdf=\"{}\" xmlns:a=\"a:\"><rdf:Description>{}{}</rdf:Description></rdf:RDF>",
+            RDF,
+            "<a:b rdf:parseType=\"Resource\">".repeat(100),
+            "</a:b>".repeat(100)
+        );
+        assert_eq!(Xmp::parse(&deep), Err(XmpError::TooDeep));
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let mut xmp = Xmp::parse(PACKET).unwrap();
+        xmp.set("dc:title", Value::Text("<New> \"title\"".to_string()))
+            .unwrap();
+        xmp.set("photoshop:Credit", Value::Text("Ferris".to_string()))
+            .unwrap();
+        assert_eq!(
+            xmp.set("nope:thing", Value::Text(String::new())),
+            Err(XmpError::UnknownPrefix("nope".to_string()))
+        );
+        assert!(xmp.remove("xmp:CreatorTool").is_some());
+        let reparsed = Xmp::parse(&xmp.to_xml()).unwrap();
+        assert_eq!(reparsed.properties, xmp.properties);
+        assert_eq!(reparsed.text("dc:title"), Some("<New> \"title\""));
+
+        let mut png = testing_png(4, 4);
+        assert!(png.xmp().is_none());
+        png.set_xmp(&xmp);
+        png.set_xmp(&reparsed);
+        let types: Vec<String> = png
+            .chunks()
+            .iter()
+            .map(|c| c.chunk_type().to_string())
+            .collect();
+        assert_eq!(types, ["IHDR", "iTXt", "IDAT", "IEND"]);
+        let read = Png::try_from(png.as_bytes().as_slice()).unwrap();
+        assert_eq!(read.xmp().unwrap().unwrap().properties, xmp.properties);
+        assert!(png.remove_xmp() && !png.remove_xmp());
+    }
+}
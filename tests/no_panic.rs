@@ -0,0 +1,202 @@
+//! Every parse and decode entry point must return an error rather than
+//! panic, whatever it's given. Inputs are valid files broken in the ways
+//! that find bugs: truncated, bytes flipped, lengths forged, sections
+//! shuffled. The fuzz targets under `fuzz/` search further
+
+#![cfg(feature = "std")]
+
+use std::io::Cursor;
+use std::panic::{self, AssertUnwindSafe};
+
+use ping::arena::Arena;
+use ping::chunk::Chunk;
+use ping::chunk_type::ChunkType;
+use ping::lazy::LazyPng;
+use ping::pixels::{self, Pixels};
+use ping::png::{Mode, ParseOptions, Png};
+use ping::streaming;
+use ping::text::{TextChunk, TextError};
+use ping::validate::{self, ValidateOptions};
+use ping::{deflate, stego};
+
+fn chunk(chunk_type: &str, data: &[u8]) -> Chunk {
+    Chunk::new(chunk_type.parse::<ChunkType>().unwrap(), data.to_vec())
+}
+
+/// A 2-bit indexed, interlaced 5x3 image with a text chunk of each kind
+fn sample() -> Vec<u8> {
+    let mut ihdr = 5u32.to_be_bytes().to_vec();
+    ihdr.extend_from_slice(&3u32.to_be_bytes());
+    ihdr.extend_from_slice(&[2, 3, 0, 0, 1]);
+    let compressed = deflate::backend().compress(b"compressed text", 6);
+    let ztxt = [&b"Comment\0\0"[..], &compressed].concat();
+    let itxt = [&b"Title\0\x01\0en\0Titel\0"[..], &compressed].concat();
+    // A 5x3 image's passes have 1, 1, 0, 1, 1, 2 and 1 rows, one byte
+    // each but the last pass's two
+    let scanlines = [
+        &[0, 0b10000000][..],
+        &[1, 0b01000000],
+        &[2, 0b10000000],
+        &[3, 0b00011000],
+        &[4, 0b01100000],
+        &[0, 0b01100000],
+        &[0, 0b10011000, 0b01000000],
+    ]
+    .concat();
+    Png::from_chunks(vec![
+        chunk("IHDR", &ihdr),
+        chunk("PLTE", &[0, 0, 0, 255, 0, 0, 0, 255, 0]),
+        chunk("tRNS", &[0, 128]),
+        chunk("tEXt", b"Author\0Ferris"),
+        chunk("zTXt", &ztxt),
+        chunk("iTXt", &itxt),
+        chunk("IDAT", &deflate::backend().compress(&scanlines, 6)),
+        chunk("IEND", b""),
+    ])
+    .as_bytes()
+}
+
+/// xorshift64, so failures reproduce
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn below(&mut self, bound: usize) -> usize {
+        (self.next() % bound.max(1) as u64) as usize
+    }
+}
+
+fn inputs() -> Vec<Vec<u8>> {
+    let sample = sample();
+    let mut rng = Rng(0x9e37_79b9_7f4a_7c15);
+    let mut inputs: Vec<Vec<u8>> = (0..=sample.len())
+        .map(|end| sample[..end].to_vec())
+        .collect();
+    let offsets = Png::try_from(sample.as_slice()).unwrap().chunk_offsets();
+    for _ in 0..1500 {
+        let mut input = sample.clone();
+        match rng.below(4) {
+            // Flip a few bits anywhere
+            0 => {
+                for _ in 0..1 + rng.below(4) {
+                    let at = rng.below(input.len());
+                    input[at] ^= 1 << rng.below(8);
+                }
+            }
+            // Forge a chunk's length, near its real one or anything at all
+            1 => {
+                let at = offsets[rng.below(offsets.len())];
+                let real = u32::from_be_bytes(input[at..at + 4].try_into().unwrap());
+                let length = match rng.below(2) {
+                    0 => real.wrapping_add(rng.below(9) as u32).wrapping_sub(4),
+                    _ => rng.next() as u32,
+                };
+                input[at..at + 4].copy_from_slice(&length.to_be_bytes());
+            }
+            // Swap the data of two chunks, fixing up their CRCs
+            2 => {
+                let png = Png::try_from(sample.as_slice()).unwrap();
+                let chunks = png.chunks();
+                let (a, b) = (rng.below(chunks.len()), rng.below(chunks.len()));
+                let mut swapped: Vec<Chunk> = chunks.to_vec();
+                swapped[a] = Chunk::new(*chunks[a].chunk_type(), chunks[b].data().to_vec());
+                swapped[b] = Chunk::new(*chunks[b].chunk_type(), chunks[a].data().to_vec());
+                input = Png::from_chunks(swapped).as_bytes();
+            }
+            // Random bytes after the signature
+            _ => {
+                input.truncate(8);
+                input.extend((0..rng.below(200)).map(|_| rng.next() as u8));
+            }
+        }
+        inputs.push(input);
+    }
+    inputs
+}
+
+/// Everything that takes bytes from a file, fed `input`
+fn decode_everything(input: &[u8]) {
+    let _ = Chunk::try_from(input);
+    let _ = Png::try_from(input);
+    let _ = Png::parse_strict(input);
+    let lenient = ParseOptions {
+        mode: Mode::Lenient,
+        ..ParseOptions::default()
+    };
+    let _ = Png::parse_with(input, &lenient);
+    let _ = Arena::new().parse(input).map(|png| png.to_png());
+    let options = ValidateOptions {
+        parallel: false,
+        keywords: true,
+        unique: true,
+        placement: true,
+        header: true,
+        indices: true,
+    };
+    let _ = validate::validate(input, &options);
+    let _ = validate::report(input, &options);
+    let _ = streaming::validate(input, &streaming::Options::default());
+    if let Ok(mut lazy) = LazyPng::new(Cursor::new(input)) {
+        for index in 0..lazy.entries().len() {
+            let _ = lazy.keyword(index);
+        }
+    }
+    let Ok((png, _)) = Png::parse_ignoring_crc(input) else {
+        return;
+    };
+    for chunk in png.chunks() {
+        let _ = TextChunk::parse_checked(chunk);
+    }
+    let _ = pixels::check_indices(&png);
+    let _ = Pixels::read(&png);
+    let _ = stego::extract(&png);
+    let _ = stego::extract_trailer(&png);
+    #[cfg(feature = "crypto")]
+    {
+        use ping::payload::{self, Keys};
+
+        let rust: ChunkType = "ruSt".parse().unwrap();
+        for chunk in png.chunks() {
+            let _ = payload::correct(chunk.data());
+            let _ = payload::decode(chunk.data(), &rust, &Keys::default());
+        }
+    }
+}
+
+#[test]
+fn test_no_panics() {
+    let hook = panic::take_hook();
+    panic::set_hook(Box::new(|_| {}));
+    let panicked: Vec<Vec<u8>> = inputs()
+        .into_iter()
+        .filter(|input| panic::catch_unwind(AssertUnwindSafe(|| decode_everything(input))).is_err())
+        .collect();
+    panic::set_hook(hook);
+    if let Some(input) = panicked.first() {
+        // Run it again with the hook back, for the message and location
+        decode_everything(input);
+        panic!("{} inputs panicked without saying where", panicked.len());
+    }
+}
+
+#[test]
+fn test_sample_decodes() {
+    let bytes = sample();
+    let options = ValidateOptions {
+        header: true,
+        indices: true,
+        ..ValidateOptions::default()
+    };
+    assert_eq!(validate::validate(&bytes, &options), Ok(8));
+    let png = Png::try_from(bytes.as_slice()).unwrap();
+    assert_eq!(Pixels::read(&png).unwrap().data.len(), 3 * 2);
+    let texts: Result<Vec<TextChunk>, TextError> =
+        png.chunks().iter().filter_map(TextChunk::parse).collect();
+    assert_eq!(texts.unwrap().len(), 3);
+}
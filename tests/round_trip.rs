@@ -0,0 +1,107 @@
+//! An unedited file serializes back to exactly the bytes it was parsed
+//! from, whichever way it's parsed and written: chunk order, how IDAT is
+//! split and data after IEND all survive, so running files through the
+//! tool never shows up as a diff
+
+#![cfg(feature = "std")]
+
+use ping::arena::Arena;
+use ping::chunk::Chunk;
+use ping::chunk_type::ChunkType;
+use ping::deflate;
+use ping::png::{Mode, ParseOptions, Png};
+
+fn chunk(chunk_type: &str, data: &[u8]) -> Chunk {
+    Chunk::new(chunk_type.parse::<ChunkType>().unwrap(), data.to_vec())
+}
+
+/// Files that a normalizing writer would change
+fn files() -> Vec<(&'static str, Vec<u8>)> {
+    let mut ihdr = 64u32.to_be_bytes().to_vec();
+    ihdr.extend_from_slice(&64u32.to_be_bytes());
+    ihdr.extend_from_slice(&[8, 0, 0, 0, 0]);
+    let image = deflate::backend().compress(&[0; 65 * 64], 6);
+    let noise: Vec<u8> = (0..100_000u32).map(|i| ((i * 7919) >> 3) as u8).collect();
+
+    // IDAT split unevenly, with an empty one among them
+    let mut split = vec![chunk("IHDR", &ihdr)];
+    let (first, rest) = image.split_at(image.len() / 3);
+    split.extend([
+        chunk("IDAT", first),
+        chunk("IDAT", b""),
+        chunk("IDAT", rest),
+    ]);
+    split.push(chunk("IEND", b""));
+
+    // Ancillary chunks out of canonical order, repeated, private and big
+    let unordered = vec![
+        chunk("IHDR", &ihdr),
+        chunk("tEXt", b"Zebra\0last keyword first"),
+        chunk("tEXt", b"Author\0one"),
+        chunk("tEXt", b"Author\0two"),
+        chunk("ruSt", &noise),
+        chunk("IDAT", &image),
+        chunk("tIME", &[7, 234, 1, 2, 3, 4, 5]),
+        chunk("prVt", b""),
+        chunk("IEND", b""),
+    ];
+
+    let mut files = vec![
+        ("split IDAT", Png::from_chunks(split).as_bytes()),
+        ("unordered", Png::from_chunks(unordered.clone()).as_bytes()),
+    ];
+    for (name, trailing) in [
+        ("trailing text", &b"appended after the end"[..]),
+        ("trailing chunk", &chunk("IEND", b"").as_bytes()),
+        ("trailing noise", &noise),
+    ] {
+        let mut png = Png::from_chunks(unordered.clone());
+        png.set_trailing(trailing.to_vec());
+        files.push((name, png.as_bytes()));
+    }
+    // A chunk after IEND isn't parsed as one
+    let after_end = Png::from_chunks(vec![chunk("IHDR", &ihdr), chunk("IEND", b"")]);
+    let mut after_end = after_end.as_bytes();
+    after_end.extend_from_slice(&chunk("tEXt", b"Hidden\0here").as_bytes());
+    files.push(("after IEND", after_end));
+    files
+}
+
+#[test]
+fn test_parse_and_write() {
+    let lenient = ParseOptions {
+        mode: Mode::Lenient,
+        ..ParseOptions::default()
+    };
+    for (name, bytes) in files() {
+        let png = Png::try_from(bytes.as_slice()).unwrap();
+        assert_eq!(png.as_bytes(), bytes, "{}", name);
+        assert_eq!(png.encoded_len(), bytes.len(), "{}", name);
+        let mut written = Vec::new();
+        png.write_to(&mut written).unwrap();
+        assert_eq!(written, bytes, "{}: write_to", name);
+        let mut appended = b"before".to_vec();
+        png.write_into(&mut appended);
+        assert_eq!(appended[6..], bytes, "{}: write_into", name);
+
+        let (ignoring, mismatches) = Png::parse_ignoring_crc(&bytes).unwrap();
+        assert!(mismatches.is_empty());
+        assert_eq!(ignoring.as_bytes(), bytes, "{}: ignoring CRCs", name);
+        let (lenient, _) = Png::parse_with(&bytes, &lenient).unwrap();
+        assert_eq!(lenient.as_bytes(), bytes, "{}: lenient", name);
+        let mut arena = Arena::new();
+        let parsed = arena.parse(&bytes).unwrap();
+        assert_eq!(parsed.to_png().as_bytes(), bytes, "{}: arena", name);
+    }
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn test_serde() {
+    for (name, bytes) in files() {
+        let png = Png::try_from(bytes.as_slice()).unwrap();
+        let json = serde_json::to_string(&png).unwrap();
+        let back: Png = serde_json::from_str(&json).unwrap();
+        assert_eq!(back.as_bytes(), bytes, "{}", name);
+    }
+}
@@ -0,0 +1,223 @@
+//! The memory bound of the streaming module, checked against the
+//! allocator on a file far larger than the bound. In its own binary so
+//! the counting allocator sees nothing but this test
+
+#![cfg(feature = "std")]
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::io::{self, Read};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use ping::chunk::Chunk;
+use ping::png::Png;
+use ping::streaming::{self, Options};
+
+struct Counting;
+
+static LIVE: AtomicUsize = AtomicUsize::new(0);
+static PEAK: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let live = LIVE.fetch_add(layout.size(), Ordering::Relaxed) + layout.size();
+        PEAK.fetch_max(live, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        LIVE.fetch_sub(layout.size(), Ordering::Relaxed);
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: Counting = Counting;
+
+/// Bytes allocated at the worst point of `run`, over what was live before
+fn peak_of(run: impl FnOnce()) -> usize {
+    let before = LIVE.load(Ordering::Relaxed);
+    PEAK.store(before, Ordering::Relaxed);
+    run();
+    PEAK.load(Ordering::Relaxed) - before
+}
+
+const CHUNKS: usize = 4;
+const CHUNK_LEN: usize = 24 << 20;
+
+/// A PNG of a few huge IDAT chunks, made up as it's read rather than held
+struct Huge {
+    /// Everything before the first IDAT's data, then each IDAT's CRC and
+    /// the next one's header, then the last CRC and IEND
+    pieces: Vec<Vec<u8>>,
+    piece: usize,
+    at: usize,
+    chunk: usize,
+}
+
+impl Huge {
+    fn new() -> Huge {
+        let ihdr = [0, 0, 0, 1, 0, 0, 0, 1, 8, 0, 0, 0, 0];
+        let ihdr = Chunk::new("IHDR".parse().unwrap(), ihdr.to_vec());
+        let iend = Chunk::new("IEND".parse().unwrap(), Vec::new());
+        let idat_head = [&(CHUNK_LEN as u32).to_be_bytes()[..], b"IDAT"].concat();
+        let mut crc = ping::crc::CrcHasher::new();
+        crc.update(b"IDAT");
+        let zeros = [0; 4096];
+        for _ in 0..CHUNK_LEN / zeros.len() {
+            crc.update(&zeros);
+        }
+        let crc = crc.finalize().to_be_bytes();
+
+        let mut first = Png::STANDARD_HEADER.to_vec();
+        first.extend(ihdr.as_bytes());
+        first.extend(&idat_head);
+        let between = [&crc[..], &idat_head].concat();
+        let last = [&crc[..], &iend.as_bytes()].concat();
+        let mut pieces = vec![first];
+        pieces.extend(std::iter::repeat_n(between, CHUNKS - 1));
+        pieces.push(last);
+        Huge {
+            pieces,
+            piece: 0,
+            at: 0,
+            chunk: 0,
+        }
+    }
+}
+
+impl Read for Huge {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.piece == self.pieces.len() {
+            return Ok(0);
+        }
+        let piece = &self.pieces[self.piece];
+        if self.at < piece.len() {
+            let n = buf.len().min(piece.len() - self.at);
+            buf[..n].copy_from_slice(&piece[self.at..self.at + n]);
+            self.at += n;
+            return Ok(n);
+        }
+        if self.piece == self.pieces.len() - 1 {
+            self.piece += 1;
+            return Ok(0);
+        }
+        let n = buf.len().min(CHUNK_LEN - self.chunk);
+        buf[..n].fill(0);
+        self.chunk += n;
+        if self.chunk == CHUNK_LEN {
+            (self.piece, self.at, self.chunk) = (self.piece + 1, 0, 0);
+        }
+        Ok(n)
+    }
+}
+
+#[test]
+fn test_memory_stays_bounded() {
+    let options = Options {
+        max_memory: 4 << 20,
+    };
+    let peak = peak_of(|| {
+        assert_eq!(
+            streaming::validate(Huge::new(), &options).unwrap(),
+            CHUNKS + 2
+        );
+    });
+    assert!(
+        peak < options.max_memory,
+        "validate peaked at {} bytes",
+        peak
+    );
+
+    let peak = peak_of(|| {
+        let removed = streaming::strip(Huge::new(), io::sink(), &options).unwrap();
+        assert_eq!(removed, 0);
+    });
+    assert!(peak < options.max_memory, "strip peaked at {} bytes", peak);
+
+    #[cfg(feature = "crypto")]
+    {
+        let peak = peak_of(|| {
+            let mut message = io::repeat(1).take(3 * CHUNK_LEN as u64);
+            let chunk_type = "ruSt".parse().unwrap();
+            let codec = ping::compress::Codec::Zlib;
+            streaming::encode(
+                Huge::new(),
+                io::sink(),
+                chunk_type,
+                &mut message,
+                codec,
+                None,
+                &options,
+            )
+            .unwrap();
+        });
+        assert!(peak < options.max_memory, "encode peaked at {} bytes", peak);
+    }
+}
+
+/// A PNG of millions of empty private chunks between IHDR and IDAT, made
+/// up as it's read
+struct Many {
+    /// What's left to read of the current piece
+    piece: Vec<u8>,
+    at: usize,
+    /// Private chunks still to come
+    left: usize,
+    tail: Option<Vec<u8>>,
+}
+
+impl Many {
+    fn new(count: usize) -> Many {
+        let ihdr = [0, 0, 0, 1, 0, 0, 0, 1, 8, 0, 0, 0, 0];
+        let ihdr = Chunk::new("IHDR".parse().unwrap(), ihdr.to_vec());
+        let idat = Chunk::new("IDAT".parse().unwrap(), Vec::new());
+        let iend = Chunk::new("IEND".parse().unwrap(), Vec::new());
+        Many {
+            piece: [&Png::STANDARD_HEADER[..], &ihdr.as_bytes()].concat(),
+            at: 0,
+            left: count,
+            tail: Some([idat.as_bytes(), iend.as_bytes()].concat()),
+        }
+    }
+}
+
+impl Read for Many {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.at == self.piece.len() {
+            self.at = 0;
+            self.piece = match self.left {
+                0 => match self.tail.take() {
+                    Some(tail) => tail,
+                    None => return Ok(0),
+                },
+                _ => {
+                    self.left -= 1;
+                    Chunk::new("ruSt".parse().unwrap(), Vec::new()).as_bytes()
+                }
+            };
+        }
+        let n = buf.len().min(self.piece.len() - self.at);
+        buf[..n].copy_from_slice(&self.piece[self.at..self.at + n]);
+        self.at += n;
+        Ok(n)
+    }
+}
+
+#[test]
+fn test_many_chunks_stay_bounded() {
+    let options = Options {
+        max_memory: 4 << 20,
+    };
+    let count = 2_000_000;
+    let peak = peak_of(|| {
+        assert_eq!(
+            streaming::validate(Many::new(count), &options).unwrap(),
+            count + 3
+        );
+    });
+    assert!(
+        peak < options.max_memory,
+        "validate peaked at {} bytes",
+        peak
+    );
+}